@@ -1,6 +1,5 @@
 use std::fs::File;
 use std::io::BufReader;
-use std::os::unix::fs::MetadataExt;
 use std::path::PathBuf;
 use std::process::ExitCode;
 
@@ -89,12 +88,12 @@ fn main() -> ExitCode {
         let new_file = File::open(&file).unwrap();
 
         // if metadata isn't already defined, use from file
-        let meta = file.metadata().unwrap();
+        let meta = NodeHeader::from_metadata(&file.metadata().unwrap());
 
-        let mode = args.mode.unwrap_or(meta.mode() as u16) & 0xfff;
-        let uid = args.uid.unwrap_or(meta.uid());
-        let gid = args.gid.unwrap_or(meta.gid());
-        let mtime = args.mtime.unwrap_or(meta.mtime() as u32);
+        let mode = args.mode.map_or(meta.permissions, |mode| mode & 0xfff);
+        let uid = args.uid.unwrap_or(meta.uid);
+        let gid = args.gid.unwrap_or(meta.gid);
+        let mtime = args.mtime.unwrap_or(meta.mtime);
         let node = NodeHeader::new(mode, uid, gid, mtime);
 
         if let Err(e) = filesystem.push_file(new_file, args.path, node) {
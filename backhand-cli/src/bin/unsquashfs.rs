@@ -1,15 +1,18 @@
-use std::collections::HashSet;
-use std::fs::{self, File, Permissions};
-use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom};
-use std::os::unix::fs::lchown;
-use std::os::unix::prelude::PermissionsExt;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::mem::ManuallyDrop;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
 use std::path::{Component, Path, PathBuf};
 use std::process::ExitCode;
 use std::sync::Mutex;
 
 use backhand::kind::Kind;
 use backhand::{
-    BufReadSeek, FilesystemReader, InnerNode, Node, NodeHeader, Squashfs, SquashfsBlockDevice,
+    BufReadSeek, ExtractOptions, FilesystemReader, InnerNode, Node, Squashfs, SquashfsBlockDevice,
     SquashfsCharacterDevice, SquashfsDir, SquashfsFileReader, SquashfsSymlink, DEFAULT_BLOCK_SIZE,
 };
 use backhand_cli::after_help;
@@ -18,10 +21,8 @@ use clap::{CommandFactory, Parser};
 use clap_complete::{generate, Shell};
 use console::Term;
 use indicatif::{HumanDuration, ProgressBar, ProgressStyle};
-use nix::libc::geteuid;
-use nix::sys::stat::{dev_t, mknod, mode_t, umask, utimensat, utimes, Mode, SFlag, UtimensatFlags};
-use nix::sys::time::{TimeSpec, TimeVal};
-use nix::unistd::mkfifo;
+use nix::libc::{fcntl, geteuid, F_GETFD};
+use nix::sys::stat::{umask, Mode};
 use rayon::prelude::*;
 use std::time::{Duration, Instant};
 
@@ -107,6 +108,21 @@ struct Args {
     #[arg(short, long)]
     list: bool,
 
+    /// Compare filesystem against DIR, do not write to DEST (ignores --quiet)
+    ///
+    /// Each difference is printed as "{kind} {path}", where kind is one of: "missing" (not in DIR),
+    /// "extra" (only in DIR), "type", "content", or "mode". Exits with failure if any differences
+    /// are found.
+    #[arg(long, name = "DIR", conflicts_with = "list")]
+    compare_dir: Option<PathBuf>,
+
+    /// Write a manifest of all nodes to OUT ("-" for stdout), do not write to DEST
+    ///
+    /// Each node is a line of "TYPE PERMISSIONS UID GID MTIME SIZE PATH", with symlinks followed by
+    /// "-> LINK". Only the metadata of the image is read, no file data is decompressed.
+    #[arg(long, name = "OUT", conflicts_with_all = ["list", "DIR", "path_filter"])]
+    manifest_only: Option<PathBuf>,
+
     /// Extract to [PATHNAME]
     #[arg(short, long, default_value = "squashfs-root", name = "PATHNAME")]
     dest: PathBuf,
@@ -130,6 +146,32 @@ struct Args {
     #[arg(short, long)]
     force: bool,
 
+    /// Skip setting ownership, permissions and times
+    #[arg(long)]
+    no_preserve: bool,
+
+    /// Encode names that can't be created on Windows and macOS filesystems
+    ///
+    /// Reserved characters, control characters and "%" are encoded as "%XX", as are reserved
+    /// names such as "CON" and trailing dots and spaces. Names that only differ in case get a "~N"
+    /// suffix. Each changed path is written to PATHNAME.names as a "SANITIZED<tab>ORIGINAL" line.
+    #[arg(long)]
+    sanitize_names: bool,
+
+    /// Summarize failures at the end and exit with failure
+    #[arg(long)]
+    keep_going: bool,
+
+    /// Abort once more than BYTES of file data have been extracted
+    #[arg(long, value_name = "BYTES")]
+    max_total_size: Option<u64>,
+
+    /// Write "extracted N/TOTAL" lines to file descriptor FD as nodes are extracted
+    ///
+    /// Written even with --quiet, for use by other programs.
+    #[arg(long, name = "FD")]
+    progress_fd: Option<RawFd>,
+
     /// Display filesystem superblock information (ignores --quiet)
     #[arg(short, long)]
     stat: bool,
@@ -171,6 +213,14 @@ fn main() -> ExitCode {
 
     let kind = Kind::from_target(&args.kind).unwrap();
 
+    if let Some(fd) = args.progress_fd {
+        // SAFETY: only checks if fd is open
+        if unsafe { fcntl(fd, F_GETFD) } == -1 {
+            eprintln!("--progress-fd {fd}: {}", io::Error::last_os_error());
+            return ExitCode::FAILURE;
+        }
+    }
+
     let mut file = BufReader::with_capacity(
         DEFAULT_BLOCK_SIZE as usize,
         File::open(args.filesystem.as_ref().unwrap()).unwrap(),
@@ -229,12 +279,29 @@ fn main() -> ExitCode {
         let line = format!("{:>14}", blue_bold.apply_to("Reading image"));
         pb.set_message(line);
     }
-    let filesystem = squashfs.into_filesystem_reader().unwrap();
+    let mut filesystem = squashfs.into_filesystem_reader().unwrap();
+    if let Some(limit) = args.max_total_size {
+        filesystem.set_extract_limit(limit);
+    }
     if !args.quiet {
         let line = format!("{:>14}", blue_bold.apply_to("Read image"));
         pb.finish_with_message(line);
     }
 
+    if let Some(out) = &args.manifest_only {
+        let manifest = filesystem.manifest();
+        let written = if out.as_os_str() == "-" {
+            io::stdout().write_all(manifest.as_bytes())
+        } else {
+            fs::write(out, manifest)
+        };
+        if let Err(e) = written {
+            eprintln!("--manifest-only {}: {e}", out.display());
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
+
     // if we can find a parent, then a filter must be applied and the exact parent dirs must be
     // found above it
     let mut files: Vec<&Node<SquashfsFileReader>> = vec![];
@@ -266,12 +333,16 @@ fn main() -> ExitCode {
         .into_iter()
         .chain(filesystem.files().filter(|a| a.fullpath.starts_with(&args.path_filter)));
 
-    // extract or list
+    // extract, list, or compare
     if args.list {
         list(nodes);
+    } else if let Some(dir) = &args.compare_dir {
+        if !compare_dir(&filesystem, nodes, dir, &args.path_filter) {
+            return ExitCode::FAILURE;
+        }
     } else {
-        // This could be expensive, only pass this in when not quiet
-        let n_nodes = if !args.quiet {
+        // This could be expensive, only pass this in when not quiet or when writing progress
+        let n_nodes = if !args.quiet || args.progress_fd.is_some() {
             Some(
                 files_len
                     + filesystem
@@ -283,7 +354,7 @@ fn main() -> ExitCode {
             None
         };
 
-        extract_all(
+        let failures = extract_all(
             &args,
             &filesystem,
             root_process,
@@ -291,6 +362,24 @@ fn main() -> ExitCode {
             n_nodes,
             start,
         );
+
+        if let Some(limit) = args.max_total_size {
+            if filesystem.extracted_bytes() > limit {
+                eprintln!(
+                    "{:>16} extraction, --max-total-size of {limit} bytes exceeded",
+                    red_bold.apply_to("Aborted")
+                );
+                return ExitCode::FAILURE;
+            }
+        }
+
+        if args.keep_going && !failures.is_empty() {
+            eprintln!("{:>16} to extract {} nodes:", red_bold.apply_to("Failed"), failures.len());
+            for failure in failures {
+                eprintln!("  {failure}");
+            }
+            return ExitCode::FAILURE;
+        }
     }
 
     ExitCode::SUCCESS
@@ -303,6 +392,130 @@ fn list<'a>(nodes: impl Iterator<Item = &'a Node<SquashfsFileReader>>) {
     }
 }
 
+/// Print the differences between `nodes` and the matching files in `dir`
+///
+/// # Returns
+/// `true` when there are no differences
+fn compare_dir<'a>(
+    filesystem: &FilesystemReader,
+    nodes: impl Iterator<Item = &'a Node<SquashfsFileReader>>,
+    dir: &Path,
+    path_filter: &Path,
+) -> bool {
+    let mut same = true;
+    let mut difference = |kind: &str, path: &Path| {
+        println!("{kind} {}", path.display());
+        same = false;
+    };
+
+    let mut image_paths = HashSet::new();
+    for node in nodes {
+        let path = &node.fullpath;
+        image_paths.insert(path.clone());
+        let filepath = dir.join(path.strip_prefix(Component::RootDir).unwrap_or(path));
+        let Ok(metadata) = fs::symlink_metadata(&filepath) else {
+            difference("missing", path);
+            continue;
+        };
+
+        let file_type = metadata.file_type();
+        let same_type = match &node.inner {
+            InnerNode::File(_) => file_type.is_file(),
+            InnerNode::Symlink(_) => file_type.is_symlink(),
+            InnerNode::Dir(_) => file_type.is_dir(),
+            InnerNode::CharacterDevice(_) => file_type.is_char_device(),
+            InnerNode::BlockDevice(_) => file_type.is_block_device(),
+            InnerNode::NamedPipe => file_type.is_fifo(),
+            InnerNode::Socket => file_type.is_socket(),
+        };
+        if !same_type {
+            difference("type", path);
+            continue;
+        }
+
+        let same_content = match &node.inner {
+            InnerNode::File(file) => {
+                let file = filesystem.file(file);
+                File::open(&filepath)
+                    .and_then(|on_disk| same_contents(file.reader(), BufReader::new(on_disk)))
+                    .unwrap_or(false)
+            }
+            InnerNode::Symlink(SquashfsSymlink { link }) => {
+                fs::read_link(&filepath).is_ok_and(|on_disk| on_disk == *link)
+            }
+            InnerNode::CharacterDevice(SquashfsCharacterDevice { device_number })
+            | InnerNode::BlockDevice(SquashfsBlockDevice { device_number }) => {
+                metadata.rdev() == u64::from(*device_number)
+            }
+            InnerNode::Dir(_) | InnerNode::NamedPipe | InnerNode::Socket => true,
+        };
+        if !same_content {
+            difference("content", path);
+        }
+
+        // symlink permissions are not used
+        if !file_type.is_symlink() && metadata.mode() & 0o7777 != u32::from(node.header.permissions)
+        {
+            difference("mode", path);
+        }
+    }
+
+    // find files only in dir
+    let mut extra = vec![];
+    let filter = dir.join(path_filter.strip_prefix(Component::RootDir).unwrap_or(path_filter));
+    find_extra(dir, &filter, &image_paths, &mut extra);
+    extra.sort();
+    for path in extra {
+        difference("extra", &path);
+    }
+
+    same
+}
+
+/// Recursively add paths under `path` not in `image_paths` to `extra`, as their image path
+fn find_extra(dir: &Path, path: &Path, image_paths: &HashSet<PathBuf>, extra: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(path) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let image_path = Path::new("/").join(path.strip_prefix(dir).unwrap());
+        if !image_paths.contains(&image_path) {
+            extra.push(image_path);
+        } else if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            find_extra(dir, &path, image_paths, extra);
+        }
+    }
+}
+
+/// Compare two readers byte for byte
+fn same_contents(mut a: impl Read, mut b: impl Read) -> io::Result<bool> {
+    let mut a_buf = vec![0; DEFAULT_BLOCK_SIZE as usize];
+    let mut b_buf = vec![0; DEFAULT_BLOCK_SIZE as usize];
+    loop {
+        let a_len = read_full(&mut a, &mut a_buf)?;
+        let b_len = read_full(&mut b, &mut b_buf)?;
+        if a_buf[..a_len] != b_buf[..b_len] {
+            return Ok(false);
+        }
+        if a_len == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Read until `buf` is full or EOF
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
 fn stat(args: Args, mut file: BufReader<File>, kind: Kind) {
     file.seek(SeekFrom::Start(args.offset)).unwrap();
     let mut reader: Box<dyn BufReadSeek> = Box::new(file);
@@ -313,7 +526,10 @@ fn stat(args: Args, mut file: BufReader<File>, kind: Kind) {
     println!("{superblock:#08x?}");
 
     // show info about compression options
-    println!("Compression Options: {compression_options:#x?}");
+    match compression_options {
+        Some(options) => println!("Compression Options: {}", options.describe()),
+        None => println!("Compression Options: None"),
+    }
 
     // show info about flags
     if superblock.inodes_uncompressed() {
@@ -353,57 +569,64 @@ fn stat(args: Args, mut file: BufReader<File>, kind: Kind) {
     }
 }
 
-fn set_attributes(
-    pb: &ProgressBar,
-    args: &Args,
-    path: &Path,
-    header: &NodeHeader,
-    root_process: bool,
-    is_file: bool,
-) {
-    // TODO Use (file_set_times) when not nightly: https://github.com/rust-lang/rust/issues/98245
-    let timeval = TimeVal::new(header.mtime as _, 0);
-    utimes(path, &timeval, &timeval).unwrap();
-
-    let mut mode = u32::from(header.permissions);
-
-    // Only chown when root
-    if root_process {
-        // TODO: Use (unix_chown) when not nightly: https://github.com/rust-lang/rust/issues/88989
-        match lchown(path, Some(header.uid), Some(header.gid)) {
-            Ok(_) => (),
-            Err(e) => {
-                if !args.quiet {
-                    let line =
-                        format!("lchown {} {} {} : {e}", path.display(), header.uid, header.gid,);
-                    failed(pb, &line);
-                }
-                return;
-            }
+/// Name of a node that can be created on restrictive filesystems, such as on Windows and macOS
+fn sanitize_name(name: &OsStr) -> OsString {
+    const RESERVED: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+
+    let bytes = name.as_bytes();
+    let utf8 = std::str::from_utf8(bytes).is_ok();
+    let stem = bytes.split(|b| *b == b'.').next().unwrap_or_default();
+    let reserved = RESERVED.iter().any(|reserved| stem.eq_ignore_ascii_case(reserved.as_bytes()));
+    let trailing = bytes.iter().rposition(|b| !matches!(b, b'.' | b' ')).map_or(0, |i| i + 1);
+
+    let mut sanitized = vec![];
+    for (i, &b) in bytes.iter().enumerate() {
+        let encode = b < 0x20
+            || b"<>:\"\\|?*%\x7f".contains(&b)
+            || (!utf8 && b >= 0x80)
+            || (reserved && i == 0)
+            || i >= trailing;
+        if encode {
+            sanitized.extend(format!("%{b:02X}").bytes());
+        } else {
+            sanitized.push(b);
         }
-    } else if is_file {
-        // bitwise-not if not rooted (disable write permissions for user/group). Following
-        // squashfs-tools/unsquashfs behavior
-        mode &= !0o022;
-    }
-
-    // set permissions
-    //
-    // NOTE: In squashfs-tools/unsquashfs they remove the write bits for user and group?
-    // I don't know if there is a reason for that but I keep the permissions the same if possible
-    if let Err(e) = fs::set_permissions(path, Permissions::from_mode(mode)) {
-        if e.kind() == std::io::ErrorKind::PermissionDenied {
-            // try without sticky bit
-            if fs::set_permissions(path, Permissions::from_mode(mode & !1000)).is_err()
-                && !args.quiet
-            {
-                let line = format!("{} : could not set permissions", path.to_str().unwrap());
-                failed(pb, &line);
-            }
+    }
+    OsString::from_vec(sanitized)
+}
+
+/// Path relative to DEST of each node of `filesystem`, see --sanitize-names
+fn sanitize_paths<'a>(filesystem: &'a FilesystemReader) -> HashMap<&'a Path, PathBuf> {
+    let mut paths: HashMap<&Path, PathBuf> = HashMap::new();
+    // case-folded paths already used
+    let mut used = HashSet::new();
+    for node in filesystem.files() {
+        let (Some(parent), Some(name)) = (node.fullpath.parent(), node.fullpath.file_name()) else {
+            paths.insert(&node.fullpath, PathBuf::new());
+            continue;
+        };
+        let parent = paths[parent].clone();
+        let name = sanitize_name(name);
+        let mut path = parent.join(&name);
+        let mut n = 0;
+        while !used.insert(path.as_os_str().as_bytes().to_ascii_lowercase()) {
+            n += 1;
+            let mut suffixed = name.clone();
+            suffixed.push(format!("~{n}"));
+            path = parent.join(suffixed);
         }
+        paths.insert(&node.fullpath, path);
     }
+    paths
 }
 
+/// Extract `nodes` to `args.dest`
+///
+/// # Returns
+/// The failures of extracting nodes
 fn extract_all<'a, S: ParallelIterator<Item = &'a Node<SquashfsFileReader>>>(
     args: &Args,
     filesystem: &'a FilesystemReader,
@@ -411,7 +634,7 @@ fn extract_all<'a, S: ParallelIterator<Item = &'a Node<SquashfsFileReader>>>(
     nodes: S,
     n_nodes: Option<usize>,
     start: Instant,
-) {
+) -> Vec<String> {
     let pb = ProgressBar::new(n_nodes.unwrap_or(0) as u64);
     if !args.quiet {
         pb.set_style(ProgressStyle::default_spinner());
@@ -432,9 +655,50 @@ fn extract_all<'a, S: ParallelIterator<Item = &'a Node<SquashfsFileReader>>>(
         pb.inc(1);
     }
 
+    // SAFETY: checked to be open in main, and not closed as it isn't owned
+    let progress = args
+        .progress_fd
+        .map(|fd| Mutex::new((ManuallyDrop::new(unsafe { File::from_raw_fd(fd) }), 0)));
+
     let processing = Mutex::new(HashSet::new());
+    let failures = Mutex::new(vec![]);
+    let fail = |line: String| {
+        if !args.quiet {
+            failed(&pb, &line);
+        }
+        failures.lock().unwrap().push(line);
+    };
+
+    let sanitized = args.sanitize_names.then(|| sanitize_paths(filesystem));
+    if let Some(sanitized) = &sanitized {
+        let mut names = args.dest.components().collect::<PathBuf>().into_os_string();
+        names.push(".names");
+        let mut lines = String::new();
+        for node in filesystem.files().filter(|a| a.fullpath.starts_with(&args.path_filter)) {
+            let path = &node.fullpath;
+            let sanitized = Path::new("/").join(&sanitized[path.as_path()]);
+            if sanitized != *path {
+                lines += &format!("{}\t{}\n", sanitized.display(), path.display());
+            }
+        }
+        if let Err(e) = fs::write(&names, lines) {
+            fail(format!("{} : {e}", Path::new(&names).display()));
+        }
+    }
+    // path of `node` relative to DEST
+    let dest_path = |path: &'a Path| match &sanitized {
+        Some(sanitized) => sanitized[path].as_path(),
+        None => path.strip_prefix(Component::RootDir).unwrap_or(path),
+    };
+
+    let options =
+        ExtractOptions { force: args.force, no_preserve: args.no_preserve, threads: None };
+    let extract_node = |node: &'a Node<SquashfsFileReader>| {
+        // stop extracting once --max-total-size is exceeded
+        if args.max_total_size.is_some_and(|limit| filesystem.extracted_bytes() > limit) {
+            return;
+        }
 
-    nodes.for_each(|node| {
         let path = &node.fullpath;
         let fullpath = path.strip_prefix(Component::RootDir).unwrap_or(path);
         if !args.quiet {
@@ -449,106 +713,11 @@ fn extract_all<'a, S: ParallelIterator<Item = &'a Node<SquashfsFileReader>>>(
             pb.inc(1);
         }
 
-        let filepath = Path::new(&args.dest).join(fullpath);
+        let filepath = Path::new(&args.dest).join(dest_path(path));
         // create required dirs, we will fix permissions later
         let _ = fs::create_dir_all(filepath.parent().unwrap());
 
         match &node.inner {
-            InnerNode::File(file) => {
-                // alloc required space for file data readers
-                // check if file exists
-                if !args.force && filepath.exists() {
-                    if !args.quiet {
-                        exists(&pb, filepath.to_str().unwrap());
-                        let mut p = processing.lock().unwrap();
-                        p.remove(fullpath);
-                    }
-                    return;
-                }
-
-                // write to file
-                let fd = File::create(&filepath).unwrap();
-                let mut writer = BufWriter::with_capacity(file.file_len(), &fd);
-                let file = filesystem.file(file);
-                let mut reader = file.reader();
-
-                match io::copy(&mut reader, &mut writer) {
-                    Ok(_) => {
-                        if args.info && !args.quiet {
-                            extracted(&pb, filepath.to_str().unwrap());
-                        }
-                        set_attributes(&pb, args, &filepath, &node.header, root_process, true);
-                    }
-                    Err(e) => {
-                        if !args.quiet {
-                            let line = format!("{} : {e}", filepath.to_str().unwrap());
-                            failed(&pb, &line);
-                            let mut p = processing.lock().unwrap();
-                            p.remove(fullpath);
-                        }
-                        return;
-                    }
-                }
-            }
-            InnerNode::Symlink(SquashfsSymlink { link }) => {
-                // create symlink
-                let link_display = link.display();
-                // check if file exists
-                if !args.force && filepath.exists() {
-                    exists(&pb, filepath.to_str().unwrap());
-                    let mut p = processing.lock().unwrap();
-                    p.remove(fullpath);
-                    return;
-                }
-
-                match std::os::unix::fs::symlink(link, &filepath) {
-                    Ok(_) => {
-                        if args.info && !args.quiet {
-                            let line = format!("{}->{link_display}", filepath.to_str().unwrap());
-                            created(&pb, &line);
-                        }
-                    }
-                    Err(e) => {
-                        if !args.quiet {
-                            let line =
-                                format!("{}->{link_display} : {e}", filepath.to_str().unwrap());
-                            failed(&pb, &line);
-                            let mut p = processing.lock().unwrap();
-                            p.remove(fullpath);
-                        }
-                        return;
-                    }
-                }
-
-                // set attributes, but special to not follow the symlink
-
-                if root_process {
-                    // TODO: Use (unix_chown) when not nightly: https://github.com/rust-lang/rust/issues/88989
-                    match lchown(&filepath, Some(node.header.uid), Some(node.header.gid)) {
-                        Ok(_) => (),
-                        Err(e) => {
-                            if !args.quiet {
-                                let line = format!(
-                                    "lchown {} {} {} : {e}",
-                                    filepath.display(),
-                                    node.header.uid,
-                                    node.header.gid,
-                                );
-                                failed(&pb, &line);
-                            }
-                            let mut p = processing.lock().unwrap();
-                            p.remove(fullpath);
-                            return;
-                        }
-                    }
-                }
-
-                // TODO Use (file_set_times) when not nightly: https://github.com/rust-lang/rust/issues/98245
-                // Make sure this doesn't follow symlinks when changed to std library!
-                let timespec = TimeSpec::new(node.header.mtime as _, 0);
-                utimensat(None, &filepath, &timespec, &timespec, UtimensatFlags::NoFollowSymlink)
-                    .unwrap();
-            }
             InnerNode::Dir(SquashfsDir { .. }) => {
                 // These permissions are corrected later (user default permissions for now)
                 //
@@ -558,132 +727,53 @@ fn extract_all<'a, S: ParallelIterator<Item = &'a Node<SquashfsFileReader>>>(
                     created(&pb, filepath.to_str().unwrap())
                 }
             }
-            InnerNode::CharacterDevice(SquashfsCharacterDevice { device_number }) => {
-                if root_process {
-                    #[allow(clippy::unnecessary_fallible_conversions)]
-                    match mknod(
-                        &filepath,
-                        SFlag::S_IFCHR,
-                        Mode::from_bits(mode_t::from(node.header.permissions)).unwrap(),
-                        dev_t::try_from(*device_number).unwrap(),
-                    ) {
-                        Ok(_) => {
-                            if args.info && !args.quiet {
-                                created(&pb, filepath.to_str().unwrap());
-                            }
-
-                            set_attributes(&pb, args, &filepath, &node.header, root_process, true);
-                        }
-                        Err(_) => {
-                            if !args.quiet {
-                                let line = format!(
-                                    "char device {}, are you superuser?",
-                                    filepath.to_str().unwrap()
-                                );
-                                failed(&pb, &line);
-                                let mut p = processing.lock().unwrap();
-                                p.remove(fullpath);
-                            }
-                            return;
-                        }
-                    }
-                } else {
-                    if !args.quiet {
-                        let line = format!(
-                            "char device {}, are you superuser?",
-                            filepath.to_str().unwrap()
-                        );
-                        failed(&pb, &line);
-                    }
-                    let mut p = processing.lock().unwrap();
-                    p.remove(fullpath);
-                    return;
-                }
-            }
-            InnerNode::BlockDevice(SquashfsBlockDevice { device_number }) => {
-                #[allow(clippy::unnecessary_fallible_conversions)]
-                match mknod(
-                    &filepath,
-                    SFlag::S_IFBLK,
-                    Mode::from_bits(mode_t::from(node.header.permissions)).unwrap(),
-                    dev_t::try_from(*device_number).unwrap(),
-                ) {
-                    Ok(_) => {
-                        if args.info && !args.quiet {
-                            created(&pb, filepath.to_str().unwrap());
-                        }
-
-                        set_attributes(&pb, args, &filepath, &node.header, root_process, true);
-                    }
-                    Err(_) => {
-                        if args.info && !args.quiet {
-                            created(&pb, filepath.to_str().unwrap());
-                            let mut p = processing.lock().unwrap();
-                            p.remove(fullpath);
-                        }
-                        return;
-                    }
+            InnerNode::File(_) | InnerNode::Symlink(_) if !args.force && filepath.exists() => {
+                if !args.quiet {
+                    exists(&pb, filepath.to_str().unwrap());
                 }
             }
-            InnerNode::NamedPipe => {
-                match mkfifo(
-                    &filepath,
-                    Mode::from_bits(mode_t::from(node.header.permissions)).unwrap(),
-                ) {
-                    Ok(_) => {
-                        if args.info && !args.quiet {
-                            created(&pb, filepath.to_str().unwrap());
-                        }
-
-                        set_attributes(&pb, args, &filepath, &node.header, root_process, true);
-                    }
-                    Err(_) => {
-                        if args.info && !args.quiet {
-                            created(&pb, filepath.to_str().unwrap());
-                        }
-                        let mut p = processing.lock().unwrap();
-                        p.remove(fullpath);
-                        return;
-                    }
-                }
+            InnerNode::CharacterDevice(_) if !root_process => {
+                fail(format!("char device {}, are you superuser?", filepath.display()));
             }
-            InnerNode::Socket => {
-                #[allow(clippy::unnecessary_fallible_conversions)]
-                match mknod(
-                    &filepath,
-                    SFlag::S_IFSOCK,
-                    Mode::from_bits(mode_t::from(node.header.permissions)).unwrap(),
-                    dev_t::try_from(0_u64).unwrap(),
-                ) {
-                    Ok(_) => {
-                        if args.info && !args.quiet {
-                            created(&pb, filepath.to_str().unwrap());
-                        }
-
-                        set_attributes(&pb, args, &filepath, &node.header, root_process, true);
-                    }
-                    Err(_) => {
-                        if args.info && !args.quiet {
-                            created(&pb, filepath.to_str().unwrap());
-                            let mut p = processing.lock().unwrap();
-                            p.remove(fullpath);
+            inner => match filesystem.extract_node_to(node, &filepath, options) {
+                Ok(()) => {
+                    if args.info && !args.quiet {
+                        match inner {
+                            InnerNode::File(_) => extracted(&pb, filepath.to_str().unwrap()),
+                            InnerNode::Symlink(SquashfsSymlink { link }) => {
+                                let line =
+                                    format!("{}->{}", filepath.to_str().unwrap(), link.display());
+                                created(&pb, &line);
+                            }
+                            _ => created(&pb, filepath.to_str().unwrap()),
                         }
-                        return;
                     }
                 }
-            }
+                Err(e) => fail(format!("{} : {e}", filepath.display())),
+            },
         }
         let mut p = processing.lock().unwrap();
         p.remove(fullpath);
+    };
+
+    nodes.for_each(|node| {
+        extract_node(node);
+        if let Some(progress) = &progress {
+            let (fd, extracted) = &mut *progress.lock().unwrap();
+            *extracted += 1;
+            let _ = writeln!(fd, "extracted {extracted}/{}", n_nodes.unwrap());
+        }
     });
 
     // fixup dir permissions
-    for node in filesystem.files().filter(|a| a.fullpath.starts_with(&args.path_filter)) {
-        if let InnerNode::Dir(SquashfsDir { .. }) = &node.inner {
-            let path = &node.fullpath;
-            let path = path.strip_prefix(Component::RootDir).unwrap_or(path);
-            let path = Path::new(&args.dest).join(path);
-            set_attributes(&pb, args, &path, &node.header, root_process, false);
+    if !args.no_preserve {
+        for node in filesystem.files().filter(|a| a.fullpath.starts_with(&args.path_filter)) {
+            if let InnerNode::Dir(SquashfsDir { .. }) = &node.inner {
+                let path = Path::new(&args.dest).join(dest_path(&node.fullpath));
+                if let Err(e) = node.set_attributes(&path) {
+                    fail(format!("{} : {e}", path.display()));
+                }
+            }
         }
     }
 
@@ -699,4 +789,6 @@ fn extract_all<'a, S: ParallelIterator<Item = &'a Node<SquashfsFileReader>>>(
             HumanDuration(start.elapsed())
         );
     }
+
+    failures.into_inner().unwrap()
 }
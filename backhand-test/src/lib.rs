@@ -1 +1,67 @@
+//! Testing helpers for `backhand` and `backhand-cli`
+//!
+//! With the `verify` feature, [`verify_against_unsquashfs`] can be used by other test suites to
+//! check that `unsquashfs-backhand` extracts their own images the same as squashfs-tools.
 
+#[cfg(feature = "verify")]
+mod verify {
+    use std::io;
+    use std::path::Path;
+    use std::process::Command;
+
+    use tempfile::tempdir;
+
+    /// Extract `image` with both `unsquashfs_backhand` and squashfs-tools `unsquashfs`, and
+    /// compare the resulting directories
+    ///
+    /// `unsquashfs_backhand` is the command running the `unsquashfs-backhand` binary, such as
+    /// `Command::new(assert_cmd::cargo::cargo_bin("unsquashfs-backhand"))` from the tests of
+    /// `backhand-cli`. `unsquashfs` is found from `PATH`. Special files are ignored by
+    /// `unsquashfs`, so this doesn't need to run as root.
+    ///
+    /// # Returns
+    /// - `Ok(())` when both extractions are the same
+    /// - `Err(_)` when either extraction fails, or the extracted directories differ
+    pub fn verify_against_unsquashfs<P: AsRef<Path>>(
+        mut unsquashfs_backhand: Command,
+        image: P,
+        offset: Option<u64>,
+    ) -> io::Result<()> {
+        let image = image.as_ref();
+        let offset = offset.unwrap_or(0).to_string();
+        let tmp_dir = tempdir()?;
+        let backhand_dir = tmp_dir.path().join("squashfs-root-rust");
+        let c_dir = tmp_dir.path().join("squashfs-root-c");
+
+        unsquashfs_backhand.arg("--quiet").arg("-d").arg(&backhand_dir);
+        unsquashfs_backhand.args(["-o", &offset]).arg(image);
+        run(unsquashfs_backhand)?;
+
+        let mut cmd = Command::new("unsquashfs");
+        // we don't run as root, avoid special file errors
+        cmd.arg("-d").arg(&c_dir).args(["-o", &offset, "-ignore-errors"]).arg(image);
+        run(cmd)?;
+
+        let different = dir_diff::is_different(&backhand_dir, &c_dir)
+            .map_err(|e| io::Error::other(format!("couldn't compare dirs: {e:?}")))?;
+        if different {
+            return Err(io::Error::other(format!(
+                "{}: extraction differs from squashfs-tools/unsquashfs",
+                image.display()
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn run(mut cmd: Command) -> io::Result<()> {
+        let status = cmd.status()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("{cmd:?} failed: {status}")));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "verify")]
+pub use verify::verify_against_unsquashfs;
@@ -0,0 +1,98 @@
+#![cfg(feature = "ffi")]
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::io::Cursor;
+use std::ptr;
+
+use backhand::ffi::{
+    backhand_close, backhand_free_buffer, backhand_list, backhand_open, backhand_read_file,
+    BackhandErrorCode, BackhandFilesystem, BackhandNodeKind,
+};
+use backhand::{FilesystemWriter, NodeHeader};
+
+extern "C" fn list_callback(
+    path: *const c_char,
+    kind: BackhandNodeKind,
+    user_data: *mut c_void,
+) -> c_int {
+    let nodes = unsafe { &mut *user_data.cast::<Vec<(String, BackhandNodeKind)>>() };
+    let path = unsafe { CStr::from_ptr(path) }.to_str().unwrap().to_string();
+    nodes.push((path, kind));
+    0
+}
+
+extern "C" fn stop_callback(_: *const c_char, _: BackhandNodeKind, _: *mut c_void) -> c_int {
+    1
+}
+
+#[test]
+fn test_ffi_open_list_read() {
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("usr", header).unwrap();
+    fs.push_file(Cursor::new(vec![0xaa; 0x30000]), "usr/large", header).unwrap();
+    fs.push_file(Cursor::new(b"hello".to_vec()), "usr/small", header).unwrap();
+    fs.push_symlink("usr/small", "link", header).unwrap();
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let image_path = tmp_dir.path().join("ffi.squashfs");
+    let mut output = std::fs::File::create(&image_path).unwrap();
+    fs.write(&mut output).unwrap();
+
+    unsafe {
+        // open
+        let mut handle: *mut BackhandFilesystem = ptr::null_mut();
+        let missing = CString::new(tmp_dir.path().join("missing").to_str().unwrap()).unwrap();
+        assert_eq!(backhand_open(missing.as_ptr(), 0, &mut handle), BackhandErrorCode::Io);
+        let path = CString::new(image_path.to_str().unwrap()).unwrap();
+        assert_eq!(backhand_open(path.as_ptr(), 0, &mut handle), BackhandErrorCode::Ok);
+        assert!(!handle.is_null());
+
+        // list
+        let mut nodes: Vec<(String, BackhandNodeKind)> = vec![];
+        let user_data = (&mut nodes as *mut Vec<_>).cast();
+        assert_eq!(backhand_list(handle, list_callback, user_data), BackhandErrorCode::Ok);
+        assert_eq!(
+            nodes,
+            [
+                ("/".to_string(), BackhandNodeKind::Dir),
+                ("/link".to_string(), BackhandNodeKind::Symlink),
+                ("/usr".to_string(), BackhandNodeKind::Dir),
+                ("/usr/large".to_string(), BackhandNodeKind::File),
+                ("/usr/small".to_string(), BackhandNodeKind::File),
+            ]
+        );
+        assert_eq!(
+            backhand_list(handle, stop_callback, ptr::null_mut()),
+            BackhandErrorCode::Stopped
+        );
+
+        // read
+        let mut buf: *mut u8 = ptr::null_mut();
+        let mut len = 0;
+        for (file, expected) in
+            [("/usr/large", vec![0xaa; 0x30000]), ("usr/small", b"hello".to_vec())]
+        {
+            let file = CString::new(file).unwrap();
+            assert_eq!(
+                backhand_read_file(handle, file.as_ptr(), &mut buf, &mut len),
+                BackhandErrorCode::Ok
+            );
+            assert_eq!(std::slice::from_raw_parts(buf, len), expected);
+            backhand_free_buffer(buf, len);
+        }
+        let file = CString::new("/usr").unwrap();
+        assert_eq!(
+            backhand_read_file(handle, file.as_ptr(), &mut buf, &mut len),
+            BackhandErrorCode::NotAFile
+        );
+        let file = CString::new("/usr/missing").unwrap();
+        assert_eq!(
+            backhand_read_file(handle, file.as_ptr(), &mut buf, &mut len),
+            BackhandErrorCode::NotFound
+        );
+
+        // close
+        backhand_close(handle);
+    }
+}
@@ -95,3 +95,104 @@ fn test_add_00() {
         test_bin_unsquashfs(&new_path, None, true, true);
     }
 }
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_subtree() {
+    use std::io::Read;
+
+    use backhand::InnerNode;
+
+    let header = NodeHeader { permissions: 0o755, ..NodeHeader::default() };
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir_all("usr/bin", header).unwrap();
+    fs.push_file(Cursor::new(b"wget".to_vec()), "usr/bin/wget", header).unwrap();
+    fs.push_symlink("bin/wget", "usr/wget", header).unwrap();
+    fs.push_dir("etc", header).unwrap();
+    fs.push_file(Cursor::new(b"passwd".to_vec()), "etc/passwd", header).unwrap();
+
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    output.set_position(0);
+    let og_fs = FilesystemReader::from_reader(output).unwrap();
+
+    assert!(FilesystemWriter::from_fs_reader_subtree(&og_fs, "/missing").is_err());
+    assert!(FilesystemWriter::from_fs_reader_subtree(&og_fs, "/etc/passwd").is_err());
+
+    let mut new_fs = FilesystemWriter::from_fs_reader_subtree(&og_fs, "/usr").unwrap();
+    let mut output = Cursor::new(vec![]);
+    new_fs.write(&mut output).unwrap();
+    output.set_position(0);
+    let fs = FilesystemReader::from_reader(output).unwrap();
+
+    let paths: Vec<_> = fs.files().map(|node| node.fullpath.to_str().unwrap()).collect();
+    assert_eq!(paths, ["/", "/bin", "/bin/wget", "/wget"]);
+    for node in fs.files() {
+        let og_path = std::path::Path::new("/usr").join(node.fullpath.strip_prefix("/").unwrap());
+        let og_node = og_fs.files().find(|n| n.fullpath == og_path).unwrap();
+        assert_eq!(node.header, og_node.header);
+        if let (InnerNode::File(file), InnerNode::File(og_file)) = (&node.inner, &og_node.inner) {
+            let mut data = vec![];
+            fs.file(file).reader().read_to_end(&mut data).unwrap();
+            let mut og_data = vec![];
+            og_fs.file(og_file).reader().read_to_end(&mut og_data).unwrap();
+            assert_eq!(data, og_data);
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_patch_file() {
+    use std::io::Read;
+
+    use backhand::{BackhandError, Id, InnerNode};
+
+    let header = NodeHeader { permissions: 0o644, uid: 0, gid: 0, mtime: 0 };
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("etc", header).unwrap();
+    fs.push_file(Cursor::new(b"old".to_vec()), "etc/config", header).unwrap();
+
+    let patched = NodeHeader { permissions: 0o600, uid: 1000, gid: 100, mtime: 1234 };
+    fs.patch_file("/etc/config", Cursor::new(b"new config".to_vec()), patched).unwrap();
+    assert!(matches!(
+        fs.patch_file("etc/missing", Cursor::new(vec![]), patched),
+        Err(BackhandError::FileNotFound)
+    ));
+    assert!(matches!(
+        fs.patch_file("etc", Cursor::new(vec![]), patched),
+        Err(BackhandError::FileNotFound)
+    ));
+
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    output.set_position(0);
+    let fs = FilesystemReader::from_reader(output).unwrap();
+    let node = fs.files().find(|node| node.fullpath.ends_with("etc/config")).unwrap();
+    assert_eq!(node.header, patched);
+    let InnerNode::File(file) = &node.inner else { panic!() };
+    let mut data = vec![];
+    fs.file(file).reader().read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"new config");
+    assert!(fs.id_table.contains(&Id::new(1000)));
+    assert!(fs.id_table.contains(&Id::new(100)));
+
+    // ids not in a fixed id table leave the file unchanged
+    let mut fs = FilesystemWriter::default();
+    fs.set_id_table(vec![Id::new(0)]);
+    fs.push_file(Cursor::new(b"old".to_vec()), "config", header).unwrap();
+    assert!(matches!(
+        fs.patch_file("config", Cursor::new(b"new".to_vec()), patched),
+        Err(BackhandError::InvalidIdTable)
+    ));
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    output.set_position(0);
+    let fs = FilesystemReader::from_reader(output).unwrap();
+    let node = fs.files().find(|node| node.fullpath.ends_with("config")).unwrap();
+    assert_eq!(node.header, header);
+    let InnerNode::File(file) = &node.inner else { panic!() };
+    let mut data = vec![];
+    fs.file(file).reader().read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"old");
+}
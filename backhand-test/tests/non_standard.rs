@@ -1,6 +1,7 @@
 mod common;
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
 
 use backhand::compression::{CompressionAction, Compressor, DefaultCompressor};
 use backhand::kind::{self, Kind};
@@ -171,3 +172,469 @@ fn test_custom_compressor() {
     const TEST_PATH: &str = "test-assets/custom_compressor";
     full_test(&asset_defs, FILE_NAME, TEST_PATH, 0, &kind, Some(0));
 }
+
+/// AVM images use big-endian types, but little-endian metadata block lengths
+#[test]
+#[cfg(feature = "gzip")]
+fn test_non_standard_avm_be_v4_0_names() {
+    use std::ffi::OsStr;
+    use std::io::Cursor;
+    use std::os::unix::ffi::OsStrExt;
+
+    use backhand::{NodeHeader, Squashfs};
+
+    let kind = Kind::from_const(kind::AVM_BE_V4_0).unwrap();
+    let header = NodeHeader::default();
+    let long = "l".repeat(255);
+    let mut names = vec![
+        OsStr::new("a").to_os_string(),
+        OsStr::new(&long).to_os_string(),
+        OsStr::from_bytes(&[0x80, 0xfe, 0xff]).to_os_string(),
+        OsStr::new("späce ünicode").to_os_string(),
+    ];
+    // enough entries for multiple directory headers and a directory index
+    names.extend((0..300).map(|i| format!("many/{i:03}").into()));
+
+    let mut fs = FilesystemWriter::default();
+    fs.set_kind(Kind::from_kind(&kind));
+    fs.set_compressor(FilesystemCompressor::new(Compressor::Gzip, None).unwrap());
+    fs.push_dir("many", header).unwrap();
+    for name in &names {
+        fs.push_file(Cursor::new(name.as_bytes().to_vec()), name, header).unwrap();
+    }
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+
+    // big-endian superblock and table pointers
+    assert_eq!(bytes[..4], *b"sqsh");
+    assert_eq!(bytes[4..8], superblock.inode_count.to_be_bytes());
+    let id_table = superblock.id_table as usize;
+    let id_block = u64::from_be_bytes(bytes[id_table..id_table + 8].try_into().unwrap()) as usize;
+    // little-endian metadata block length
+    let len = u16::from_le_bytes(bytes[id_block..id_block + 2].try_into().unwrap()) & 0x7fff;
+    assert_eq!(id_block + 2 + len as usize, id_table);
+
+    let squashfs =
+        Squashfs::from_reader_with_offset_and_kind(Cursor::new(bytes.clone()), 0, kind).unwrap();
+    for name in &names {
+        assert!(squashfs.lookup(name).unwrap().is_some(), "{name:?}");
+    }
+    let fs = squashfs.into_filesystem_reader().unwrap();
+    let mut expected: Vec<_> = names.iter().map(|name| Path::new("/").join(name)).collect();
+    expected.push("/".into());
+    expected.push("/many".into());
+    expected.sort();
+    let found: Vec<_> = fs.files().map(|node| node.fullpath.clone()).collect();
+    assert_eq!(found, expected);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_non_standard_version_minor() {
+    use std::io::Cursor;
+
+    use backhand::{NodeHeader, Squashfs};
+
+    let kind = Kind::from_const(kind::LE_V4_0).unwrap().with_version(4, 1);
+    let mut fs = FilesystemWriter::default();
+    fs.set_kind(Kind::from_kind(&kind));
+    fs.push_file(Cursor::new(b"hello".to_vec()), "file", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+
+    assert_eq!((superblock.version_major, superblock.version_minor), (4, 1));
+    assert_eq!(bytes[28..30], 4_u16.to_le_bytes());
+    assert_eq!(bytes[30..32], 1_u16.to_le_bytes());
+
+    // only readable with the same version
+    let squashfs =
+        Squashfs::from_reader_with_offset_and_kind(Cursor::new(bytes.clone()), 0, kind).unwrap();
+    assert_eq!(squashfs.superblock.version_minor, 1);
+    assert!(Squashfs::from_reader(Cursor::new(bytes)).is_err());
+}
+
+/// Every node type, ids, fragments, data blocks, and compression options with [`kind::BE_V4_0`]
+#[test]
+#[cfg(feature = "gzip")]
+fn test_non_standard_be_v4_0_round_trip() {
+    use std::io::{Cursor, Read};
+    use std::path::PathBuf;
+
+    use backhand::compression::{CompressionOptions, Gzip};
+    use backhand::{InnerNode, NodeHeader, Squashfs};
+
+    /// `(path, header, node description)` of each node
+    fn tree(fs: &FilesystemReader) -> Vec<(PathBuf, NodeHeader, String)> {
+        fs.files()
+            .map(|node| {
+                let inner = match &node.inner {
+                    InnerNode::File(file) => {
+                        let mut bytes = vec![];
+                        fs.file(file).reader().read_to_end(&mut bytes).unwrap();
+                        format!("file {bytes:02x?}")
+                    }
+                    InnerNode::Symlink(symlink) => format!("symlink {:?}", symlink.link),
+                    InnerNode::Dir(_) => "dir".to_string(),
+                    InnerNode::CharacterDevice(dev) => format!("char {}", dev.device_number),
+                    InnerNode::BlockDevice(dev) => format!("block {}", dev.device_number),
+                    InnerNode::NamedPipe => "fifo".to_string(),
+                    InnerNode::Socket => "socket".to_string(),
+                };
+                (node.fullpath.clone(), node.header, inner)
+            })
+            .collect()
+    }
+
+    let be = || Kind::from_const(kind::BE_V4_0).unwrap();
+    let options =
+        CompressionOptions::Gzip(Gzip { compression_level: 9, window_size: 15, strategies: 0 });
+    let small: Vec<u8> = (0..0x100).map(|i| i as u8).collect();
+    let large: Vec<u8> = (0..0x50000_u32).map(|i| (i % 251) as u8).collect();
+
+    let header = NodeHeader { permissions: 0o644, uid: 1000, gid: 100, mtime: 0x1234_5678 };
+    let mut fs = FilesystemWriter::default();
+    fs.set_kind(be());
+    fs.set_time(0x1122_3344);
+    fs.set_compressor(FilesystemCompressor::new(Compressor::Gzip, Some(options.clone())).unwrap());
+    fs.set_root_mode(0o711);
+    fs.push_dir("usr", NodeHeader { permissions: 0o755, uid: 0, gid: 0, ..header }).unwrap();
+    fs.push_file(Cursor::new(small.clone()), "usr/small", header).unwrap();
+    fs.push_file(Cursor::new(large.clone()), "usr/large", header).unwrap();
+    fs.push_symlink("usr/small", "link", NodeHeader { uid: 2000, ..header }).unwrap();
+    fs.push_char_device(0x0102, "char", NodeHeader { gid: 3000, ..header }).unwrap();
+    fs.push_block_device(0x0304, "block", header).unwrap();
+    fs.push_fifo("fifo", header).unwrap();
+    fs.push_socket("socket", header).unwrap();
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+
+    // big-endian on disk
+    assert_eq!(bytes[..4], *b"sqsh");
+    assert_eq!(bytes[40..48], superblock.bytes_used.to_be_bytes());
+
+    let squashfs =
+        Squashfs::from_reader_with_offset_and_kind(Cursor::new(bytes.clone()), 0, be()).unwrap();
+    assert_eq!(squashfs.superblock, superblock);
+    assert_eq!(squashfs.compression_options, Some(options));
+    assert_eq!(squashfs.superblock.mod_time, 0x1122_3344);
+    assert_eq!(squashfs.superblock.id_count, 5);
+    assert!(squashfs.fragments.as_ref().is_some_and(|fragments| fragments.len() == 1));
+    assert!(squashfs.lookup("usr/large").unwrap().is_some());
+
+    let fs = squashfs.into_filesystem_reader().unwrap();
+    let root = NodeHeader { permissions: 0o711, uid: 0, gid: 0, mtime: 0 };
+    let expected = vec![
+        (PathBuf::from("/"), root, "dir".to_string()),
+        ("/block".into(), header, "block 772".to_string()),
+        ("/char".into(), NodeHeader { gid: 3000, ..header }, "char 258".to_string()),
+        ("/fifo".into(), header, "fifo".to_string()),
+        ("/link".into(), NodeHeader { uid: 2000, ..header }, "symlink \"usr/small\"".to_string()),
+        ("/socket".into(), header, "socket".to_string()),
+        ("/usr".into(), NodeHeader { permissions: 0o755, uid: 0, gid: 0, ..header }, "dir".into()),
+        ("/usr/large".into(), header, format!("file {large:02x?}")),
+        ("/usr/small".into(), header, format!("file {small:02x?}")),
+    ];
+    assert_eq!(tree(&fs), expected);
+
+    // writing again from the read image is the same
+    let mut fs = FilesystemWriter::from_fs_reader(&fs).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    assert_eq!(output.into_inner(), bytes);
+}
+
+/// Larger tables and dirs with [`kind::BE_V4_0`], read the same as with [`kind::LE_V4_0`]
+#[test]
+#[cfg(feature = "gzip")]
+fn test_non_standard_be_v4_0_same_as_le() {
+    use std::io::{Cursor, Read};
+
+    use backhand::{InnerNode, MetadataComp, NodeHeader, Squashfs, SuperBlock};
+
+    let write = |kind: Kind, metadata_compression: bool| {
+        let mut fs = FilesystemWriter::default();
+        fs.set_kind(kind);
+        fs.set_block_size(0x1000);
+        fs.set_compressor(FilesystemCompressor::new(Compressor::Gzip, None).unwrap());
+        let compress = metadata_compression;
+        fs.set_metadata_compression(MetadataComp {
+            inodes: compress,
+            dirs: compress,
+            ids: compress,
+            fragments_table: compress,
+        });
+        fs.push_dir("many", NodeHeader::default()).unwrap();
+        // multiple metadata blocks of ids, fragments, inodes, and dirs, with a directory index
+        for i in 0..2100_u32 {
+            let header = NodeHeader { permissions: 0o644, uid: i, gid: 0, mtime: i };
+            let data: Vec<u8> = i.to_le_bytes().iter().copied().cycle().take(0x801).collect();
+            fs.push_file(Cursor::new(data), format!("many/{i:04}"), header).unwrap();
+        }
+        let large: Vec<u8> = (0..0x5000_u32).map(|i| (i % 251) as u8).collect();
+        fs.push_file(Cursor::new(large), "large", NodeHeader::default()).unwrap();
+        fs.push_symlink("many/0000", "link", NodeHeader::default()).unwrap();
+        let mut output = Cursor::new(vec![]);
+        fs.write(&mut output).unwrap();
+        output.into_inner()
+    };
+    let be = || Kind::from_const(kind::BE_V4_0).unwrap();
+    let le = || Kind::from_const(kind::LE_V4_0).unwrap();
+
+    // without metadata compression, the tables have the same length and all positions match
+    let le_bytes = write(le(), false);
+    let be_bytes = write(be(), false);
+    assert_eq!(be_bytes[..4], *b"sqsh");
+    assert_eq!(be_bytes.len(), le_bytes.len());
+    let le_squashfs =
+        Squashfs::from_reader_with_offset_and_kind(Cursor::new(le_bytes), 0, le()).unwrap();
+    let be_squashfs =
+        Squashfs::from_reader_with_offset_and_kind(Cursor::new(be_bytes), 0, be()).unwrap();
+    assert_eq!(be_squashfs.superblock.magic, *b"sqsh");
+    let superblock = SuperBlock { magic: *b"hsqs", ..be_squashfs.superblock };
+    assert_eq!(superblock, le_squashfs.superblock);
+    assert_eq!(be_squashfs.inodes, le_squashfs.inodes);
+    assert_eq!(be_squashfs.root_inode, le_squashfs.root_inode);
+    assert_eq!(be_squashfs.id, le_squashfs.id);
+    assert_eq!(be_squashfs.fragments, le_squashfs.fragments);
+    assert!(be_squashfs.id.len() > 2048);
+    assert!(be_squashfs.fragments.as_ref().unwrap().len() > 512);
+    let many = be_squashfs.lookup("many/2099").unwrap();
+    assert_eq!(many, le_squashfs.lookup("many/2099").unwrap());
+    assert!(many.is_some());
+
+    // with metadata compression, the same nodes and data
+    let tree = |fs: &FilesystemReader| -> Vec<_> {
+        fs.files()
+            .map(|node| {
+                let mut bytes = vec![];
+                if let InnerNode::File(file) = &node.inner {
+                    fs.file(file).reader().read_to_end(&mut bytes).unwrap();
+                }
+                (node.fullpath.clone(), node.header, bytes)
+            })
+            .collect()
+    };
+    let le_fs = le_squashfs.into_filesystem_reader().unwrap();
+    let be_bytes = write(be(), true);
+    let be_fs =
+        FilesystemReader::from_reader_with_offset_and_kind(Cursor::new(be_bytes.clone()), 0, be())
+            .unwrap();
+    assert_eq!(tree(&be_fs), tree(&le_fs));
+
+    // not readable as little-endian, and writing again from the read image is the same
+    assert!(Squashfs::from_reader(Cursor::new(be_bytes.clone())).is_err());
+    let mut fs = FilesystemWriter::from_fs_reader(&be_fs).unwrap();
+    fs.set_metadata_compression(MetadataComp {
+        inodes: true,
+        dirs: true,
+        ids: true,
+        fragments_table: true,
+    });
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    assert_eq!(output.into_inner(), be_bytes);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_non_standard_normalize() {
+    use std::io::{Cursor, Read};
+
+    use backhand::{InnerNode, NodeHeader, Squashfs, DEFAULT_PAD_LEN};
+
+    let data: Vec<u8> = (0..0x30000_u32).map(|i| (i % 253) as u8).collect();
+    let header = NodeHeader::default();
+    let be = || Kind::from_const(kind::BE_V4_0).unwrap();
+
+    // big-endian, without padding or compression options, after some other data
+    let mut fs = FilesystemWriter::default();
+    fs.set_kind(be());
+    fs.set_no_padding();
+    fs.set_emit_compression_options(false);
+    fs.push_dir("dir", header).unwrap();
+    fs.push_file(Cursor::new(data.clone()), "dir/file", header).unwrap();
+    fs.push_symlink("dir/file", "link", header).unwrap();
+    let mut output = Cursor::new(vec![0xff; 0x123]);
+    fs.write_with_offset(&mut output, 0x123).unwrap();
+    let quirky = output.into_inner();
+    assert_eq!(quirky[0x123..][..4], *b"sqsh");
+
+    let reader =
+        FilesystemReader::from_reader_with_offset_and_kind(Cursor::new(quirky), 0x123, be())
+            .unwrap();
+    let mut fs = FilesystemWriter::from_fs_reader_normalized(&reader).unwrap();
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+
+    assert_eq!(bytes[..4], *b"hsqs");
+    assert_eq!(bytes.len() % DEFAULT_PAD_LEN as usize, 0);
+    assert_eq!(1 << superblock.block_log, superblock.block_size);
+
+    #[cfg(feature = "__test_unsquashfs")]
+    {
+        use std::process::Command;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("normalized.squashfs");
+        std::fs::write(&path, &bytes).unwrap();
+        let dest = tmp_dir.path().join("squashfs-root");
+        let output = Command::new("unsquashfs").arg("-d").arg(&dest).arg(&path).output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(std::fs::read(dest.join("dir/file")).unwrap(), data);
+        assert_eq!(std::fs::read_link(dest.join("link")).unwrap(), Path::new("dir/file"));
+    }
+
+    // same contents, with the default kind
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+    assert_eq!(squashfs.superblock, superblock);
+    let normalized = squashfs.into_filesystem_reader().unwrap();
+    let paths: Vec<_> = normalized.files().map(|node| node.fullpath.clone()).collect();
+    let expected: Vec<_> = reader.files().map(|node| node.fullpath.clone()).collect();
+    assert_eq!(paths, expected);
+    let file = normalized
+        .files()
+        .find_map(|node| match &node.inner {
+            InnerNode::File(file) => Some(file),
+            _ => None,
+        })
+        .unwrap();
+    let mut read = vec![];
+    normalized.file(file).reader().read_to_end(&mut read).unwrap();
+    assert_eq!(read, data);
+}
+
+/// Compression with a zstd dictionary, only readable with the same dictionary
+#[test]
+#[cfg(feature = "zstd")]
+fn test_zstd_dictionary_compressor() {
+    use std::io::{Cursor, Read};
+
+    use backhand::compression::{CompressionOptions, Zstd};
+    use backhand::{InnerNode, NodeHeader, Squashfs, SuperBlock};
+
+    const DICTIONARY: &[u8] =
+        br#"{"name": "", "version": "", "license": "MIT", "dependencies": {}}"#;
+
+    #[derive(Copy, Clone)]
+    struct ZstdDictionary;
+
+    impl CompressionAction for ZstdDictionary {
+        fn decompress(
+            &self,
+            bytes: &[u8],
+            out: &mut Vec<u8>,
+            compressor: Compressor,
+        ) -> Result<(), BackhandError> {
+            assert_eq!(compressor, Compressor::Zstd);
+            let mut decoder = zstd::bulk::Decompressor::with_dictionary(DICTIONARY)?;
+            decoder.decompress_to_buffer(bytes, out)?;
+            Ok(())
+        }
+
+        fn compress(
+            &self,
+            bytes: &[u8],
+            _fc: FilesystemCompressor,
+            _block_size: u32,
+        ) -> Result<Vec<u8>, BackhandError> {
+            let mut encoder = zstd::bulk::Compressor::with_dictionary(3, DICTIONARY)?;
+            Ok(encoder.compress(bytes)?)
+        }
+
+        fn compression_options(
+            &self,
+            superblock: &mut SuperBlock,
+            kind: &Kind,
+            fs_compressor: FilesystemCompressor,
+        ) -> Result<Vec<u8>, BackhandError> {
+            DefaultCompressor.compression_options(superblock, kind, fs_compressor)
+        }
+    }
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.set_kind(Kind::new_with_const(&ZstdDictionary, kind::LE_V4_0));
+    let options = CompressionOptions::Zstd(Zstd { compression_level: 3 });
+    fs.set_compressor(FilesystemCompressor::new(Compressor::Zstd, Some(options)).unwrap());
+    let packages: Vec<_> = (0..32)
+        .map(|i| format!(r#"{{"name": "package-{i}", "version": "1.{i}", "license": "MIT"}}"#))
+        .collect();
+    for (i, package) in packages.iter().enumerate() {
+        fs.push_file(Cursor::new(package.clone()), format!("package-{i}.json"), header).unwrap();
+    }
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+
+    let kind = Kind::new_with_const(&ZstdDictionary, kind::LE_V4_0);
+    let squashfs =
+        Squashfs::from_reader_with_offset_and_kind(Cursor::new(&bytes), 0, kind).unwrap();
+    let reader = squashfs.into_filesystem_reader().unwrap();
+    for (i, package) in packages.iter().enumerate() {
+        let name = format!("package-{i}.json");
+        let node = reader.files().find(|node| node.fullpath.ends_with(&name)).unwrap();
+        let InnerNode::File(file) = &node.inner else { panic!() };
+        let mut data = String::new();
+        reader.file(file).reader().read_to_string(&mut data).unwrap();
+        assert_eq!(&data, package);
+    }
+
+    // the data can't be decompressed without the dictionary
+    let reader = FilesystemReader::from_reader(Cursor::new(&bytes)).unwrap();
+    let node = reader.files().find(|node| node.fullpath.ends_with("package-0.json")).unwrap();
+    let InnerNode::File(file) = &node.inner else { panic!() };
+    assert!(reader.file(file).reader().read_to_end(&mut vec![]).is_err());
+}
+
+/// AVM images may start with a vendor header, skipped when reading with the AVM kind
+#[test]
+#[cfg(feature = "gzip")]
+fn test_non_standard_avm_be_v4_0_header() {
+    use std::io::Cursor;
+
+    use backhand::{ExtractOptions, NodeHeader};
+
+    let kind = Kind::from_const(kind::AVM_BE_V4_0).unwrap();
+    let header = NodeHeader { permissions: 0o755, uid: 0, gid: 0, mtime: 0 };
+    let mut fs = FilesystemWriter::default();
+    fs.set_kind(Kind::from_kind(&kind));
+    fs.set_compressor(FilesystemCompressor::new(Compressor::Gzip, None).unwrap());
+    fs.push_dir_all("etc/init.d", header).unwrap();
+    fs.push_file(Cursor::new(b"FRITZ!Box".to_vec()), "etc/version", header).unwrap();
+    fs.push_file(Cursor::new(vec![0xaa; 0x30000]), "etc/init.d/rc.S", header).unwrap();
+    let mut image = b"AVM firmware header".to_vec();
+    image.resize(0x100, 0);
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    image.extend_from_slice(output.get_ref());
+
+    // only skipped with the AVM kind
+    let be = Kind::from_const(kind::BE_V4_0).unwrap();
+    assert!(FilesystemReader::from_reader_with_offset_and_kind(Cursor::new(&image), 0, be).is_err());
+
+    for offset in [0, 0x80, 0x100] {
+        let fs = FilesystemReader::from_reader_with_offset_and_kind(
+            Cursor::new(&image),
+            offset,
+            Kind::from_kind(&kind),
+        )
+        .unwrap();
+        assert_eq!(fs.files().count(), 5);
+    }
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let image_path = tmp_dir.path().join("filesystem.image");
+    std::fs::write(&image_path, &image).unwrap();
+    assert_eq!(backhand::read_file(&image_path, "/etc/version").unwrap(), b"FRITZ!Box");
+
+    let fs =
+        FilesystemReader::from_reader_with_offset_and_kind(Cursor::new(&image), 0, kind).unwrap();
+    let dest = tmp_dir.path().join("squashfs-root");
+    fs.extract_all_to(&dest, ExtractOptions::default()).unwrap();
+    assert_eq!(std::fs::read(dest.join("etc/version")).unwrap(), b"FRITZ!Box");
+    assert_eq!(std::fs::read(dest.join("etc/init.d/rc.S")).unwrap(), vec![0xaa; 0x30000]);
+}
@@ -3,7 +3,7 @@ mod common;
 use std::fs::File;
 use std::io::{BufWriter, Cursor};
 
-use backhand::compression::Compressor;
+use backhand::compression::{CompressionAction, Compressor};
 use backhand::{
     kind, CompressionExtra, ExtraXz, FilesystemCompressor, FilesystemWriter, NodeHeader,
     SuperBlock, DEFAULT_BLOCK_SIZE,
@@ -11,6 +11,39 @@ use backhand::{
 use common::{test_bin_unsquashfs, test_squashfs_tools_unsquashfs};
 use test_assets_ureq::TestAssetDef;
 
+/// Never reduces the size, so all metadata is stored uncompressed
+#[derive(Copy, Clone)]
+struct Uncompressed;
+
+impl CompressionAction for Uncompressed {
+    fn decompress(
+        &self,
+        _: &[u8],
+        _: &mut Vec<u8>,
+        _: Compressor,
+    ) -> Result<(), backhand::BackhandError> {
+        unreachable!()
+    }
+
+    fn compress(
+        &self,
+        bytes: &[u8],
+        _: FilesystemCompressor,
+        _: u32,
+    ) -> Result<Vec<u8>, backhand::BackhandError> {
+        Ok([bytes, &[0]].concat())
+    }
+
+    fn compression_options(
+        &self,
+        _: &mut SuperBlock,
+        _: &kind::Kind,
+        _: FilesystemCompressor,
+    ) -> Result<Vec<u8>, backhand::BackhandError> {
+        Ok(vec![])
+    }
+}
+
 #[test]
 #[cfg(all(feature = "xz", feature = "gzip"))]
 fn test_raw_00() {
@@ -174,3 +207,3138 @@ drwxrw-rw- 1000/1000                27 1970-01-01 00:00 squashfs-root/usr/bin
         test_bin_unsquashfs(&new_path, None, true, true);
     }
 }
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_file_compression() {
+    use std::io::Read;
+
+    use backhand::{FileCompression, FilesystemReader, InnerNode};
+
+    let header = NodeHeader::default();
+    let stored = vec![0xaa; DEFAULT_BLOCK_SIZE as usize * 2];
+    let compressed = vec![0xff; DEFAULT_BLOCK_SIZE as usize * 2];
+
+    let mut fs = FilesystemWriter::default();
+    fs.push_file_with_compression(
+        Cursor::new(stored.clone()),
+        "stored",
+        header,
+        FileCompression::Store,
+    )
+    .unwrap();
+    fs.push_file_with_compression(
+        Cursor::new(compressed.clone()),
+        "compressed",
+        header,
+        FileCompression::Default,
+    )
+    .unwrap();
+    fs.push_file_with_compression(
+        Cursor::new(vec![0xbb; 10]),
+        "small",
+        header,
+        FileCompression::Store,
+    )
+    .unwrap();
+    // same data as stored, written before it, but not a duplicate as it's compressed
+    fs.push_file(Cursor::new(stored.clone()), "default_copy", header).unwrap();
+
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    output.set_position(0);
+
+    let fs = FilesystemReader::from_reader(output).unwrap();
+    for node in fs.files() {
+        let InnerNode::File(file) = &node.inner else {
+            continue;
+        };
+        let mut bytes = vec![];
+        fs.file(file).reader().read_to_end(&mut bytes).unwrap();
+        match node.fullpath.to_str().unwrap() {
+            "/stored" => {
+                assert_eq!(bytes, stored);
+                assert_eq!(file.block_sizes().len(), 2);
+                assert!(file.block_sizes().iter().all(|b| b.uncompressed()));
+            }
+            "/default_copy" => {
+                assert_eq!(bytes, stored);
+                assert!(file.block_sizes().iter().all(|b| !b.uncompressed()));
+            }
+            "/compressed" => {
+                assert_eq!(bytes, compressed);
+                assert_eq!(file.block_sizes().len(), 2);
+                assert!(file.block_sizes().iter().all(|b| !b.uncompressed()));
+            }
+            "/small" => {
+                assert_eq!(bytes, vec![0xbb; 10]);
+                // stored as a single uncompressed block, not a fragment
+                assert_eq!(file.frag_index(), 0xffffffff);
+                assert_eq!(file.block_sizes().len(), 1);
+                assert!(file.block_sizes()[0].uncompressed());
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_inode() {
+    use backhand::{FilesystemReader, InnerNode, InodeInner};
+
+    let header = NodeHeader::default();
+    let file_len = DEFAULT_BLOCK_SIZE as usize * 3 + 10;
+    let mut fs = FilesystemWriter::default();
+    fs.push_file(Cursor::new(vec![0xaa; file_len]), "file", header).unwrap();
+
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    output.set_position(0);
+
+    // the inode table is only kept when asked for
+    let fs = FilesystemReader::from_reader(output.clone()).unwrap();
+    assert!(fs.files().all(|node| fs.inode(node).is_none()));
+
+    let kind = kind::Kind::from_const(kind::LE_V4_0).unwrap().with_retained_inodes(true);
+    let fs = FilesystemReader::from_reader_with_offset_and_kind(output, 0, kind).unwrap();
+    let node = fs.files().find(|node| node.fullpath.to_str() == Some("/file")).unwrap();
+    assert!(matches!(node.inner, InnerNode::File(_)));
+
+    let inode = fs.inode(node).unwrap();
+    assert_eq!(node.inode_number(), Some(inode.header.inode_number));
+    let InodeInner::BasicFile(basic_file) = &inode.inner else {
+        panic!("not a basic file");
+    };
+    // tail-end fragments are not used, the last 10 bytes are in a block
+    assert_eq!(basic_file.block_sizes.len(), file_len.div_ceil(DEFAULT_BLOCK_SIZE as usize));
+    assert_eq!(basic_file.frag_index, 0xffffffff);
+
+    let root = fs.files().next().unwrap();
+    assert!(matches!(fs.inode(root).unwrap().inner, InodeInner::BasicDirectory(_)));
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_lookup() {
+    use backhand::{InodeInner, Squashfs};
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("dir", header).unwrap();
+    for i in 0..1000 {
+        fs.push_file(Cursor::new(vec![i as u8; 4]), format!("dir/{i:04}"), header).unwrap();
+    }
+    fs.push_symlink("dir/0999", "link", header).unwrap();
+
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    output.set_position(0);
+
+    let squashfs = Squashfs::from_reader(output).unwrap();
+    assert!(matches!(squashfs.lookup("/").unwrap().unwrap().inner, InodeInner::BasicDirectory(_)));
+    for name in ["/dir/0000", "/dir/0500", "dir/0999"] {
+        let inode = squashfs.lookup(name).unwrap().unwrap();
+        assert!(matches!(inode.inner, InodeInner::BasicFile(_)), "{name}");
+    }
+    assert!(matches!(
+        squashfs.lookup("/link").unwrap().unwrap().inner,
+        InodeInner::BasicSymlink(_)
+    ));
+    assert!(squashfs.lookup("/dir/1000").unwrap().is_none());
+    assert!(squashfs.lookup("/dir/0000/file").unwrap().is_none());
+    assert!(squashfs.lookup("/missing").unwrap().is_none());
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_for_each_node() {
+    use backhand::FilesystemReader;
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("dir", header).unwrap();
+    for i in 0..100 {
+        fs.push_file(Cursor::new(vec![i as u8; 4]), format!("dir/{i:02}"), header).unwrap();
+    }
+    fs.push_symlink("dir/00", "link", header).unwrap();
+
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    output.set_position(0);
+
+    let fs = FilesystemReader::from_reader(output).unwrap();
+    let mut count = 0;
+    let mut paths = vec![];
+    fs.for_each_node(|node| {
+        count += 1;
+        paths.push(node.fullpath.clone());
+    });
+    assert_eq!(count, fs.files().count());
+    assert_eq!(count, 103);
+    assert!(paths.iter().eq(fs.files().map(|node| &node.fullpath)));
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_dir_index() {
+    use backhand::{FilesystemReader, InodeInner, Squashfs};
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("dir", header).unwrap();
+    for i in 0..2000 {
+        fs.push_file(Cursor::new(vec![]), format!("dir/{i:04}"), header).unwrap();
+    }
+
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+
+    #[cfg(feature = "__test_unsquashfs")]
+    {
+        use std::process::Command;
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("dir_index.squashfs");
+        std::fs::write(&path, &bytes).unwrap();
+        let output = Command::new("unsquashfs").arg("-lln").arg(&path).output().unwrap();
+        assert!(output.status.success());
+        // root, dir, and entries
+        assert_eq!(std::str::from_utf8(&output.stdout).unwrap().lines().count(), 2002);
+    }
+
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+    let InodeInner::ExtendedDirectory(dir) = &squashfs.lookup("/dir").unwrap().unwrap().inner
+    else {
+        panic!("not an extended directory");
+    };
+    assert!(dir.index_count > 0);
+    assert_eq!(dir.index_count as usize, dir.dir_index.len());
+    for i in 0..2000 {
+        let inode = squashfs.lookup(format!("/dir/{i:04}")).unwrap().unwrap();
+        assert!(matches!(inode.inner, InodeInner::BasicFile(_)));
+    }
+    assert!(squashfs.lookup("/dir/2000").unwrap().is_none());
+
+    let fs = FilesystemReader::from_reader(Cursor::new(bytes)).unwrap();
+    assert_eq!(fs.files().count(), 2002);
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn test_raw_empty_compression_options() {
+    use backhand::compression::{CompressionOptions, Gzip};
+    use backhand::{FilesystemReader, Squashfs};
+
+    let options =
+        CompressionOptions::Gzip(Gzip { compression_level: 9, window_size: 15, strategies: 0 });
+    let compressor = FilesystemCompressor::new(Compressor::Gzip, Some(options)).unwrap();
+    let mut fs = FilesystemWriter::default();
+    fs.set_compressor(compressor);
+    fs.push_file(Cursor::new(b"hello".to_vec()), "file", NodeHeader::default()).unwrap();
+
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    assert!(superblock.compressor_options_are_present());
+    let mut bytes = output.into_inner();
+
+    // replace the compression options metadata block with an empty compressed block, the
+    // following options bytes are unreferenced
+    bytes[96..98].copy_from_slice(&0_u16.to_le_bytes());
+
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+    assert!(squashfs.superblock.compressor_options_are_present());
+    assert_eq!(squashfs.compression_options, None);
+
+    let fs = FilesystemReader::from_reader(Cursor::new(bytes)).unwrap();
+    assert_eq!(fs.files().count(), 2);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_trailing_bytes() {
+    use backhand::Squashfs;
+
+    let mut fs = FilesystemWriter::default();
+    fs.set_no_padding();
+    fs.push_file(Cursor::new(b"hello".to_vec()), "file", NodeHeader::default()).unwrap();
+
+    let mut output = Cursor::new(vec![]);
+    let (superblock, bytes_written) = fs.write(&mut output).unwrap();
+    let mut bytes = output.into_inner();
+
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+    assert_eq!(squashfs.declared_bytes_used(), superblock.bytes_used);
+    assert_eq!(squashfs.actual_length(), bytes_written);
+    assert_eq!(squashfs.trailing_bytes(), 0);
+
+    // appended data, with the image at an offset
+    bytes.extend_from_slice(&[0xff; 0x100]);
+    let mut image = vec![0x00; 0x200];
+    image.extend_from_slice(&bytes);
+    let squashfs = Squashfs::from_reader_with_offset(Cursor::new(image), 0x200).unwrap();
+    assert_eq!(squashfs.declared_bytes_used(), superblock.bytes_used);
+    assert_eq!(squashfs.actual_length(), bytes_written + 0x100);
+    assert_eq!(squashfs.trailing_bytes(), 0x100);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_always_fragment() {
+    use std::io::Read;
+
+    use backhand::{FilesystemReader, InnerNode, Squashfs};
+
+    let block_size = DEFAULT_BLOCK_SIZE as usize;
+    let tail = vec![0xbb; block_size + 0x18000];
+    let aligned = vec![0xcc; block_size * 2];
+    let small = vec![0xdd; 0x10000];
+
+    let write = |always_fragment: bool| {
+        let header = NodeHeader::default();
+        let mut fs = FilesystemWriter::default();
+        fs.set_always_fragment(always_fragment);
+        fs.push_file(Cursor::new(tail.clone()), "tail", header).unwrap();
+        fs.push_file(Cursor::new(aligned.clone()), "aligned", header).unwrap();
+        fs.push_file(Cursor::new(small.clone()), "small", header).unwrap();
+        let mut output = Cursor::new(vec![]);
+        fs.write(&mut output).unwrap();
+        output.into_inner()
+    };
+
+    let check = |bytes: Vec<u8>, always_fragment: bool, frag_count: u32| {
+        let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+        assert_eq!(squashfs.superblock.fragments_are_always_generated(), always_fragment);
+        assert_eq!(squashfs.superblock.frag_count, frag_count);
+
+        let fs = FilesystemReader::from_reader(Cursor::new(bytes)).unwrap();
+        for (name, expected, fragment) in [
+            ("/tail", &tail, always_fragment),
+            ("/aligned", &aligned, false),
+            ("/small", &small, true),
+        ] {
+            let node = fs.files().find(|node| node.fullpath.to_str() == Some(name)).unwrap();
+            let InnerNode::File(file) = &node.inner else { panic!("not a file") };
+            let reader_file = fs.file(file);
+            assert_eq!(reader_file.fragment().is_some(), fragment, "{name}");
+            let mut read = vec![];
+            reader_file.reader().read_to_end(&mut read).unwrap();
+            assert_eq!(&read, expected, "{name}");
+        }
+        fs
+    };
+
+    // only the small file is in a fragment
+    check(write(false), false, 1);
+
+    // the tail-end doesn't fit in the same fragment as the small file
+    let bytes = write(true);
+    let fs = check(bytes, true, 2);
+
+    // copying the data keeps the tail-end fragments
+    let mut fs = FilesystemWriter::from_fs_reader(&fs).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    check(output.into_inner(), true, 2);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_read_cancellable() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use backhand::{BackhandError, FilesystemReader, InnerNode};
+
+    let block_size = DEFAULT_BLOCK_SIZE as usize;
+    let data: Vec<u8> = (0..block_size * 4).map(|i| (i / block_size) as u8).collect();
+
+    let mut fs = FilesystemWriter::default();
+    fs.push_file(Cursor::new(data.clone()), "file", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    output.set_position(0);
+
+    let fs = FilesystemReader::from_reader(output).unwrap();
+    let node = fs.files().find(|node| node.fullpath.to_str() == Some("/file")).unwrap();
+    let InnerNode::File(file) = &node.inner else { panic!("not a file") };
+    let cancel = Arc::new(AtomicBool::new(false));
+
+    // not cancelled, read all blocks at once
+    let mut buf = vec![0; data.len()];
+    let mut reader = fs.file(file).reader();
+    assert_eq!(reader.read_cancellable(&mut buf, &cancel).unwrap(), data.len());
+    assert_eq!(buf, data);
+    assert_eq!(reader.read_cancellable(&mut buf, &cancel).unwrap(), 0);
+
+    // cancel after the first block
+    let mut reader = fs.file(file).reader();
+    let mut buf = vec![0; block_size];
+    assert_eq!(reader.read_cancellable(&mut buf, &cancel).unwrap(), block_size);
+    assert_eq!(buf, data[..block_size]);
+    cancel.store(true, Ordering::Relaxed);
+    let err = reader.read_cancellable(&mut buf, &cancel).unwrap_err();
+    let err = err.into_inner().unwrap().downcast::<BackhandError>().unwrap();
+    assert!(matches!(*err, BackhandError::Cancelled));
+}
+
+#[test]
+fn test_raw_read_cancellable_short_block() {
+    use std::sync::atomic::AtomicBool;
+
+    use backhand::kind::Kind;
+    use backhand::{FilesystemReader, InnerNode};
+
+    let block_size = DEFAULT_BLOCK_SIZE as usize;
+    let data = vec![0xaa; block_size * 2];
+    let mut fs = FilesystemWriter::default();
+    fs.set_kind(Kind::new_with_const(&Uncompressed, kind::LE_V4_0));
+    fs.push_file(Cursor::new(data.clone()), "file", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let mut bytes = output.into_inner();
+
+    // the first block is stored uncompressed with a size of 1 in the inode, so the blocks end
+    // before the length of the file
+    let stored = (DEFAULT_BLOCK_SIZE | 1 << 24).to_le_bytes();
+    let position = bytes.windows(4).skip(data.len()).position(|w| w == stored).unwrap();
+    bytes[data.len() + position..][..4].copy_from_slice(&(1_u32 | 1 << 24).to_le_bytes());
+
+    let fs = FilesystemReader::from_reader(Cursor::new(bytes)).unwrap();
+    let node = fs.files().find(|node| node.fullpath.to_str() == Some("/file")).unwrap();
+    let InnerNode::File(file) = &node.inner else { panic!("not a file") };
+    let mut buf = vec![0; data.len()];
+    let err = fs.file(file).reader().read_cancellable(&mut buf, &AtomicBool::new(false));
+    assert_eq!(err.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_superblock_bytes() {
+    use backhand::kind::Kind;
+    use backhand::Squashfs;
+
+    for kind in [kind::LE_V4_0, kind::BE_V4_0] {
+        let kind = Kind::from_const(kind).unwrap();
+        let mut fs = FilesystemWriter::default();
+        fs.set_kind(Kind::from_kind(&kind));
+        fs.push_file(Cursor::new(b"hello".to_vec()), "file", NodeHeader::default()).unwrap();
+
+        let mut output = Cursor::new(vec![]);
+        fs.write(&mut output).unwrap();
+        let bytes = output.into_inner();
+
+        let squashfs = Squashfs::from_reader_with_offset_and_kind(
+            Cursor::new(bytes.clone()),
+            0,
+            Kind::from_kind(&kind),
+        )
+        .unwrap();
+        let raw = squashfs.raw_superblock_bytes();
+        assert_eq!(raw[..4], kind.magic());
+        assert_eq!(raw[..], bytes[..96]);
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_fragment_usage() {
+    use backhand::FilesystemReader;
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    // three fit in the first fragment block, the fourth is in the next
+    for name in ["a", "b", "c", "d"] {
+        fs.push_file(Cursor::new(vec![0x01; 0xa000]), name, header).unwrap();
+    }
+    fs.push_file(Cursor::new(vec![0x02; DEFAULT_BLOCK_SIZE as usize]), "large", header).unwrap();
+    fs.push_dir("dir", header).unwrap();
+
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    output.set_position(0);
+
+    let fs = FilesystemReader::from_reader(output).unwrap();
+    let usage: Vec<(usize, Vec<&str>)> = fs
+        .fragment_usage()
+        .into_iter()
+        .map(|(index, nodes)| {
+            (index, nodes.iter().map(|node| node.fullpath.to_str().unwrap()).collect())
+        })
+        .collect();
+    assert_eq!(usage, [(0, vec!["/a", "/b", "/c"]), (1, vec!["/d"])]);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_squashfs_retained() {
+    use std::io::Read;
+
+    use backhand::{FilesystemReader, InnerNode, Squashfs};
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("dir", header).unwrap();
+    fs.push_file(Cursor::new(b"hello".to_vec()), "dir/file", header).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+
+    let fs = FilesystemReader::from_reader(Cursor::new(bytes.clone())).unwrap();
+    assert!(fs.raw_squashfs().is_none());
+
+    let mut squashfs = Squashfs::from_reader(Cursor::new(bytes)).unwrap();
+    squashfs.kind = kind::Kind::from_const(kind::LE_V4_0).unwrap().with_retained_inodes(true);
+    let fs = squashfs.into_filesystem_reader_retaining().unwrap();
+    let raw = fs.raw_squashfs().unwrap();
+    assert_eq!(raw.inodes.len(), raw.superblock.inode_count as usize);
+    assert_eq!(raw.inodes.len(), fs.files().count());
+
+    // raw inodes and the node tree refer to the same inodes
+    let node = fs.files().find(|node| node.fullpath.to_str() == Some("/dir/file")).unwrap();
+    let inode = raw.lookup("/dir/file").unwrap().unwrap();
+    assert_eq!(raw.inodes.get(&inode.header.inode_number), fs.inode(node));
+
+    // the reader is still usable
+    let InnerNode::File(file) = &node.inner else { panic!("not a file") };
+    let mut read = vec![];
+    fs.file(file).reader().read_to_end(&mut read).unwrap();
+    assert_eq!(read, b"hello");
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_fragment_order() {
+    use backhand::{FilesystemReader, FragmentOrder};
+
+    let write = |order: Option<FragmentOrder>| {
+        let header = NodeHeader::default();
+        let mut fs = FilesystemWriter::default();
+        if let Some(order) = order {
+            fs.set_fragment_order(order);
+        }
+        // three fit in a fragment block
+        for (i, name) in ["z1", "a1", "z2", "a2"].into_iter().enumerate() {
+            fs.push_file(Cursor::new(vec![i as u8; 0x9000]), name, header).unwrap();
+        }
+        let mut output = Cursor::new(vec![]);
+        fs.write(&mut output).unwrap();
+        output.into_inner()
+    };
+    let usage = |bytes: &[u8]| -> Vec<(usize, Vec<String>)> {
+        let fs = FilesystemReader::from_reader(Cursor::new(bytes.to_vec())).unwrap();
+        fs.fragment_usage()
+            .into_iter()
+            .map(|(index, nodes)| {
+                (index, nodes.iter().map(|node| node.fullpath.display().to_string()).collect())
+            })
+            .collect()
+    };
+
+    let default = write(None);
+    let sorted = write(Some(FragmentOrder::ByPathSorted));
+    let insertion = write(Some(FragmentOrder::ByInsertion));
+    assert_eq!(default, sorted);
+    assert_ne!(sorted, insertion);
+
+    let files = |files: &[&str]| files.iter().map(|file| file.to_string()).collect::<Vec<_>>();
+    assert_eq!(usage(&sorted), [(0, files(&["/a1", "/a2", "/z1"])), (1, files(&["/z2"]))]);
+    assert_eq!(usage(&insertion), [(0, files(&["/a1", "/z1", "/z2"])), (1, files(&["/a2"]))]);
+}
+
+#[test]
+fn test_raw_symlink_target_size() {
+    use backhand::kind::Kind;
+    use backhand::{BackhandError, FilesystemReader, InnerNode};
+
+    // longer than a filename, but still a valid path
+    let target = "t".repeat(1000);
+    let mut fs = FilesystemWriter::default();
+    fs.set_kind(Kind::new_with_const(&Uncompressed, kind::LE_V4_0));
+    fs.push_symlink(&target, "link", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let mut bytes = output.into_inner();
+
+    let fs = FilesystemReader::from_reader(Cursor::new(bytes.clone())).unwrap();
+    let node = fs.files().find(|node| node.fullpath.to_str() == Some("/link")).unwrap();
+    let InnerNode::Symlink(symlink) = &node.inner else { panic!("not a symlink") };
+    assert_eq!(symlink.link.to_str(), Some(target.as_str()));
+
+    // target_size is right before the uncompressed target
+    let target_start = bytes.windows(target.len()).position(|w| w == target.as_bytes()).unwrap();
+    let target_size = target_start - 4..target_start;
+    assert_eq!(bytes[target_size.clone()], 1000_u32.to_le_bytes());
+    bytes[target_size].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(matches!(
+        FilesystemReader::from_reader(Cursor::new(bytes)),
+        Err(BackhandError::CorruptedOrInvalidSquashfs)
+    ));
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_files_preorder() {
+    use std::path::Path;
+
+    use backhand::{FilesystemReader, InnerNode};
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    for dir in ["a", "a/b", "a-b", "a/b/c", "z"] {
+        fs.push_dir(dir, header).unwrap();
+    }
+    for file in ["a/file", "a/b/c/file", "a-b/file", "a.txt", "a/b/file", "z/file"] {
+        fs.push_file(Cursor::new(vec![]), file, header).unwrap();
+    }
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    output.set_position(0);
+
+    let fs = FilesystemReader::from_reader(output).unwrap();
+    let nodes: Vec<_> = fs.files().collect();
+    let paths: Vec<_> = nodes.iter().map(|node| node.fullpath.to_str().unwrap()).collect();
+    assert_eq!(
+        paths,
+        [
+            "/",
+            "/a",
+            "/a/b",
+            "/a/b/c",
+            "/a/b/c/file",
+            "/a/b/file",
+            "/a/file",
+            "/a-b",
+            "/a-b/file",
+            "/a.txt",
+            "/z",
+            "/z/file"
+        ]
+    );
+
+    // each dir is directly followed by its first child, and all of its descendants
+    for (index, node) in nodes.iter().enumerate() {
+        if !matches!(node.inner, InnerNode::Dir(_)) {
+            continue;
+        }
+        let descendants = nodes[index + 1..]
+            .iter()
+            .take_while(|child| child.fullpath.starts_with(&node.fullpath))
+            .count();
+        let all = nodes.iter().filter(|child| child.fullpath.starts_with(&node.fullpath)).count();
+        assert_eq!(descendants + 1, all, "{}", node.fullpath.display());
+        if let Some(child) = nodes.get(index + 1).filter(|_| descendants > 0) {
+            assert_eq!(child.fullpath.parent(), Some(Path::new(&node.fullpath)));
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_lazy_inodes() {
+    use std::io::Read;
+
+    use backhand::{FileCompression, FilesystemReader, InnerNode, InodeInner, Squashfs};
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    // inode table spanning many metadata blocks, with inodes continuing into the next block
+    for i in 0..3000 {
+        fs.push_dir(format!("{i:04}"), header).unwrap();
+        fs.push_file(Cursor::new(vec![i as u8; 3]), format!("{i:04}/file"), header).unwrap();
+        fs.push_symlink("x".repeat(i % 300 + 1), format!("{i:04}/link"), header).unwrap();
+    }
+    let data: Vec<u8> = (0..0x1000).map(|i| i as u8).collect();
+    let stored = FileCompression::Store;
+    fs.push_file_with_compression(Cursor::new(data.clone()), "stored", header, stored).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+    let mut lazy = Squashfs::from_reader_lazy(Cursor::new(bytes.clone())).unwrap();
+    assert!(lazy.inodes.is_empty());
+    assert_eq!(lazy.root_inode, squashfs.root_inode);
+    for i in (0..3000).step_by(7).chain((0..3000).rev().step_by(11)) {
+        for name in ["", "/file", "/link"] {
+            let path = format!("/{i:04}{name}");
+            let inode = lazy.lookup(&path).unwrap().unwrap();
+            assert_eq!(*inode, *squashfs.lookup(&path).unwrap().unwrap(), "{path}");
+        }
+    }
+    assert!(lazy.lookup("/0000/missing").unwrap().is_none());
+    assert!(lazy.inodes.is_empty());
+
+    // read an uncompressed file, only using the inode found
+    let inode = lazy.lookup("/stored").unwrap().unwrap();
+    let InodeInner::BasicFile(file) = &inode.inner else { panic!("not a basic file") };
+    let start = file.blocks_start as usize;
+    assert_eq!(bytes[start..][..file.file_size as usize], data);
+
+    // same tree and inodes as reading all inodes
+    let kind = kind::Kind::from_const(kind::LE_V4_0).unwrap().with_retained_inodes(true);
+    let eager =
+        FilesystemReader::from_reader_with_offset_and_kind(Cursor::new(bytes.clone()), 0, kind)
+            .unwrap();
+    lazy.kind = kind::Kind::from_const(kind::LE_V4_0).unwrap().with_retained_inodes(true);
+    let lazy = lazy.into_filesystem_reader().unwrap();
+    assert_eq!(lazy.files().count(), eager.files().count());
+    for (lazy_node, eager_node) in lazy.files().zip(eager.files()) {
+        assert_eq!(lazy_node.fullpath, eager_node.fullpath);
+        assert_eq!(lazy_node.header, eager_node.header);
+        assert_eq!(lazy.inode(lazy_node), eager.inode(eager_node));
+        assert!(lazy.inode(lazy_node).is_some());
+        if let (InnerNode::File(lazy_file), InnerNode::File(eager_file)) =
+            (&lazy_node.inner, &eager_node.inner)
+        {
+            let (mut lazy_bytes, mut eager_bytes) = (vec![], vec![]);
+            lazy.file(lazy_file).reader().read_to_end(&mut lazy_bytes).unwrap();
+            eager.file(eager_file).reader().read_to_end(&mut eager_bytes).unwrap();
+            assert_eq!(lazy_bytes, eager_bytes);
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_inode_count_mismatch() {
+    use backhand::kind::Kind;
+    use backhand::{BackhandError, FilesystemReader, Squashfs};
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("dir", header).unwrap();
+    fs.push_file(Cursor::new(b"file"), "dir/file", header).unwrap();
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+    assert_eq!(superblock.inode_count, 3);
+    assert_eq!(bytes[4..8], 3_u32.to_le_bytes());
+
+    for inode_count in [0, 2, 4, u32::MAX] {
+        let mut bytes = bytes.clone();
+        bytes[4..8].copy_from_slice(&inode_count.to_le_bytes());
+        let err = Squashfs::from_reader(Cursor::new(bytes.clone())).err().unwrap();
+        assert!(matches!(err, BackhandError::CorruptedOrInvalidSquashfs), "{inode_count}: {err}");
+        let err = FilesystemReader::from_reader(Cursor::new(bytes.clone())).err().unwrap();
+        assert!(matches!(err, BackhandError::CorruptedOrInvalidSquashfs), "{inode_count}: {err}");
+
+        // only warn, reading all inodes of the inode table
+        let kind = Kind::from_const(kind::LE_V4_0).unwrap().with_lenient_inode_count(true);
+        let reader =
+            FilesystemReader::from_reader_with_offset_and_kind(Cursor::new(bytes), 0, kind)
+                .unwrap();
+        let paths: Vec<_> = reader.files().map(|node| node.fullpath.clone()).collect();
+        assert_eq!(paths, ["/", "/dir", "/dir/file"].map(std::path::PathBuf::from));
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_set_id_table() {
+    use backhand::{BackhandError, FilesystemReader, Id, Squashfs};
+
+    let id_table = vec![Id::new(0), Id::new(2000), Id::new(100), Id::new(1000), Id::new(5)];
+    let mut fs = FilesystemWriter::default();
+    fs.set_id_table(id_table.clone());
+    let header = NodeHeader { uid: 1000, gid: 100, ..NodeHeader::default() };
+    fs.push_dir("dir", header).unwrap();
+    fs.push_file(Cursor::new(b"file"), "dir/file", NodeHeader { uid: 2000, ..header }).unwrap();
+    let err = fs.push_dir("missing", NodeHeader { gid: 1, ..header }).unwrap_err();
+    assert!(matches!(err, BackhandError::InvalidIdTable));
+    assert!(fs.mut_file("missing").is_none());
+
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+    assert_eq!(superblock.id_count, 5);
+
+    // written in the given order, including unused ids
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+    assert_eq!(squashfs.id, id_table);
+    let inode = squashfs.lookup("/dir/file").unwrap().unwrap();
+    assert_eq!((inode.header.uid, inode.header.gid), (1, 2));
+
+    let fs = FilesystemReader::from_reader(Cursor::new(bytes)).unwrap();
+    let headers: Vec<_> = fs.files().map(|node| (node.header.uid, node.header.gid)).collect();
+    assert_eq!(headers, [(0, 0), (1000, 100), (2000, 100)]);
+
+    // ids of the root aren't added either
+    let mut fs = FilesystemWriter::default();
+    fs.set_id_table(id_table);
+    fs.set_root_uid(7);
+    let err = fs.write(Cursor::new(vec![])).unwrap_err();
+    assert!(matches!(err, BackhandError::InvalidIdTable));
+}
+
+#[test]
+fn test_raw_unsorted_dir_entries() {
+    use std::io::Read;
+
+    use backhand::kind::Kind;
+    use backhand::{FilesystemReader, InnerNode, Squashfs};
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.set_kind(Kind::new_with_const(&Uncompressed, kind::LE_V4_0));
+    fs.push_dir("dir", header).unwrap();
+    for name in ["entry_aaa", "entry_bbb", "entry_ccc"] {
+        fs.push_file(Cursor::new(name), format!("dir/{name}"), header).unwrap();
+    }
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let mut bytes = output.into_inner();
+
+    // swap the names of the first and last entry in the uncompressed directory table, so the
+    // entries are ordered: entry_ccc, entry_bbb, entry_aaa
+    let find = |bytes: &[u8], name: &[u8]| bytes.windows(name.len()).rposition(|w| w == name);
+    let first = find(&bytes, b"entry_aaa").unwrap();
+    let last = find(&bytes, b"entry_ccc").unwrap();
+    assert!(first < last);
+    bytes[first..][..9].copy_from_slice(b"entry_ccc");
+    bytes[last..][..9].copy_from_slice(b"entry_aaa");
+
+    // lookups stop at the first entry after the name, unless dirs are marked as unsorted
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+    assert!(squashfs.lookup("/dir/entry_aaa").unwrap().is_none());
+    let kind = Kind::from_const(kind::LE_V4_0).unwrap().with_unsorted_dirs(true);
+    let squashfs =
+        Squashfs::from_reader_with_offset_and_kind(Cursor::new(bytes.clone()), 0, kind).unwrap();
+    for name in ["entry_aaa", "entry_bbb", "entry_ccc"] {
+        assert!(squashfs.lookup(format!("/dir/{name}")).unwrap().is_some(), "{name}");
+    }
+    assert!(squashfs.lookup("/dir/entry_ddd").unwrap().is_none());
+
+    // all files found, with the data of the inode each name now points to
+    let fs = FilesystemReader::from_reader(Cursor::new(bytes)).unwrap();
+    let files: Vec<_> = fs
+        .files()
+        .filter_map(|node| match &node.inner {
+            InnerNode::File(file) => {
+                let mut data = String::new();
+                fs.file(file).reader().read_to_string(&mut data).unwrap();
+                Some((node.fullpath.to_str().unwrap().to_string(), data))
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        files,
+        [
+            ("/dir/entry_aaa".to_string(), "entry_ccc".to_string()),
+            ("/dir/entry_bbb".to_string(), "entry_bbb".to_string()),
+            ("/dir/entry_ccc".to_string(), "entry_aaa".to_string()),
+        ]
+    );
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_compressed_size() {
+    use backhand::{FilesystemReader, InnerNode};
+
+    let header = NodeHeader::default();
+    let large: Vec<u8> =
+        (0..0x1_0000_u32).flat_map(|i| i.to_le_bytes()).chain([1; 0x100]).collect();
+    let small: Vec<u8> = (0..0x1000_u32).map(|i| (i % 7) as u8).collect();
+    let stored: Vec<u8> = (0..0x100_u32).map(|i| i as u8).collect();
+
+    for fragments_uncompressed in [false, true] {
+        let mut fs = FilesystemWriter::default();
+        fs.set_always_fragment(true);
+        fs.set_fragments_uncompressed(fragments_uncompressed);
+        fs.push_file(Cursor::new(large.clone()), "large", header).unwrap();
+        fs.push_file(Cursor::new(small.clone()), "small", header).unwrap();
+        fs.push_file(Cursor::new(stored.clone()), "stored", header).unwrap();
+        let mut output = Cursor::new(vec![]);
+        fs.write(&mut output).unwrap();
+
+        let fs = FilesystemReader::from_reader(Cursor::new(output.into_inner())).unwrap();
+        let files: Vec<_> = fs
+            .files()
+            .filter_map(|node| match &node.inner {
+                InnerNode::File(file) => Some(fs.file(file)),
+                _ => None,
+            })
+            .collect();
+        let fragment = files[0].fragment().unwrap();
+        assert!(files.iter().all(|file| file.fragment() == Some(fragment)));
+
+        // 2 blocks, with the tail of each file stored in the same fragment block
+        let InnerNode::File(large_file) = &fs.files().nth(1).unwrap().inner else { panic!() };
+        let blocks: u64 =
+            large_file.block_sizes().iter().map(|block| u64::from(block.size())).sum();
+        assert_eq!(large_file.block_sizes().len(), 2);
+        let tails = [0x100, small.len() as u64, stored.len() as u64];
+        let fragment_len: u64 = tails.iter().sum();
+        let fragment_size = u64::from(fragment.size.size());
+        let expected: Vec<_> =
+            tails.iter().map(|tail| tail * fragment_size / fragment_len).collect();
+        assert_eq!(files[0].compressed_size(), blocks + expected[0]);
+        assert_eq!(files[1].compressed_size(), expected[1]);
+        assert_eq!(files[2].compressed_size(), expected[2]);
+
+        if fragments_uncompressed {
+            assert_eq!(fragment_size, fragment_len);
+            assert_eq!(files[2].compressed_size(), stored.len() as u64);
+        } else {
+            assert!(files[0].compressed_size() < large.len() as u64);
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_fragment_reproducible() {
+    use backhand::Squashfs;
+
+    let write = |fragments_uncompressed: bool| {
+        let header = NodeHeader::default();
+        let mut fs = FilesystemWriter::default();
+        fs.set_fragments_uncompressed(fragments_uncompressed);
+        for i in 0..10_u8 {
+            fs.push_file(Cursor::new(vec![i; 0x111 * usize::from(i)]), format!("{i}"), header)
+                .unwrap();
+        }
+        let mut output = Cursor::new(vec![]);
+        fs.write(&mut output).unwrap();
+        output.into_inner()
+    };
+
+    for fragments_uncompressed in [false, true] {
+        let bytes = write(fragments_uncompressed);
+        assert_eq!(bytes, write(fragments_uncompressed));
+
+        // the partially filled fragment block only contains the tails, without padding
+        let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+        let fragments = squashfs.fragments.unwrap();
+        assert_eq!(fragments.len(), 1);
+        let fragment = fragments[0];
+        assert_eq!(fragment.size.uncompressed(), fragments_uncompressed);
+        if fragments_uncompressed {
+            let expected: Vec<u8> =
+                (1..10_u8).flat_map(|i| vec![i; 0x111 * usize::from(i)]).collect();
+            let start = fragment.start as usize;
+            assert_eq!(fragment.size.size() as usize, expected.len());
+            assert_eq!(bytes[start..][..expected.len()], expected);
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_uncompressed_id_table() {
+    use backhand::{FilesystemReader, Squashfs};
+
+    let mut fs = FilesystemWriter::default();
+    fs.set_root_uid(0);
+    for (i, (uid, gid)) in [(1000, 100), (0, 0x1234_5678), (u32::MAX, 5)].into_iter().enumerate() {
+        let header = NodeHeader { uid, gid, ..NodeHeader::default() };
+        fs.push_file(Cursor::new(vec![]), format!("{i}"), header).unwrap();
+    }
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+
+    // pointer to the single metadata block, with the uncompressed bit set in its length
+    let id_table = superblock.id_table as usize;
+    let block = u64::from_le_bytes(bytes[id_table..][..8].try_into().unwrap()) as usize;
+    let len = u16::from_le_bytes(bytes[block..][..2].try_into().unwrap());
+    assert_eq!(len, 0x8000 | (superblock.id_count * 4));
+
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+    let ids: Vec<_> = squashfs.id.iter().map(|id| id.num).collect();
+    assert_eq!(ids, [0, 100, 1000, 0x1234_5678, 5, u32::MAX]);
+
+    let fs = FilesystemReader::from_reader(Cursor::new(bytes)).unwrap();
+    let owners: Vec<_> = fs.files().map(|node| (node.header.uid, node.header.gid)).collect();
+    assert_eq!(owners, [(0, 0), (1000, 100), (0, 0x1234_5678), (u32::MAX, 5)]);
+}
+
+#[test]
+fn test_raw_write_with_sidecar() {
+    use backhand::kind::Kind;
+    use backhand::{Sidecar, Squashfs};
+    use deku::DekuContainerRead;
+
+    let large: Vec<u8> = (0..0x30000_u32).map(|i| (i % 251) as u8).collect();
+    let small: Vec<u8> = (0..0x100_u32).map(|i| i as u8).collect();
+
+    // all uncompressed, so data and inodes can be found directly in the image
+    let writer = || {
+        let header = NodeHeader::default();
+        let mut fs = FilesystemWriter::default();
+        fs.set_kind(Kind::new_with_const(&Uncompressed, kind::LE_V4_0));
+        fs.push_dir("dir", header).unwrap();
+        fs.push_file(Cursor::new(large.clone()), "dir/large", header).unwrap();
+        fs.push_file(Cursor::new(small.clone()), "dir/small", header).unwrap();
+        fs.push_symlink("dir/small", "link", header).unwrap();
+        fs
+    };
+    let mut output = Cursor::new(vec![]);
+    let mut sidecar = vec![];
+    let (superblock, _) = writer().write_with_sidecar(&mut output, &mut sidecar).unwrap();
+    let bytes = output.into_inner();
+
+    // same image as without the sidecar
+    let mut without = Cursor::new(vec![]);
+    writer().write(&mut without).unwrap();
+    assert_eq!(without.into_inner(), bytes);
+
+    let (rest, index) = Sidecar::from_bytes((&sidecar, 0)).unwrap();
+    assert!(rest.0.is_empty());
+    assert_eq!(index.inode_table, superblock.inode_table);
+    let paths: Vec<_> = index.entries.iter().map(|entry| entry.path.as_slice()).collect();
+    assert_eq!(paths, [&b"/"[..], b"/dir", b"/dir/large", b"/dir/small", b"/link"]);
+    assert_eq!(index.entries[0].inode, superblock.root_inode);
+
+    // each inode reference points to the inode header of the node
+    for entry in &index.entries {
+        let start = (index.inode_table + (entry.inode >> 16)) as usize;
+        let inode = start + 2 + (entry.inode & 0xffff) as usize;
+        let inode_number = u32::from_le_bytes(bytes[inode + 12..][..4].try_into().unwrap());
+        assert_eq!(inode_number, entry.inode_number);
+    }
+
+    // data blocks of large
+    let large_entry = &index.entries[2];
+    assert_eq!(large_entry.file_size, large.len() as u64);
+    assert_eq!(large_entry.frag_index, 0xffffffff);
+    assert_eq!(large_entry.block_count, 2);
+    let len: u32 = large_entry.block_sizes.iter().map(|block| block.size()).sum();
+    assert!(large_entry.block_sizes.iter().all(|block| block.uncompressed()));
+    let start = large_entry.blocks_start as usize;
+    assert_eq!(bytes[start..][..len as usize], large);
+
+    // tail-end of small in the fragment block
+    let small_entry = &index.entries[3];
+    assert_eq!(small_entry.file_size, small.len() as u64);
+    assert!(small_entry.block_sizes.is_empty());
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+    let fragment = squashfs.fragments.unwrap()[small_entry.frag_index as usize];
+    let start = (fragment.start + u64::from(small_entry.block_offset)) as usize;
+    assert_eq!(bytes[start..][..small.len()], small);
+}
+
+/// Dir entries with an inode type not known to backhand, such as from vendor extensions
+#[test]
+fn test_raw_unknown_inode() {
+    use backhand::kind::Kind;
+    use backhand::{BackhandError, FilesystemReader, OnUnknownInode, Sidecar, Squashfs};
+    use deku::DekuContainerRead;
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.set_kind(Kind::new_with_const(&Uncompressed, kind::LE_V4_0));
+    fs.push_dir("dir", header).unwrap();
+    fs.push_file(Cursor::new(b"first".to_vec()), "dir/first", header).unwrap();
+    fs.push_file(Cursor::new(b"vendor".to_vec()), "dir/vendor", header).unwrap();
+    fs.push_file(Cursor::new(b"second".to_vec()), "dir/second", header).unwrap();
+    let mut output = Cursor::new(vec![]);
+    let mut sidecar = vec![];
+    fs.write_with_sidecar(&mut output, &mut sidecar).unwrap();
+    let mut bytes = output.into_inner();
+
+    // change the type of "vendor" in both the inode and dir entry to an unknown type, the dir
+    // table being after the file data
+    const UNKNOWN: u16 = 0x20;
+    let (_, index) = Sidecar::from_bytes((&sidecar, 0)).unwrap();
+    let entry = index.entries.iter().find(|entry| entry.path == b"/dir/vendor").unwrap();
+    let inode = (index.inode_table + (entry.inode >> 16) + 2 + (entry.inode & 0xffff)) as usize;
+    bytes[inode..][..2].copy_from_slice(&UNKNOWN.to_le_bytes());
+    let name = bytes.windows(b"vendor".len()).rposition(|w| w == b"vendor").unwrap();
+    bytes[name - 4..][..2].copy_from_slice(&UNKNOWN.to_le_bytes());
+
+    // the inode table can't be parsed without knowing the size of every inode
+    assert!(Squashfs::from_reader(Cursor::new(bytes.clone())).is_err());
+
+    let squashfs = Squashfs::from_reader_lazy(Cursor::new(bytes.clone())).unwrap();
+    assert!(matches!(squashfs.into_filesystem_reader(), Err(BackhandError::UnknownInode(UNKNOWN))));
+
+    let kind = Kind::from_const(kind::LE_V4_0).unwrap().with_on_unknown_inode(OnUnknownInode::Skip);
+    let reader =
+        FilesystemReader::from_reader_with_offset_and_kind(Cursor::new(bytes), 0, kind).unwrap();
+    let paths: Vec<_> = reader.files().map(|node| node.fullpath.clone()).collect();
+    assert_eq!(paths, ["/", "/dir", "/dir/first", "/dir/second"].map(std::path::PathBuf::from));
+    for (path, expected) in [("/dir/first", &b"first"[..]), ("/dir/second", b"second")] {
+        let node = reader.files().find(|node| node.fullpath.to_str() == Some(path)).unwrap();
+        let backhand::InnerNode::File(file) = &node.inner else { panic!() };
+        let mut data = vec![];
+        std::io::Read::read_to_end(&mut reader.file(file).reader(), &mut data).unwrap();
+        assert_eq!(data, expected);
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_next_image_offset() {
+    use backhand::Squashfs;
+
+    let image = |name: &str| {
+        let mut fs = FilesystemWriter::default();
+        fs.push_file(Cursor::new(name.as_bytes().to_vec()), name, NodeHeader::default()).unwrap();
+        let mut output = Cursor::new(vec![]);
+        fs.write(&mut output).unwrap();
+        output.into_inner()
+    };
+    // first image after an unaligned prefix, with the second image at the next 4K
+    let prefix = vec![0xff; 0x1000 - 0x10];
+    let mut blob = [prefix.clone(), image("first")].concat();
+    blob.resize(blob.len().next_multiple_of(0x1000), 0);
+    let second_start = blob.len() as u64;
+    blob.extend(image("second"));
+
+    let squashfs =
+        Squashfs::from_reader_with_offset(Cursor::new(&blob), prefix.len() as u64).unwrap();
+    let next = squashfs.next_image_offset();
+    assert_eq!(next, second_start);
+    assert_eq!(&blob[next as usize..][..4], b"hsqs");
+    assert_eq!(
+        squashfs.next_image_offset_aligned(1),
+        (prefix.len() as u64) + squashfs.declared_bytes_used()
+    );
+
+    let squashfs = Squashfs::from_reader_with_offset(Cursor::new(&blob), next).unwrap();
+    assert_eq!(squashfs.next_image_offset(), blob.len() as u64);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_preserve_inode_numbers() {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use backhand::{FilesystemReader, Squashfs};
+
+    let inode_numbers = |bytes: &[u8]| -> HashMap<PathBuf, u32> {
+        let reader = FilesystemReader::from_reader(Cursor::new(bytes)).unwrap();
+        reader.files().map(|node| (node.fullpath.clone(), node.inode_number().unwrap())).collect()
+    };
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("dir", header).unwrap();
+    fs.push_file(Cursor::new(b"a".to_vec()), "dir/a", header).unwrap();
+    fs.push_file(Cursor::new(b"b".to_vec()), "dir/b", header).unwrap();
+    fs.push_symlink("dir/a", "link", header).unwrap();
+    let mut original = Cursor::new(vec![]);
+    fs.write(&mut original).unwrap();
+    let original = original.into_inner();
+    let original_numbers = inode_numbers(&original);
+
+    // add a node sorted before all others, shifting sequential inode numbers
+    let rebuild = |bytes: &[u8], preserve: bool| {
+        let reader = FilesystemReader::from_reader(Cursor::new(bytes.to_vec())).unwrap();
+        let mut fs = FilesystemWriter::from_fs_reader(&reader).unwrap();
+        fs.set_preserve_inode_numbers(preserve);
+        fs.push_file(Cursor::new(b"new".to_vec()), "/0new", header).unwrap();
+        let mut output = Cursor::new(vec![]);
+        fs.write(&mut output).unwrap();
+        output.into_inner()
+    };
+
+    let sequential = inode_numbers(&rebuild(&original, false));
+    assert_ne!(sequential[&PathBuf::from("/dir/a")], original_numbers[&PathBuf::from("/dir/a")]);
+
+    let rebuilt = rebuild(&original, true);
+    let preserved = inode_numbers(&rebuilt);
+    for (path, inode_number) in &original_numbers {
+        assert_eq!(preserved[path], *inode_number, "{path:?}");
+    }
+    // the new node uses the lowest unused inode number
+    assert_eq!(preserved[&PathBuf::from("/0new")], original_numbers.len() as u32 + 1);
+
+    // the rebuilt image reads as valid, including looking up inodes by path
+    let squashfs = Squashfs::from_reader(Cursor::new(rebuilt.clone())).unwrap();
+    assert_eq!(squashfs.superblock.inode_count, preserved.len() as u32);
+    let inode = squashfs.lookup("/dir/b").unwrap().unwrap();
+    assert_eq!(inode.header.inode_number, original_numbers[&PathBuf::from("/dir/b")]);
+    let reader = FilesystemReader::from_reader(Cursor::new(rebuilt)).unwrap();
+    let node = reader.files().find(|node| node.fullpath.as_os_str() == "/dir/a").unwrap();
+    let backhand::InnerNode::File(file) = &node.inner else { panic!() };
+    let mut data = vec![];
+    std::io::Read::read_to_end(&mut reader.file(file).reader(), &mut data).unwrap();
+    assert_eq!(data, b"a");
+
+    // with nodes removed, preserved inode numbers can be past the inode count
+    let reader = FilesystemReader::from_reader(Cursor::new(original)).unwrap();
+    let mut fs = FilesystemWriter::from_fs_reader_subtree(&reader, "/dir").unwrap();
+    fs.set_preserve_inode_numbers(true);
+    let max = original_numbers.values().max().unwrap();
+    assert!(*max > 3);
+    assert!(matches!(
+        fs.write(Cursor::new(vec![])),
+        Err(backhand::BackhandError::InvalidInodeNumber(inode_number, 3)) if inode_number > 3
+    ));
+    fs.set_preserve_inode_numbers(false);
+    fs.write(Cursor::new(vec![])).unwrap();
+}
+
+/// Xattr id table spanning multiple metadata blocks, appended to a written image
+#[test]
+fn test_raw_xattr_ids() {
+    use backhand::kind::Kind;
+    use backhand::{Squashfs, XattrId};
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.set_kind(Kind::new_with_const(&Uncompressed, kind::LE_V4_0));
+    fs.set_no_padding();
+    fs.push_file(Cursor::new(b"file".to_vec()), "file", header).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let mut bytes = output.into_inner();
+
+    // key-value table, contents not parsed
+    let kv_start = bytes.len() as u64;
+    bytes.extend([0x04, 0x80, 0, 0, 0, 0]);
+
+    // more ids than fit in one 8KiB metadata block
+    let ids: Vec<XattrId> = (0..600)
+        .map(|i| XattrId { xattr: (i as u64) << 4, count: i % 3 + 1, size: i * 2 })
+        .collect();
+    let id_bytes: Vec<u8> = ids
+        .iter()
+        .flat_map(|id| {
+            [&id.xattr.to_le_bytes()[..], &id.count.to_le_bytes(), &id.size.to_le_bytes()].concat()
+        })
+        .collect();
+    let mut block_ptrs = vec![];
+    for block in id_bytes.chunks(0x2000) {
+        block_ptrs.push(bytes.len() as u64);
+        bytes.extend((block.len() as u16 | 0x8000).to_le_bytes());
+        bytes.extend(block);
+    }
+    assert_eq!(block_ptrs.len(), 2);
+
+    let xattr_table = bytes.len() as u64;
+    bytes.extend(kv_start.to_le_bytes());
+    bytes.extend((ids.len() as u32).to_le_bytes());
+    bytes.extend(0_u32.to_le_bytes());
+    for ptr in block_ptrs {
+        bytes.extend(ptr.to_le_bytes());
+    }
+    let bytes_used = bytes.len() as u64;
+    bytes[40..48].copy_from_slice(&bytes_used.to_le_bytes());
+    bytes[56..64].copy_from_slice(&xattr_table.to_le_bytes());
+
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+    let xattr_ids = squashfs.xattr_ids.as_ref().unwrap();
+    assert_eq!(xattr_ids.kv_start, kv_start);
+    assert_eq!(xattr_ids.ids, ids);
+    squashfs.into_filesystem_reader().unwrap();
+
+    // a count larger than the ids in the table, read without xattrs
+    bytes[xattr_table as usize + 8..][..4].copy_from_slice(&601_u32.to_le_bytes());
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes)).unwrap();
+    assert!(squashfs.xattr_ids.is_none());
+    squashfs.into_filesystem_reader().unwrap();
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_kernel_compatible() {
+    use backhand::compression::{CompressionOptions, Lz4};
+    use backhand::kind::Kind;
+    use backhand::BackhandError;
+    use deku::ctx::Endian;
+    use deku::prelude::*;
+
+    let write = |f: &dyn Fn(&mut FilesystemWriter)| {
+        let mut fs = FilesystemWriter::default();
+        fs.set_kernel_compatible(true);
+        fs.push_file(Cursor::new(b"file".to_vec()), "file", NodeHeader::default()).unwrap();
+        f(&mut fs);
+        fs.write(Cursor::new(vec![]))
+    };
+    let incompatible = |f: &dyn Fn(&mut FilesystemWriter)| {
+        matches!(write(f), Err(BackhandError::KernelIncompatible(_)))
+    };
+
+    write(&|_| {}).unwrap();
+    write(&|fs| fs.set_kernel_compatible(false)).unwrap();
+
+    assert!(incompatible(&|fs| fs.set_kind(Kind::from_const(kind::BE_V4_0).unwrap())));
+    assert!(incompatible(&|fs| {
+        fs.set_kind(Kind::from_const(kind::LE_V4_0).unwrap().with_version(4, 1))
+    }));
+    assert!(incompatible(&|fs| {
+        fs.set_compressor(FilesystemCompressor::new(Compressor::Lzma, None).unwrap())
+    }));
+    assert!(incompatible(&|fs| {
+        let options = CompressionOptions::Lz4(Lz4 { version: 2, flags: 0 });
+        fs.set_compressor(FilesystemCompressor::new(Compressor::Lz4, Some(options)).unwrap())
+    }));
+
+    // xz dictionary sizes of 20KiB, and 24KiB as the sum of 16KiB and 8KiB
+    let xz = |dictionary_size: u32| {
+        let bytes = [dictionary_size.to_le_bytes(), 0_u32.to_le_bytes()].concat();
+        let mut cursor = Cursor::new(bytes);
+        let mut reader = Reader::new(&mut cursor);
+        CompressionOptions::from_reader_with_ctx(&mut reader, (Endian::Little, Compressor::Xz))
+            .unwrap()
+    };
+    assert!(incompatible(&|fs| {
+        let compressor = FilesystemCompressor::new(Compressor::Xz, Some(xz(0x5000))).unwrap();
+        fs.set_compressor(compressor)
+    }));
+    write(&|fs| {
+        let compressor = FilesystemCompressor::new(Compressor::Xz, Some(xz(0x6000))).unwrap();
+        fs.set_compressor(compressor)
+    })
+    .unwrap();
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_parent_inode() {
+    use backhand::FilesystemReader;
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("a", header).unwrap();
+    fs.push_dir("a/b", header).unwrap();
+    fs.push_dir("c", header).unwrap();
+    fs.push_file(Cursor::new(b"file".to_vec()), "a/file", header).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+
+    let kind = kind::Kind::from_const(kind::LE_V4_0).unwrap().with_retained_inodes(true);
+    let reader = FilesystemReader::from_reader_with_offset_and_kind(
+        Cursor::new(output.into_inner()),
+        0,
+        kind,
+    )
+    .unwrap();
+    let node = |path: &str| reader.files().find(|node| node.fullpath.as_os_str() == path).unwrap();
+    let inode_number = |path: &str| node(path).inode_number().unwrap();
+
+    assert_eq!(reader.parent_inode(node("/a")), Some(inode_number("/")));
+    assert_eq!(reader.parent_inode(node("/c")), Some(inode_number("/")));
+    assert_eq!(reader.parent_inode(node("/a/b")), Some(inode_number("/a")));
+    assert_eq!(reader.parent_inode(node("/a/file")), None);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_symlink_link_count() {
+    use backhand::{FilesystemReader, InodeInner};
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.push_file(Cursor::new(b"file".to_vec()), "file", header).unwrap();
+    fs.push_symlink("file", "link", header).unwrap();
+    fs.push_symlink_with_links("file", "linked", header, 2).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+
+    let kind = kind::Kind::from_const(kind::LE_V4_0).unwrap().with_retained_inodes(true);
+    let reader = FilesystemReader::from_reader_with_offset_and_kind(
+        Cursor::new(output.into_inner()),
+        0,
+        kind,
+    )
+    .unwrap();
+    let link_count = |reader: &FilesystemReader, path: &str| {
+        let node = reader.files().find(|node| node.fullpath.as_os_str() == path).unwrap();
+        let InodeInner::BasicSymlink(symlink) = &reader.inode(node).unwrap().inner else {
+            panic!()
+        };
+        symlink.link_count
+    };
+    assert_eq!(link_count(&reader, "/link"), 1);
+    assert_eq!(link_count(&reader, "/linked"), 2);
+
+    // kept when written again
+    let mut fs = FilesystemWriter::from_fs_reader(&reader).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let kind = kind::Kind::from_const(kind::LE_V4_0).unwrap().with_retained_inodes(true);
+    let reader = FilesystemReader::from_reader_with_offset_and_kind(
+        Cursor::new(output.into_inner()),
+        0,
+        kind,
+    )
+    .unwrap();
+    assert_eq!(link_count(&reader, "/link"), 1);
+    assert_eq!(link_count(&reader, "/linked"), 2);
+}
+
+#[test]
+fn test_raw_check_overlaps() {
+    use backhand::kind::Kind;
+    use backhand::{Overlap, Sidecar, Squashfs};
+    use deku::DekuContainerRead;
+
+    let data = |byte: u8| Cursor::new(vec![byte; DEFAULT_BLOCK_SIZE as usize * 2]);
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.set_kind(Kind::new_with_const(&Uncompressed, kind::LE_V4_0));
+    fs.push_file(data(b'a'), "a", header).unwrap();
+    fs.push_file(data(b'b'), "b", header).unwrap();
+    // deduplicated with a
+    fs.push_file(data(b'a'), "c", header).unwrap();
+    fs.push_file(Cursor::new(b"small".to_vec()), "small", header).unwrap();
+    let mut output = Cursor::new(vec![]);
+    let mut sidecar = vec![];
+    fs.write_with_sidecar(&mut output, &mut sidecar).unwrap();
+    let mut bytes = output.into_inner();
+
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+    assert_eq!(squashfs.check_overlaps(), []);
+
+    // point the data of b into the second block of a
+    let (_, index) = Sidecar::from_bytes((&sidecar, 0)).unwrap();
+    let entry = |path: &[u8]| index.entries.iter().find(|entry| entry.path == path).unwrap();
+    let (a, b) = (entry(b"/a"), entry(b"/b"));
+    let inode = (index.inode_table + (b.inode >> 16) + 2 + (b.inode & 0xffff)) as usize;
+    let blocks_start = a.blocks_start + u64::from(DEFAULT_BLOCK_SIZE);
+    bytes[inode + 16..][..4].copy_from_slice(&(blocks_start as u32).to_le_bytes());
+
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes)).unwrap();
+    let a_end = a.blocks_start + u64::from(DEFAULT_BLOCK_SIZE) * 2;
+    let overlap =
+        |first| Overlap { first, second: b.inode_number, start: blocks_start, end: a_end };
+    let mut overlaps = squashfs.check_overlaps();
+    overlaps.sort_by_key(|overlap| overlap.first);
+    // both a and the deduplicated c
+    assert_eq!(overlaps, [overlap(a.inode_number), overlap(entry(b"/c").inode_number)]);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_eager_compression() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use backhand::compression::DefaultCompressor;
+    use backhand::kind::Kind;
+    use backhand::FilesystemReader;
+
+    static COMPRESSED: AtomicUsize = AtomicUsize::new(0);
+
+    /// Count the compressed data blocks
+    struct Counting;
+
+    impl CompressionAction for Counting {
+        fn decompress(
+            &self,
+            bytes: &[u8],
+            out: &mut Vec<u8>,
+            compressor: Compressor,
+        ) -> Result<(), backhand::BackhandError> {
+            DefaultCompressor.decompress(bytes, out, compressor)
+        }
+
+        fn compress(
+            &self,
+            bytes: &[u8],
+            fc: FilesystemCompressor,
+            block_size: u32,
+        ) -> Result<Vec<u8>, backhand::BackhandError> {
+            if bytes.len() == block_size as usize {
+                COMPRESSED.fetch_add(1, Ordering::SeqCst);
+            }
+            DefaultCompressor.compress(bytes, fc, block_size)
+        }
+
+        fn compression_options(
+            &self,
+            superblock: &mut SuperBlock,
+            kind: &kind::Kind,
+            fs_compressor: FilesystemCompressor,
+        ) -> Result<Vec<u8>, backhand::BackhandError> {
+            DefaultCompressor.compression_options(superblock, kind, fs_compressor)
+        }
+    }
+
+    let data: Vec<u8> = (0..DEFAULT_BLOCK_SIZE as usize * 3 + 0x100).map(|i| i as u8).collect();
+    let write = |eager: bool| {
+        let mut fs = FilesystemWriter::default();
+        fs.set_kind(Kind::new_with_const(&Counting, kind::LE_V4_0));
+        fs.set_eager_compression(eager);
+        let header = NodeHeader::default();
+        fs.push_file(Cursor::new(data.clone()), "large", header).unwrap();
+        fs.push_file(Cursor::new(data.clone()), "duplicate", header).unwrap();
+        fs.push_file(Cursor::new(b"small".to_vec()), "small", header).unwrap();
+        let pushed = COMPRESSED.swap(0, Ordering::SeqCst);
+        let mut output = Cursor::new(vec![]);
+        fs.write(&mut output).unwrap();
+        (output.into_inner(), pushed, COMPRESSED.swap(0, Ordering::SeqCst))
+    };
+
+    // the duplicate isn't compressed
+    let (lazy_bytes, pushed, written) = write(false);
+    assert_eq!((pushed, written), (0, 3));
+
+    // all full blocks are compressed before writing, resulting in the same image
+    let (bytes, pushed, written) = write(true);
+    assert_eq!((pushed, written), (6, 0));
+    assert_eq!(bytes, lazy_bytes);
+
+    let fs = FilesystemReader::from_reader(Cursor::new(bytes)).unwrap();
+    for (name, expected) in [("/large", &data[..]), ("/duplicate", &data[..]), ("/small", b"small")]
+    {
+        let node = fs.files().find(|node| node.fullpath.to_str() == Some(name)).unwrap();
+        let backhand::InnerNode::File(file) = &node.inner else { panic!("not a file") };
+        let mut read = vec![];
+        std::io::Read::read_to_end(&mut fs.file(file).reader(), &mut read).unwrap();
+        assert_eq!(read, expected);
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_max_block_size() {
+    use std::io::Read;
+
+    use backhand::{FilesystemReader, InnerNode, MAX_BLOCK_SIZE};
+
+    const BLOCK: usize = MAX_BLOCK_SIZE as usize;
+
+    // incompressible, so full blocks are stored uncompressed
+    let mut state = 0x1234_5678_u32;
+    let mut random = |len: usize| -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    };
+    let files = [
+        ("empty", vec![]),
+        ("small", b"small".to_vec()),
+        ("block_minus_one", random(BLOCK - 1)),
+        ("block", random(BLOCK)),
+        ("block_plus_one", random(BLOCK + 1)),
+        ("blocks_plus_one", random(BLOCK * 2 + 1)),
+        ("compressible", vec![0xaa; BLOCK * 2 + 0x100]),
+    ];
+
+    let read_files = |bytes: &[u8]| {
+        let fs = FilesystemReader::from_reader(Cursor::new(bytes.to_vec())).unwrap();
+        assert_eq!(fs.block_size, MAX_BLOCK_SIZE);
+        for (name, expected) in &files {
+            let node = fs
+                .files()
+                .find(|node| node.fullpath.to_str() == Some(&format!("/{name}")))
+                .unwrap();
+            let InnerNode::File(file) = &node.inner else { panic!("not a file") };
+            let mut read = vec![];
+            fs.file(file).reader().read_to_end(&mut read).unwrap();
+            assert_eq!(&read, expected, "{name}");
+        }
+        fs
+    };
+
+    let mut fs = FilesystemWriter::default();
+    fs.set_block_size(MAX_BLOCK_SIZE);
+    for (name, bytes) in &files {
+        fs.push_file(Cursor::new(bytes.clone()), name, NodeHeader::default()).unwrap();
+    }
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+    let reader = read_files(&bytes);
+
+    // round-trip, copying the data blocks as is
+    let mut fs = FilesystemWriter::from_fs_reader(&reader).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let round_trip = output.into_inner();
+    read_files(&round_trip);
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    for (name, bytes) in [("max_block_size.squashfs", &bytes), ("round_trip.squashfs", &round_trip)]
+    {
+        let path = tmp_dir.path().join(name);
+        std::fs::write(&path, bytes).unwrap();
+        test_bin_unsquashfs(path.to_str().unwrap(), None, true, true);
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_read_file() {
+    use backhand::kind::Kind;
+    use backhand::BackhandError;
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    for (name, kind) in [("le.squashfs", kind::LE_V4_0), ("be.squashfs", kind::BE_V4_0)] {
+        let header = NodeHeader::default();
+        let mut fs = FilesystemWriter::default();
+        fs.set_kind(Kind::from_const(kind).unwrap());
+        fs.push_dir("etc", header).unwrap();
+        fs.push_file(Cursor::new(b"ID=backhand\n".to_vec()), "etc/os-release", header).unwrap();
+        fs.push_symlink("os-release", "etc/release", header).unwrap();
+        let image = tmp_dir.path().join(name);
+        fs.write(&mut BufWriter::new(File::create(&image).unwrap())).unwrap();
+
+        assert_eq!(backhand::read_file(&image, "/etc/os-release").unwrap(), b"ID=backhand\n");
+        // root prefix is optional
+        assert_eq!(backhand::read_file(&image, "etc/os-release").unwrap(), b"ID=backhand\n");
+        assert!(matches!(
+            backhand::read_file(&image, "/etc/missing"),
+            Err(BackhandError::FileNotFound)
+        ));
+        for path in ["/etc", "/etc/release"] {
+            assert!(matches!(
+                backhand::read_file(&image, path),
+                Err(BackhandError::NotAFile(not_a_file)) if not_a_file == std::path::Path::new(path)
+            ));
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_from_fs_reader_lazy() {
+    use std::io::{BufRead, Read, Seek, SeekFrom};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use backhand::FilesystemReader;
+
+    /// Count the bytes read from `inner`
+    struct CountingReader {
+        inner: Cursor<Vec<u8>>,
+        read: Arc<AtomicUsize>,
+    }
+
+    impl Read for CountingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            self.read.fetch_add(n, Ordering::SeqCst);
+            Ok(n)
+        }
+    }
+
+    impl BufRead for CountingReader {
+        fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+            self.inner.fill_buf()
+        }
+
+        fn consume(&mut self, amt: usize) {
+            self.read.fetch_add(amt, Ordering::SeqCst);
+            self.inner.consume(amt)
+        }
+    }
+
+    impl Seek for CountingReader {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    let data: Vec<u8> = (0..DEFAULT_BLOCK_SIZE as usize * 4 + 0x100).map(|i| i as u8).collect();
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.push_file(Cursor::new(data.clone()), "large", header).unwrap();
+    fs.push_file(Cursor::new(b"small".to_vec()), "small", header).unwrap();
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    let image = output.into_inner();
+
+    let read = Arc::new(AtomicUsize::new(0));
+    let reader = CountingReader { inner: Cursor::new(image.clone()), read: Arc::clone(&read) };
+    let reader = FilesystemReader::from_reader(reader).unwrap();
+    let read_tables = read.load(Ordering::SeqCst);
+
+    // nothing is read until writing
+    let mut fs = FilesystemWriter::from_fs_reader(&reader).unwrap();
+    assert_eq!(read.load(Ordering::SeqCst), read_tables);
+
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let read_data = read.load(Ordering::SeqCst) - read_tables;
+    // only the data blocks and the fragment, copied as is
+    let data_end = superblock.inode_table as usize;
+    assert!(read_data > 0);
+    assert!(read_data <= data_end);
+    assert_eq!(output.get_ref()[..data_end], image[..data_end]);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_lenient_version_minor() {
+    use std::io::Read;
+
+    use backhand::kind::Kind;
+    use backhand::{FilesystemReader, InnerNode};
+
+    let mut fs = FilesystemWriter::default();
+    fs.push_file(Cursor::new(b"data".to_vec()), "file", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let mut bytes = output.into_inner();
+    // version_minor
+    bytes[30..32].copy_from_slice(&1_u16.to_le_bytes());
+
+    assert!(FilesystemReader::from_reader(Cursor::new(bytes.clone())).is_err());
+
+    let kind = Kind::from_const(kind::LE_V4_0).unwrap().with_lenient_version_minor(true);
+    let fs =
+        FilesystemReader::from_reader_with_offset_and_kind(Cursor::new(bytes), 0, kind).unwrap();
+    let node = fs.files().find(|node| node.fullpath.to_str() == Some("/file")).unwrap();
+    let InnerNode::File(file) = &node.inner else { panic!("not a file") };
+    let mut read = vec![];
+    fs.file(file).reader().read_to_end(&mut read).unwrap();
+    assert_eq!(read, b"data");
+
+    // written with the minor version of the kind
+    let mut fs = FilesystemWriter::from_fs_reader(&fs).unwrap();
+    let (superblock, _) = fs.write(&mut Cursor::new(vec![])).unwrap();
+    assert_eq!(superblock.version_minor, 0);
+}
+
+#[test]
+fn test_raw_strict_metadata() {
+    use backhand::kind::Kind;
+    use backhand::{BackhandError, Squashfs};
+
+    let kind = || Kind::new_with_const(&Uncompressed, kind::LE_V4_0);
+    let mut fs = FilesystemWriter::default();
+    fs.set_kind(kind());
+    fs.push_file(Cursor::new(b"data".to_vec()), "file", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+
+    let read = |bytes: Vec<u8>| {
+        let kind = kind().with_strict_metadata(true);
+        Squashfs::from_reader_with_offset_and_kind(Cursor::new(bytes), 0, kind)
+    };
+    read(bytes.clone()).unwrap();
+
+    // stored length of the first inode table block, with the uncompressed bit
+    for len in [0x8000_u16, 0x8000 | 0x2001] {
+        let mut bytes = bytes.clone();
+        let inode_table = superblock.inode_table as usize;
+        bytes[inode_table..][..2].copy_from_slice(&len.to_le_bytes());
+        assert!(matches!(read(bytes), Err(BackhandError::CorruptedOrInvalidSquashfs)));
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_trailer() {
+    use backhand::{FilesystemReader, Squashfs, DEFAULT_PAD_LEN};
+
+    let trailer = br#"{"build": "1234"}"#.to_vec();
+    let mut fs = FilesystemWriter::default();
+    fs.push_file(Cursor::new(b"data".to_vec()), "file", NodeHeader::default()).unwrap();
+    fs.set_trailer(trailer.clone());
+    let mut output = Cursor::new(vec![]);
+    let (superblock, bytes_written) = fs.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+    assert_eq!(bytes_written, bytes.len() as u64);
+    // after the padding
+    assert_eq!(bytes.len() % DEFAULT_PAD_LEN as usize, trailer.len());
+    assert!(bytes.ends_with(&trailer));
+
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+    assert_eq!(squashfs.declared_bytes_used(), superblock.bytes_used);
+    let fs = FilesystemReader::from_reader(Cursor::new(bytes)).unwrap();
+    assert_eq!(fs.trailer().unwrap(), trailer);
+
+    // no trailer
+    let mut fs = FilesystemWriter::default();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let fs = FilesystemReader::from_reader(Cursor::new(output.into_inner())).unwrap();
+    assert_eq!(fs.trailer().unwrap(), b"");
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_from_tree() {
+    use backhand::{FilesystemReader, InnerNode, TreeSpec};
+
+    let header = NodeHeader { permissions: 0o755, uid: 1000, gid: 1000, mtime: 0 };
+    let tree = TreeSpec::dir(
+        "",
+        header,
+        vec![
+            TreeSpec::dir(
+                "a",
+                header,
+                vec![TreeSpec::dir(
+                    "b",
+                    header,
+                    vec![TreeSpec::file(
+                        "c",
+                        NodeHeader { permissions: 0o644, ..header },
+                        b"abc".to_vec(),
+                    )],
+                )],
+            ),
+            TreeSpec::symlink("link", header, "a/b/c"),
+        ],
+    );
+    let mut fs = FilesystemWriter::from_tree(&tree).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+
+    let fs = FilesystemReader::from_reader(Cursor::new(output.into_inner())).unwrap();
+    let paths: Vec<_> = fs.files().map(|node| node.fullpath.to_str().unwrap()).collect();
+    assert_eq!(paths, ["/", "/a", "/a/b", "/a/b/c", "/link"]);
+    assert_eq!(fs.files().next().unwrap().header, header);
+    let file = fs.files().find(|node| node.fullpath.ends_with("c")).unwrap();
+    assert_eq!(file.header.permissions, 0o644);
+    let InnerNode::File(file) = &file.inner else { panic!() };
+    let mut data = vec![];
+    std::io::Read::read_to_end(&mut fs.file(file).reader(), &mut data).unwrap();
+    assert_eq!(data, b"abc");
+    let link = fs.files().find(|node| node.fullpath.ends_with("link")).unwrap();
+    let InnerNode::Symlink(link) = &link.inner else { panic!() };
+    assert_eq!(link.link.to_str().unwrap(), "a/b/c");
+
+    // root must be a directory
+    let file = TreeSpec::file("", header, vec![]);
+    assert!(FilesystemWriter::from_tree(&file).is_err());
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_require_non_empty() {
+    use backhand::BackhandError;
+
+    let mut fs = FilesystemWriter::default();
+    fs.set_require_non_empty(true);
+    let mut output = Cursor::new(vec![]);
+    assert!(matches!(fs.write(&mut output), Err(BackhandError::EmptyImage)));
+    assert!(output.into_inner().is_empty());
+
+    fs.push_dir("dir", NodeHeader::default()).unwrap();
+    fs.write(Cursor::new(vec![])).unwrap();
+
+    // off by default
+    let mut fs = FilesystemWriter::default();
+    fs.write(Cursor::new(vec![])).unwrap();
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_read_chunked() {
+    use backhand::{FilesystemReader, InnerNode};
+
+    let block_size = DEFAULT_BLOCK_SIZE as usize;
+    let data: Vec<u8> = (0..block_size * 3 + 100).map(|i| (i % 251) as u8).collect();
+    let mut fs = FilesystemWriter::default();
+    fs.push_file(Cursor::new(data.clone()), "file", NodeHeader::default()).unwrap();
+    fs.push_file(Cursor::new(vec![]), "empty", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+
+    let fs = FilesystemReader::from_reader(Cursor::new(output.into_inner())).unwrap();
+    for node in fs.files() {
+        let InnerNode::File(file) = &node.inner else { continue };
+        let mut chunks = vec![];
+        let mut read = vec![];
+        fs.file(file)
+            .read_chunked(|chunk| {
+                chunks.push(chunk.len());
+                read.extend_from_slice(chunk);
+            })
+            .unwrap();
+        assert_eq!(chunks.iter().sum::<usize>(), file.file_len());
+        if node.fullpath.ends_with("file") {
+            assert_eq!(chunks, [block_size, block_size, block_size, 100]);
+            assert_eq!(read, data);
+        } else {
+            assert!(chunks.is_empty());
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_from_slice() {
+    use backhand::{FilesystemReader, InnerNode, Squashfs};
+
+    let mut fs = FilesystemWriter::default();
+    fs.push_file(Cursor::new(b"borrowed".to_vec()), "file", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let image = output.into_inner();
+
+    let squashfs = Squashfs::from_slice(&image).unwrap();
+    assert_eq!(squashfs.superblock.inode_count, 2);
+
+    let fs = FilesystemReader::from_slice(&image).unwrap();
+    let node = fs.files().find(|node| node.fullpath.ends_with("file")).unwrap();
+    let InnerNode::File(file) = &node.inner else { panic!() };
+    let mut data = vec![];
+    std::io::Read::read_to_end(&mut fs.file(file).reader(), &mut data).unwrap();
+    assert_eq!(data, b"borrowed");
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_detect_from_superblock() {
+    use backhand::kind::Kind;
+
+    for (kind, magic) in [(kind::LE_V4_0, *b"hsqs"), (kind::BE_V4_0, *b"sqsh")] {
+        let mut fs = FilesystemWriter::default();
+        fs.set_kind(Kind::from_const(kind).unwrap());
+        let mut output = Cursor::new(vec![]);
+        fs.write(&mut output).unwrap();
+        let superblock: [u8; 96] = output.into_inner()[..96].try_into().unwrap();
+        let detected = Kind::detect_from_superblock(&superblock).unwrap();
+        assert_eq!(detected.magic(), magic);
+
+        // v3 superblock, with the same magic
+        let mut v3 = superblock;
+        if magic == *b"hsqs" {
+            v3[28..30].copy_from_slice(&3_u16.to_le_bytes());
+        } else {
+            v3[28..30].copy_from_slice(&3_u16.to_be_bytes());
+        }
+        assert!(Kind::detect_from_superblock(&v3).is_none());
+    }
+
+    let mut unknown = [0; 96];
+    unknown[..4].copy_from_slice(b"abcd");
+    assert!(Kind::detect_from_superblock(&unknown).is_none());
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_section_layout() {
+    use std::io::Read;
+
+    use backhand::{FilesystemReader, InnerNode, SectionLayout};
+
+    let big: Vec<u8> = (0..DEFAULT_BLOCK_SIZE as usize * 2 + 10).map(|i| (i % 13) as u8).collect();
+    let files = [("big", big.clone()), ("small", b"small".to_vec()), ("dup", big)];
+
+    let mut fs = FilesystemWriter::default();
+    fs.set_section_layout(SectionLayout::MetadataFirst);
+    for (name, data) in &files {
+        fs.push_file(Cursor::new(data.clone()), name, NodeHeader::default()).unwrap();
+    }
+    fs.push_dir("dir", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    let image = output.into_inner();
+
+    let fs = FilesystemReader::from_reader(Cursor::new(image.clone())).unwrap();
+    for (name, data) in &files {
+        let node = fs.files().find(|node| node.fullpath.ends_with(name)).unwrap();
+        let InnerNode::File(file) = &node.inner else { panic!() };
+        // data is after all tables
+        if !file.block_sizes().is_empty() {
+            assert!(file.blocks_start() > superblock.id_table);
+        }
+        let mut read = vec![];
+        fs.file(file).reader().read_to_end(&mut read).unwrap();
+        assert_eq!(&read, data);
+    }
+    assert!(fs.fragments.as_ref().unwrap().iter().all(|frag| frag.start > superblock.id_table));
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("metadata_first.squashfs");
+    std::fs::write(&path, image).unwrap();
+    test_bin_unsquashfs(path.to_str().unwrap(), None, true, true);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_dir_size_past_table() {
+    use backhand::{FilesystemReader, InodeRef, MetadataComp, Squashfs};
+
+    let mut fs = FilesystemWriter::default();
+    // inodes stored uncompressed, so the root inode can be modified in place
+    fs.set_metadata_compression(MetadataComp { inodes: false, ..MetadataComp::default() });
+    for name in ["a", "b", "c"] {
+        fs.push_file(Cursor::new(name.as_bytes().to_vec()), name, NodeHeader::default()).unwrap();
+    }
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    let mut bytes = output.into_inner();
+
+    // file_size of the root BasicDirectory, after the 16 byte inode header, block_index and
+    // link_count, past the metadata block header
+    let (block_start, offset) = InodeRef::decode(superblock.root_inode);
+    let file_size =
+        superblock.inode_table as usize + block_start as usize + 2 + offset as usize + 24;
+    let original = u16::from_le_bytes(bytes[file_size..][..2].try_into().unwrap());
+    bytes[file_size..][..2].copy_from_slice(&0xffff_u16.to_le_bytes());
+
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+    assert!(squashfs.lookup("/c").unwrap().is_some());
+    let fs = FilesystemReader::from_reader(Cursor::new(bytes.clone())).unwrap();
+    let paths: Vec<_> = fs.files().map(|node| node.fullpath.to_str().unwrap()).collect();
+    assert_eq!(paths, ["/", "/a", "/b", "/c"]);
+
+    // one byte short, the truncated directory header is not read
+    bytes[file_size..][..2].copy_from_slice(&(original - 1).to_le_bytes());
+    let fs = FilesystemReader::from_reader(Cursor::new(bytes)).unwrap();
+    let paths: Vec<_> = fs.files().map(|node| node.fullpath.to_str().unwrap()).collect();
+    assert_eq!(paths, ["/"]);
+}
+
+#[test]
+#[cfg(all(feature = "xz", feature = "trace-compression"))]
+fn test_raw_compression_trace() {
+    use std::sync::{Arc, Mutex};
+
+    use backhand::{FilesystemReader, InnerNode};
+
+    let trace = Arc::new(Mutex::new(vec![]));
+    let mut fs = FilesystemWriter::default();
+    fs.set_compression_trace(trace.clone());
+    let data: Vec<u8> = (0..DEFAULT_BLOCK_SIZE as usize * 3 + 100).map(|i| (i % 7) as u8).collect();
+    fs.push_file(Cursor::new(data), "big", NodeHeader::default()).unwrap();
+    fs.push_file(Cursor::new(b"small".to_vec()), "small", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+
+    let fs = FilesystemReader::from_reader(Cursor::new(output.into_inner())).unwrap();
+    let data_blocks: usize = fs
+        .files()
+        .filter_map(|node| match &node.inner {
+            InnerNode::File(file) => Some(file.block_sizes().len()),
+            _ => None,
+        })
+        .sum();
+    let fragments = fs.fragments.as_ref().map_or(0, |fragments| fragments.len());
+    let trace = trace.lock().unwrap();
+    assert_eq!(trace.len(), data_blocks + fragments);
+    assert!(trace.iter().all(|event| event.compressor == Compressor::Xz));
+    assert_eq!(trace[0].input_len, DEFAULT_BLOCK_SIZE as usize);
+    assert!(trace[0].output_len < trace[0].input_len);
+    assert!(!trace[0].stored_uncompressed);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_read_plan() {
+    use backhand::FilesystemReader;
+
+    let mut fs = FilesystemWriter::default();
+    let data: Vec<u8> = (0..DEFAULT_BLOCK_SIZE as usize * 2 + 100).map(|i| (i % 7) as u8).collect();
+    fs.push_file(Cursor::new(data), "big", NodeHeader::default()).unwrap();
+    fs.push_file(Cursor::new(b"small".to_vec()), "small", NodeHeader::default()).unwrap();
+    fs.push_dir("dir", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    let mut bytes = output.into_inner();
+
+    let fs = FilesystemReader::from_reader(Cursor::new(bytes.clone())).unwrap();
+    let plan = fs.read_plan().unwrap();
+    let paths: Vec<_> = plan.iter().map(|(path, _)| path.to_str().unwrap()).collect();
+    assert_eq!(paths, ["/big", "/small"]);
+    assert_eq!(plan[0].1.len(), 3);
+    assert!(plan[1].1[0].fragment);
+    for (_, locations) in &plan {
+        assert!(locations.iter().all(|location| location.end() <= superblock.bytes_used));
+    }
+
+    // move the fragment block past the end of the image, in the uncompressed fragment table
+    let frag_table = superblock.frag_table as usize;
+    let metadata = u64::from_le_bytes(bytes[frag_table..][..8].try_into().unwrap()) as usize;
+    bytes[metadata + 2..][..8].copy_from_slice(&0x10_0000_u64.to_le_bytes());
+    let fs = FilesystemReader::from_reader(Cursor::new(bytes)).unwrap();
+    let plan = fs.read_plan().unwrap();
+    let out_of_range: Vec<_> = plan
+        .iter()
+        .filter(|(_, locations)| {
+            locations.iter().any(|location| location.end() > superblock.bytes_used)
+        })
+        .map(|(path, _)| path.to_str().unwrap())
+        .collect();
+    assert_eq!(out_of_range, ["/small"]);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_multi_block_lookup_tables() {
+    use std::io::Read;
+
+    use backhand::{FilesystemReader, InnerNode};
+
+    // each fragment block holds one file, 16 byte fragment entries span 3 metadata blocks
+    const FILES: u32 = 1100;
+    let mut fs = FilesystemWriter::default();
+    fs.set_block_size(0x1000);
+    for i in 0..FILES {
+        let data: Vec<u8> = (0..4000).map(|n| (n as u32 ^ i) as u8).collect();
+        // 4 byte ids span 2 metadata blocks
+        let header = NodeHeader { permissions: 0o644, uid: i, gid: FILES + i, mtime: 0 };
+        fs.push_file(Cursor::new(data), format!("{i:04}"), header).unwrap();
+    }
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    assert_eq!(superblock.frag_count, FILES);
+    assert_eq!(u32::from(superblock.id_count), FILES * 2);
+
+    let fs = FilesystemReader::from_reader(Cursor::new(output.into_inner())).unwrap();
+    assert_eq!(fs.fragments.as_ref().unwrap().len(), FILES as usize);
+    assert_eq!(fs.id_table.len(), FILES as usize * 2);
+    // the last files have their fragments in the last metadata block
+    for i in [0, FILES - 1] {
+        let node = fs.files().find(|node| node.fullpath.ends_with(format!("{i:04}"))).unwrap();
+        assert_eq!((node.header.uid, node.header.gid), (i, FILES + i));
+        let InnerNode::File(file) = &node.inner else { panic!() };
+        let mut data = vec![];
+        fs.file(file).reader().read_to_end(&mut data).unwrap();
+        let expected: Vec<u8> = (0..4000).map(|n| (n as u32 ^ i) as u8).collect();
+        assert_eq!(data, expected);
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_extract_limit() {
+    use std::io::Read;
+
+    use backhand::{BackhandError, FilesystemReader, InnerNode};
+
+    let mut fs = FilesystemWriter::default();
+    fs.push_file(Cursor::new(vec![0xff; 0x30000]), "a", NodeHeader::default()).unwrap();
+    fs.push_file(Cursor::new(vec![0xee; 0x30000]), "b", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let image = output.into_inner();
+
+    // total of 0x60000 bytes, limit only allows the first file
+    let mut fs = FilesystemReader::from_reader(Cursor::new(image.clone())).unwrap();
+    fs.set_extract_limit(0x40000);
+    let mut results = vec![];
+    for node in fs.files() {
+        let InnerNode::File(file) = &node.inner else { continue };
+        let mut data = vec![];
+        results.push(fs.file(file).reader().read_to_end(&mut data).map(|_| data.len()));
+    }
+    assert_eq!(results[0].as_ref().unwrap(), &0x30000);
+    let err = results[1].as_ref().unwrap_err();
+    assert_eq!(err.to_string(), BackhandError::ExtractLimitExceeded(0x40000).to_string());
+    assert!(fs.extracted_bytes() > 0x40000);
+
+    // a limit of the exact total is not exceeded
+    let mut fs = FilesystemReader::from_reader(Cursor::new(image)).unwrap();
+    fs.set_extract_limit(0x60000);
+    for node in fs.files() {
+        let InnerNode::File(file) = &node.inner else { continue };
+        let mut data = vec![];
+        fs.file(file).reader().read_to_end(&mut data).unwrap();
+    }
+    assert_eq!(fs.extracted_bytes(), 0x60000);
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn test_raw_compression_options_raw() {
+    use std::io::Read;
+
+    use backhand::compression::{CompressionOptions, DefaultCompressor};
+    use backhand::kind::Kind;
+    use backhand::{BackhandError, FilesystemReader, Flags, InnerNode, Squashfs};
+
+    // gzip options are 8 bytes, these have 4 more that aren't modeled
+    const RAW: [u8; 12] = [9, 0, 0, 0, 15, 0, 1, 0, 0xaa, 0xbb, 0xcc, 0xdd];
+
+    /// Gzip, writing the raw options as an uncompressed metadata block
+    #[derive(Copy, Clone)]
+    struct RawOptions;
+
+    impl CompressionAction for RawOptions {
+        fn decompress(
+            &self,
+            bytes: &[u8],
+            out: &mut Vec<u8>,
+            compressor: Compressor,
+        ) -> Result<(), BackhandError> {
+            DefaultCompressor.decompress(bytes, out, compressor)
+        }
+
+        fn compress(
+            &self,
+            bytes: &[u8],
+            fc: FilesystemCompressor,
+            block_size: u32,
+        ) -> Result<Vec<u8>, BackhandError> {
+            DefaultCompressor.compress(bytes, fc, block_size)
+        }
+
+        fn compression_options(
+            &self,
+            superblock: &mut SuperBlock,
+            _: &Kind,
+            _: FilesystemCompressor,
+        ) -> Result<Vec<u8>, BackhandError> {
+            superblock.flags |= Flags::CompressorOptionsArePresent as u16;
+            Ok([&(RAW.len() as u16 | 0x8000).to_le_bytes()[..], &RAW].concat())
+        }
+    }
+
+    let options = CompressionOptions::Raw(RAW.to_vec());
+    let result = FilesystemCompressor::new(Compressor::Gzip, Some(options.clone()));
+    assert!(matches!(result, Err(BackhandError::InvalidCompressionOption)));
+
+    // a full block, copied as is, and a fragment
+    let data = vec![0xaa; DEFAULT_BLOCK_SIZE as usize + 5];
+    let mut fs = FilesystemWriter::default();
+    fs.set_kind(Kind::new(&RawOptions));
+    fs.set_compressor(FilesystemCompressor::new(Compressor::Gzip, None).unwrap());
+    fs.push_file(Cursor::new(data.clone()), "file", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+    assert_eq!(squashfs.compression_options, Some(options.clone()));
+
+    // the raw options aren't written when rewriting the image, the data blocks are copied as is
+    let reader = FilesystemReader::from_reader(Cursor::new(bytes.clone())).unwrap();
+    assert_eq!(reader.compression_options, Some(options));
+    let mut fs = FilesystemWriter::from_fs_reader(&reader).unwrap();
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    assert!(!superblock.compressor_options_are_present());
+    let rewritten = output.into_inner();
+
+    let block = |reader: &FilesystemReader, bytes: &[u8]| {
+        let node = reader.files().find(|node| node.fullpath.ends_with("file")).unwrap();
+        let InnerNode::File(file) = &node.inner else { panic!() };
+        let mut data = vec![];
+        reader.file(file).reader().read_to_end(&mut data).unwrap();
+        let location = reader.file(file).block_locations().unwrap()[0];
+        (data, bytes[location.start as usize..][..location.len as usize].to_vec())
+    };
+    let written = FilesystemReader::from_reader(Cursor::new(rewritten.clone())).unwrap();
+    assert_eq!(written.compression_options, None);
+    let (read, written_block) = block(&written, &rewritten);
+    assert_eq!(read, data);
+    assert_eq!(written_block, block(&reader, &bytes).1);
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn test_raw_table_pointers() {
+    use backhand::Squashfs;
+
+    // 16 byte fragment entries, 512 per metadata block
+    const FRAGMENTS: u32 = 520;
+    // 4 byte ids, 2048 per metadata block
+    const IDS: u32 = 2100;
+    let mut fs = FilesystemWriter::default();
+    fs.set_compressor(FilesystemCompressor::new(Compressor::Gzip, None).unwrap());
+    fs.set_block_size(0x1000);
+    for i in 0..IDS {
+        // each fragment block holds one file
+        let data: Vec<u8> =
+            if i < FRAGMENTS { (0..3000).map(|n| (n as u32 ^ i) as u8).collect() } else { vec![] };
+        let header = NodeHeader { permissions: 0o644, uid: i, gid: 0, mtime: 0 };
+        fs.push_file(Cursor::new(data), format!("{i:04}"), header).unwrap();
+    }
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    assert_eq!(superblock.frag_count, FRAGMENTS);
+    assert_eq!(u32::from(superblock.id_count), IDS);
+
+    let squashfs = Squashfs::from_reader(Cursor::new(output.into_inner())).unwrap();
+    let pointers = squashfs.table_pointers();
+    assert_eq!(pointers.fragment.len(), 2);
+    assert_eq!(pointers.id.len(), 2);
+    assert!(pointers.export.is_empty());
+    // the metadata blocks are written before the pointers to them
+    assert!(pointers.fragment[0] < pointers.fragment[1]);
+    assert!(pointers.fragment[1] < superblock.frag_table);
+    assert!(pointers.id[1] < superblock.id_table);
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn test_raw_fit_into() {
+    use std::io::Read;
+
+    use backhand::{BackhandError, FilesystemReader, InnerNode};
+
+    // incompressible 16KiB, repeated: only compresses with blocks holding more than one repeat
+    let mut state = 1_u32;
+    let chunk: Vec<u8> = (0..0x4000)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect();
+    let data = chunk.repeat(16);
+    let new_fs = || {
+        let mut fs = FilesystemWriter::default();
+        fs.set_compressor(FilesystemCompressor::new(Compressor::Gzip, None).unwrap());
+        fs.set_block_size(0x1000);
+        fs.push_file(Cursor::new(data.clone()), "file", NodeHeader::default()).unwrap();
+        fs
+    };
+
+    let mut output = Cursor::new(vec![]);
+    let (superblock, bytes_written) = new_fs().fit_into(&mut output, 0x3_0000).unwrap();
+    assert!(bytes_written <= 0x3_0000);
+    assert_eq!(superblock.block_size, 0x8000);
+    let fs = FilesystemReader::from_reader(Cursor::new(output.into_inner())).unwrap();
+    let node = fs.files().find(|node| node.fullpath.ends_with("file")).unwrap();
+    let InnerNode::File(file) = &node.inner else { panic!() };
+    let mut read = vec![];
+    fs.file(file).reader().read_to_end(&mut read).unwrap();
+    assert_eq!(read, data);
+
+    // the first block size fits
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = new_fs().fit_into(&mut output, 0x10_0000).unwrap();
+    assert_eq!(superblock.block_size, 0x1000);
+
+    let mut output = Cursor::new(vec![]);
+    let result = new_fs().fit_into(&mut output, 0x1000);
+    assert!(matches!(result, Err(BackhandError::ImageTooLarge(0x1000))));
+    assert!(output.into_inner().is_empty());
+
+    // on error, the writer is unchanged and files read from an image are still copied as is
+    let mut source = Cursor::new(vec![]);
+    new_fs().write(&mut source).unwrap();
+    let source = source.into_inner();
+    let reader = FilesystemReader::from_reader(Cursor::new(source.clone())).unwrap();
+    let mut fs = FilesystemWriter::from_fs_reader(&reader).unwrap();
+    fs.push_file(Cursor::new(b"pushed".to_vec()), "pushed", NodeHeader::default()).unwrap();
+    let result = fs.fit_into(Cursor::new(vec![]), 0x1000);
+    assert!(matches!(result, Err(BackhandError::ImageTooLarge(0x1000))));
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    assert_eq!(superblock.block_size, 0x1000);
+    assert_eq!(superblock.compressor, Compressor::Gzip);
+    let output = output.into_inner();
+    let fs = FilesystemReader::from_reader(Cursor::new(output.clone())).unwrap();
+    let blocks = |fs: &FilesystemReader, bytes: &[u8]| {
+        let node = fs.files().find(|node| node.fullpath.ends_with("file")).unwrap();
+        let InnerNode::File(file) = &node.inner else { panic!() };
+        let location = fs.file(file).block_locations().unwrap()[0];
+        bytes[location.start as usize..][..location.len as usize].to_vec()
+    };
+    assert_eq!(blocks(&fs, &output), blocks(&reader, &source));
+    let node = fs.files().find(|node| node.fullpath.ends_with("pushed")).unwrap();
+    let InnerNode::File(file) = &node.inner else { panic!() };
+    let mut read = vec![];
+    fs.file(file).reader().read_to_end(&mut read).unwrap();
+    assert_eq!(read, b"pushed");
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_fit_into_compressor() {
+    use backhand::FilesystemReader;
+
+    // doesn't fit uncompressed with any block size
+    let mut fs = FilesystemWriter::default();
+    fs.set_compressor(FilesystemCompressor::new(Compressor::None, None).unwrap());
+    fs.push_file(Cursor::new(vec![0; 0x10_0000]), "zeros", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    let (superblock, bytes_written) = fs.fit_into(&mut output, 0x1_0000).unwrap();
+    assert!(bytes_written <= 0x1_0000);
+    assert_eq!(superblock.compressor, Compressor::Xz);
+    assert_eq!(superblock.block_size, DEFAULT_BLOCK_SIZE);
+
+    let fs = FilesystemReader::from_reader(Cursor::new(output.into_inner())).unwrap();
+    assert_eq!(fs.compressor, Compressor::Xz);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_export_table() {
+    use backhand::FilesystemReader;
+
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("dir", NodeHeader::default()).unwrap();
+    fs.push_file(Cursor::new(b"fragment".to_vec()), "dir/file", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    let mut bytes = output.into_inner();
+
+    let fs = FilesystemReader::from_reader(Cursor::new(bytes.clone())).unwrap();
+    assert!(!fs.is_exportable());
+    assert_eq!(fs.export_entry_count(), 0);
+
+    // append an uncompressed export table metadata block with a ref for each inode, and the
+    // pointer to it
+    bytes.truncate(superblock.bytes_used as usize);
+    let block_start = bytes.len() as u64;
+    let len = superblock.inode_count as u16 * 8;
+    bytes.extend_from_slice(&(len | 0x8000).to_le_bytes());
+    bytes.resize(bytes.len() + len as usize, 0);
+    let export_table = bytes.len() as u64;
+    bytes.extend_from_slice(&block_start.to_le_bytes());
+    let flags = superblock.flags | 0x80;
+    bytes[24..26].copy_from_slice(&flags.to_le_bytes());
+    let bytes_used = bytes.len() as u64;
+    bytes[40..48].copy_from_slice(&bytes_used.to_le_bytes());
+    bytes[88..96].copy_from_slice(&export_table.to_le_bytes());
+
+    let fs = FilesystemReader::from_reader(Cursor::new(bytes)).unwrap();
+    assert!(fs.is_exportable());
+    assert_eq!(fs.export_entry_count(), 3);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_name_edges() {
+    use std::ffi::OsStr;
+    use std::io::Read;
+    use std::os::unix::ffi::OsStrExt;
+
+    use backhand::{BackhandError, FilesystemReader, InnerNode};
+
+    // name_size is stored as length minus one, 256 is the longest name
+    let long = "n".repeat(256);
+    let non_utf8 = OsStr::from_bytes(b"caf\xe9\xff");
+    let names = [OsStr::new(&long), non_utf8, OsStr::new("a")];
+
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir(&long, NodeHeader::default()).unwrap();
+    for name in names {
+        let path = std::path::Path::new(&long).join(name);
+        fs.push_file(Cursor::new(name.as_bytes().to_vec()), path, NodeHeader::default()).unwrap();
+    }
+    // one byte too long
+    let too_long = "n".repeat(257);
+    assert!(matches!(
+        fs.push_file(Cursor::new(vec![]), &too_long, NodeHeader::default()),
+        Err(BackhandError::InvalidFilePath)
+    ));
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+
+    let check = |image: Vec<u8>| {
+        let fs = FilesystemReader::from_reader(Cursor::new(image)).unwrap();
+        for name in names {
+            let path = std::path::Path::new("/").join(&long).join(name);
+            let node = fs.files().find(|node| node.fullpath == path).unwrap();
+            assert_eq!(node.fullpath.file_name().unwrap().len(), name.len());
+            let InnerNode::File(file) = &node.inner else { panic!() };
+            let mut data = vec![];
+            fs.file(file).reader().read_to_end(&mut data).unwrap();
+            assert_eq!(data, name.as_bytes());
+        }
+    };
+    let image = output.into_inner();
+    check(image.clone());
+
+    // read -> write -> read
+    let reader = FilesystemReader::from_reader(Cursor::new(image)).unwrap();
+    let mut fs = FilesystemWriter::from_fs_reader(&reader).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    check(output.into_inner());
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_non_utf8_name_bytes() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    use backhand::{FilesystemReader, MetadataComp};
+
+    let name = OsStr::from_bytes(b"\xff\xfename\x80");
+    let mut fs = FilesystemWriter::default();
+    fs.set_metadata_compression(MetadataComp { dirs: false, ..MetadataComp::default() });
+    fs.push_file(Cursor::new(b"data".to_vec()), name, NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    let image = output.into_inner();
+
+    // the name bytes are in the uncompressed directory table as is
+    let dir_table = &image[superblock.dir_table as usize..];
+    assert!(dir_table.windows(name.len()).any(|window| window == name.as_bytes()));
+
+    let fs = FilesystemReader::from_reader(Cursor::new(image)).unwrap();
+    let node = fs.files().find(|node| node.fullpath != std::path::Path::new("/")).unwrap();
+    assert_eq!(node.fullpath.file_name().unwrap().as_bytes(), name.as_bytes());
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_extract_all_to() {
+    use std::num::NonZeroUsize;
+    use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+
+    use backhand::{BackhandError, ExtractOptions, FilesystemReader};
+
+    let header = NodeHeader { permissions: 0o755, uid: 0, gid: 0, mtime: 1_000_000 };
+    let file_header = NodeHeader { permissions: 0o640, ..header };
+    let big: Vec<u8> = (0..DEFAULT_BLOCK_SIZE as usize * 2 + 10).map(|i| (i % 251) as u8).collect();
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir_all("usr/bin", header).unwrap();
+    fs.push_dir("empty", NodeHeader { permissions: 0o700, ..header }).unwrap();
+    for i in 0..20 {
+        let data = format!("file {i}").into_bytes();
+        fs.push_file(Cursor::new(data), format!("usr/bin/{i}"), file_header).unwrap();
+    }
+    fs.push_file(Cursor::new(big.clone()), "big", file_header).unwrap();
+    fs.push_symlink("usr/bin/0", "link", header).unwrap();
+    fs.push_fifo("fifo", file_header).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let fs = FilesystemReader::from_reader(Cursor::new(output.into_inner())).unwrap();
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let dest = tmp_dir.path().join("squashfs-root");
+    let options = ExtractOptions { threads: NonZeroUsize::new(4), ..ExtractOptions::default() };
+    fs.extract_all_to(&dest, options).unwrap();
+
+    for i in 0..20 {
+        let data = std::fs::read(dest.join(format!("usr/bin/{i}"))).unwrap();
+        assert_eq!(data, format!("file {i}").as_bytes());
+    }
+    assert_eq!(std::fs::read(dest.join("big")).unwrap(), big);
+    assert_eq!(std::fs::read_link(dest.join("link")).unwrap().to_str(), Some("usr/bin/0"));
+    assert!(std::fs::metadata(dest.join("fifo")).unwrap().file_type().is_fifo());
+    let metadata = std::fs::metadata(dest.join("big")).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+    assert_eq!(metadata.mtime(), 1_000_000);
+    let metadata = std::fs::metadata(dest.join("empty")).unwrap();
+    assert!(metadata.is_dir());
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o700);
+    assert_eq!(metadata.mtime(), 1_000_000);
+
+    // existing files are an error, unless forced
+    let err = fs.extract_all_to(&dest, ExtractOptions::default()).unwrap_err();
+    let BackhandError::StdIo(err) = err else { panic!("{err}") };
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    std::fs::write(dest.join("big"), b"changed").unwrap();
+    let options = ExtractOptions { force: true, no_preserve: true, ..ExtractOptions::default() };
+    fs.extract_all_to(&dest, options).unwrap();
+    assert_eq!(std::fs::read(dest.join("big")).unwrap(), big);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_write_stats() {
+    use backhand::{SectionLayout, WriteStats};
+
+    let mut state = 0x2545_f491_u32;
+    let random: Vec<u8> = (0..DEFAULT_BLOCK_SIZE)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+    let new_fs = || {
+        let mut fs = FilesystemWriter::default();
+        let zeros = vec![0; DEFAULT_BLOCK_SIZE as usize];
+        fs.push_file(Cursor::new(zeros), "zeros", NodeHeader::default()).unwrap();
+        fs.push_file(Cursor::new(random.clone()), "random", NodeHeader::default()).unwrap();
+        fs.push_file(Cursor::new(b"small".to_vec()), "small", NodeHeader::default()).unwrap();
+        fs
+    };
+
+    let mut output = Cursor::new(vec![]);
+    let (_, _, stats) = new_fs().write_with_stats(&mut output).unwrap();
+    // the random block and the fragment holding "small" don't compress
+    assert_eq!(stats.data_blocks_compressed, 1);
+    assert_eq!(stats.data_blocks_uncompressed, 2);
+    // inode, dir, fragment and id tables
+    assert_eq!(stats.metadata_blocks_compressed + stats.metadata_blocks_uncompressed, 4);
+    // data, fragment, inode and dir blocks, the lookup tables are uncompressed by default
+    assert_eq!(stats.compressor_calls, 5);
+    assert!(stats.compressor_input_bytes >= 2 * u64::from(DEFAULT_BLOCK_SIZE));
+    let ratio = stats.compression_ratio().unwrap();
+    assert!(ratio > 0.0 && ratio < 1.0, "{ratio}");
+    assert_eq!(WriteStats::default().compression_ratio(), None);
+
+    // the same image as with write
+    let mut expected = Cursor::new(vec![]);
+    new_fs().write(&mut expected).unwrap();
+    assert_eq!(output.into_inner(), expected.into_inner());
+
+    // tables written to find their length aren't counted
+    let mut fs = new_fs();
+    fs.set_section_layout(SectionLayout::MetadataFirst);
+    let (_, _, stats) = fs.write_with_stats(Cursor::new(vec![])).unwrap();
+    assert_eq!(stats.data_blocks_compressed, 1);
+    assert_eq!(stats.metadata_blocks_compressed + stats.metadata_blocks_uncompressed, 4);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_inode_at() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    use backhand::{BackhandError, FilesystemReader, InodeRef, Sidecar};
+    use deku::DekuContainerRead;
+
+    // enough inodes for multiple inode table blocks, with some inodes continuing into the next
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("dir", NodeHeader::default()).unwrap();
+    for i in 0..1000 {
+        let data = vec![i as u8; 0x100 + i];
+        fs.push_file(Cursor::new(data), format!("dir/{i:04}"), NodeHeader::default()).unwrap();
+    }
+    let mut output = Cursor::new(vec![]);
+    let mut sidecar = vec![];
+    let (superblock, _) = fs.write_with_sidecar(&mut output, &mut sidecar).unwrap();
+    let (_, index) = Sidecar::from_bytes((&sidecar, 0)).unwrap();
+
+    let kind = kind::Kind::from_const(kind::LE_V4_0).unwrap().with_retained_inodes(true);
+    let fs = FilesystemReader::from_reader_with_offset_and_kind(
+        Cursor::new(output.into_inner()),
+        0,
+        kind,
+    )
+    .unwrap();
+    assert!(index.entries.iter().any(|entry| InodeRef::decode(entry.inode).0 != 0));
+    for (entry, node) in index.entries.iter().zip(fs.files()) {
+        assert_eq!(node.fullpath.as_os_str(), OsStr::from_bytes(&entry.path));
+        let inode = fs.inode_at(entry.inode).unwrap();
+        assert_eq!(Some(&inode), fs.inode(node));
+    }
+    assert_eq!(fs.inode_at(superblock.root_inode).unwrap().header.inode_number, 1);
+
+    // the block start is past the inode table
+    let past = InodeRef::encode((superblock.dir_table - superblock.inode_table) as u32, 0);
+    assert!(matches!(fs.inode_at(past), Err(BackhandError::CorruptedOrInvalidSquashfs)));
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_node_header_from_metadata() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use backhand::FilesystemReader;
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("file");
+    let file = File::create(&path).unwrap();
+    file.set_modified(UNIX_EPOCH + Duration::from_secs(0x634f_5237)).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o1751)).unwrap();
+    let metadata = std::fs::metadata(&path).unwrap();
+
+    let header = NodeHeader::from_metadata(&metadata);
+    assert_eq!(header, NodeHeader::new(0o1751, metadata.uid(), metadata.gid(), 0x634f_5237));
+
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("dir", NodeHeader::for_dir()).unwrap();
+    fs.push_file(Cursor::new(vec![]), "dir/file", NodeHeader::for_file()).unwrap();
+    fs.push_file(File::open(&path).unwrap(), "dir/host", header).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+
+    let fs = FilesystemReader::from_reader(Cursor::new(output.into_inner())).unwrap();
+    let headers: Vec<_> = fs.files().skip(1).map(|node| node.header).collect();
+    assert_eq!(headers, [NodeHeader::for_dir(), NodeHeader::for_file(), header]);
+    assert_eq!(headers[0].permissions, 0o755);
+    assert_eq!(headers[1].permissions, 0o644);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_push_with_metadata() {
+    use std::io::Read;
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::net::UnixListener;
+    use std::path::{Path, PathBuf};
+
+    use backhand::{FilesystemReader, InnerNode};
+    use nix::sys::stat::Mode;
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let host = tmp_dir.path();
+    std::fs::create_dir(host.join("dir")).unwrap();
+    std::fs::write(host.join("dir/file"), b"host file").unwrap();
+    std::os::unix::fs::symlink("dir/file", host.join("link")).unwrap();
+    nix::unistd::mkfifo(&host.join("fifo"), Mode::from_bits_truncate(0o640)).unwrap();
+    let _socket = UnixListener::bind(host.join("socket")).unwrap();
+
+    let mut fs = FilesystemWriter::default();
+    for name in ["dir", "dir/file", "link", "fifo", "socket"] {
+        let metadata = std::fs::symlink_metadata(host.join(name)).unwrap();
+        fs.push_with_metadata(name, host.join(name), &metadata).unwrap();
+    }
+    // following the symlink
+    let metadata = std::fs::metadata(host.join("link")).unwrap();
+    fs.push_with_metadata("followed", host.join("link"), &metadata).unwrap();
+    let null = std::fs::symlink_metadata("/dev/null").unwrap();
+    fs.push_with_metadata("null", "/dev/null", &null).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+
+    let fs = FilesystemReader::from_reader(Cursor::new(output.into_inner())).unwrap();
+    let nodes: Vec<_> = fs
+        .files()
+        .map(|node| {
+            let inner = match &node.inner {
+                InnerNode::File(file) => {
+                    let mut bytes = vec![];
+                    fs.file(file).reader().read_to_end(&mut bytes).unwrap();
+                    format!("file {}", String::from_utf8(bytes).unwrap())
+                }
+                InnerNode::Symlink(symlink) => format!("symlink {}", symlink.link.display()),
+                InnerNode::Dir(_) => "dir".to_string(),
+                InnerNode::CharacterDevice(dev) => format!("char {}:{}", dev.major(), dev.minor()),
+                InnerNode::BlockDevice(dev) => format!("block {:#x}", dev.device_number),
+                InnerNode::NamedPipe => "fifo".to_string(),
+                InnerNode::Socket => "socket".to_string(),
+            };
+            (node.fullpath.clone(), inner)
+        })
+        .collect();
+    let expected: Vec<(PathBuf, String)> = vec![
+        ("/".into(), "dir".into()),
+        ("/dir".into(), "dir".into()),
+        ("/dir/file".into(), "file host file".into()),
+        ("/fifo".into(), "fifo".into()),
+        ("/followed".into(), "file host file".into()),
+        ("/link".into(), "symlink dir/file".into()),
+        (
+            "/null".into(),
+            format!("char {}:{}", nix::libc::major(null.rdev()), nix::libc::minor(null.rdev())),
+        ),
+        ("/socket".into(), "socket".into()),
+    ];
+    assert_eq!(nodes, expected);
+
+    let fifo = fs.files().find(|node| node.fullpath == Path::new("/fifo")).unwrap();
+    let metadata = std::fs::symlink_metadata(host.join("fifo")).unwrap();
+    assert_eq!(fifo.header, NodeHeader::from_metadata(&metadata));
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_zero_fragments() {
+    use std::io::Read;
+
+    use backhand::{BackhandError, InnerNode, Squashfs};
+
+    let full: Vec<u8> = (0..DEFAULT_BLOCK_SIZE * 2).map(|i| (i % 251) as u8).collect();
+
+    // only full blocks, with the fragment table pointer set and frag_count changed to 0
+    let mut fs = FilesystemWriter::default();
+    fs.push_file(Cursor::new(full.clone()), "full", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    let (superblock, _) = fs.write(&mut output).unwrap();
+    let mut bytes = output.into_inner();
+    assert_ne!(superblock.frag_table, u64::MAX);
+    bytes[16..20].copy_from_slice(&0_u32.to_le_bytes());
+
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes)).unwrap();
+    assert_eq!(squashfs.fragments, None);
+    assert!(squashfs.table_pointers().fragment.is_empty());
+    let fs = squashfs.into_filesystem_reader().unwrap();
+    assert_eq!(fs.fragments, None);
+    assert!(fs.fragment_usage().is_empty());
+    for node in fs.files() {
+        if let InnerNode::File(file) = &node.inner {
+            let reader_file = fs.file(file);
+            assert!(reader_file.fragment().is_none());
+            let locations = reader_file.block_locations().unwrap();
+            assert!(locations.iter().all(|location| !location.fragment));
+            let mut read = vec![];
+            reader_file.reader().read_to_end(&mut read).unwrap();
+            assert_eq!(read.len(), file.file_len());
+        }
+    }
+
+    // a file with its tail-end in a fragment, with frag_count changed to 0
+    let mut fs = FilesystemWriter::default();
+    fs.push_file(Cursor::new(full[..0x100].to_vec()), "tail", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let mut bytes = output.into_inner();
+    bytes[16..20].copy_from_slice(&0_u32.to_le_bytes());
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes)).unwrap();
+    assert_eq!(squashfs.fragments, None);
+    assert!(matches!(
+        squashfs.into_filesystem_reader(),
+        Err(BackhandError::CorruptedOrInvalidSquashfs)
+    ));
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_fragment_out_of_bounds() {
+    use std::io::{ErrorKind, Read};
+    use std::path::Path;
+
+    use backhand::{BackhandError, DataSize, FilesystemReader, InnerNode};
+
+    let data: Vec<u8> = (0..0x100).map(|i| i as u8).collect();
+    let mut fs = FilesystemWriter::default();
+    fs.push_file(Cursor::new(data), "tail", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+
+    let read_tail = |fs: &FilesystemReader| {
+        let node = fs.files().find(|node| node.fullpath == Path::new("/tail")).unwrap();
+        let InnerNode::File(file) = &node.inner else { panic!("not a file") };
+        let mut read = vec![];
+        fs.file(file).reader().read_to_end(&mut read)
+    };
+
+    // frag_index past the fragment table
+    let mut fs = FilesystemReader::from_reader(Cursor::new(bytes.clone())).unwrap();
+    fs.fragments.as_mut().unwrap().clear();
+    assert_eq!(read_tail(&fs).unwrap_err().kind(), ErrorKind::InvalidData);
+    let node = fs.files().find(|node| node.fullpath == Path::new("/tail")).unwrap();
+    let InnerNode::File(file) = &node.inner else { panic!("not a file") };
+    assert!(matches!(
+        fs.file(file).block_locations(),
+        Err(BackhandError::CorruptedOrInvalidSquashfs)
+    ));
+
+    // block_offset and tail-end past the end of the fragment block
+    let mut fs = FilesystemReader::from_reader(Cursor::new(bytes)).unwrap();
+    fs.fragments.as_mut().unwrap()[0].size = DataSize::new_uncompressed(0x10);
+    assert_eq!(read_tail(&fs).unwrap_err().kind(), ErrorKind::InvalidData);
+    // also when reading the cached fragment block again
+    assert_eq!(read_tail(&fs).unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+#[cfg(all(unix, feature = "xz"))]
+fn test_raw_symlink_target_not_utf8() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    use backhand::{InodeInner, Squashfs};
+
+    let target = OsStr::from_bytes(b"target\xff");
+    let mut fs = FilesystemWriter::default();
+    fs.push_symlink(target, "link", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+
+    let squashfs = Squashfs::from_reader(Cursor::new(output.into_inner())).unwrap();
+    let symlink = squashfs
+        .inodes
+        .values()
+        .find_map(|inode| match &inode.inner {
+            InodeInner::BasicSymlink(symlink) => Some(symlink),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(symlink.target_path, b"target\xff");
+    assert_eq!(symlink.target(), "target\u{fffd}");
+    assert!(format!("{symlink:?}").contains("target\u{fffd}"));
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_device_major_minor() {
+    use backhand::{
+        device_number_from_major_minor, major_minor_from_device_number, FilesystemReader, InnerNode,
+    };
+
+    assert_eq!(device_number_from_major_minor(1, 3), 0x0103);
+    assert_eq!(device_number_from_major_minor(0xfff, 0xf_ffff), 0xffff_ffff);
+    assert_eq!(major_minor_from_device_number(0xffff_ffff), (0xfff, 0xf_ffff));
+    // bits that don't fit are dropped
+    assert_eq!(device_number_from_major_minor(0x1001, 0x10_0002), 0x0102);
+
+    let mut fs = FilesystemWriter::default();
+    let char_device = device_number_from_major_minor(0x123, 0x4_5678);
+    let block_device = device_number_from_major_minor(259, 0x100);
+    fs.push_char_device(char_device, "char", NodeHeader::default()).unwrap();
+    fs.push_block_device(block_device, "block", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+
+    let fs = FilesystemReader::from_reader(Cursor::new(output.into_inner())).unwrap();
+    let mut devices = 0;
+    for node in fs.files() {
+        match &node.inner {
+            InnerNode::CharacterDevice(device) => {
+                assert_eq!(device.device_number, char_device);
+                assert_eq!((device.major(), device.minor()), (0x123, 0x4_5678));
+                devices += 1;
+            }
+            InnerNode::BlockDevice(device) => {
+                assert_eq!(device.device_number, block_device);
+                assert_eq!((device.major(), device.minor()), (259, 0x100));
+                devices += 1;
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(devices, 2);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_file_by_inode() {
+    use std::io::Read;
+    use std::path::Path;
+
+    use backhand::{FilesystemReader, InnerNode};
+
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("dir", NodeHeader::default()).unwrap();
+    fs.push_file(Cursor::new(b"first".to_vec()), "dir/first", NodeHeader::default()).unwrap();
+    fs.push_file(Cursor::new(b"second".to_vec()), "second", NodeHeader::default()).unwrap();
+    fs.push_symlink("second", "link", NodeHeader::default()).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+
+    let mut fs = FilesystemReader::from_reader(Cursor::new(output.into_inner())).unwrap();
+    let read = |fs: &FilesystemReader, path: &str| {
+        let node = fs.files().find(|node| node.fullpath == Path::new(path)).unwrap();
+        let file = fs.file_by_inode(node.inode_number().unwrap()).unwrap();
+        let mut bytes = vec![];
+        file.reader().read_to_end(&mut bytes).unwrap();
+        bytes
+    };
+    assert_eq!(read(&fs, "/dir/first"), b"first");
+    assert_eq!(read(&fs, "/second"), b"second");
+
+    for node in fs.files() {
+        let inode_number = node.inode_number().unwrap();
+        assert_eq!(fs.node_by_inode(inode_number).unwrap().fullpath, node.fullpath);
+        let is_file = matches!(node.inner, InnerNode::File(_));
+        assert_eq!(fs.file_by_inode(inode_number).is_some(), is_file);
+    }
+    assert!(fs.node_by_inode(0).is_none());
+    assert!(fs.node_by_inode(fs.files().count() as u32 + 1).is_none());
+
+    // still found after changing the order of the nodes
+    fs.root.nodes.reverse();
+    assert_eq!(read(&fs, "/dir/first"), b"first");
+    assert_eq!(read(&fs, "/second"), b"second");
+}
+
+/// Images like those of `mksquashfs -noI`, `-noD`, and `-noF`, with the uncompressed bit set in
+/// each block as well as the superblock flag
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_selectively_uncompressed() {
+    use std::io::Read;
+
+    use backhand::{FileCompression, FilesystemReader, InnerNode, MetadataComp, Squashfs};
+
+    const NO_D: u16 = 0b10;
+
+    let full: Vec<u8> = (0..DEFAULT_BLOCK_SIZE * 2 + 0x10).map(|i| (i % 7) as u8).collect();
+    let tail = vec![3u8; 0x100];
+
+    let write = |no_i: bool, no_d: bool, no_f: bool| {
+        let compression = if no_d { FileCompression::Store } else { FileCompression::Default };
+        let mut fs = FilesystemWriter::default();
+        fs.set_fragments_uncompressed(no_f);
+        if no_i {
+            fs.set_metadata_compression(MetadataComp {
+                inodes: false,
+                dirs: false,
+                ..MetadataComp::default()
+            });
+        }
+        let header = NodeHeader::default();
+        fs.push_file_with_compression(Cursor::new(full.clone()), "full", header, compression)
+            .unwrap();
+        fs.push_file(Cursor::new(tail.clone()), "tail", header).unwrap();
+        let mut output = Cursor::new(vec![]);
+        let (superblock, _) = fs.write(&mut output).unwrap();
+        let mut bytes = output.into_inner();
+        // the writer only stores the -noD flag per block, as mksquashfs does with the flag
+        if no_d {
+            let flags = superblock.flags | NO_D;
+            bytes[24..26].copy_from_slice(&flags.to_le_bytes());
+        }
+        (superblock, bytes)
+    };
+
+    let check = |bytes: Vec<u8>| {
+        let fs = FilesystemReader::from_reader(Cursor::new(bytes)).unwrap();
+        let mut files = 0;
+        for node in fs.files() {
+            let InnerNode::File(file) = &node.inner else { continue };
+            let mut read = vec![];
+            fs.file(file).reader().read_to_end(&mut read).unwrap();
+            let expected = if node.fullpath.ends_with("full") { &full } else { &tail };
+            assert_eq!(&read, expected);
+            files += 1;
+        }
+        assert_eq!(files, 2);
+        fs
+    };
+    let metadata_uncompressed = |bytes: &[u8], start: u64| {
+        let start = start as usize;
+        u16::from_le_bytes([bytes[start], bytes[start + 1]]) & 0x8000 != 0
+    };
+    let blocks_uncompressed = |fs: &FilesystemReader, fragment: bool| {
+        fs.files()
+            .filter_map(|node| match &node.inner {
+                InnerNode::File(file) => Some(fs.file(file).block_locations().unwrap()),
+                _ => None,
+            })
+            .flatten()
+            .filter(|location| location.fragment == fragment)
+            .all(|location| location.uncompressed)
+    };
+
+    // -noI
+    let (superblock, bytes) = write(true, false, false);
+    assert!(superblock.inodes_uncompressed());
+    assert!(metadata_uncompressed(&bytes, superblock.inode_table));
+    assert!(metadata_uncompressed(&bytes, superblock.dir_table));
+    let fs = check(bytes);
+    assert!(!blocks_uncompressed(&fs, false));
+
+    // -noD
+    let (superblock, bytes) = write(false, true, false);
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+    assert!(squashfs.superblock.data_block_stored_uncompressed());
+    assert!(!metadata_uncompressed(&bytes, superblock.inode_table));
+    let fs = check(bytes);
+    assert!(blocks_uncompressed(&fs, false));
+    assert!(!blocks_uncompressed(&fs, true));
+
+    // -noF
+    let (superblock, bytes) = write(false, false, true);
+    assert!(superblock.fragments_stored_uncompressed());
+    let fs = check(bytes);
+    assert!(!blocks_uncompressed(&fs, false));
+    assert!(blocks_uncompressed(&fs, true));
+
+    // with all of them, nothing is decompressed, so the compressor isn't needed
+    let (_, mut bytes) = write(true, true, true);
+    assert!(blocks_uncompressed(&check(bytes.clone()), false));
+    #[cfg(not(feature = "lzo"))]
+    {
+        let lzo = 3_u16;
+        bytes[20..22].copy_from_slice(&lzo.to_le_bytes());
+        // without compression options for lzo
+        let flags = u16::from_le_bytes([bytes[24], bytes[25]]) & !0x400;
+        bytes[24..26].copy_from_slice(&flags.to_le_bytes());
+        check(bytes);
+    }
+}
+
+#[test]
+#[cfg(all(unix, feature = "xz"))]
+fn test_raw_open_existing_write_back() {
+    use std::fs;
+    use std::io::Read;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    use backhand::{BackhandError, FilesystemReader, InnerNode, SquashfsFileWriter};
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let image = tmp_dir.path().join("base.squashfs");
+    let base: Vec<u8> = (0..DEFAULT_BLOCK_SIZE * 3 + 0x20).map(|i| (i % 13) as u8).collect();
+
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("etc", NodeHeader::default()).unwrap();
+    fs.push_file(Cursor::new(base.clone()), "etc/base", NodeHeader::default()).unwrap();
+    fs.write(fs::File::create(&image).unwrap()).unwrap();
+    fs::set_permissions(&image, fs::Permissions::from_mode(0o640)).unwrap();
+    let original = fs::read(&image).unwrap();
+
+    let blocks = |bytes: &[u8]| {
+        let fs = FilesystemReader::from_reader(Cursor::new(bytes.to_vec())).unwrap();
+        let node = fs.files().find(|node| node.fullpath == Path::new("/etc/base")).unwrap();
+        let InnerNode::File(file) = &node.inner else { panic!("not a file") };
+        fs.file(file)
+            .block_locations()
+            .unwrap()
+            .iter()
+            .filter(|location| !location.fragment)
+            .map(|location| {
+                let start = location.start as usize;
+                bytes[start..start + location.len as usize].to_vec()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // an existing file with the name of the new file is kept
+    let existing_temp = tmp_dir.path().join("base.squashfs.tmp");
+    fs::write(&existing_temp, b"keep").unwrap();
+
+    let mut fs = FilesystemWriter::open_existing(&image).unwrap();
+    fs.push_file(Cursor::new(b"overlay".to_vec()), "etc/overlay", NodeHeader::default()).unwrap();
+    // renaming over a dir that isn't empty fails, after writing
+    let dir = tmp_dir.path().join("dir");
+    fs::create_dir_all(dir.join("child")).unwrap();
+    assert!(fs.write_back(&dir).is_err());
+    // the files of the image can still be written, the pushed file was read
+    fs.replace_file("etc/overlay", Cursor::new(b"overlay".to_vec())).unwrap();
+    fs.write_back(&image).unwrap();
+    drop(fs);
+    assert_eq!(fs::read(&existing_temp).unwrap(), b"keep");
+    fs::remove_file(&existing_temp).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    let written = fs::read(&image).unwrap();
+    let fs = FilesystemReader::from_reader(Cursor::new(written.clone())).unwrap();
+    let read = |path: &str| {
+        let node = fs.files().find(|node| node.fullpath == Path::new(path)).unwrap();
+        let InnerNode::File(file) = &node.inner else { panic!("not a file") };
+        let mut bytes = vec![];
+        fs.file(file).reader().read_to_end(&mut bytes).unwrap();
+        bytes
+    };
+    assert_eq!(read("/etc/base"), base);
+    assert_eq!(read("/etc/overlay"), b"overlay");
+    // the data blocks of the image are copied as is
+    assert_eq!(blocks(&written), blocks(&original));
+    assert_eq!(fs::metadata(&image).unwrap().permissions().mode() & 0o777, 0o640);
+    let entries: Vec<_> =
+        fs::read_dir(tmp_dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(entries, ["base.squashfs"]);
+
+    // on error, the image isn't replaced
+    let mut fs = FilesystemWriter::open_existing(&image).unwrap();
+    let missing = tmp_dir.path().join("missing").join("base.squashfs");
+    assert!(fs.write_back(&missing).is_err());
+    assert_eq!(fs::read(&image).unwrap(), written);
+
+    // files of an image are only written by the writer that opened it
+    let reader = FilesystemReader::from_reader(Cursor::new(written)).unwrap();
+    let node = reader.files().find(|node| node.fullpath == Path::new("/etc/base")).unwrap();
+    let InnerNode::File(file) = &node.inner else { panic!("not a file") };
+    let mut fs = FilesystemWriter::default();
+    fs.push_file(Cursor::new(vec![]), "file", NodeHeader::default()).unwrap();
+    *fs.mut_file("file").unwrap() = SquashfsFileWriter::Existing(file.clone());
+    assert!(matches!(fs.write(Cursor::new(vec![])), Err(BackhandError::ImageNotOpen)));
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_raw_manifest_escaped_names() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    use backhand::FilesystemReader;
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("a b", header).unwrap();
+    fs.push_file(Cursor::new(b"x"), "a b/new\nline", header).unwrap();
+    fs.push_file(Cursor::new(b"x"), "100%", header).unwrap();
+    fs.push_file(Cursor::new(b"x"), OsStr::from_bytes(b"\xffn\xc3\xa4me"), header).unwrap();
+    fs.push_file(Cursor::new(b"x"), "n\u{e4}me", header).unwrap();
+    fs.push_symlink("a b/new\nline", "link", header).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+
+    let fs = FilesystemReader::from_reader(Cursor::new(output.into_inner())).unwrap();
+    let manifest = fs.manifest();
+    let paths: Vec<_> = manifest.lines().map(|line| line.splitn(7, ' ').nth(6).unwrap()).collect();
+    assert_eq!(
+        paths,
+        [
+            "/",
+            "/100%25",
+            "/a%20b",
+            "/a%20b/new%0Aline",
+            "/link -> a%20b/new%0Aline",
+            "/n\u{e4}me",
+            "/%FFn%C3%A4me",
+        ]
+    );
+}
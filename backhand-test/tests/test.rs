@@ -11,31 +11,10 @@ use test_assets_ureq::TestAssetDef;
 use test_log::test;
 use tracing::info;
 
-#[cfg(feature = "gzip")]
-fn has_gzip_feature() -> bool {
-    true
-}
-
-#[cfg(not(feature = "gzip"))]
-fn has_gzip_feature() -> bool {
-    false
-}
-
 enum Verify {
     Extract,
 }
 
-fn only_read(assets_defs: &[TestAssetDef], filepath: &str, test_path: &str, offset: u64) {
-    common::download_backoff(assets_defs, test_path);
-
-    let og_path = format!("{test_path}/{filepath}");
-    let file = BufReader::new(File::open(&og_path).unwrap());
-    info!("calling from_reader");
-    let _ = FilesystemReader::from_reader_with_offset(file, offset).unwrap();
-
-    // TODO: this should still check our own unsquashfs
-}
-
 /// - Download file
 /// - Read into Squashfs
 /// - Into Filesystem
@@ -70,7 +49,7 @@ fn full_test_inner(
     let file = BufReader::new(File::open(&og_path).unwrap());
     info!("calling from_reader");
     let og_filesystem = FilesystemReader::from_reader_with_offset(file, offset).unwrap();
-    let og_comp_opts = og_filesystem.compression_options;
+    let og_comp_opts = og_filesystem.compression_options.clone();
     let mut new_filesystem = FilesystemWriter::from_fs_reader(&og_filesystem).unwrap();
 
     // convert to bytes
@@ -89,7 +68,7 @@ fn full_test_inner(
         FilesystemReader::from_reader_with_offset(created_file, offset).unwrap();
 
     // compression options are the same
-    let new_comp_opts = written_new_filesystem.compression_options;
+    let new_comp_opts = written_new_filesystem.compression_options.clone();
     assert_eq!(og_comp_opts, new_comp_opts);
 
     drop(written_new_filesystem);
@@ -127,7 +106,7 @@ fn full_test_inner(
 
 /// mksquashfs ./target/release/squashfs-deku out.squashfs -comp gzip -Xcompression-level 2 -always-use-fragments
 #[test]
-#[cfg(any(feature = "gzip", feature = "gzip-zune-inflate"))]
+#[cfg(feature = "any-gzip")]
 fn test_00() {
     const FILE_NAME: &str = "out.squashfs";
     let asset_defs = [TestAssetDef {
@@ -137,16 +116,12 @@ fn test_00() {
     }];
     const TEST_PATH: &str = "test-assets/test_00";
 
-    if has_gzip_feature() {
-        full_test(&asset_defs, FILE_NAME, TEST_PATH, 0, Verify::Extract, true);
-    } else {
-        only_read(&asset_defs, FILE_NAME, TEST_PATH, 0);
-    }
+    full_test(&asset_defs, FILE_NAME, TEST_PATH, 0, Verify::Extract, true);
 }
 
 /// mksquashfs ./target/release/squashfs-deku out.squashfs -comp gzip -Xcompression-level 2
 #[test]
-#[cfg(any(feature = "gzip", feature = "gzip-zune-inflate"))]
+#[cfg(feature = "any-gzip")]
 fn test_01() {
     const FILE_NAME: &str = "out.squashfs";
     let asset_defs = [TestAssetDef {
@@ -155,11 +130,7 @@ fn test_01() {
         url: format!("https://wcampbell.dev/squashfs/testing/test_01/{FILE_NAME}"),
     }];
     const TEST_PATH: &str = "test-assets/test_01";
-    if has_gzip_feature() {
-        full_test(&asset_defs, FILE_NAME, TEST_PATH, 0, Verify::Extract, true);
-    } else {
-        only_read(&asset_defs, FILE_NAME, TEST_PATH, 0);
-    }
+    full_test(&asset_defs, FILE_NAME, TEST_PATH, 0, Verify::Extract, true);
 }
 
 /// mksquashfs ./target/release/squashfs-deku out.squashfs -comp xz
@@ -218,7 +189,7 @@ fn test_05() {
 
 /// mksquashfs ./target/release/squashfs-deku out.squashfs -comp gzip -always-use-fragments
 #[test]
-#[cfg(any(feature = "gzip", feature = "gzip-zune-inflate"))]
+#[cfg(feature = "any-gzip")]
 fn test_06() {
     const FILE_NAME: &str = "out.squashfs";
     let asset_defs = [TestAssetDef {
@@ -227,16 +198,12 @@ fn test_06() {
         url: format!("https://wcampbell.dev/squashfs/testing/test_06/{FILE_NAME}"),
     }];
     const TEST_PATH: &str = "test-assets/test_06";
-    if has_gzip_feature() {
-        full_test(&asset_defs, FILE_NAME, TEST_PATH, 0, Verify::Extract, true);
-    } else {
-        only_read(&asset_defs, FILE_NAME, TEST_PATH, 0);
-    }
+    full_test(&asset_defs, FILE_NAME, TEST_PATH, 0, Verify::Extract, true);
 }
 
 /// mksquashfs ./target/release/squashfs-deku out.squashfs -comp gzip
 #[test]
-#[cfg(any(feature = "gzip", feature = "gzip-zune-inflate"))]
+#[cfg(feature = "any-gzip")]
 fn test_07() {
     const FILE_NAME: &str = "out.squashfs";
     let asset_defs = [TestAssetDef {
@@ -246,11 +213,7 @@ fn test_07() {
     }];
     const TEST_PATH: &str = "test-assets/test_07";
 
-    if has_gzip_feature() {
-        full_test(&asset_defs, FILE_NAME, TEST_PATH, 0, Verify::Extract, true);
-    } else {
-        only_read(&asset_defs, FILE_NAME, TEST_PATH, 0);
-    }
+    full_test(&asset_defs, FILE_NAME, TEST_PATH, 0, Verify::Extract, true);
 }
 
 // mksquashfs ./target/release/squashfs-deku out.squashfs -comp xz -Xbcj arm
@@ -326,7 +289,7 @@ fn test_openwrt_netgear_ex6100v2() {
 }
 
 #[test]
-#[cfg(any(feature = "gzip", feature = "gzip-zune-inflate"))]
+#[cfg(feature = "any-gzip")]
 fn test_appimage_plexamp() {
     const FILE_NAME: &str = "Plexamp-4.6.1.AppImage";
     let asset_defs = [TestAssetDef {
@@ -336,15 +299,11 @@ fn test_appimage_plexamp() {
     }];
     const TEST_PATH: &str = "test-assets/test_appimage_plexamp";
 
-    if has_gzip_feature() {
-        full_test(&asset_defs, FILE_NAME, TEST_PATH, 0x2dfe8, Verify::Extract, true);
-    } else {
-        only_read(&asset_defs, FILE_NAME, TEST_PATH, 0x2dfe8);
-    }
+    full_test(&asset_defs, FILE_NAME, TEST_PATH, 0x2dfe8, Verify::Extract, true);
 }
 
 #[test]
-#[cfg(any(feature = "gzip", feature = "gzip-zune-inflate"))]
+#[cfg(feature = "any-gzip")]
 fn test_appimage_firefox() {
     const FILE_NAME: &str = "firefox-108.0.r20221215175817-x86_64.AppImage";
     let asset_defs = [TestAssetDef {
@@ -354,11 +313,7 @@ fn test_appimage_firefox() {
     }];
     const TEST_PATH: &str = "test-assets/test_appimage_firefox";
 
-    if has_gzip_feature() {
-        full_test(&asset_defs, FILE_NAME, TEST_PATH, 0x2f4c0, Verify::Extract, true);
-    } else {
-        only_read(&asset_defs, FILE_NAME, TEST_PATH, 0x2f4c0);
-    }
+    full_test(&asset_defs, FILE_NAME, TEST_PATH, 0x2f4c0, Verify::Extract, true);
 }
 
 /// Archer\ AX1800\(US\)_V3_221016.zip from https://www.tp-link.com/us/support/download/archer-ax1800/#Firmware
@@ -431,7 +386,7 @@ fn test_slow_archlinux_iso_rootfs() {
 }
 
 #[test]
-#[cfg(any(feature = "gzip", feature = "gzip-zune-inflate"))]
+#[cfg(feature = "any-gzip")]
 fn test_many_files() {
     const FILE_NAME: &str = "many_files.squashfs";
     let asset_defs = [TestAssetDef {
@@ -441,15 +396,11 @@ fn test_many_files() {
     }];
 
     const TEST_PATH: &str = "test-assets/test_many_files";
-    if has_gzip_feature() {
-        full_test(&asset_defs, FILE_NAME, TEST_PATH, 0, Verify::Extract, true);
-    } else {
-        only_read(&asset_defs, FILE_NAME, TEST_PATH, 0);
-    }
+    full_test(&asset_defs, FILE_NAME, TEST_PATH, 0, Verify::Extract, true);
 }
 
 #[test]
-#[cfg(any(feature = "gzip", feature = "gzip-zune-inflate"))]
+#[cfg(feature = "any-gzip")]
 fn test_many_dirs() {
     const FILE_NAME: &str = "many_dirs.squashfs";
     let asset_defs = [TestAssetDef {
@@ -459,15 +410,11 @@ fn test_many_dirs() {
     }];
 
     const TEST_PATH: &str = "test-assets/test_many_dirs";
-    if has_gzip_feature() {
-        full_test(&asset_defs, FILE_NAME, TEST_PATH, 0, Verify::Extract, true);
-    } else {
-        only_read(&asset_defs, FILE_NAME, TEST_PATH, 0);
-    }
+    full_test(&asset_defs, FILE_NAME, TEST_PATH, 0, Verify::Extract, true);
 }
 
 #[test]
-#[cfg(any(feature = "gzip", feature = "gzip-zune-inflate"))]
+#[cfg(feature = "any-gzip")]
 fn test_few_dirs_many_files() {
     const FILE_NAME: &str = "few_dirs_many_files.squashfs";
     let asset_defs = [TestAssetDef {
@@ -478,15 +425,11 @@ fn test_few_dirs_many_files() {
 
     const TEST_PATH: &str = "test-assets/test_few_dirs_many_files";
 
-    if has_gzip_feature() {
-        full_test(&asset_defs, FILE_NAME, TEST_PATH, 0, Verify::Extract, true);
-    } else {
-        only_read(&asset_defs, FILE_NAME, TEST_PATH, 0);
-    }
+    full_test(&asset_defs, FILE_NAME, TEST_PATH, 0, Verify::Extract, true);
 }
 
 #[test]
-#[cfg(any(feature = "gzip", feature = "gzip-zune-inflate"))]
+#[cfg(feature = "any-gzip")]
 fn test_socket_fifo() {
     const FILE_NAME: &str = "squashfs_v4.specfile.bin";
     let asset_defs = [TestAssetDef {
@@ -497,11 +440,7 @@ fn test_socket_fifo() {
 
     const TEST_PATH: &str = "test-assets/socket_fifo";
 
-    if has_gzip_feature() {
-        full_test_inner(&asset_defs, FILE_NAME, TEST_PATH, 0, Verify::Extract, true, false);
-    } else {
-        only_read(&asset_defs, FILE_NAME, TEST_PATH, 0);
-    }
+    full_test_inner(&asset_defs, FILE_NAME, TEST_PATH, 0, Verify::Extract, true, false);
 }
 
 #[test]
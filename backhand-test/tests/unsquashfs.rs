@@ -136,3 +136,592 @@ fn test_unsquashfs_cli_auto_offset() {
         cmd.assert().code(&[0] as &[i32]);
     }
 }
+
+#[test]
+#[cfg(all(feature = "__test_unsquashfs", feature = "xz"))]
+fn test_verify_against_unsquashfs() {
+    use std::io::Cursor;
+
+    use backhand::{FilesystemWriter, NodeHeader};
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir_all("usr/bin", header).unwrap();
+    fs.push_file(Cursor::new(b"hello".to_vec()), "usr/bin/hello", header).unwrap();
+    fs.push_file(Cursor::new(vec![0xff; 0x30000]), "large", header).unwrap();
+    fs.push_symlink("usr/bin/hello", "hello", header).unwrap();
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let image_path = tmp_dir.path().join("verify.squashfs");
+    let mut output = std::fs::File::create(&image_path).unwrap();
+    fs.write(&mut output).unwrap();
+
+    let unsquashfs_backhand = common::get_base_command("unsquashfs-backhand");
+    tests::verify_against_unsquashfs(unsquashfs_backhand, &image_path, None).unwrap();
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_unsquashfs_no_preserve() {
+    use std::io::Cursor;
+    use std::os::unix::fs::PermissionsExt;
+
+    use backhand::{FilesystemWriter, NodeHeader};
+
+    let header = NodeHeader { permissions: 0o600, uid: 0, gid: 0, mtime: 0 };
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("dir", NodeHeader { permissions: 0o700, ..header }).unwrap();
+    fs.push_file(Cursor::new(b"hello".to_vec()), "dir/file", header).unwrap();
+    fs.push_symlink("dir/file", "link", header).unwrap();
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let image_path = tmp_dir.path().join("no_preserve.squashfs");
+    let mut output = std::fs::File::create(&image_path).unwrap();
+    fs.write(&mut output).unwrap();
+
+    let dest = tmp_dir.path().join("squashfs-root");
+    let cmd = common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args([
+            "--no-preserve",
+            "--quiet",
+            "-d",
+            dest.to_str().unwrap(),
+            image_path.to_str().unwrap(),
+        ])
+        .unwrap();
+    cmd.assert().code(0);
+
+    assert_eq!(std::fs::read(dest.join("dir/file")).unwrap(), b"hello");
+    assert_eq!(std::fs::read_link(dest.join("link")).unwrap().to_str(), Some("dir/file"));
+    // modes and times are host defaults, not from the image
+    let metadata = std::fs::metadata(dest.join("dir/file")).unwrap();
+    assert_ne!(metadata.permissions().mode() & 0o777, 0o600);
+    assert_ne!(metadata.modified().unwrap(), std::time::UNIX_EPOCH);
+    let metadata = std::fs::metadata(dest.join("dir")).unwrap();
+    assert_ne!(metadata.permissions().mode() & 0o777, 0o700);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_unsquashfs_compare_dir() {
+    use std::io::Cursor;
+    use std::os::unix::fs::PermissionsExt;
+
+    use backhand::{FilesystemWriter, NodeHeader};
+
+    let header = NodeHeader { permissions: 0o755, uid: 0, gid: 0, mtime: 0 };
+    let file_header = NodeHeader { permissions: 0o644, ..header };
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("dir", header).unwrap();
+    fs.push_file(Cursor::new(b"hello".to_vec()), "dir/a", file_header).unwrap();
+    fs.push_file(Cursor::new(vec![0xff; 0x30000]), "dir/b", file_header).unwrap();
+    fs.push_file(Cursor::new(b"c".to_vec()), "dir/c", file_header).unwrap();
+    fs.push_symlink("dir/a", "link", header).unwrap();
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let image_path = tmp_dir.path().join("compare.squashfs");
+    let mut output = std::fs::File::create(&image_path).unwrap();
+    fs.write(&mut output).unwrap();
+    let image_path = image_path.to_str().unwrap();
+
+    let dest = tmp_dir.path().join("squashfs-root");
+    let dest_str = dest.to_str().unwrap();
+    let cmd = common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args(["--quiet", "-d", dest_str, image_path])
+        .unwrap();
+    cmd.assert().code(0);
+
+    // no differences
+    let cmd = common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args(["--quiet", "--compare-dir", dest_str, image_path])
+        .unwrap();
+    cmd.assert().code(0).stdout("");
+
+    // modify
+    std::fs::write(dest.join("dir/a"), b"world").unwrap();
+    std::fs::set_permissions(dest.join("dir/b"), std::fs::Permissions::from_mode(0o600)).unwrap();
+    std::fs::remove_file(dest.join("dir/c")).unwrap();
+    std::fs::write(dest.join("dir/d"), b"d").unwrap();
+    common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args(["--quiet", "--compare-dir", dest_str, image_path])
+        .assert()
+        .code(1)
+        .stdout(
+            r#"content /dir/a
+mode /dir/b
+missing /dir/c
+extra /dir/d
+"#,
+        );
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_unsquashfs_keep_going() {
+    use std::io::Cursor;
+
+    use backhand::{FilesystemWriter, NodeHeader};
+
+    let header = NodeHeader { permissions: 0o755, ..NodeHeader::default() };
+    let mut fs = FilesystemWriter::default();
+    fs.push_file(Cursor::new(b"a".to_vec()), "a", header).unwrap();
+    fs.push_file(Cursor::new(b"b".to_vec()), "b", header).unwrap();
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let image_path = tmp_dir.path().join("keep_going.squashfs");
+    let mut output = std::fs::File::create(&image_path).unwrap();
+    fs.write(&mut output).unwrap();
+    let image_path = image_path.to_str().unwrap();
+
+    // a dir in place of the file "a" fails extraction of "a", even as root
+    let dest = tmp_dir.path().join("squashfs-root");
+    std::fs::create_dir_all(dest.join("a")).unwrap();
+    let dest = dest.to_str().unwrap();
+
+    // without --keep-going, failures are not reported in the exit code
+    common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args(["--quiet", "--force", "-d", dest, image_path])
+        .assert()
+        .code(0);
+
+    let assert = common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args(["--quiet", "--force", "--keep-going", "-d", dest, image_path])
+        .assert()
+        .code(1);
+    let stderr = std::str::from_utf8(&assert.get_output().stderr).unwrap();
+    assert!(stderr.contains("to extract 1 nodes:"), "{stderr}");
+    assert!(stderr.contains(&format!("{dest}/a : ")), "{stderr}");
+    assert_eq!(std::fs::read(format!("{dest}/b")).unwrap(), b"b");
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_unsquashfs_fragments_uncompressed() {
+    use std::io::Cursor;
+
+    use backhand::{FilesystemWriter, NodeHeader};
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.set_fragments_uncompressed(true);
+    fs.push_file(Cursor::new(vec![b'a'; 0x1000]), "a", header).unwrap();
+    fs.push_file(Cursor::new(vec![b'b'; 0x1000]), "b", header).unwrap();
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let image_path = tmp_dir.path().join("fragments_uncompressed.squashfs");
+    let mut output = std::fs::File::create(&image_path).unwrap();
+    fs.write(&mut output).unwrap();
+
+    // fragment block is stored as-is
+    let image = std::fs::read(&image_path).unwrap();
+    let mut fragment = vec![b'a'; 0x1000];
+    fragment.extend_from_slice(&[b'b'; 0x1000]);
+    assert!(image.windows(fragment.len()).any(|window| window == fragment));
+
+    let cmd = common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args(["-s", "--quiet", image_path.to_str().unwrap()])
+        .unwrap();
+    let stdout = String::from_utf8(cmd.stdout).unwrap();
+    assert!(stdout.contains("flag: fragments stored uncompressed\n"));
+
+    let dest = tmp_dir.path().join("squashfs-root");
+    let cmd = common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args(["--quiet", "-d", dest.to_str().unwrap(), image_path.to_str().unwrap()])
+        .unwrap();
+    cmd.assert().code(0);
+    assert_eq!(std::fs::read(dest.join("a")).unwrap(), vec![b'a'; 0x1000]);
+    assert_eq!(std::fs::read(dest.join("b")).unwrap(), vec![b'b'; 0x1000]);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_unsquashfs_metadata_compression() {
+    use std::io::Cursor;
+
+    use backhand::{FilesystemWriter, Id, MetadataComp, NodeHeader};
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    // enough ids for the id table to be compressible
+    fs.set_id_table((0..1000).map(Id::new).collect());
+    fs.set_metadata_compression(MetadataComp {
+        inodes: false,
+        ids: true,
+        ..MetadataComp::default()
+    });
+    fs.push_dir("dir", header).unwrap();
+    fs.push_file(Cursor::new(vec![b'a'; 0x1000]), "dir/a", header).unwrap();
+    fs.push_file(Cursor::new(vec![b'b'; 0x30000]), "dir/b", header).unwrap();
+    // enough entries for the dir table to be compressible
+    for i in 0..100 {
+        fs.push_file(Cursor::new(vec![]), format!("dir/empty_{i:03}"), header).unwrap();
+    }
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let image_path = tmp_dir.path().join("metadata_compression.squashfs");
+    let mut output = std::fs::File::create(&image_path).unwrap();
+    let (superblock, _) = fs.write(&mut output).unwrap();
+
+    // uncompressed bit of the first metadata block of each table
+    let image = std::fs::read(&image_path).unwrap();
+    let uncompressed = |start: u64| {
+        let len = u16::from_le_bytes(image[start as usize..][..2].try_into().unwrap());
+        len & 0x8000 != 0
+    };
+    let id_block =
+        u64::from_le_bytes(image[superblock.id_table as usize..][..8].try_into().unwrap());
+    let frag_block =
+        u64::from_le_bytes(image[superblock.frag_table as usize..][..8].try_into().unwrap());
+    assert!(uncompressed(superblock.inode_table));
+    assert!(!uncompressed(superblock.dir_table));
+    assert!(!uncompressed(id_block));
+    assert!(uncompressed(frag_block));
+
+    let cmd = common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args(["-s", "--quiet", image_path.to_str().unwrap()])
+        .unwrap();
+    let stdout = String::from_utf8(cmd.stdout).unwrap();
+    assert!(stdout.contains("flag: inodes uncompressed\n"));
+
+    let dest = tmp_dir.path().join("squashfs-root");
+    let cmd = common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args(["--quiet", "-d", dest.to_str().unwrap(), image_path.to_str().unwrap()])
+        .unwrap();
+    cmd.assert().code(0);
+    assert_eq!(std::fs::read(dest.join("dir/a")).unwrap(), vec![b'a'; 0x1000]);
+    assert_eq!(std::fs::read(dest.join("dir/b")).unwrap(), vec![b'b'; 0x30000]);
+
+    #[cfg(feature = "__test_unsquashfs")]
+    {
+        let dest = tmp_dir.path().join("squashfs-root-c");
+        let mut cmd = std::process::Command::new("unsquashfs");
+        cmd.args(["-d", dest.to_str().unwrap(), image_path.to_str().unwrap()]);
+        cmd.assert().code(0);
+        assert_eq!(std::fs::read(dest.join("dir/b")).unwrap(), vec![b'b'; 0x30000]);
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_unsquashfs_progress_fd() {
+    use std::io::Cursor;
+
+    use backhand::{FilesystemWriter, NodeHeader};
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("dir", header).unwrap();
+    for name in ["a", "b", "c"] {
+        fs.push_file(Cursor::new(name.as_bytes()), format!("dir/{name}"), header).unwrap();
+    }
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let image_path = tmp_dir.path().join("progress.squashfs");
+    let mut output = std::fs::File::create(&image_path).unwrap();
+    fs.write(&mut output).unwrap();
+    let image_path = image_path.to_str().unwrap();
+    let dest = tmp_dir.path().join("squashfs-root");
+    let dest = dest.to_str().unwrap();
+
+    // stderr as the progress fd, piped to this test
+    let cmd = common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args(["--quiet", "--progress-fd", "2", "-d", dest, image_path])
+        .unwrap();
+    assert!(cmd.stdout.is_empty());
+    let stderr = String::from_utf8(cmd.stderr).unwrap();
+    let expected: Vec<_> = (1..=5).map(|n| format!("extracted {n}/5")).collect();
+    assert_eq!(stderr.lines().collect::<Vec<_>>(), expected);
+    assert_eq!(std::fs::read(format!("{dest}/dir/c")).unwrap(), b"c");
+
+    common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args(["--quiet", "--force", "--progress-fd", "1000", "-d", dest, image_path])
+        .assert()
+        .code(1);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_unsquashfs_manifest_only() {
+    use std::io::Cursor;
+    use std::path::{Path, PathBuf};
+
+    use backhand::{FilesystemWriter, NodeHeader};
+
+    fn walk(root: &Path, dir: &Path, paths: &mut Vec<PathBuf>) {
+        let mut entries: Vec<_> = std::fs::read_dir(dir).unwrap().map(|e| e.unwrap()).collect();
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            paths.push(Path::new("/").join(entry.path().strip_prefix(root).unwrap()));
+            if entry.file_type().unwrap().is_dir() {
+                walk(root, &entry.path(), paths);
+            }
+        }
+    }
+
+    let header = NodeHeader::new(0o755, 1000, 1000, 0x634f_5237);
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("dir", header).unwrap();
+    fs.push_file(Cursor::new(vec![b'a'; 0x100]), "dir/a", header).unwrap();
+    fs.push_file(Cursor::new(b"b"), "dir/b", header).unwrap();
+    fs.push_dir("dir/sub", header).unwrap();
+    fs.push_symlink("dir/a", "link", header).unwrap();
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let image_path = tmp_dir.path().join("manifest.squashfs");
+    let mut output = std::fs::File::create(&image_path).unwrap();
+    fs.write(&mut output).unwrap();
+    let image_path = image_path.to_str().unwrap();
+    let manifest_path = tmp_dir.path().join("manifest.txt");
+    let dest = tmp_dir.path().join("squashfs-root");
+
+    common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args(["--quiet", "--manifest-only", manifest_path.to_str().unwrap(), image_path])
+        .assert()
+        .success();
+    assert!(!dest.exists());
+    let manifest = std::fs::read_to_string(&manifest_path).unwrap();
+    let lines: Vec<_> = manifest.lines().collect();
+    assert_eq!(lines[2], "- 0755 1000 1000 1666142775 256 /dir/a");
+    assert_eq!(lines[4], "d 0755 1000 1000 1666142775 0 /dir/sub");
+    assert_eq!(lines[5], "l 0755 1000 1000 1666142775 0 /link -> dir/a");
+
+    // same manifest on stdout
+    let cmd = common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args(["--quiet", "--manifest-only", "-", image_path])
+        .unwrap();
+    assert_eq!(String::from_utf8(cmd.stdout).unwrap(), manifest);
+
+    // same nodes as a full extraction
+    common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args(["--quiet", "--no-preserve", "-d", dest.to_str().unwrap(), image_path])
+        .assert()
+        .success();
+    let mut extracted = vec![PathBuf::from("/")];
+    walk(&dest, &dest, &mut extracted);
+    let manifest_paths: Vec<_> = lines
+        .iter()
+        .map(|line| {
+            let path = line.splitn(7, ' ').nth(6).unwrap();
+            PathBuf::from(path.split(" -> ").next().unwrap())
+        })
+        .collect();
+    assert_eq!(manifest_paths, extracted);
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_unsquashfs_special_mode_bits() {
+    use std::io::Cursor;
+    use std::os::unix::fs::PermissionsExt;
+
+    use backhand::{FilesystemReader, FilesystemWriter, NodeHeader};
+
+    let modes = [("setuid", 0o104755), ("setgid", 0o102755), ("sticky", 0o041777)];
+    let mut fs = FilesystemWriter::default();
+    for (name, mode) in modes {
+        let header = NodeHeader::default().with_mode(mode);
+        if mode & 0o040000 != 0 {
+            fs.push_dir(name, header).unwrap();
+        } else {
+            fs.push_file(Cursor::new(b"#!/bin/sh\n"), name, header).unwrap();
+        }
+    }
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let image_path = tmp_dir.path().join("modes.squashfs");
+    let mut output = std::fs::File::create(&image_path).unwrap();
+    fs.write(&mut output).unwrap();
+
+    let reader = FilesystemReader::from_reader(std::io::BufReader::new(
+        std::fs::File::open(&image_path).unwrap(),
+    ))
+    .unwrap();
+    for (name, mode) in modes {
+        let node = reader.files().find(|node| node.fullpath.ends_with(name)).unwrap();
+        assert_eq!(u32::from(node.header.permissions), mode & 0o7777, "{name}");
+    }
+
+    let dest = tmp_dir.path().join("squashfs-root");
+    common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args(["--quiet", "-d", dest.to_str().unwrap(), image_path.to_str().unwrap()])
+        .assert()
+        .success();
+    // permissions are set after chown, which clears setuid and setgid
+    for (name, mode) in modes {
+        let extracted = std::fs::metadata(dest.join(name)).unwrap().permissions().mode();
+        assert_eq!(extracted & 0o7777, mode & 0o7777, "{name}");
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_unsquashfs_sanitize_names() {
+    use std::io::Cursor;
+
+    use backhand::{FilesystemWriter, NodeHeader};
+
+    let header = NodeHeader::default();
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("dir:x", header).unwrap();
+    fs.push_file(Cursor::new(b"inner"), "dir:x/inner", header).unwrap();
+    fs.push_file(Cursor::new(b"colon"), "a:b", header).unwrap();
+    fs.push_file(Cursor::new(b"con"), "con.txt", header).unwrap();
+    fs.push_file(Cursor::new(b"upper"), "Case", header).unwrap();
+    fs.push_file(Cursor::new(b"lower"), "case", header).unwrap();
+    fs.push_file(Cursor::new(b"dot"), "dot.", header).unwrap();
+    fs.push_file(Cursor::new(b"plain"), "plain", header).unwrap();
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let image_path = tmp_dir.path().join("names.squashfs");
+    let mut output = std::fs::File::create(&image_path).unwrap();
+    fs.write(&mut output).unwrap();
+    let dest = tmp_dir.path().join("squashfs-root");
+
+    common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args([
+            "--quiet",
+            "--sanitize-names",
+            "-d",
+            dest.to_str().unwrap(),
+            image_path.to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+
+    for (path, contents) in [
+        ("dir%3Ax/inner", &b"inner"[..]),
+        ("a%3Ab", b"colon"),
+        ("%63on.txt", b"con"),
+        ("Case", b"upper"),
+        ("case~1", b"lower"),
+        ("dot%2E", b"dot"),
+        ("plain", b"plain"),
+    ] {
+        assert_eq!(std::fs::read(dest.join(path)).unwrap(), contents, "{path}");
+    }
+    assert!(!dest.join("a:b").exists());
+
+    let names = std::fs::read_to_string(tmp_dir.path().join("squashfs-root.names")).unwrap();
+    assert_eq!(
+        names.lines().collect::<Vec<_>>(),
+        [
+            "/a%3Ab\t/a:b",
+            "/case~1\t/case",
+            "/%63on.txt\t/con.txt",
+            "/dir%3Ax\t/dir:x",
+            "/dir%3Ax/inner\t/dir:x/inner",
+            "/dot%2E\t/dot.",
+        ]
+    );
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_unsquashfs_max_total_size() {
+    use std::io::Cursor;
+
+    use backhand::{FilesystemWriter, NodeHeader};
+
+    let mut fs = FilesystemWriter::default();
+    fs.push_file(Cursor::new(vec![0xff; 0x30000]), "a", NodeHeader::default()).unwrap();
+    fs.push_file(Cursor::new(vec![0xee; 0x30000]), "b", NodeHeader::default()).unwrap();
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let image_path = tmp_dir.path().join("max_total_size.squashfs");
+    let mut output = std::fs::File::create(&image_path).unwrap();
+    fs.write(&mut output).unwrap();
+
+    for (limit, code) in [("65536", 1), ("393216", 0)] {
+        let dest = tmp_dir.path().join(format!("squashfs-root-{limit}"));
+        common::get_base_command("unsquashfs-backhand")
+            .env("RUST_LOG", "none")
+            .args([
+                "--quiet",
+                "--max-total-size",
+                limit,
+                "-d",
+                dest.to_str().unwrap(),
+                image_path.to_str().unwrap(),
+            ])
+            .assert()
+            .code(code);
+        // no partially written files are left behind
+        let extracted = ["a", "b"].map(|name| dest.join(name).exists());
+        assert_eq!(extracted, [code == 0; 2]);
+    }
+}
+
+#[test]
+#[cfg(feature = "xz")]
+fn test_unsquashfs_fifo_socket() {
+    use std::io::Cursor;
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use std::path::Path;
+
+    use backhand::{FilesystemWriter, InodeId, NodeHeader, Squashfs};
+
+    let header = NodeHeader { permissions: 0o640, uid: 0, gid: 0, mtime: 0 };
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("run", NodeHeader::for_dir()).unwrap();
+    fs.push_fifo("run/fifo", header).unwrap();
+    fs.push_socket("run/socket", header).unwrap();
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let bytes = output.into_inner();
+
+    let squashfs = Squashfs::from_reader(Cursor::new(bytes.clone())).unwrap();
+    let count = |id| squashfs.inodes.values().filter(|inode| inode.id == id).count();
+    assert_eq!(count(InodeId::BasicNamedPipe), 1);
+    assert_eq!(count(InodeId::BasicSocket), 1);
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let image_path = tmp_dir.path().join("special.squashfs");
+    std::fs::write(&image_path, &bytes).unwrap();
+
+    let check = |dest: &Path| {
+        let fifo = std::fs::symlink_metadata(dest.join("run/fifo")).unwrap();
+        assert!(fifo.file_type().is_fifo());
+        assert_eq!(fifo.permissions().mode() & 0o7777, 0o640);
+        let socket = std::fs::symlink_metadata(dest.join("run/socket")).unwrap();
+        assert!(socket.file_type().is_socket());
+        assert_eq!(socket.permissions().mode() & 0o7777, 0o640);
+    };
+
+    let dest = tmp_dir.path().join("squashfs-root");
+    let cmd = common::get_base_command("unsquashfs-backhand")
+        .env("RUST_LOG", "none")
+        .args(["--quiet", "-d", dest.to_str().unwrap(), image_path.to_str().unwrap()])
+        .unwrap();
+    cmd.assert().code(0);
+    check(&dest);
+
+    #[cfg(feature = "__test_unsquashfs")]
+    {
+        let dest = tmp_dir.path().join("squashfs-root-tools");
+        let output = std::process::Command::new("unsquashfs")
+            .arg("-d")
+            .arg(&dest)
+            .arg(&image_path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        check(&dest);
+    }
+}
@@ -4,7 +4,7 @@ use std::process::Command;
 use std::time::Duration;
 
 use assert_cmd::prelude::*;
-use backhand::{FilesystemReader, FilesystemWriter};
+use backhand::{FilesystemReader, FilesystemWriter, NodeHeader, Squashfs};
 use criterion::*;
 use tempfile::tempdir;
 use test_assets_ureq::dl_test_files_backoff;
@@ -188,5 +188,25 @@ pub fn bench_unsquashfs_extract(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, bench_read_write, bench_read, bench_unsquashfs_extract);
+pub fn bench_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lookup");
+
+    // one large directory
+    let mut fs = FilesystemWriter::default();
+    fs.push_dir("dir", NodeHeader::default()).unwrap();
+    for i in 0..50_000 {
+        fs.push_file(Cursor::new(vec![]), format!("dir/{i:05}"), NodeHeader::default()).unwrap();
+    }
+    let mut output = Cursor::new(vec![]);
+    fs.write(&mut output).unwrap();
+    let squashfs = Squashfs::from_reader(Cursor::new(output.into_inner())).unwrap();
+
+    group.bench_function("50k-entry-dir", |b| {
+        b.iter(|| black_box(squashfs.lookup("/dir/49999").unwrap().unwrap()))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_read_write, bench_read, bench_unsquashfs_extract, bench_lookup);
 criterion_main!(benches);
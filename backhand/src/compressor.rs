@@ -7,7 +7,7 @@ use deku::prelude::*;
 use flate2::read::ZlibEncoder;
 #[cfg(feature = "any-flate2")]
 use flate2::Compression;
-use tracing::trace;
+use tracing::{error, trace};
 #[cfg(feature = "xz")]
 use xz2::read::{XzDecoder, XzEncoder};
 #[cfg(feature = "xz")]
@@ -35,7 +35,7 @@ pub enum Compressor {
     Zstd = 6,
 }
 
-#[derive(Debug, DekuRead, DekuWrite, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, DekuRead, DekuWrite, PartialEq, Eq, Clone)]
 #[deku(endian = "endian", ctx = "endian: deku::ctx::Endian, compressor: Compressor")]
 #[deku(id = "compressor")]
 pub enum CompressionOptions {
@@ -56,6 +56,152 @@ pub enum CompressionOptions {
 
     #[deku(id = "Compressor::Lzma")]
     Lzma,
+
+    /// Options that aren't modeled by backhand, kept as their bytes
+    ///
+    /// Read when the options of an image can't be parsed as the options of its compressor. These
+    /// can't be used to compress: [`FilesystemWriter::from_fs_reader`] only uses them to copy the
+    /// data blocks of the image as is, and writes the image without compression options.
+    ///
+    /// [`FilesystemWriter::from_fs_reader`]: crate::FilesystemWriter::from_fs_reader
+    #[deku(id_pat = "_")]
+    Raw(#[deku(read_all)] Vec<u8>),
+}
+
+impl CompressionOptions {
+    /// Bytes of these options, as stored in the metadata block after the superblock of an image
+    /// of `kind`
+    ///
+    /// This doesn't include the header of the metadata block. [`Self::Lzma`] has no options, and
+    /// is empty. [`Self::Raw`] is returned as is.
+    ///
+    /// ```rust
+    /// # use backhand::compression::{CompressionOptions, Zstd};
+    /// # use backhand::kind::{self, Kind};
+    /// let kind = Kind::from_const(kind::LE_V4_0).unwrap();
+    /// let options = CompressionOptions::Zstd(Zstd { compression_level: 15 });
+    /// assert_eq!(options.to_bytes(&kind).unwrap(), [15, 0, 0, 0]);
+    /// ```
+    pub fn to_bytes(&self, kind: &Kind) -> Result<Vec<u8>, BackhandError> {
+        let mut bytes = Cursor::new(vec![]);
+        let mut writer = Writer::new(&mut bytes);
+        let endian = kind.inner.type_endian;
+        match self {
+            Self::Gzip(gzip) => gzip.to_writer(&mut writer, endian)?,
+            Self::Lz4(lz4) => lz4.to_writer(&mut writer, endian)?,
+            Self::Zstd(zstd) => zstd.to_writer(&mut writer, endian)?,
+            Self::Xz(xz) => xz.to_writer(&mut writer, endian)?,
+            Self::Lzo(lzo) => lzo.to_writer(&mut writer, endian)?,
+            Self::Lzma => {}
+            Self::Raw(raw) => return Ok(raw.clone()),
+        }
+        Ok(bytes.into_inner())
+    }
+
+    /// Parse the options of `compressor` from `bytes`, as returned by [`Self::to_bytes`]
+    ///
+    /// Returns [`BackhandError::InvalidCompressionOption`] if not all of `bytes` are used.
+    pub fn from_bytes(
+        bytes: &[u8],
+        compressor: Compressor,
+        kind: &Kind,
+    ) -> Result<Self, BackhandError> {
+        let mut cursor = Cursor::new(bytes);
+        let mut reader = Reader::new(&mut cursor);
+        let options =
+            Self::from_reader_with_ctx(&mut reader, (kind.inner.type_endian, compressor))?;
+        if !reader.end() {
+            return Err(BackhandError::InvalidCompressionOption);
+        }
+        Ok(options)
+    }
+
+    /// Concise human readable summary, such as `xz: dict_size=32768, filters=none`
+    ///
+    /// ```rust
+    /// # use backhand::compression::{CompressionOptions, Zstd};
+    /// let options = CompressionOptions::Zstd(Zstd { compression_level: 15 });
+    /// assert_eq!(options.describe(), "zstd: level=15");
+    /// ```
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Gzip(gzip) => {
+                let strategies = [
+                    (0x0001, "default"),
+                    (0x0002, "filtered"),
+                    (0x0004, "huffman_only"),
+                    (0x0008, "run_length_encoded"),
+                    (0x0010, "fixed"),
+                ];
+                let strategies = flag_names(u32::from(gzip.strategies), &strategies);
+                format!(
+                    "gzip: level={}, window={}, strategies={strategies}",
+                    gzip.compression_level, gzip.window_size
+                )
+            }
+            Self::Lzo(lzo) => {
+                let algorithm = match lzo.algorithm {
+                    0 => "lzo1x_1".to_string(),
+                    1 => "lzo1x_1_11".to_string(),
+                    2 => "lzo1x_1_12".to_string(),
+                    3 => "lzo1x_1_15".to_string(),
+                    4 => "lzo1x_999".to_string(),
+                    algorithm => format!("{algorithm:#x}"),
+                };
+                format!("lzo: algorithm={algorithm}, level={}", lzo.compression_level)
+            }
+            Self::Xz(xz) => {
+                let filters = [
+                    (0x0001, "x86"),
+                    (0x0002, "powerpc"),
+                    (0x0004, "ia64"),
+                    (0x0008, "arm"),
+                    (0x0010, "armthumb"),
+                    (0x0020, "sparc"),
+                ];
+                let filters = flag_names(xz.filters.0, &filters);
+                let mut description =
+                    format!("xz: dict_size={}, filters={filters}", xz.dictionary_size);
+                if let Some(bit_opts) = xz.bit_opts {
+                    description.push_str(&format!(", bit_opts={bit_opts:#x}"));
+                }
+                if let Some(fb) = xz.fb {
+                    description.push_str(&format!(", fb={fb}"));
+                }
+                description
+            }
+            Self::Lz4(lz4) => {
+                let flags = flag_names(lz4.flags, &[(0x0001, "hc")]);
+                format!("lz4: version={}, flags={flags}", lz4.version)
+            }
+            Self::Zstd(zstd) => format!("zstd: level={}", zstd.compression_level),
+            Self::Lzma => "lzma".to_string(),
+            Self::Raw(raw) => {
+                let bytes: String = raw.iter().map(|b| format!("{b:02x}")).collect();
+                format!("raw: {bytes}")
+            }
+        }
+    }
+}
+
+/// Names of the set bits of `value`, joined with `|`, or `none`
+///
+/// Unknown bits are shown as hex.
+fn flag_names(value: u32, names: &[(u32, &str)]) -> String {
+    let mut found: Vec<String> = names
+        .iter()
+        .filter(|(bit, _)| value & bit != 0)
+        .map(|(_, name)| name.to_string())
+        .collect();
+    let unknown = names.iter().fold(value, |value, (bit, _)| value & !bit);
+    if unknown != 0 {
+        found.push(format!("{unknown:#x}"));
+    }
+    if found.is_empty() {
+        "none".to_string()
+    } else {
+        found.join("|")
+    }
 }
 
 #[derive(Debug, DekuRead, DekuWrite, PartialEq, Eq, Clone, Copy)]
@@ -147,6 +293,12 @@ pub struct Zstd {
 /// ideas of compression with custom tables and such! Thus, if the need arises you can implement
 /// your own [`CompressionAction`] to override the compression and de-compression used in this
 /// library by default.
+///
+/// This is also how compression with a zstd dictionary is supported. Squashfs has no place to
+/// store the dictionary, and zstd frames only refer to a dictionary by id, so each block can only
+/// be decompressed with the same dictionary. Such an image is then only readable with a
+/// [`CompressionAction`] having the dictionary, not by the Linux kernel, `squashfs-tools`, or the
+/// [`DefaultCompressor`].
 pub trait CompressionAction {
     /// Decompress function used for all decompression actions
     ///
@@ -237,7 +389,7 @@ impl CompressionAction for DefaultCompressor {
             }
             #[cfg(feature = "zstd")]
             Compressor::Zstd => {
-                let mut decoder = zstd::bulk::Decompressor::new().unwrap();
+                let mut decoder = zstd::bulk::Decompressor::new()?;
                 decoder.decompress_to_buffer(bytes, out)?;
             }
             _ => return Err(BackhandError::UnsupportedCompression(compressor)),
@@ -252,7 +404,16 @@ impl CompressionAction for DefaultCompressor {
         fc: FilesystemCompressor,
         block_size: u32,
     ) -> Result<Vec<u8>, BackhandError> {
-        match (fc.id, fc.options, fc.extra) {
+        // raw options aren't understood, so compress as if there were none, as they aren't written
+        let options = match fc.options {
+            Some(CompressionOptions::Raw(_)) if fc.id == Compressor::Lz4 => {
+                error!("lz4 requires compression options");
+                return Err(BackhandError::InvalidCompressionOption);
+            }
+            Some(CompressionOptions::Raw(_)) => None,
+            options => options,
+        };
+        match (fc.id, options, fc.extra) {
             (Compressor::None, None, _) => Ok(bytes.to_vec()),
             #[cfg(feature = "xz")]
             (Compressor::Xz, option @ (Some(CompressionOptions::Xz(_)) | None), extra) => {
@@ -368,33 +529,110 @@ impl CompressionAction for DefaultCompressor {
         if let Some(options) = &fs_compressor.options {
             trace!("writing compression options");
             superblock.flags |= Flags::CompressorOptionsArePresent as u16;
-            let mut compression_opt_buf_out = Cursor::new(vec![]);
-            let mut writer = Writer::new(&mut compression_opt_buf_out);
-            match options {
-                CompressionOptions::Gzip(gzip) => {
-                    gzip.to_writer(&mut writer, kind.inner.type_endian)?
-                }
-                CompressionOptions::Lz4(lz4) => {
-                    lz4.to_writer(&mut writer, kind.inner.type_endian)?
-                }
-                CompressionOptions::Zstd(zstd) => {
-                    zstd.to_writer(&mut writer, kind.inner.type_endian)?
-                }
-                CompressionOptions::Xz(xz) => xz.to_writer(&mut writer, kind.inner.type_endian)?,
-                CompressionOptions::Lzo(lzo) => {
-                    lzo.to_writer(&mut writer, kind.inner.type_endian)?
-                }
-                CompressionOptions::Lzma => {}
-            }
+            let bytes = options.to_bytes(kind)?;
             let mut metadata = MetadataWriter::new(
                 fs_compressor,
                 superblock.block_size,
                 Kind { inner: kind.inner.clone() },
             );
-            metadata.write_all(compression_opt_buf_out.get_ref())?;
+            metadata.write_all(&bytes)?;
             metadata.finalize(&mut w)?;
         }
 
         Ok(w.into_inner())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_bytes_round_trip() {
+        let options = [
+            (
+                Compressor::Gzip,
+                CompressionOptions::Gzip(Gzip {
+                    compression_level: 9,
+                    window_size: 15,
+                    strategies: 0x1,
+                }),
+            ),
+            (Compressor::Lzo, CompressionOptions::Lzo(Lzo { algorithm: 4, compression_level: 8 })),
+            (
+                Compressor::Xz,
+                CompressionOptions::Xz(Xz {
+                    dictionary_size: 0x2_0000,
+                    filters: XzFilter(0x9),
+                    bit_opts: None,
+                    fb: None,
+                }),
+            ),
+            (
+                Compressor::Xz,
+                CompressionOptions::Xz(Xz {
+                    dictionary_size: 0x2_0000,
+                    filters: XzFilter(0),
+                    bit_opts: Some(0x10),
+                    fb: Some(273),
+                }),
+            ),
+            (Compressor::Lz4, CompressionOptions::Lz4(Lz4 { version: 1, flags: 0x1 })),
+            (Compressor::Zstd, CompressionOptions::Zstd(Zstd { compression_level: 15 })),
+            (Compressor::Lzma, CompressionOptions::Lzma),
+        ];
+        for kind in [crate::kind::LE_V4_0, crate::kind::BE_V4_0] {
+            let kind = Kind::from_const(kind).unwrap();
+            for (compressor, options) in options.clone() {
+                let bytes = options.to_bytes(&kind).unwrap();
+                let read = CompressionOptions::from_bytes(&bytes, compressor, &kind).unwrap();
+                assert_eq!(read, options);
+            }
+        }
+
+        let kind = Kind::from_const(crate::kind::BE_V4_0).unwrap();
+        let zstd = CompressionOptions::Zstd(Zstd { compression_level: 15 });
+        assert_eq!(zstd.to_bytes(&kind).unwrap(), [0, 0, 0, 15]);
+        // not all bytes used
+        let result = CompressionOptions::from_bytes(&[0, 0, 0, 15, 0], Compressor::Zstd, &kind);
+        assert!(matches!(result, Err(BackhandError::InvalidCompressionOption)));
+
+        // raw options are written as is, and read for compressors without modeled options
+        let raw = CompressionOptions::Raw(vec![1, 2, 3]);
+        assert_eq!(raw.to_bytes(&kind).unwrap(), [1, 2, 3]);
+        let read = CompressionOptions::from_bytes(&[1, 2, 3], Compressor::None, &kind).unwrap();
+        assert_eq!(read, raw);
+    }
+
+    #[test]
+    fn test_describe() {
+        let xz = CompressionOptions::Xz(Xz {
+            dictionary_size: 0x8000,
+            filters: XzFilter(0),
+            bit_opts: None,
+            fb: None,
+        });
+        assert_eq!(xz.describe(), "xz: dict_size=32768, filters=none");
+
+        let xz = CompressionOptions::Xz(Xz {
+            dictionary_size: 0x2_0000,
+            filters: XzFilter(0x0009),
+            bit_opts: Some(0x10),
+            fb: Some(273),
+        });
+        assert_eq!(xz.describe(), "xz: dict_size=131072, filters=x86|arm, bit_opts=0x10, fb=273");
+
+        let gzip = CompressionOptions::Gzip(Gzip {
+            compression_level: 9,
+            window_size: 15,
+            strategies: 0x0001,
+        });
+        assert_eq!(gzip.describe(), "gzip: level=9, window=15, strategies=default");
+
+        let lz4 = CompressionOptions::Lz4(Lz4 { version: 1, flags: 0x0003 });
+        assert_eq!(lz4.describe(), "lz4: version=1, flags=hc|0x2");
+
+        let raw = CompressionOptions::Raw(vec![0x01, 0xab]);
+        assert_eq!(raw.describe(), "raw: 01ab");
+    }
+}
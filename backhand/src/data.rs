@@ -2,16 +2,19 @@
 
 use std::collections::HashMap;
 use std::io::{Read, Seek, Write};
+#[cfg(feature = "trace-compression")]
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use deku::prelude::*;
 use solana_nohash_hasher::IntMap;
 use tracing::trace;
 use xxhash_rust::xxh64::xxh64;
 
-use crate::compressor::CompressionAction;
+use crate::compressor::{CompressionAction, CompressionOptions, Compressor};
 use crate::error::BackhandError;
 use crate::filesystem::reader::SquashfsRawData;
-use crate::filesystem::writer::FilesystemCompressor;
+use crate::filesystem::writer::{FileCompression, FilesystemCompressor};
 use crate::fragment::Fragment;
 use crate::reader::WriteSeek;
 
@@ -65,12 +68,218 @@ impl DataSize {
     }
 }
 
+/// Data or fragment block written by [`FilesystemWriter`](crate::FilesystemWriter), see
+/// [`FilesystemWriter::set_compression_trace`](crate::FilesystemWriter::set_compression_trace)
+#[cfg(feature = "trace-compression")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CompressEvent {
+    /// Length of the uncompressed block
+    pub input_len: usize,
+    /// Length of the block as written
+    pub output_len: usize,
+    /// Compressor of the image
+    pub compressor: Compressor,
+    /// Block stored uncompressed, as compression didn't reduce its size or wasn't used
+    pub stored_uncompressed: bool,
+}
+
+/// Blocks written and compressor calls of [`FilesystemWriter::write_with_stats`](crate::FilesystemWriter::write_with_stats)
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct WriteStats {
+    /// Data and fragment blocks stored compressed
+    pub data_blocks_compressed: u64,
+    /// Data and fragment blocks stored uncompressed, as compression didn't reduce their size or
+    /// wasn't used
+    pub data_blocks_uncompressed: u64,
+    /// Metadata blocks stored compressed
+    pub metadata_blocks_compressed: u64,
+    /// Metadata blocks stored uncompressed
+    pub metadata_blocks_uncompressed: u64,
+    /// Calls of [`CompressionAction::compress`], not including the compression of
+    /// [`FilesystemWriter::set_eager_compression`](crate::FilesystemWriter::set_eager_compression)
+    pub compressor_calls: u64,
+    /// Total length of the bytes given to the compressor
+    pub compressor_input_bytes: u64,
+    /// Total length of the bytes returned by the compressor
+    pub compressor_output_bytes: u64,
+    /// Total time spent in the compressor
+    pub compressor_time: Duration,
+}
+
+impl WriteStats {
+    /// Ratio of the compressor output to input bytes over all calls, `None` without calls
+    pub fn compression_ratio(&self) -> Option<f64> {
+        (self.compressor_input_bytes != 0)
+            .then(|| self.compressor_output_bytes as f64 / self.compressor_input_bytes as f64)
+    }
+
+    /// Compress `bytes` with `kind`, counting the call
+    pub(crate) fn compress(
+        &mut self,
+        kind: &dyn CompressionAction,
+        bytes: &[u8],
+        fc: FilesystemCompressor,
+        block_size: u32,
+    ) -> Result<Vec<u8>, BackhandError> {
+        let start = Instant::now();
+        let cb = kind.compress(bytes, fc, block_size)?;
+        self.compressor_time += start.elapsed();
+        self.compressor_calls += 1;
+        self.compressor_input_bytes += bytes.len() as u64;
+        self.compressor_output_bytes += cb.len() as u64;
+        Ok(cb)
+    }
+
+    /// Count a written data or fragment block
+    pub(crate) fn add_data_block(&mut self, uncompressed: bool) {
+        if uncompressed {
+            self.data_blocks_uncompressed += 1;
+        } else {
+            self.data_blocks_compressed += 1;
+        }
+    }
+
+    /// Count a metadata block
+    pub(crate) fn add_metadata_block(&mut self, compressed: bool) {
+        if compressed {
+            self.metadata_blocks_compressed += 1;
+        } else {
+            self.metadata_blocks_uncompressed += 1;
+        }
+    }
+
+    /// Add the counts of `other`
+    pub(crate) fn add(&mut self, other: &Self) {
+        self.data_blocks_compressed += other.data_blocks_compressed;
+        self.data_blocks_uncompressed += other.data_blocks_uncompressed;
+        self.metadata_blocks_compressed += other.metadata_blocks_compressed;
+        self.metadata_blocks_uncompressed += other.metadata_blocks_uncompressed;
+        self.compressor_calls += other.compressor_calls;
+        self.compressor_input_bytes += other.compressor_input_bytes;
+        self.compressor_output_bytes += other.compressor_output_bytes;
+        self.compressor_time += other.compressor_time;
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Added {
     // Only Data was added
-    Data { blocks_start: u32, block_sizes: Vec<DataSize> },
+    Data {
+        blocks_start: u32,
+        block_sizes: Vec<DataSize>,
+    },
     // Only Fragment was added
-    Fragment { frag_index: u32, block_offset: u32 },
+    Fragment {
+        frag_index: u32,
+        block_offset: u32,
+    },
+    // Data was added, with the tail-end in a Fragment
+    DataAndFragment {
+        blocks_start: u32,
+        block_sizes: Vec<DataSize>,
+        frag_index: u32,
+        block_offset: u32,
+    },
+}
+
+/// File data compressed while being pushed, see
+/// [`FilesystemWriter::set_eager_compression`](crate::FilesystemWriter::set_eager_compression)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressedFile {
+    /// Compressor the blocks were compressed with
+    pub(crate) compressor: Compressor,
+    pub(crate) compression_options: Option<CompressionOptions>,
+    /// Block size the data was split into
+    pub(crate) block_size: u32,
+    pub(crate) file_compression: FileCompression,
+    pub(crate) file_len: usize,
+    /// Length and hash of the first chunk, used for duplicate file checking
+    pub(crate) first_len: usize,
+    pub(crate) hash: u64,
+    /// Compressed (or stored) full blocks
+    pub(crate) blocks: Vec<(DataSize, Vec<u8>)>,
+    /// Un-compressed tail-end, stored as a fragment or a block when writing
+    pub(crate) tail: Option<Vec<u8>>,
+}
+
+impl CompressedFile {
+    /// Read all of `reader`, compressing each full block
+    pub(crate) fn new(
+        kind: &dyn CompressionAction,
+        fs_compressor: FilesystemCompressor,
+        block_size: u32,
+        reader: impl Read,
+        file_compression: FileCompression,
+    ) -> Result<Self, BackhandError> {
+        let mut chunk_reader =
+            DataWriterChunkReader { chunk: vec![0u8; block_size as usize], file_len: 0, reader };
+
+        let mut chunk = chunk_reader.read_chunk()?;
+        let first_len = chunk.len();
+        let hash = xxh64(chunk, 0);
+
+        let mut blocks = vec![];
+        let mut tail = None;
+        loop {
+            // the tail-end is kept as is, until it is known if it's stored in a fragment
+            if chunk.len() != block_size as usize && file_compression == FileCompression::Default {
+                tail = Some(chunk.to_vec());
+                break;
+            }
+            if chunk.is_empty() {
+                break;
+            }
+
+            let cb = match file_compression {
+                FileCompression::Default => {
+                    Some(kind.compress(chunk, fs_compressor.clone(), block_size)?)
+                }
+                FileCompression::Store => None,
+            };
+            match cb {
+                // store compressed
+                Some(cb) if cb.len() <= chunk.len() => {
+                    blocks.push((DataSize::new_compressed(cb.len() as u32), cb));
+                }
+                // compression didn't reduce size or not requested, store uncompressed
+                _ => blocks.push((DataSize::new_uncompressed(chunk.len() as u32), chunk.to_vec())),
+            }
+            chunk = chunk_reader.read_chunk()?;
+        }
+
+        Ok(Self {
+            compressor: fs_compressor.id,
+            compression_options: fs_compressor.options,
+            block_size,
+            file_compression,
+            file_len: chunk_reader.file_len,
+            first_len,
+            hash,
+            blocks,
+            tail,
+        })
+    }
+
+    /// Decompress all blocks, returning the original file data
+    pub(crate) fn decompress(
+        &self,
+        kind: &dyn CompressionAction,
+    ) -> Result<Vec<u8>, BackhandError> {
+        let mut bytes = Vec::with_capacity(self.file_len);
+        for (size, block) in &self.blocks {
+            if size.uncompressed() {
+                bytes.extend_from_slice(block);
+            } else {
+                let mut out = Vec::with_capacity(self.block_size as usize);
+                kind.decompress(block, &mut out, self.compressor)?;
+                bytes.extend_from_slice(&out);
+            }
+        }
+        if let Some(tail) = &self.tail {
+            bytes.extend_from_slice(tail);
+        }
+        Ok(bytes)
+    }
 }
 
 struct DataWriterChunkReader<R: std::io::Read> {
@@ -104,12 +313,23 @@ pub(crate) struct DataWriter<'a> {
     kind: &'a dyn CompressionAction,
     block_size: u32,
     fs_compressor: FilesystemCompressor,
-    /// If some, cache of HashMap<file_len, HashMap<hash, (file_len, Added)>>
+    /// If some, cache of HashMap<file_len, HashMap<(hash, file_compression), (file_len, Added)>>
+    ///
+    /// Files with a different [`FileCompression`] are stored differently, and never duplicates.
     #[allow(clippy::type_complexity)]
-    dup_cache: Option<IntMap<u64, IntMap<u64, (usize, Added)>>>,
+    dup_cache: Option<IntMap<u64, HashMap<(u64, FileCompression), (usize, Added)>>>,
     /// Un-written fragment_bytes
     pub(crate) fragment_bytes: Vec<u8>,
     pub(crate) fragment_table: Vec<Fragment>,
+    /// If true, store the tail-end of files with data blocks in a fragment
+    always_fragment: bool,
+    /// If true, store fragment blocks uncompressed
+    fragments_uncompressed: bool,
+    /// If some, each written data and fragment block is recorded
+    #[cfg(feature = "trace-compression")]
+    pub(crate) compression_trace: Option<Arc<Mutex<Vec<CompressEvent>>>>,
+    /// Written blocks and compressor calls
+    pub(crate) stats: WriteStats,
 }
 
 impl<'a> DataWriter<'a> {
@@ -118,6 +338,8 @@ impl<'a> DataWriter<'a> {
         fs_compressor: FilesystemCompressor,
         block_size: u32,
         no_duplicate_files: bool,
+        always_fragment: bool,
+        fragments_uncompressed: bool,
     ) -> Self {
         Self {
             kind,
@@ -126,11 +348,53 @@ impl<'a> DataWriter<'a> {
             dup_cache: no_duplicate_files.then_some(HashMap::default()),
             fragment_bytes: Vec::with_capacity(block_size as usize),
             fragment_table: vec![],
+            always_fragment,
+            fragments_uncompressed,
+            #[cfg(feature = "trace-compression")]
+            compression_trace: None,
+            stats: WriteStats::default(),
+        }
+    }
+
+    /// Record a block of `input_len` uncompressed bytes written with `size`, counting it and
+    /// tracing it if enabled
+    #[cfg_attr(not(feature = "trace-compression"), allow(unused_variables))]
+    fn trace_block(&mut self, input_len: usize, size: DataSize) {
+        self.stats.add_data_block(size.uncompressed());
+        #[cfg(feature = "trace-compression")]
+        if let Some(trace) = &self.compression_trace {
+            trace.lock().unwrap().push(CompressEvent {
+                input_len,
+                output_len: size.size() as usize,
+                compressor: self.fs_compressor.id,
+                stored_uncompressed: size.uncompressed(),
+            });
         }
     }
 
-    /// Add to data writer, either a pre-compressed Data or Fragment
-    // TODO: support tail-end fragments (off by default in squashfs-tools/mksquashfs)
+    /// Add `bytes` to the current fragment_bytes, first calling [`Self::finalize`] if they don't fit
+    ///
+    /// Returns the `(frag_index, block_offset)` of the added bytes.
+    fn add_fragment<W: WriteSeek>(
+        &mut self,
+        bytes: &[u8],
+        writer: W,
+    ) -> Result<(u32, u32), BackhandError> {
+        // if this doesn't fit in the current fragment bytes
+        // compress the current fragment bytes and add to data_bytes
+        if (bytes.len() + self.fragment_bytes.len()) > self.block_size as usize {
+            self.finalize(writer)?;
+        }
+
+        // add to fragment bytes
+        let frag_index = self.fragment_table.len() as u32;
+        let block_offset = self.fragment_bytes.len() as u32;
+        self.fragment_bytes.extend_from_slice(bytes);
+
+        Ok((frag_index, block_offset))
+    }
+
+    /// Add to data writer, either a pre-compressed Data or Fragment, or both with `always_fragment`
     pub(crate) fn just_copy_it<W: WriteSeek>(
         &mut self,
         mut reader: SquashfsRawData,
@@ -153,56 +417,78 @@ impl<'a> DataWriter<'a> {
         // write and early return if fragment
         if first_block.fragment {
             reader.decompress(first_block, &mut read_buf, &mut decompress_buf)?;
-            // if this doesn't fit in the current fragment bytes
-            // compress the current fragment bytes and add to data_bytes
-            if (decompress_buf.len() + self.fragment_bytes.len()) > self.block_size as usize {
-                self.finalize(writer)?;
-            }
-            // add to fragment bytes
-            let frag_index = self.fragment_table.len() as u32;
-            let block_offset = self.fragment_bytes.len() as u32;
-            self.fragment_bytes.write_all(&decompress_buf)?;
+            let (frag_index, block_offset) = self.add_fragment(&decompress_buf, writer)?;
 
             return Ok((decompress_buf.len(), Added::Fragment { frag_index, block_offset }));
         }
 
         //if is a block, just copy it
         writer.write_all(&read_buf)?;
+        self.stats.add_data_block(first_block.uncompressed);
+        let mut tail_fragment = None;
         while let Some(block) = reader.next_block(&mut read_buf) {
             let block = block?;
-            if block.fragment {
+            if block.fragment && self.always_fragment {
+                reader.decompress(block, &mut read_buf, &mut decompress_buf)?;
+                tail_fragment = Some(self.add_fragment(&decompress_buf, &mut writer)?);
+            } else if block.fragment {
                 reader.decompress(block, &mut read_buf, &mut decompress_buf)?;
-                // TODO: support tail-end fragments, for now just treat it like a block
-                let cb =
-                    self.kind.compress(&decompress_buf, self.fs_compressor, self.block_size)?;
+                // without always_fragment, treat the tail-end like a block
+                let cb = self.stats.compress(
+                    self.kind,
+                    &decompress_buf,
+                    self.fs_compressor.clone(),
+                    self.block_size,
+                )?;
                 // compression didn't reduce size
-                if cb.len() > decompress_buf.len() {
+                let size = if cb.len() > decompress_buf.len() {
                     // store uncompressed
-                    block_sizes.push(DataSize::new_uncompressed(decompress_buf.len() as u32));
                     writer.write_all(&decompress_buf)?;
+                    DataSize::new_uncompressed(decompress_buf.len() as u32)
                 } else {
                     // store compressed
-                    block_sizes.push(DataSize::new_compressed(cb.len() as u32));
                     writer.write_all(&cb)?;
-                }
+                    DataSize::new_compressed(cb.len() as u32)
+                };
+                self.trace_block(decompress_buf.len(), size);
+                block_sizes.push(size);
             } else {
                 //if is a block, just copy it
                 writer.write_all(&read_buf)?;
+                self.stats.add_data_block(block.uncompressed);
             }
         }
         let file_size = reader.file.file.file_len();
-        Ok((file_size, Added::Data { blocks_start, block_sizes }))
+        let added = match tail_fragment {
+            Some((frag_index, block_offset)) => {
+                Added::DataAndFragment { blocks_start, block_sizes, frag_index, block_offset }
+            }
+            None => Added::Data { blocks_start, block_sizes },
+        };
+        Ok((file_size, added))
     }
 
-    /// Add to data writer, either a Data or Fragment
+    /// Add to data writer, either a Data or Fragment, or both with `always_fragment`
     ///
     /// If `self.dup_cache` is on, return alrady added `(usize, Added)` if duplicate
     /// is found
-    // TODO: support tail-end fragments (off by default in squashfs-tools/mksquashfs)
     pub(crate) fn add_bytes<W: WriteSeek>(
+        &mut self,
+        reader: impl Read,
+        writer: W,
+    ) -> Result<(usize, Added), BackhandError> {
+        self.add_bytes_with_compression(reader, writer, FileCompression::Default)
+    }
+
+    /// Same as [`Self::add_bytes`], but with a per-file `file_compression` override
+    ///
+    /// With [`FileCompression::Store`], fragments are not used and all blocks are written
+    /// uncompressed.
+    pub(crate) fn add_bytes_with_compression<W: WriteSeek>(
         &mut self,
         reader: impl Read,
         mut writer: W,
+        file_compression: FileCompression,
     ) -> Result<(usize, Added), BackhandError> {
         let mut chunk_reader = DataWriterChunkReader {
             chunk: vec![0u8; self.block_size as usize],
@@ -214,17 +500,8 @@ impl<'a> DataWriter<'a> {
         let mut chunk = chunk_reader.read_chunk()?;
 
         // chunk size not exactly the size of the block
-        if chunk.len() != self.block_size as usize {
-            // if this doesn't fit in the current fragment bytes
-            // compress the current fragment bytes and add to data_bytes
-            if (chunk.len() + self.fragment_bytes.len()) > self.block_size as usize {
-                self.finalize(writer)?;
-            }
-
-            // add to fragment bytes
-            let frag_index = self.fragment_table.len() as u32;
-            let block_offset = self.fragment_bytes.len() as u32;
-            self.fragment_bytes.write_all(chunk)?;
+        if chunk.len() != self.block_size as usize && file_compression == FileCompression::Default {
+            let (frag_index, block_offset) = self.add_fragment(chunk, writer)?;
 
             return Ok((chunk_reader.file_len, Added::Fragment { frag_index, block_offset }));
         }
@@ -233,61 +510,173 @@ impl<'a> DataWriter<'a> {
         let blocks_start = writer.stream_position()? as u32;
         let mut block_sizes = vec![];
 
-        // If duplicate file checking is enabled, use the old data position as this file if it hashes the same
-        if let Some(dup_cache) = &self.dup_cache {
-            if let Some(c) = dup_cache.get(&(chunk.len() as u64)) {
-                let hash = xxh64(chunk, 0);
-                if let Some(res) = c.get(&hash) {
-                    trace!("duplicate file data found");
-                    return Ok(res.clone());
-                }
-            }
-        }
-
         // Save information needed to add to duplicate_cache later
         let chunk_len = chunk.len();
         let hash = xxh64(chunk, 0);
 
+        // If duplicate file checking is enabled, use the old data position as this file if it hashes the same
+        if let Some(res) = self.duplicate(chunk_len, hash, file_compression) {
+            return Ok(res);
+        }
+
+        let mut tail_fragment = None;
         while !chunk.is_empty() {
-            let cb = self.kind.compress(chunk, self.fs_compressor, self.block_size)?;
+            // with always_fragment, the tail-end is stored in a fragment
+            if chunk.len() != self.block_size as usize
+                && self.always_fragment
+                && file_compression == FileCompression::Default
+            {
+                tail_fragment = Some(self.add_fragment(chunk, &mut writer)?);
+                break;
+            }
 
-            // compression didn't reduce size
-            if cb.len() > chunk.len() {
-                // store uncompressed
-                block_sizes.push(DataSize::new_uncompressed(chunk.len() as u32));
-                writer.write_all(chunk)?;
-            } else {
+            let cb = match file_compression {
+                FileCompression::Default => Some(self.stats.compress(
+                    self.kind,
+                    chunk,
+                    self.fs_compressor.clone(),
+                    self.block_size,
+                )?),
+                FileCompression::Store => None,
+            };
+
+            let size = match cb {
                 // store compressed
-                block_sizes.push(DataSize::new_compressed(cb.len() as u32));
-                writer.write_all(&cb)?;
-            }
+                Some(cb) if cb.len() <= chunk.len() => {
+                    writer.write_all(&cb)?;
+                    DataSize::new_compressed(cb.len() as u32)
+                }
+                // compression didn't reduce size or not requested, store uncompressed
+                _ => {
+                    writer.write_all(chunk)?;
+                    DataSize::new_uncompressed(chunk.len() as u32)
+                }
+            };
+            self.trace_block(chunk.len(), size);
+            block_sizes.push(size);
             chunk = chunk_reader.read_chunk()?;
         }
 
         // Add to duplicate information cache
-        let added = (chunk_reader.file_len, Added::Data { blocks_start, block_sizes });
+        let added = match tail_fragment {
+            Some((frag_index, block_offset)) => {
+                Added::DataAndFragment { blocks_start, block_sizes, frag_index, block_offset }
+            }
+            None => Added::Data { blocks_start, block_sizes },
+        };
+        let added = (chunk_reader.file_len, added);
+        self.add_duplicate(chunk_len, hash, file_compression, &added);
+        Ok(added)
+    }
 
-        // If duplicate files checking is enbaled, then add this to it's memory
-        if let Some(dup_cache) = &mut self.dup_cache {
-            if let Some(entry) = dup_cache.get_mut(&(chunk_len as u64)) {
-                entry.insert(hash, added.clone());
-            } else {
-                let mut hashmap = IntMap::default();
-                hashmap.insert(hash, added.clone());
-                dup_cache.insert(chunk_len as u64, hashmap);
+    /// Add to data writer the already compressed blocks of `file`, storing the tail-end like
+    /// [`Self::add_bytes_with_compression`]
+    pub(crate) fn add_compressed<W: WriteSeek>(
+        &mut self,
+        file: &CompressedFile,
+        mut writer: W,
+    ) -> Result<(usize, Added), BackhandError> {
+        // smaller than a block, only a fragment
+        if file.blocks.is_empty() {
+            if let Some(tail) = &file.tail {
+                let (frag_index, block_offset) = self.add_fragment(tail, writer)?;
+                return Ok((file.file_len, Added::Fragment { frag_index, block_offset }));
+            }
+        }
+
+        let blocks_start = writer.stream_position()? as u32;
+        if let Some(res) = self.duplicate(file.first_len, file.hash, file.file_compression) {
+            return Ok(res);
+        }
+
+        let mut block_sizes = Vec::with_capacity(file.blocks.len() + 1);
+        for (size, block) in &file.blocks {
+            self.trace_block(self.block_size as usize, *size);
+            block_sizes.push(*size);
+            writer.write_all(block)?;
+        }
+
+        let mut tail_fragment = None;
+        match &file.tail {
+            Some(tail) if tail.is_empty() => {}
+            Some(tail) if self.always_fragment => {
+                tail_fragment = Some(self.add_fragment(tail, &mut writer)?);
             }
+            Some(tail) => {
+                let cb = self.stats.compress(
+                    self.kind,
+                    tail,
+                    self.fs_compressor.clone(),
+                    self.block_size,
+                )?;
+                let size = if cb.len() <= tail.len() {
+                    writer.write_all(&cb)?;
+                    DataSize::new_compressed(cb.len() as u32)
+                } else {
+                    writer.write_all(tail)?;
+                    DataSize::new_uncompressed(tail.len() as u32)
+                };
+                self.trace_block(tail.len(), size);
+                block_sizes.push(size);
+            }
+            None => {}
         }
+
+        let added = match tail_fragment {
+            Some((frag_index, block_offset)) => {
+                Added::DataAndFragment { blocks_start, block_sizes, frag_index, block_offset }
+            }
+            None => Added::Data { blocks_start, block_sizes },
+        };
+        let added = (file.file_len, added);
+        self.add_duplicate(file.first_len, file.hash, file.file_compression, &added);
         Ok(added)
     }
 
+    /// If duplicate file checking is enabled, the already added file with the same first chunk
+    fn duplicate(
+        &self,
+        chunk_len: usize,
+        hash: u64,
+        file_compression: FileCompression,
+    ) -> Option<(usize, Added)> {
+        let res =
+            self.dup_cache.as_ref()?.get(&(chunk_len as u64))?.get(&(hash, file_compression))?;
+        trace!("duplicate file data found");
+        Some(res.clone())
+    }
+
+    /// If duplicate files checking is enabled, then add this to it's memory
+    fn add_duplicate(
+        &mut self,
+        chunk_len: usize,
+        hash: u64,
+        file_compression: FileCompression,
+        added: &(usize, Added),
+    ) {
+        if let Some(dup_cache) = &mut self.dup_cache {
+            let files = dup_cache.entry(chunk_len as u64).or_default();
+            files.insert((hash, file_compression), added.clone());
+        }
+    }
+
     /// Compress the fragments that were under length, write to data, add to fragment table, clear
     /// current fragment_bytes
     pub fn finalize<W: Write + Seek>(&mut self, mut writer: W) -> Result<(), BackhandError> {
         let start = writer.stream_position()?;
-        let cb = self.kind.compress(&self.fragment_bytes, self.fs_compressor, self.block_size)?;
+        let cb = if self.fragments_uncompressed {
+            vec![]
+        } else {
+            self.stats.compress(
+                self.kind,
+                &self.fragment_bytes,
+                self.fs_compressor.clone(),
+                self.block_size,
+            )?
+        };
 
-        // compression didn't reduce size
-        let size = if cb.len() > self.fragment_bytes.len() {
+        // compression didn't reduce size or not requested
+        let size = if self.fragments_uncompressed || cb.len() > self.fragment_bytes.len() {
             // store uncompressed
             writer.write_all(&self.fragment_bytes)?;
             DataSize::new_uncompressed(self.fragment_bytes.len() as u32)
@@ -296,6 +685,7 @@ impl<'a> DataWriter<'a> {
             writer.write_all(&cb)?;
             DataSize::new_compressed(cb.len() as u32)
         };
+        self.trace_block(self.fragment_bytes.len(), size);
         self.fragment_table.push(Fragment::new(start, size, 0));
         self.fragment_bytes.clear();
         Ok(())
@@ -320,6 +710,8 @@ mod tests {
             FilesystemCompressor::new(Compressor::Gzip, None).unwrap(),
             DEFAULT_BLOCK_SIZE,
             true,
+            false,
+            false,
         );
         let bytes = [0xff_u8; DEFAULT_BLOCK_SIZE as usize * 2];
         let mut writer = Cursor::new(vec![]);
@@ -336,6 +728,8 @@ mod tests {
             FilesystemCompressor::new(Compressor::Gzip, None).unwrap(),
             DEFAULT_BLOCK_SIZE,
             false,
+            false,
+            false,
         );
         let bytes = [0xff_u8; DEFAULT_BLOCK_SIZE as usize * 2];
         let mut writer = Cursor::new(vec![]);
@@ -8,7 +8,6 @@ use std::path::{Component, Path};
 
 use deku::prelude::*;
 
-use crate::inode::InodeId;
 use crate::unix_string::OsStrExt;
 use crate::BackhandError;
 
@@ -54,8 +53,9 @@ pub struct DirEntry {
     pub(crate) offset: u16,
     /// The difference of this inode’s number to the reference stored in the header.
     pub(crate) inode_offset: i16,
-    /// The inode type. For extended inodes, the basic type is stored here instead.
-    pub(crate) t: InodeId,
+    /// The [`InodeId`](crate::InodeId) of the inode type. For extended inodes, the basic type is stored here
+    /// instead. Kept as stored, as the type can be unknown to backhand.
+    pub(crate) t: u16,
     /// One less than the size of the entry name.
     pub(crate) name_size: u16,
     // TODO: CString
@@ -95,14 +95,15 @@ pub struct DirectoryIndex {
 }
 
 impl DirectoryIndex {
-    pub fn name(&self) -> String {
-        std::str::from_utf8(&self.name).unwrap().to_string()
+    pub fn name(&self) -> &OsStr {
+        OsStr::from_bytes(&self.name)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::inode::InodeId;
 
     #[test]
     fn no_invalid_dir_entry() {
@@ -110,7 +111,7 @@ mod tests {
         let dir = DirEntry {
             offset: 0x300,
             inode_offset: 0x0,
-            t: InodeId::BasicDirectory,
+            t: InodeId::BasicDirectory as u16,
             name_size: 0x1,
             name: b"/".to_vec(),
         };
@@ -120,7 +121,7 @@ mod tests {
         let dir = DirEntry {
             offset: 0x300,
             inode_offset: 0x0,
-            t: InodeId::BasicDirectory,
+            t: InodeId::BasicDirectory as u16,
             name_size: 0x1,
             name: b"/nice/".to_vec(),
         };
@@ -2,7 +2,7 @@ use std::ffi::OsStr;
 use std::fmt;
 
 use crate::data::Added;
-use crate::dir::{Dir, DirEntry};
+use crate::dir::{Dir, DirEntry, DirectoryIndex};
 use crate::inode::{
     BasicDeviceSpecialFile, BasicDirectory, BasicFile, BasicSymlink, ExtendedDirectory, IPCNode,
     Inode, InodeHeader, InodeId, InodeInner,
@@ -24,8 +24,8 @@ pub(crate) struct Entry<'a> {
 }
 
 impl<'a> Entry<'a> {
-    pub fn name(&self) -> String {
-        std::str::from_utf8(self.name).unwrap().to_string()
+    pub fn name(&self) -> &OsStr {
+        OsStr::from_bytes(self.name)
     }
 
     /// Write data and metadata for path node (Basic Directory or ExtendedDirectory)
@@ -40,6 +40,7 @@ impl<'a> Entry<'a> {
         file_size: usize,
         block_offset: u16,
         block_index: u32,
+        dir_index: Vec<DirectoryIndex>,
         superblock: &SuperBlock,
         kind: &Kind,
         id_table: &[Id],
@@ -53,8 +54,9 @@ impl<'a> Entry<'a> {
             permissions: header.permissions,
             mtime: header.mtime,
         };
-        // if entry won't fit in file_size of regular dir entry, create extended directory
-        let dir_inode = if file_size > u16::MAX as usize {
+        // if entry won't fit in file_size of regular dir entry or has a directory index, create
+        // extended directory
+        let dir_inode = if file_size > u16::MAX as usize || !dir_index.is_empty() {
             Inode::new(
                 InodeId::ExtendedDirectory,
                 header,
@@ -63,13 +65,11 @@ impl<'a> Entry<'a> {
                     file_size: file_size.try_into().unwrap(), // u32
                     block_index,
                     parent_inode,
-                    // TODO: Support Directory Index
-                    index_count: 0,
+                    index_count: dir_index.len().try_into().unwrap(),
                     block_offset,
                     // TODO(#32): Support xattr
                     xattr_index: 0xffff_ffff,
-                    // TODO: Support Directory Index
-                    dir_index: vec![],
+                    dir_index,
                 }),
             )
         } else {
@@ -128,6 +128,15 @@ impl<'a> Entry<'a> {
                 file_size: file_size.try_into().unwrap(),
                 block_sizes: vec![],
             },
+            Added::DataAndFragment { blocks_start, block_sizes, frag_index, block_offset } => {
+                BasicFile {
+                    blocks_start: *blocks_start,
+                    frag_index: *frag_index,
+                    block_offset: *block_offset,
+                    file_size: file_size.try_into().unwrap(),
+                    block_sizes: block_sizes.to_vec(),
+                }
+            }
         };
 
         let file_inode = Inode::new(InodeId::BasicFile, header, InodeInner::BasicFile(basic_file));
@@ -141,6 +150,7 @@ impl<'a> Entry<'a> {
         node_path: &'a OsStr,
         header: NodeHeader,
         symlink: &SquashfsSymlink,
+        link_count: u32,
         inode: u32,
         inode_writer: &mut MetadataWriter,
         superblock: &SuperBlock,
@@ -161,7 +171,7 @@ impl<'a> Entry<'a> {
             InodeId::BasicSymlink,
             header,
             InodeInner::BasicSymlink(BasicSymlink {
-                link_count: 0x1,
+                link_count,
                 target_size: link.len().try_into().unwrap(),
                 target_path: link.to_vec(),
             }),
@@ -323,7 +333,7 @@ impl Entry<'_> {
             let new_entry = DirEntry {
                 offset: e.offset,
                 inode_offset: (inode - lowest_inode).try_into().unwrap(),
-                t: e.t.into_base_type(),
+                t: e.t.into_base_type() as u16,
                 name_size: e.name_size,
                 name: e.name.to_vec(),
             };
@@ -338,6 +348,7 @@ impl Entry<'_> {
         let mut dirs = vec![];
         let mut creating_dir = vec![];
         let mut lowest_inode = u32::MAX;
+        let mut highest_inode = 0;
         let mut iter = entries.iter().peekable();
         let mut creating_start = if let Some(entry) = iter.peek() {
             entry.start
@@ -346,15 +357,16 @@ impl Entry<'_> {
         };
 
         while let Some(e) = iter.next() {
-            if e.inode < lowest_inode {
-                lowest_inode = e.inode;
-            }
+            lowest_inode = lowest_inode.min(e.inode);
+            highest_inode = highest_inode.max(e.inode);
             creating_dir.push(e);
 
             // last entry
             if let Some(next) = &iter.peek() {
-                // if the next entry would be > the lowest_inode
-                let max_inode = (next.inode as u64).abs_diff(lowest_inode as u64) > i16::MAX as u64;
+                // if the next entry would be too far from the lowest_inode, such as when inode
+                // numbers are not in the order of the entries
+                let max_inode =
+                    highest_inode.max(next.inode) - lowest_inode.min(next.inode) > i16::MAX as u32;
                 // make sure entries have the correct start and amount of directories
                 if next.start != creating_start || creating_dir.len() >= 255 || max_inode {
                     let dir = Self::create_dir(&creating_dir, creating_start, lowest_inode);
@@ -362,6 +374,7 @@ impl Entry<'_> {
                     creating_dir = vec![];
                     creating_start = next.start;
                     lowest_inode = u32::MAX;
+                    highest_inode = 0;
                 }
             }
             // last entry
@@ -418,7 +431,7 @@ mod tests {
                     dir_entries: vec![DirEntry {
                         offset: 0x100,
                         inode_offset: 0x0,
-                        t: InodeId::BasicDirectory,
+                        t: InodeId::BasicDirectory as u16,
                         name_size: 0x1,
                         name: b"aa".to_vec(),
                     },],
@@ -431,14 +444,14 @@ mod tests {
                         DirEntry {
                             offset: 0x300,
                             inode_offset: 0x0,
-                            t: InodeId::BasicDirectory,
+                            t: InodeId::BasicDirectory as u16,
                             name_size: 0x1,
                             name: b"bb".to_vec(),
                         },
                         DirEntry {
                             offset: 0x200,
                             inode_offset: 0x1,
-                            t: InodeId::BasicDirectory,
+                            t: InodeId::BasicDirectory as u16,
                             name_size: 0x1,
                             name: b"zz".to_vec(),
                         },
@@ -448,4 +461,18 @@ mod tests {
             dir
         );
     }
+
+    #[test]
+    fn test_entry_non_utf8_name() {
+        let entry = Entry {
+            start: 0,
+            offset: 0,
+            inode: 1,
+            t: InodeId::BasicFile,
+            name_size: 0x1,
+            name: b"\xff\xfe",
+        };
+        assert_eq!(entry.name().as_bytes(), b"\xff\xfe");
+        assert!(format!("{entry:?}").contains(r#"name: "\xFF\xFE""#));
+    }
 }
@@ -1,6 +1,7 @@
 //! Errors
 
 use std::collections::TryReserveError;
+use std::path::PathBuf;
 use std::{io, string};
 
 use thiserror::Error;
@@ -29,6 +30,9 @@ pub enum BackhandError {
     #[error("file not found")]
     FileNotFound,
 
+    #[error("not a file: {0}")]
+    NotAFile(PathBuf),
+
     #[error("branch was thought to be unreachable")]
     Unreachable,
 
@@ -38,6 +42,9 @@ pub enum BackhandError {
     #[error("unsupported inode: {0:?}, please fill github issue to add support")]
     UnsupportedInode(InodeInner),
 
+    #[error("unknown inode type: {0}")]
+    UnknownInode(u16),
+
     #[error("corrupted or invalid squashfs image")]
     CorruptedOrInvalidSquashfs,
 
@@ -53,11 +60,38 @@ pub enum BackhandError {
     #[error("file duplicated in squashfs image")]
     DuplicatedFileName,
 
+    #[error("inode number {0} duplicated in squashfs image")]
+    DuplicatedInodeNumber(u32),
+
+    #[error("inode number {0} not within the {1} inodes of the squashfs image")]
+    InvalidInodeNumber(u32, u32),
+
+    #[error("file of an image not opened with FilesystemWriter::open_existing")]
+    ImageNotOpen,
+
     #[error("allocator try_reserve error")]
     TryReserveError(#[from] TryReserveError),
 
     #[error("invalid id_table for node")]
     InvalidIdTable,
+
+    #[error("image not mountable by the linux kernel: {0}")]
+    KernelIncompatible(&'static str),
+
+    #[error("read was cancelled")]
+    Cancelled,
+
+    #[error("image has no nodes other than the root")]
+    EmptyImage,
+
+    #[error("extract limit of {0} bytes exceeded")]
+    ExtractLimitExceeded(u64),
+
+    #[error("image doesn't fit into {0} bytes")]
+    ImageTooLarge(u64),
+
+    #[error("device number {0:#x} has a major over 12 bits or a minor over 20 bits")]
+    InvalidDeviceNumber(u64),
 }
 
 impl From<BackhandError> for io::Error {
@@ -69,17 +103,26 @@ impl From<BackhandError> for io::Error {
             StrUtf8(_) => Self::from(io::ErrorKind::InvalidData),
             UnsupportedCompression(_) => Self::from(io::ErrorKind::Unsupported),
             FileNotFound => Self::from(io::ErrorKind::NotFound),
+            NotAFile(_) | EmptyImage | ImageTooLarge(_) | InvalidDeviceNumber(_) | ImageNotOpen => {
+                Self::from(io::ErrorKind::InvalidInput)
+            }
             Unreachable
             | Deku(_)
             | UnexpectedInode(_)
             | UnsupportedInode(_)
+            | UnknownInode(_)
             | CorruptedOrInvalidSquashfs
             | InvalidCompressionOption
             | InvalidFilePath
             | UndefineFileName
             | DuplicatedFileName
+            | DuplicatedInodeNumber(_)
+            | InvalidInodeNumber(_, _)
             | InvalidIdTable
+            | KernelIncompatible(_)
             | TryReserveError(_) => Self::from(io::ErrorKind::InvalidData),
+            Cancelled => Self::other(Cancelled),
+            ExtractLimitExceeded(limit) => Self::other(ExtractLimitExceeded(limit)),
         }
     }
 }
@@ -0,0 +1,219 @@
+//! C FFI for reading images
+//!
+//! Open an image with [`backhand_open`], and close it with [`backhand_close`]. While open, nodes
+//! can be listed with [`backhand_list`] and file contents read with [`backhand_read_file`].
+//!
+//! All fallible functions return a [`BackhandErrorCode`], with [`BackhandErrorCode::Ok`] on
+//! success. Panics are caught before unwinding into the caller, and returned as
+//! [`BackhandErrorCode::Panic`].
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString, OsStr};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use crate::error::BackhandError;
+use crate::filesystem::normalize_squashfs_path;
+use crate::unix_string::OsStrExt;
+use crate::{FilesystemReader, InnerNode};
+
+/// Opaque handle to an open image
+pub struct BackhandFilesystem {
+    reader: FilesystemReader<'static>,
+}
+
+/// Error codes returned from the FFI functions
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BackhandErrorCode {
+    Ok = 0,
+    /// A pointer argument was null, or a string argument wasn't valid
+    InvalidArgument = 1,
+    /// [`BackhandError::StdIo`]
+    Io = 2,
+    /// [`BackhandError::FileNotFound`]
+    NotFound = 3,
+    /// [`BackhandError::UnsupportedCompression`]
+    UnsupportedCompression = 4,
+    /// [`BackhandError::CorruptedOrInvalidSquashfs`] and other errors from an invalid image
+    Corrupted = 5,
+    /// The path is not a regular file
+    NotAFile = 6,
+    /// The list callback stopped the listing
+    Stopped = 7,
+    /// backhand panicked
+    Panic = 8,
+}
+
+impl From<&BackhandError> for BackhandErrorCode {
+    fn from(value: &BackhandError) -> Self {
+        match value {
+            BackhandError::StdIo(_) => Self::Io,
+            BackhandError::FileNotFound => Self::NotFound,
+            BackhandError::UnsupportedCompression(_) => Self::UnsupportedCompression,
+            BackhandError::NotAFile(_) => Self::NotAFile,
+            _ => Self::Corrupted,
+        }
+    }
+}
+
+/// Run `f`, returning [`BackhandErrorCode::Panic`] instead of unwinding across the FFI boundary
+fn catch_panic(f: impl FnOnce() -> BackhandErrorCode) -> BackhandErrorCode {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(BackhandErrorCode::Panic)
+}
+
+/// Kind of node given to the [`BackhandListCallback`]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BackhandNodeKind {
+    File = 0,
+    Symlink = 1,
+    Dir = 2,
+    CharacterDevice = 3,
+    BlockDevice = 4,
+    NamedPipe = 5,
+    Socket = 6,
+}
+
+/// Called with the full path of each node, returning non-zero stops the listing
+pub type BackhandListCallback =
+    extern "C" fn(path: *const c_char, kind: BackhandNodeKind, user_data: *mut c_void) -> c_int;
+
+/// Open the image at `path`, starting at `offset`, into `out`
+///
+/// # Safety
+/// `path` must be a valid nul-terminated string, and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn backhand_open(
+    path: *const c_char,
+    offset: u64,
+    out: *mut *mut BackhandFilesystem,
+) -> BackhandErrorCode {
+    catch_panic(|| {
+        if path.is_null() || out.is_null() {
+            return BackhandErrorCode::InvalidArgument;
+        }
+        let path = Path::new(OsStr::from_bytes(CStr::from_ptr(path).to_bytes()));
+
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(_) => return BackhandErrorCode::Io,
+        };
+        match FilesystemReader::from_reader_with_offset(BufReader::new(file), offset) {
+            Ok(reader) => {
+                *out = Box::into_raw(Box::new(BackhandFilesystem { reader }));
+                BackhandErrorCode::Ok
+            }
+            Err(e) => (&e).into(),
+        }
+    })
+}
+
+/// Close `fs` from [`backhand_open`]
+///
+/// # Safety
+/// `fs` must be from [`backhand_open`] and not already closed, or null.
+#[no_mangle]
+pub unsafe extern "C" fn backhand_close(fs: *mut BackhandFilesystem) {
+    if !fs.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(fs))));
+    }
+}
+
+/// Call `callback` with each node of `fs`, passing along `user_data`
+///
+/// The path given to `callback` is only valid during the call.
+///
+/// # Safety
+/// `fs` must be from [`backhand_open`] and not closed.
+#[no_mangle]
+pub unsafe extern "C" fn backhand_list(
+    fs: *const BackhandFilesystem,
+    callback: BackhandListCallback,
+    user_data: *mut c_void,
+) -> BackhandErrorCode {
+    catch_panic(|| {
+        let Some(fs) = fs.as_ref() else {
+            return BackhandErrorCode::InvalidArgument;
+        };
+
+        for node in fs.reader.files() {
+            let kind = match node.inner {
+                InnerNode::File(_) => BackhandNodeKind::File,
+                InnerNode::Symlink(_) => BackhandNodeKind::Symlink,
+                InnerNode::Dir(_) => BackhandNodeKind::Dir,
+                InnerNode::CharacterDevice(_) => BackhandNodeKind::CharacterDevice,
+                InnerNode::BlockDevice(_) => BackhandNodeKind::BlockDevice,
+                InnerNode::NamedPipe => BackhandNodeKind::NamedPipe,
+                InnerNode::Socket => BackhandNodeKind::Socket,
+            };
+            let Ok(path) = CString::new(node.fullpath.as_os_str().as_bytes()) else {
+                return BackhandErrorCode::Corrupted;
+            };
+            if callback(path.as_ptr(), kind, user_data) != 0 {
+                return BackhandErrorCode::Stopped;
+            }
+        }
+
+        BackhandErrorCode::Ok
+    })
+}
+
+/// Read the contents of the file at `path` in `fs` into a new buffer, written to `out_buf` and
+/// `out_len`
+///
+/// The buffer must be freed with [`backhand_free_buffer`].
+///
+/// # Safety
+/// `fs` must be from [`backhand_open`] and not closed, `path` must be a valid nul-terminated
+/// string, and `out_buf` and `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn backhand_read_file(
+    fs: *const BackhandFilesystem,
+    path: *const c_char,
+    out_buf: *mut *mut u8,
+    out_len: *mut usize,
+) -> BackhandErrorCode {
+    catch_panic(|| {
+        let Some(fs) = fs.as_ref() else {
+            return BackhandErrorCode::InvalidArgument;
+        };
+        if path.is_null() || out_buf.is_null() || out_len.is_null() {
+            return BackhandErrorCode::InvalidArgument;
+        }
+        let path = Path::new(OsStr::from_bytes(CStr::from_ptr(path).to_bytes()));
+        let Ok(path) = normalize_squashfs_path(path) else {
+            return BackhandErrorCode::InvalidArgument;
+        };
+
+        let Some(node) = fs.reader.files().find(|node| node.fullpath == path) else {
+            return BackhandErrorCode::NotFound;
+        };
+        let InnerNode::File(file) = &node.inner else {
+            return BackhandErrorCode::NotAFile;
+        };
+
+        let mut bytes = vec![];
+        if let Err(e) = fs.reader.file(file).reader().read_to_end(&mut bytes) {
+            return (&BackhandError::StdIo(e)).into();
+        }
+
+        let bytes = bytes.into_boxed_slice();
+        *out_len = bytes.len();
+        *out_buf = Box::into_raw(bytes).cast();
+        BackhandErrorCode::Ok
+    })
+}
+
+/// Free `buf` of `len` from [`backhand_read_file`]
+///
+/// # Safety
+/// `buf` and `len` must be from [`backhand_read_file`] and not already freed, or `buf` is null.
+#[no_mangle]
+pub unsafe extern "C" fn backhand_free_buffer(buf: *mut u8, len: usize) {
+    if !buf.is_null() {
+        let buf = std::ptr::slice_from_raw_parts_mut(buf, len);
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(buf))));
+    }
+}
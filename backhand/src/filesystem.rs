@@ -1,4 +1,6 @@
 //! In-memory representation of SquashFS filesystem tree used for writing to image
+#[cfg(unix)]
+pub mod extract;
 pub mod node;
 pub mod reader;
 pub mod writer;
@@ -7,8 +9,11 @@ use std::path::{Component, Path, PathBuf};
 
 use crate::BackhandError;
 
+/// Longest name of a directory entry, as its `name_size` is stored as the length minus one
+pub(crate) const MAX_NAME_LEN: usize = 256;
+
 // normalize the path, always starts with root, solve relative paths and don't
-// allow prefix (windows stuff like "C:/")
+// allow prefix (windows stuff like "C:/") or names longer than MAX_NAME_LEN
 pub fn normalize_squashfs_path(src: &Path) -> Result<PathBuf, BackhandError> {
     //always starts with root "/"
     let mut ret = PathBuf::from(Component::RootDir.as_os_str());
@@ -21,6 +26,9 @@ pub fn normalize_squashfs_path(src: &Path) -> Result<PathBuf, BackhandError> {
             Component::ParentDir => {
                 ret.pop();
             }
+            Component::Normal(c) if c.len() > MAX_NAME_LEN => {
+                return Err(BackhandError::InvalidFilePath)
+            }
             Component::Normal(c) => {
                 ret.push(c);
             }
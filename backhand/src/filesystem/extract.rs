@@ -0,0 +1,245 @@
+//! Extraction of a [`FilesystemReader`] into a directory
+
+use std::fs::{self, File, Permissions};
+use std::io::{self, BufWriter, Write};
+use std::num::NonZeroUsize;
+use std::os::unix::fs::{lchown, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use nix::sys::stat::{dev_t, mknod, mode_t, utimensat, Mode, SFlag, UtimensatFlags};
+use nix::sys::time::TimeSpec;
+use nix::unistd::{geteuid, mkfifo};
+use tracing::{error, trace};
+
+use crate::error::BackhandError;
+use crate::filesystem::node::{InnerNode, Node, NodeHeader, SquashfsFileReader, SquashfsSymlink};
+use crate::FilesystemReader;
+
+/// Options of [`FilesystemReader::extract_all_to`]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ExtractOptions {
+    /// Replace existing files, instead of returning an error
+    pub force: bool,
+    /// Skip setting ownership, permissions and times
+    pub no_preserve: bool,
+    /// Amount of threads extracting nodes, [`thread::available_parallelism`] if not set
+    pub threads: Option<NonZeroUsize>,
+}
+
+impl FilesystemReader<'_> {
+    /// Extract all nodes into `dest`, creating `dest` if it doesn't exist
+    ///
+    /// Dirs are created first, then the data of files is read and decompressed by multiple
+    /// threads, each writing whole files. Symlinks, devices, named pipes and sockets are created
+    /// as well, where devices can only be created by root.
+    ///
+    /// Ownership is only set when running as root. Otherwise, the write permission of group and
+    /// others is removed from all nodes but dirs, the same as `unsquashfs`. The permissions and
+    /// times of dirs are set after all nodes are extracted.
+    ///
+    /// Stops at the first error, which is returned. Nodes extracted before the error are kept.
+    ///
+    /// ```rust,no_run
+    /// # use std::fs::File;
+    /// # use std::io::BufReader;
+    /// # use backhand::{ExtractOptions, FilesystemReader};
+    /// let file = BufReader::new(File::open("image.squashfs").unwrap());
+    /// let filesystem = FilesystemReader::from_reader(file).unwrap();
+    /// filesystem.extract_all_to("squashfs-root", ExtractOptions::default()).unwrap();
+    /// ```
+    pub fn extract_all_to<P: AsRef<Path>>(
+        &self,
+        dest: P,
+        options: ExtractOptions,
+    ) -> Result<(), BackhandError> {
+        let dest = dest.as_ref();
+        fs::create_dir_all(dest)?;
+        let root = geteuid().is_root();
+        let dest_path = |node: &Node<SquashfsFileReader>| -> PathBuf {
+            let path = &node.fullpath;
+            dest.join(path.strip_prefix(Component::RootDir).unwrap_or(path))
+        };
+
+        // create the dirs first, so all other nodes can be extracted in any order
+        let (dirs, nodes): (Vec<_>, Vec<_>) =
+            self.files().partition(|node| matches!(node.inner, InnerNode::Dir(_)));
+        for node in &dirs {
+            fs::create_dir_all(dest_path(node))?;
+        }
+
+        let threads = options
+            .threads
+            .or_else(|| thread::available_parallelism().ok())
+            .map_or(1, NonZeroUsize::get);
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let first_error = Mutex::new(None);
+        thread::scope(|s| {
+            for _ in 0..threads.min(nodes.len()) {
+                s.spawn(|| {
+                    while !failed.load(Ordering::Relaxed) {
+                        let Some(node) = nodes.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                            break;
+                        };
+                        let path = dest_path(node);
+                        trace!("extracting {}", path.display());
+                        if let Err(e) = self.extract_node(node, &path, options, root) {
+                            error!("{}: {e}", path.display());
+                            failed.store(true, Ordering::Relaxed);
+                            first_error.lock().unwrap().get_or_insert(e);
+                        }
+                    }
+                });
+            }
+        });
+        if let Some(e) = first_error.into_inner().unwrap() {
+            return Err(e);
+        }
+
+        if !options.no_preserve {
+            for node in &dirs {
+                set_attributes(&dest_path(node), &node.header, root, true)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Extract `node` to `path`, with the parent of `path` already created
+    ///
+    /// Used by [`Self::extract_all_to`] for each node, which is useful to extract a subset of
+    /// the nodes. Dirs are only created, set their attributes with [`Node::set_attributes`]
+    /// after extracting the nodes inside them. [`ExtractOptions::threads`] isn't used.
+    pub fn extract_node_to(
+        &self,
+        node: &Node<SquashfsFileReader>,
+        path: &Path,
+        options: ExtractOptions,
+    ) -> Result<(), BackhandError> {
+        if let InnerNode::Dir(_) = node.inner {
+            fs::create_dir_all(path)?;
+            return Ok(());
+        }
+        self.extract_node(node, path, options, geteuid().is_root())
+    }
+
+    /// Create the non-dir `node` at `path`
+    fn extract_node(
+        &self,
+        node: &Node<SquashfsFileReader>,
+        path: &Path,
+        options: ExtractOptions,
+        root: bool,
+    ) -> Result<(), BackhandError> {
+        if options.force && path.symlink_metadata().is_ok() {
+            fs::remove_file(path)?;
+        }
+
+        let permissions = node.header.permissions;
+        match &node.inner {
+            InnerNode::File(file) => {
+                let out = File::options().write(true).create_new(true).open(path)?;
+                let mut writer =
+                    BufWriter::with_capacity(file.file_len().min(self.block_size as usize), out);
+                let copied = io::copy(&mut self.file(file).reader(), &mut writer)
+                    .and_then(|_| writer.flush());
+                if let Err(e) = copied {
+                    // don't leave a partially written file behind
+                    drop(writer);
+                    let _ = fs::remove_file(path);
+                    return Err(e.into());
+                }
+            }
+            InnerNode::Symlink(SquashfsSymlink { link }) => {
+                std::os::unix::fs::symlink(link, path)?;
+            }
+            InnerNode::CharacterDevice(device) => {
+                let device_number = host_device_number(device.major(), device.minor())?;
+                mknod(path, SFlag::S_IFCHR, mode(permissions), device_number)
+                    .map_err(io::Error::from)?;
+            }
+            InnerNode::BlockDevice(device) => {
+                let device_number = host_device_number(device.major(), device.minor())?;
+                mknod(path, SFlag::S_IFBLK, mode(permissions), device_number)
+                    .map_err(io::Error::from)?;
+            }
+            InnerNode::NamedPipe => mkfifo(path, mode(permissions)).map_err(io::Error::from)?,
+            InnerNode::Socket => {
+                mknod(path, SFlag::S_IFSOCK, mode(permissions), 0).map_err(io::Error::from)?
+            }
+            InnerNode::Dir(_) => return Ok(()),
+        }
+
+        if !options.no_preserve {
+            set_attributes(path, &node.header, root, false)?;
+        }
+        Ok(())
+    }
+}
+
+impl Node<SquashfsFileReader> {
+    /// Set the times, ownership and permissions of the extracted node at `path`
+    ///
+    /// Ownership is only set when running as root. Otherwise, the write permission of group and
+    /// others is removed, unless this is a dir.
+    pub fn set_attributes(&self, path: &Path) -> Result<(), BackhandError> {
+        let is_dir = matches!(self.inner, InnerNode::Dir(_));
+        set_attributes(path, &self.header, geteuid().is_root(), is_dir)
+    }
+}
+
+/// Mode of a special file with `permissions`
+fn mode(permissions: u16) -> Mode {
+    Mode::from_bits_truncate(mode_t::from(permissions))
+}
+
+/// Device number of the host of `major` and `minor`
+fn host_device_number(major: u32, minor: u32) -> io::Result<dev_t> {
+    #[cfg(target_os = "linux")]
+    {
+        Ok(nix::sys::stat::makedev(u64::from(major), u64::from(minor)))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        #[allow(clippy::unnecessary_fallible_conversions)]
+        dev_t::try_from(crate::device_number_from_major_minor(major, minor))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+}
+
+/// Set the times, ownership if `root`, and permissions of `path` from `header`
+fn set_attributes(
+    path: &Path,
+    header: &NodeHeader,
+    root: bool,
+    is_dir: bool,
+) -> Result<(), BackhandError> {
+    let time = TimeSpec::new(header.mtime as _, 0);
+    utimensat(None, path, &time, &time, UtimensatFlags::NoFollowSymlink)
+        .map_err(io::Error::from)?;
+
+    let mut mode = u32::from(header.permissions);
+    if root {
+        lchown(path, Some(header.uid), Some(header.gid))?;
+    } else if !is_dir {
+        // following unsquashfs, remove write permissions for group and others
+        mode &= !0o022;
+    }
+
+    // symlinks don't have permissions
+    let is_symlink = path.symlink_metadata()?.file_type().is_symlink();
+    if !is_symlink {
+        if let Err(e) = fs::set_permissions(path, Permissions::from_mode(mode)) {
+            if e.kind() != io::ErrorKind::PermissionDenied {
+                return Err(e.into());
+            }
+            // try without sticky bit
+            fs::set_permissions(path, Permissions::from_mode(mode & !0o1000))?;
+        }
+    }
+
+    Ok(())
+}
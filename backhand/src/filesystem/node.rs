@@ -1,13 +1,18 @@
 use core::fmt;
+#[cfg(unix)]
+use std::fs::Metadata;
 use std::io::Read;
 use std::num::NonZeroUsize;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use super::normalize_squashfs_path;
-use crate::data::Added;
+use crate::data::{Added, CompressedFile};
 use crate::inode::{BasicFile, ExtendedFile, InodeHeader};
-use crate::{BackhandError, DataSize, FilesystemReaderFile, Id};
+use crate::{BackhandError, DataSize, FileCompression, FilesystemReaderFile, Id};
 
 /// File information for Node
 #[derive(Debug, PartialEq, Eq, Default, Clone, Copy)]
@@ -24,6 +29,94 @@ impl NodeHeader {
     pub fn new(permissions: u16, uid: u32, gid: u32, mtime: u32) -> Self {
         Self { permissions, uid, gid, mtime }
     }
+
+    /// Header for a file, with permissions `0o644` owned by root
+    ///
+    /// Unlike [`Self::default`], which has permissions `0`, the file is readable by everyone.
+    ///
+    /// ```rust
+    /// # use backhand::NodeHeader;
+    /// let header = NodeHeader::for_file().with_uid(1000).with_gid(100).with_mtime(0x634f_5237);
+    /// assert_eq!(header, NodeHeader::new(0o644, 1000, 100, 0x634f_5237));
+    /// ```
+    pub fn for_file() -> Self {
+        Self { permissions: 0o644, ..Self::default() }
+    }
+
+    /// Header for a dir, with permissions `0o755` owned by root
+    ///
+    /// Unlike [`Self::default`], which has permissions `0`, the dir can be listed and entered by
+    /// everyone.
+    ///
+    /// ```rust
+    /// # use backhand::NodeHeader;
+    /// let header = NodeHeader::for_dir().with_mode(0o700);
+    /// assert_eq!(header.permissions, 0o700);
+    /// ```
+    pub fn for_dir() -> Self {
+        Self { permissions: 0o755, ..Self::default() }
+    }
+
+    /// Header from the permissions, owner, and modification time of a file on the host
+    ///
+    /// A modification time before the Unix epoch is stored as `0`, and after the year 2106 as
+    /// `u32::MAX`.
+    ///
+    /// ```rust,no_run
+    /// # use backhand::NodeHeader;
+    /// let metadata = std::fs::metadata("/etc/os-release").unwrap();
+    /// let header = NodeHeader::from_metadata(&metadata);
+    /// ```
+    #[cfg(unix)]
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        let mtime = u32::try_from(metadata.mtime().max(0)).unwrap_or(u32::MAX);
+        Self::new(0, metadata.uid(), metadata.gid(), mtime).with_mode(metadata.mode())
+    }
+
+    /// Set the owner user id
+    pub fn with_uid(mut self, uid: u32) -> Self {
+        self.uid = uid;
+        self
+    }
+
+    /// Set the owner group id
+    pub fn with_gid(mut self, gid: u32) -> Self {
+        self.gid = gid;
+        self
+    }
+
+    /// Set the modification time, in seconds since the Unix epoch
+    pub fn with_mtime(mut self, mtime: u32) -> Self {
+        self.mtime = mtime;
+        self
+    }
+
+    /// Set `permissions` from a full Unix `mode`
+    ///
+    /// The permission bits, including setuid (`0o4000`), setgid (`0o2000`) and sticky (`0o1000`),
+    /// are kept. The file type bits are ignored, as the type is given by the node.
+    ///
+    /// ```rust
+    /// # use backhand::NodeHeader;
+    /// let header = NodeHeader::default().with_mode(0o104755);
+    /// assert_eq!(header.permissions, 0o4755);
+    /// ```
+    pub fn with_mode(mut self, mode: u32) -> Self {
+        self.permissions = (mode & 0o7777) as u16;
+        self
+    }
+
+    /// `mtime` as a [`SystemTime`], counted in seconds since the Unix epoch
+    ///
+    /// ```rust
+    /// # use std::time::{Duration, UNIX_EPOCH};
+    /// # use backhand::NodeHeader;
+    /// let header = NodeHeader { mtime: 0x634f_5237, ..NodeHeader::default() };
+    /// assert_eq!(header.mtime_systemtime(), UNIX_EPOCH + Duration::from_secs(1666142775));
+    /// ```
+    pub fn mtime_systemtime(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(u64::from(self.mtime))
+    }
 }
 
 impl NodeHeader {
@@ -45,6 +138,18 @@ pub struct Node<T> {
     pub fullpath: PathBuf,
     pub header: NodeHeader,
     pub inner: InnerNode<T>,
+    /// Inode number of this node in the image it was read from
+    pub(crate) inode_number: Option<u32>,
+    /// Link count of the inode of a symlink, if read from an image or set with
+    /// [`FilesystemWriter::push_symlink_with_links`](crate::FilesystemWriter::push_symlink_with_links)
+    pub(crate) link_count: Option<u32>,
+    /// Compression of a file, see
+    /// [`FilesystemWriter::push_file_with_compression`](crate::FilesystemWriter::push_file_with_compression)
+    pub(crate) file_compression: FileCompression,
+    /// Order a file was pushed in, see [`FragmentOrder::ByInsertion`]
+    ///
+    /// [`FragmentOrder::ByInsertion`]: crate::FragmentOrder::ByInsertion
+    pub(crate) insertion: Option<usize>,
 }
 
 impl<T> PartialEq for Node<T> {
@@ -66,13 +171,34 @@ impl<T> Ord for Node<T> {
 
 impl<T> Node<T> {
     pub(crate) fn new(fullpath: PathBuf, header: NodeHeader, inner: InnerNode<T>) -> Self {
-        Self { fullpath, header, inner }
+        Self {
+            fullpath,
+            header,
+            inner,
+            inode_number: None,
+            link_count: None,
+            file_compression: FileCompression::Default,
+            insertion: None,
+        }
     }
 
     pub fn new_root(header: NodeHeader) -> Self {
         let fullpath = PathBuf::from("/");
         let inner = InnerNode::Dir(SquashfsDir::default());
-        Self { fullpath, header, inner }
+        Self {
+            fullpath,
+            header,
+            inner,
+            inode_number: None,
+            link_count: None,
+            file_compression: FileCompression::Default,
+            insertion: None,
+        }
+    }
+
+    /// Inode number of this node, if read from an image
+    pub fn inode_number(&self) -> Option<u32> {
+        self.inode_number
     }
 }
 
@@ -137,9 +263,29 @@ impl SquashfsFileReader {
 pub enum SquashfsFileWriter<'a, 'b, 'c> {
     UserDefined(Arc<Mutex<dyn Read + 'c>>),
     SquashfsFile(FilesystemReaderFile<'a, 'b>),
+    /// Data compressed when pushed, see
+    /// [`FilesystemWriter::set_eager_compression`](crate::FilesystemWriter::set_eager_compression)
+    Compressed(CompressedFile),
+    /// File of the image opened with
+    /// [`FilesystemWriter::open_existing`](crate::FilesystemWriter::open_existing), read from it
+    /// when writing
+    Existing(SquashfsFileReader),
     Consumed(usize, Added),
 }
 
+impl SquashfsFileWriter<'_, '_, '_> {
+    /// Copy of this file to write it again, `None` if the data is read from a reader or was
+    /// already written
+    pub(crate) fn try_clone(&self) -> Option<Self> {
+        match self {
+            Self::SquashfsFile(file) => Some(Self::SquashfsFile(*file)),
+            Self::Compressed(file) => Some(Self::Compressed(file.clone())),
+            Self::Existing(file) => Some(Self::Existing(file.clone())),
+            Self::UserDefined(_) | Self::Consumed(_, _) => None,
+        }
+    }
+}
+
 impl fmt::Debug for SquashfsFileWriter<'_, '_, '_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("FileWriter").finish()
@@ -162,12 +308,60 @@ pub struct SquashfsCharacterDevice {
     pub device_number: u32,
 }
 
+impl SquashfsCharacterDevice {
+    /// Major number of the device
+    pub fn major(&self) -> u32 {
+        major_minor_from_device_number(self.device_number).0
+    }
+
+    /// Minor number of the device
+    pub fn minor(&self) -> u32 {
+        major_minor_from_device_number(self.device_number).1
+    }
+}
+
 /// Block Device for filesystem
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct SquashfsBlockDevice {
     pub device_number: u32,
 }
 
+impl SquashfsBlockDevice {
+    /// Major number of the device
+    pub fn major(&self) -> u32 {
+        major_minor_from_device_number(self.device_number).0
+    }
+
+    /// Minor number of the device
+    pub fn minor(&self) -> u32 {
+        major_minor_from_device_number(self.device_number).1
+    }
+}
+
+/// Device number of `major` and `minor`, as stored in device inodes
+///
+/// This is the 32 bit encoding of linux (`new_encode_dev`), also used by `mksquashfs`: the low 8
+/// bits of `minor`, 12 bits of `major`, then the upper 12 bits of `minor`. Bits of `major` past
+/// 12 bits and of `minor` past 20 bits are dropped.
+///
+/// ```rust
+/// # use backhand::{device_number_from_major_minor, major_minor_from_device_number};
+/// let device_number = device_number_from_major_minor(8, 0x12345);
+/// assert_eq!(device_number, 0x1230_0845);
+/// assert_eq!(major_minor_from_device_number(device_number), (8, 0x12345));
+/// ```
+pub fn device_number_from_major_minor(major: u32, minor: u32) -> u32 {
+    (minor & 0xff) | ((major & 0xfff) << 8) | ((minor & 0xf_ff00) << 12)
+}
+
+/// `(major, minor)` of the `device_number` of a device inode, see
+/// [`device_number_from_major_minor`]
+pub fn major_minor_from_device_number(device_number: u32) -> (u32, u32) {
+    let major = (device_number >> 8) & 0xfff;
+    let minor = (device_number & 0xff) | ((device_number >> 12) & 0xf_ff00);
+    (major, minor)
+}
+
 #[derive(Debug, Clone)]
 pub struct Nodes<T> {
     pub nodes: Vec<Node<T>>,
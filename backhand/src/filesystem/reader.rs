@@ -1,16 +1,26 @@
-use std::io::{Read, SeekFrom};
-use std::sync::{Mutex, RwLock};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::sync::{Mutex, OnceLock, RwLock};
 
-use super::node::Nodes;
+use solana_nohash_hasher::IntMap;
+
+use super::node::{InnerNode, Nodes};
+use super::normalize_squashfs_path;
 use crate::compressor::{CompressionOptions, Compressor};
 use crate::data::DataSize;
 use crate::error::BackhandError;
 use crate::fragment::Fragment;
 use crate::id::Id;
-use crate::kinds::Kind;
+use crate::inode::{Inode, InodeInner, InodeRef};
+use crate::kinds::{Kind, AVM_BE_V4_0, BE_V4_0};
 use crate::reader::BufReadSeek;
-use crate::squashfs::Cache;
-use crate::{Node, Squashfs, SquashfsFileReader};
+use crate::squashfs::{read_lazy_inode, Cache};
+use crate::unix_string::OsStrExt;
+use crate::{Node, Squashfs, SquashfsFileReader, SuperBlock};
 
 /// Representation of SquashFS filesystem after read from image
 /// - Use [`Self::from_reader`] to read into `Self` from a `reader`
@@ -86,12 +96,37 @@ pub struct FilesystemReader<'b> {
     pub fragments: Option<Vec<Fragment>>,
     /// All files and directories in filesystem
     pub root: Nodes<SquashfsFileReader>,
+    /// Inode Table `<InodeNumber, Inode>`, empty unless [`Kind::with_retained_inodes`]
+    pub(crate) inodes: IntMap<u32, Inode>,
+    /// Index in [`Self::root`] of the first node of each inode number
+    pub(crate) inode_nodes: IntMap<u32, usize>,
+    /// Uncompressed length of each fragment block by fragment index, built on first use by
+    /// [`FilesystemReaderFile::compressed_size`]
+    pub(crate) fragment_lens: OnceLock<IntMap<usize, u64>>,
     /// File reader
     pub(crate) reader: Mutex<Box<dyn BufReadSeek + 'b>>,
     /// Cache used in the decompression
     pub(crate) cache: RwLock<Cache>,
     /// Superblock Flag to remove duplicate flags
     pub(crate) no_duplicate_files: bool,
+    /// Superblock Flag to store the tail-end of files in fragments
+    pub(crate) always_fragment: bool,
+    /// Superblock Flag to store fragment blocks uncompressed
+    pub(crate) fragments_uncompressed: bool,
+    /// Superblock Flag of an NFS export table existing
+    pub(crate) exportable: bool,
+    /// Amount of entries in the export table
+    pub(crate) export_count: usize,
+    /// Amount of bytes used by the image, not including padding
+    pub(crate) bytes_used: u64,
+    /// Retained from [`Squashfs::into_filesystem_reader_retaining`]
+    pub(crate) raw: Option<Box<Squashfs<'b>>>,
+    /// Set by [`Self::set_extract_limit`]
+    pub(crate) extract_limit: Option<u64>,
+    /// Bytes of file data decompressed, counted against `extract_limit`
+    pub(crate) extracted: AtomicU64,
+    /// Superblock of the image, used to locate the inode table in [`Self::inode_at`]
+    pub(crate) superblock: SuperBlock,
 }
 
 impl<'b> FilesystemReader<'b> {
@@ -106,6 +141,13 @@ impl<'b> FilesystemReader<'b> {
         squashfs.into_filesystem_reader()
     }
 
+    /// Call [`Squashfs::from_slice`], then [`Squashfs::into_filesystem_reader`]
+    ///
+    /// The data of files is read from `bytes` without copying the image.
+    pub fn from_slice(bytes: &'b [u8]) -> Result<Self, BackhandError> {
+        Squashfs::from_slice(bytes)?.into_filesystem_reader()
+    }
+
     /// Same as [`Self::from_reader`], but seek'ing to `offset` in `reader` before reading
     pub fn from_reader_with_offset<R>(reader: R, offset: u64) -> Result<Self, BackhandError>
     where
@@ -128,6 +170,38 @@ impl<'b> FilesystemReader<'b> {
         squashfs.into_filesystem_reader()
     }
 
+    /// Bytes after the image and its padding until the end of the reader, such as written with
+    /// [`FilesystemWriter::set_trailer`]
+    ///
+    /// The zero bytes following the image are treated as padding, so leading zero bytes of the
+    /// trailer are not returned.
+    ///
+    /// [`FilesystemWriter::set_trailer`]: crate::FilesystemWriter::set_trailer
+    pub fn trailer(&self) -> Result<Vec<u8>, BackhandError> {
+        let mut reader = self.reader.lock().unwrap();
+        reader.seek(SeekFrom::Start(self.bytes_used))?;
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+        bytes.drain(..start);
+        Ok(bytes)
+    }
+
+    /// Limit the total bytes of file data read from this filesystem to `limit`
+    ///
+    /// Once the bytes read from all files exceed `limit`, every following read of file data
+    /// returns [`BackhandError::ExtractLimitExceeded`]. This guards against images that expand
+    /// to far more than they occupy. Calling this resets the count of bytes read.
+    pub fn set_extract_limit(&mut self, limit: u64) {
+        self.extract_limit = Some(limit);
+        *self.extracted.get_mut() = 0;
+    }
+
+    /// Total bytes of file data read from this filesystem
+    pub fn extracted_bytes(&self) -> u64 {
+        self.extracted.load(Ordering::Relaxed)
+    }
+
     /// Return a file handler for this file
     pub fn file<'a>(&'a self, file: &'a SquashfsFileReader) -> FilesystemReaderFile<'a, 'b> {
         FilesystemReaderFile::new(self, file)
@@ -135,6 +209,9 @@ impl<'b> FilesystemReader<'b> {
 
     /// Iterator of all files, including the root
     ///
+    /// Nodes are sorted by their path components, which is depth-first pre-order: each dir is
+    /// followed by all nodes inside of it, with siblings sorted by name.
+    ///
     /// # Example
     /// Used when extracting a file from the image, for example using [`FilesystemReaderFile`]:
     /// ```rust,no_run
@@ -164,6 +241,272 @@ impl<'b> FilesystemReader<'b> {
     pub fn files(&self) -> impl Iterator<Item = &Node<SquashfsFileReader>> {
         self.root.nodes.iter()
     }
+
+    /// Call `f` with each node, including the root, in the same order as [`Self::files`]
+    ///
+    /// ```rust,no_run
+    /// # use std::fs::File;
+    /// # use std::io::BufReader;
+    /// # use backhand::FilesystemReader;
+    /// # let file = BufReader::new(File::open("image.squashfs").unwrap());
+    /// let filesystem = FilesystemReader::from_reader(file).unwrap();
+    /// filesystem.for_each_node(|node| println!("{}", node.fullpath.display()));
+    /// ```
+    pub fn for_each_node<F>(&self, f: F)
+    where
+        F: FnMut(&Node<SquashfsFileReader>),
+    {
+        self.files().for_each(f)
+    }
+
+    /// Locations of the blocks read from the image for the data of each file, see
+    /// [`FilesystemReaderFile::block_locations`]
+    ///
+    /// Nothing is read from the image, allowing all locations to be checked before extracting.
+    ///
+    /// ```rust,no_run
+    /// # use std::fs::File;
+    /// # use std::io::BufReader;
+    /// # use backhand::FilesystemReader;
+    /// # let file = BufReader::new(File::open("image.squashfs").unwrap());
+    /// # let image_len = 0;
+    /// let filesystem = FilesystemReader::from_reader(file).unwrap();
+    /// for (path, locations) in filesystem.read_plan().unwrap() {
+    ///     if locations.iter().any(|location| location.end() > image_len) {
+    ///         panic!("{} reads past the end of the image", path.display());
+    ///     }
+    /// }
+    /// ```
+    pub fn read_plan(&self) -> Result<Vec<(PathBuf, Vec<BlockLocation>)>, BackhandError> {
+        self.files()
+            .filter_map(|node| match &node.inner {
+                InnerNode::File(file) => Some((node, file)),
+                _ => None,
+            })
+            .map(|(node, file)| Ok((node.fullpath.clone(), self.file(file).block_locations()?)))
+            .collect()
+    }
+
+    /// Tree and metadata of all nodes, without reading any file data
+    ///
+    /// Each node is a line in the same order as [`Self::files`], in the form
+    /// `{type} {permissions} {uid} {gid} {mtime} {size} {path}`:
+    /// - `type` is one of `d` (dir), `-` (file), `l` (symlink), `c` (char device), `b` (block
+    ///   device), `p` (named pipe), or `s` (socket)
+    /// - `permissions` is in octal
+    /// - `size` is the length of files, the device number of devices, and `0` for other nodes
+    /// - symlinks are followed by ` -> {link}`
+    ///
+    /// In `path` and `link`, spaces, control characters, `%` and bytes that aren't valid UTF-8 are
+    /// encoded as `%XX`, so each line can be split on spaces.
+    ///
+    /// ```rust,no_run
+    /// # use std::fs::File;
+    /// # use std::io::BufReader;
+    /// # use backhand::FilesystemReader;
+    /// # let file = BufReader::new(File::open("image.squashfs").unwrap());
+    /// let filesystem = FilesystemReader::from_reader(file).unwrap();
+    /// std::fs::write("image.manifest", filesystem.manifest()).unwrap();
+    /// ```
+    pub fn manifest(&self) -> String {
+        let mut manifest = String::new();
+        for node in self.files() {
+            let (t, size) = match &node.inner {
+                InnerNode::Dir(_) => ('d', 0),
+                InnerNode::File(file) => ('-', file.file_len() as u64),
+                InnerNode::Symlink(_) => ('l', 0),
+                InnerNode::CharacterDevice(dev) => ('c', u64::from(dev.device_number)),
+                InnerNode::BlockDevice(dev) => ('b', u64::from(dev.device_number)),
+                InnerNode::NamedPipe => ('p', 0),
+                InnerNode::Socket => ('s', 0),
+            };
+            let header = &node.header;
+            manifest += &format!(
+                "{t} {:04o} {} {} {} {size} {}",
+                header.permissions,
+                header.uid,
+                header.gid,
+                header.mtime,
+                manifest_escape(node.fullpath.as_os_str().as_bytes())
+            );
+            if let InnerNode::Symlink(symlink) = &node.inner {
+                manifest +=
+                    &format!(" -> {}", manifest_escape(symlink.link.as_os_str().as_bytes()));
+            }
+            manifest.push('\n');
+        }
+        manifest
+    }
+
+    /// Files stored in each fragment block, grouped by the index of the fragment in
+    /// [`Self::fragments`]
+    ///
+    /// Sorted by fragment index, with files in the same order as [`Self::files`]. Fragments that
+    /// aren't used by any file are not included.
+    pub fn fragment_usage(&self) -> Vec<(usize, Vec<&Node<SquashfsFileReader>>)> {
+        let mut usage: BTreeMap<usize, Vec<&Node<SquashfsFileReader>>> = BTreeMap::new();
+        for node in self.files() {
+            if let InnerNode::File(file) = &node.inner {
+                if self.file(file).fragment().is_some() {
+                    usage.entry(file.frag_index()).or_default().push(node);
+                }
+            }
+        }
+        usage.into_iter().collect()
+    }
+
+    /// [`Squashfs`] this was created from, if created with
+    /// [`Squashfs::into_filesystem_reader_retaining`]
+    pub fn raw_squashfs(&self) -> Option<&Squashfs<'b>> {
+        self.raw.as_deref()
+    }
+
+    /// Image has an NFS export table, from the superblock flag
+    pub fn is_exportable(&self) -> bool {
+        self.exportable
+    }
+
+    /// Amount of entries in the export table, one for each inode, or `0` without an export table
+    pub fn export_entry_count(&self) -> usize {
+        self.export_count
+    }
+
+    /// Decode the [`Inode`] referenced by `reference` from the inode table of the image
+    ///
+    /// `reference` is the metadata block start relative to the inode table and the offset into
+    /// the uncompressed block, as encoded by [`InodeRef::encode`] and stored in dir entries and
+    /// the root inode of the [`SuperBlock`]. Only the metadata blocks containing the inode are
+    /// read and decompressed.
+    ///
+    /// ```rust,no_run
+    /// # use std::fs::File;
+    /// # use std::io::BufReader;
+    /// # use backhand::{FilesystemReader, InodeRef, Squashfs};
+    /// let file = BufReader::new(File::open("image.squashfs").unwrap());
+    /// let squashfs = Squashfs::from_reader(file).unwrap();
+    /// let root_inode = squashfs.superblock.root_inode;
+    /// let filesystem = squashfs.into_filesystem_reader().unwrap();
+    /// let inode = filesystem.inode_at(root_inode).unwrap();
+    ///
+    /// // the same inode, from the metadata block start and offset
+    /// let (start, offset) = InodeRef::decode(root_inode);
+    /// assert_eq!(filesystem.inode_at(InodeRef::encode(start, offset)).unwrap(), inode);
+    /// ```
+    pub fn inode_at(&self, reference: u64) -> Result<Inode, BackhandError> {
+        let (start, offset) = InodeRef::decode(reference);
+        let mut reader = self.reader.lock().unwrap();
+        read_lazy_inode(
+            &mut *reader,
+            &mut Default::default(),
+            &self.superblock,
+            &self.kind,
+            u64::from(start),
+            offset,
+        )
+    }
+
+    /// Node with the inode number `inode_number`, such as from the export table
+    ///
+    /// Hard links share the same inode number, in that case the first of their nodes in the order
+    /// of [`Self::files`] is returned. `None` if no node has this inode number.
+    pub fn node_by_inode(&self, inode_number: u32) -> Option<&Node<SquashfsFileReader>> {
+        let indexed = self
+            .inode_nodes
+            .get(&inode_number)
+            .and_then(|index| self.root.nodes.get(*index))
+            .filter(|node| node.inode_number == Some(inode_number));
+        // the index is built when reading, and is out of date if `root` was changed since
+        indexed.or_else(|| self.files().find(|node| node.inode_number == Some(inode_number)))
+    }
+
+    /// File with the inode number `inode_number`, see [`Self::node_by_inode`]
+    ///
+    /// `None` if no node has this inode number, or if it isn't a file.
+    ///
+    /// ```rust,no_run
+    /// # use std::fs::File;
+    /// # use std::io::{BufReader, Read};
+    /// # use backhand::FilesystemReader;
+    /// let file = BufReader::new(File::open("image.squashfs").unwrap());
+    /// let filesystem = FilesystemReader::from_reader(file).unwrap();
+    /// if let Some(file) = filesystem.file_by_inode(2) {
+    ///     let mut bytes = vec![];
+    ///     file.reader().read_to_end(&mut bytes).unwrap();
+    /// }
+    /// ```
+    pub fn file_by_inode(&self, inode_number: u32) -> Option<FilesystemReaderFile<'_, 'b>> {
+        match &self.node_by_inode(inode_number)?.inner {
+            InnerNode::File(file) => Some(self.file(file)),
+            _ => None,
+        }
+    }
+
+    /// Uncompressed length of each fragment block by fragment index, from the files stored in it
+    fn fragment_lens(&self) -> &IntMap<usize, u64> {
+        self.fragment_lens.get_or_init(|| {
+            // the uncompressed fragment block ends with the last tail-end stored in it
+            let mut lens = IntMap::default();
+            for node in self.files() {
+                let InnerNode::File(file) = &node.inner else {
+                    continue;
+                };
+                if file.frag_index() == 0xffffffff {
+                    continue;
+                }
+                let end = u64::from(file.block_offset()) + self.file(file).tail_len();
+                let len = lens.entry(file.frag_index()).or_insert(0);
+                *len = end.max(*len);
+            }
+            lens
+        })
+    }
+
+    /// Raw [`Inode`] of `node`, as read from the image
+    ///
+    /// `None` unless the image was read with [`Kind::with_retained_inodes`].
+    pub fn inode(&self, node: &Node<SquashfsFileReader>) -> Option<&Inode> {
+        node.inode_number.and_then(|inode_number| self.inodes.get(&inode_number))
+    }
+
+    /// Inode number of the parent dir of the dir `node`, as stored in its [`Inode`]
+    ///
+    /// `None` if `node` isn't a dir. The root dir has no parent, mksquashfs stores the inode count
+    /// plus one, and [`FilesystemWriter`](crate::FilesystemWriter) stores `0`. Like
+    /// [`Self::inode`], this needs [`Kind::with_retained_inodes`].
+    pub fn parent_inode(&self, node: &Node<SquashfsFileReader>) -> Option<u32> {
+        match &self.inode(node)?.inner {
+            InodeInner::BasicDirectory(dir) => Some(dir.parent_inode),
+            InodeInner::ExtendedDirectory(dir) => Some(dir.parent_inode),
+            _ => None,
+        }
+    }
+}
+
+/// `bytes` of a path with spaces, control characters, `%` and bytes that aren't valid UTF-8
+/// encoded as `%XX`, see [`FilesystemReader::manifest`]
+fn manifest_escape(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    match std::str::from_utf8(bytes) {
+        Ok(s) => {
+            for c in s.chars() {
+                if c <= ' ' || c == '\x7f' || c == '%' {
+                    escaped += &format!("%{:02X}", c as u8);
+                } else {
+                    escaped.push(c);
+                }
+            }
+        }
+        Err(_) => {
+            for &b in bytes {
+                if b <= b' ' || b >= 0x7f || b == b'%' {
+                    escaped += &format!("%{b:02X}");
+                } else {
+                    escaped.push(char::from(b));
+                }
+            }
+        }
+    }
+    escaped
 }
 
 /// Filesystem handle for file
@@ -188,12 +531,117 @@ impl<'a, 'b> FilesystemReaderFile<'a, 'b> {
         self.raw_data_reader().into_reader()
     }
 
+    /// Read and decompress the data of this file, calling `f` with each decompressed block
+    ///
+    /// Unlike [`Self::reader`], no data is copied into a caller buffer: `f` is called with at
+    /// most one block at a time, the last call being the tail-end of the file, allowing the file
+    /// to be hashed or written incrementally.
+    ///
+    /// ```rust,no_run
+    /// # use std::fs::File;
+    /// # use std::io::BufReader;
+    /// # use backhand::{FilesystemReader, InnerNode};
+    /// # let file = BufReader::new(File::open("image.squashfs").unwrap());
+    /// let filesystem = FilesystemReader::from_reader(file).unwrap();
+    /// for node in filesystem.files() {
+    ///     if let InnerNode::File(file) = &node.inner {
+    ///         let mut len = 0;
+    ///         filesystem.file(file).read_chunked(|chunk| len += chunk.len()).unwrap();
+    ///         assert_eq!(len, file.file_len());
+    ///     }
+    /// }
+    /// ```
+    pub fn read_chunked<F: FnMut(&[u8])>(&self, mut f: F) -> Result<(), BackhandError> {
+        let mut raw_data = self.raw_data_reader();
+        let mut buf_read = Vec::with_capacity(self.system.block_size as usize);
+        let mut buf_decompress = vec![];
+        let mut bytes_available = self.file.file_len();
+        while bytes_available != 0 {
+            let Some(block) = raw_data.next_block(&mut buf_read) else {
+                break;
+            };
+            buf_decompress.clear();
+            raw_data.decompress(block?, &mut buf_read, &mut buf_decompress)?;
+            let len = buf_decompress.len().min(bytes_available);
+            f(&buf_decompress[..len]);
+            bytes_available -= len;
+        }
+        Ok(())
+    }
+
     pub fn fragment(&self) -> Option<&'a Fragment> {
         if self.file.frag_index() == 0xffffffff {
             None
         } else {
-            self.system.fragments.as_ref().map(|fragments| &fragments[self.file.frag_index()])
+            self.system
+                .fragments
+                .as_ref()
+                .and_then(|fragments| fragments.get(self.file.frag_index()))
+        }
+    }
+
+    /// Size of this file as stored in the image, unlike the uncompressed
+    /// [`SquashfsFileReader::file_len`]
+    ///
+    /// This is the sum of the data blocks, plus the share of the fragment block used by the
+    /// tail-end of the file. The share is proportional to the uncompressed bytes of the fragment
+    /// block used by this file, the length of the fragment block being found from all files stored
+    /// in it, once for all files of the reader. Data shared with other files from removing duplicate files is counted for each file.
+    pub fn compressed_size(&self) -> u64 {
+        let blocks: u64 = self.file.block_sizes().iter().map(|block| u64::from(block.size())).sum();
+        let Some(fragment) = self.fragment() else {
+            return blocks;
+        };
+
+        let fragment_len =
+            self.system.fragment_lens().get(&self.file.frag_index()).copied().unwrap_or(0);
+        if fragment_len == 0 {
+            return blocks;
+        }
+        blocks + self.tail_len() * u64::from(fragment.size.size()) / fragment_len
+    }
+
+    /// Locations of the data blocks of this file in the image, followed by its fragment block
+    ///
+    /// Positions are from the start of the image, not including an offset given when reading.
+    /// Blocks of sparse files, not stored in the image, have a `len` of `0`. Returns
+    /// [`BackhandError::CorruptedOrInvalidSquashfs`] if the fragment of this file isn't in the
+    /// fragment table, or if the data blocks end past the largest position.
+    pub fn block_locations(&self) -> Result<Vec<BlockLocation>, BackhandError> {
+        let block_sizes = self.file.block_sizes();
+        let mut locations = Vec::with_capacity(block_sizes.len() + 1);
+        let mut start = self.file.blocks_start();
+        for size in block_sizes {
+            let len = size.size();
+            let uncompressed = size.uncompressed();
+            locations.push(BlockLocation { start, len, uncompressed, fragment: false });
+            start = start
+                .checked_add(u64::from(len))
+                .ok_or(BackhandError::CorruptedOrInvalidSquashfs)?;
+        }
+
+        if self.file.frag_index() != 0xffffffff {
+            let fragment = self
+                .system
+                .fragments
+                .as_ref()
+                .and_then(|fragments| fragments.get(self.file.frag_index()))
+                .ok_or(BackhandError::CorruptedOrInvalidSquashfs)?;
+            locations.push(BlockLocation {
+                start: fragment.start,
+                len: fragment.size.size(),
+                uncompressed: fragment.size.uncompressed(),
+                fragment: true,
+            });
         }
+        Ok(locations)
+    }
+
+    /// Length of the tail-end of the file, not stored in data blocks
+    fn tail_len(&self) -> u64 {
+        let block_size = u64::from(self.system.block_size);
+        let blocks_len = self.file.block_sizes().len() as u64 * block_size;
+        (self.file.file_len() as u64).saturating_sub(blocks_len)
     }
 
     pub(crate) fn raw_data_reader(&self) -> SquashfsRawData<'a, 'b> {
@@ -210,6 +658,27 @@ impl<'a> IntoIterator for FilesystemReaderFile<'a, '_> {
     }
 }
 
+/// Location of a data or fragment block in the image, see
+/// [`FilesystemReaderFile::block_locations`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BlockLocation {
+    /// Position of the block in the image
+    pub start: u64,
+    /// Length of the block as stored in the image
+    pub len: u32,
+    /// Block stored uncompressed
+    pub uncompressed: bool,
+    /// Fragment block, containing the tail-end of the file
+    pub fragment: bool,
+}
+
+impl BlockLocation {
+    /// Position in the image after the block
+    pub fn end(&self) -> u64 {
+        self.start + u64::from(self.len)
+    }
+}
+
 pub enum BlockFragment<'a> {
     Block(&'a DataSize),
     Fragment(&'a Fragment),
@@ -244,13 +713,16 @@ pub(crate) struct SquashfsRawData<'a, 'b> {
     pub(crate) file: FilesystemReaderFile<'a, 'b>,
     current_block: BlockIterator<'a>,
     pub(crate) pos: u64,
+    /// If true, the fragment of this file isn't in the fragment table
+    missing_fragment: bool,
 }
 
 impl<'a, 'b> SquashfsRawData<'a, 'b> {
     pub fn new(file: FilesystemReaderFile<'a, 'b>) -> Self {
         let pos = file.file.blocks_start();
         let current_block = file.into_iter();
-        Self { file, current_block, pos }
+        let missing_fragment = file.file.frag_index() != 0xffffffff && file.fragment().is_none();
+        Self { file, current_block, pos, missing_fragment }
     }
 
     fn read_raw_data(
@@ -283,7 +755,7 @@ impl<'a, 'b> SquashfsRawData<'a, 'b> {
                     let cache = self.file.system.cache.read().unwrap();
                     if let Some(cache_bytes) = cache.fragment_cache.get(&fragment.start) {
                         //if in cache, just return the cache, don't read it
-                        let range = self.fragment_range();
+                        let range = self.fragment_range(cache_bytes.len())?;
                         tracing::trace!("fragment in cache: {:02x}:{range:02x?}", fragment.start);
                         data.resize(range.end - range.start, 0);
                         data.copy_from_slice(&cache_bytes[range]);
@@ -313,10 +785,10 @@ impl<'a, 'b> SquashfsRawData<'a, 'b> {
                         .write()
                         .unwrap()
                         .fragment_cache
-                        .insert(self.file.fragment().unwrap().start, data.clone());
+                        .insert(fragment.start, data.clone());
 
                     //apply the fragment offset
-                    let range = self.fragment_range();
+                    let range = self.fragment_range(data.len())?;
                     data.drain(range.end..);
                     data.drain(..range.start);
                 }
@@ -327,18 +799,42 @@ impl<'a, 'b> SquashfsRawData<'a, 'b> {
 
     #[inline]
     pub fn next_block(&mut self, buf: &mut Vec<u8>) -> Option<Result<RawDataBlock, BackhandError>> {
-        self.current_block.next().map(|next| self.read_raw_data(buf, &next))
+        match self.current_block.next() {
+            Some(next) => Some(self.read_raw_data(buf, &next)),
+            // the fragment of this file isn't in the fragment table
+            None if self.missing_fragment => {
+                self.missing_fragment = false;
+                tracing::error!(
+                    "fragment {} not in the {} fragments",
+                    self.file.file.frag_index(),
+                    self.file.system.fragments.as_ref().map_or(0, Vec::len)
+                );
+                Some(Err(BackhandError::CorruptedOrInvalidSquashfs))
+            }
+            None => None,
+        }
     }
 
+    /// Range of the tail-end of this file in its uncompressed fragment block of `block_len`
     #[inline]
-    fn fragment_range(&self) -> std::ops::Range<usize> {
-        let block_len = self.file.system.block_size as usize;
+    fn fragment_range(&self, block_len: usize) -> Result<std::ops::Range<usize>, BackhandError> {
+        let block_size = self.file.system.block_size as usize;
         let block_num = self.file.file.block_sizes().len();
         let file_size = self.file.file.file_len();
-        let frag_len = file_size - (block_num * block_len);
         let frag_start = self.file.file.block_offset() as usize;
-        let frag_end = frag_start + frag_len;
-        frag_start..frag_end
+        let frag_end = block_num
+            .checked_mul(block_size)
+            .and_then(|blocks_len| file_size.checked_sub(blocks_len))
+            .and_then(|frag_len| frag_start.checked_add(frag_len))
+            .filter(|frag_end| *frag_end <= block_len);
+        let Some(frag_end) = frag_end else {
+            tracing::error!(
+                "fragment {} offset {frag_start:#x} out of the fragment block of {block_len:#x} bytes",
+                self.file.file.frag_index()
+            );
+            return Err(BackhandError::CorruptedOrInvalidSquashfs);
+        };
+        Ok(frag_start..frag_end)
     }
 
     pub fn decompress(
@@ -371,11 +867,20 @@ impl<'a, 'b> SquashfsRawData<'a, 'b> {
                     .insert(self.file.fragment().unwrap().start, output_buf.clone());
 
                 //apply the fragment offset
-                let range = self.fragment_range();
+                let range = self.fragment_range(output_buf.len())?;
                 output_buf.drain(range.end..);
                 output_buf.drain(..range.start);
             }
         }
+        let system = self.file.system;
+        let extracted = system.extracted.fetch_add(output_buf.len() as u64, Ordering::Relaxed)
+            + output_buf.len() as u64;
+        if let Some(limit) = system.extract_limit {
+            if extracted > limit {
+                output_buf.clear();
+                return Err(BackhandError::ExtractLimitExceeded(limit));
+            }
+        }
         Ok(())
     }
 
@@ -438,6 +943,54 @@ impl<'a, 'b> SquashfsReadFile<'a, 'b> {
         self.last_read = 0;
         Ok(())
     }
+
+    /// Same as [`Read::read`], but fills as much of `buf` as possible, checking `cancel` before
+    /// reading each block
+    ///
+    /// Returns [`BackhandError::Cancelled`] (as an [`std::io::Error`]) once `cancel` is set,
+    /// allowing long reads to be aborted from another thread.
+    ///
+    /// ```rust,no_run
+    /// # use std::fs::File;
+    /// # use std::io::BufReader;
+    /// # use std::sync::atomic::AtomicBool;
+    /// # use std::sync::Arc;
+    /// # use backhand::{FilesystemReader, InnerNode};
+    /// # let file = BufReader::new(File::open("image.squashfs").unwrap());
+    /// let filesystem = FilesystemReader::from_reader(file).unwrap();
+    /// let cancel = Arc::new(AtomicBool::new(false));
+    /// for node in filesystem.files() {
+    ///     if let InnerNode::File(file) = &node.inner {
+    ///         let mut reader = filesystem.file(file).reader();
+    ///         let mut buf = vec![0; file.file_len()];
+    ///         reader.read_cancellable(&mut buf, &cancel).unwrap();
+    ///     }
+    /// }
+    /// ```
+    pub fn read_cancellable(
+        &mut self,
+        buf: &mut [u8],
+        cancel: &AtomicBool,
+    ) -> std::io::Result<usize> {
+        let mut read_len = 0;
+        while read_len < buf.len() && self.bytes_available != 0 {
+            //no data available, read the next block
+            if self.available().is_empty() {
+                if cancel.load(Ordering::Relaxed) {
+                    return Err(BackhandError::Cancelled.into());
+                }
+                self.read_next_block()?;
+                // blocks ran out or were shorter than the length of the file
+                if self.available().is_empty() {
+                    tracing::error!("file data ends {} bytes early", self.bytes_available);
+                    return Err(BackhandError::CorruptedOrInvalidSquashfs.into());
+                }
+            }
+            read_len += self.read_available(&mut buf[read_len..]);
+        }
+
+        Ok(read_len)
+    }
 }
 
 impl Read for SquashfsReadFile<'_, '_> {
@@ -458,3 +1011,55 @@ impl Read for SquashfsReadFile<'_, '_> {
         Ok(self.read_available(buf))
     }
 }
+
+/// Read all data of the file at `path` in the image file at `image`
+///
+/// The kind of the image is detected with [`Kind::detect_from_superblock`], trying
+/// [`AVM_BE_V4_0`] if a [`BE_V4_0`] image can't be read or no kind is detected, such as with the
+/// header of AVM images before the superblock. Returns [`BackhandError::FileNotFound`] if `path`
+/// doesn't exist, and [`BackhandError::NotAFile`] if `path` is a dir, symlink, or other non-file
+/// node.
+///
+/// ```rust,no_run
+/// let bytes = backhand::read_file("image.squashfs", "/etc/os-release").unwrap();
+/// ```
+pub fn read_file<P, Q>(image: P, path: Q) -> Result<Vec<u8>, BackhandError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let path = normalize_squashfs_path(path.as_ref())?;
+    let mut superblock = [0; 96];
+    File::open(image.as_ref())?.read_exact(&mut superblock)?;
+    let kind = Kind::detect_from_superblock(&superblock);
+
+    // AVM images can't be told apart from the superblock, and may start with a header
+    let try_avm = kind.as_ref().map_or(true, |kind| kind.inner.magic == BE_V4_0.magic);
+    let mut result = match kind {
+        Some(kind) => {
+            let reader = BufReader::new(File::open(image.as_ref())?);
+            FilesystemReader::from_reader_with_offset_and_kind(reader, 0, kind)
+        }
+        None => Err(BackhandError::CorruptedOrInvalidSquashfs),
+    };
+    if result.is_err() && try_avm {
+        let reader = BufReader::new(File::open(image.as_ref())?);
+        let kind = Kind { inner: Arc::new(AVM_BE_V4_0) };
+        result = FilesystemReader::from_reader_with_offset_and_kind(reader, 0, kind);
+    }
+    let filesystem = result?;
+
+    let node = filesystem
+        .root
+        .nodes
+        .binary_search_by(|node| node.fullpath.cmp(&path))
+        .map(|index| &filesystem.root.nodes[index])
+        .map_err(|_| BackhandError::FileNotFound)?;
+    let InnerNode::File(file) = &node.inner else {
+        return Err(BackhandError::NotAFile(path));
+    };
+
+    let mut bytes = vec![];
+    filesystem.file(file).reader().read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
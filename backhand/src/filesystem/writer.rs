@@ -1,34 +1,49 @@
+use std::collections::HashSet;
 use std::ffi::OsStr;
-use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
 use std::num::NonZeroUsize;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use deku::prelude::*;
+use solana_nohash_hasher::IntMap;
 use tracing::{error, info, trace};
 
 use super::node::{InnerNode, Nodes};
 use super::normalize_squashfs_path;
-use crate::compressor::{CompressionOptions, Compressor};
-use crate::data::DataWriter;
+use crate::compressor::{CompressionOptions, Compressor, Gzip, Zstd};
+#[cfg(feature = "trace-compression")]
+use crate::data::CompressEvent;
+use crate::data::{Added, CompressedFile, DataWriter, WriteStats};
+use crate::dir::DirectoryIndex;
 use crate::entry::Entry;
 use crate::error::BackhandError;
 use crate::filesystem::node::SquashfsSymlink;
 use crate::id::Id;
+use crate::inode::InodeRef;
 use crate::kind::Kind;
-use crate::kinds::LE_V4_0;
+use crate::kinds::{InnerKind, LE_V4_0};
 use crate::metadata::{self, MetadataWriter, METADATA_MAXSIZE};
 use crate::reader::WriteSeek;
+use crate::sidecar::{Sidecar, SidecarEntry};
 use crate::squashfs::SuperBlock;
+use crate::unix_string::OsStrExt;
 use crate::{
-    fragment, FilesystemReader, Flags, Node, NodeHeader, SquashfsBlockDevice,
-    SquashfsCharacterDevice, SquashfsDir, SquashfsFileWriter, DEFAULT_BLOCK_SIZE, DEFAULT_PAD_LEN,
-    MAX_BLOCK_SIZE, MIN_BLOCK_SIZE,
+    fragment, FilesystemReader, FilesystemReaderFile, Flags, Node, NodeHeader, SquashfsBlockDevice,
+    SquashfsCharacterDevice, SquashfsDir, SquashfsFileReader, SquashfsFileWriter,
+    DEFAULT_BLOCK_SIZE, DEFAULT_PAD_LEN, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE,
 };
 
 /// Representation of SquashFS filesystem to be written back to an image
+///
+/// Paths of pushed nodes are normalized: they are always relative to the root `/`, `.` and `..`
+/// are resolved, and repeated `/` are ignored. Each directory entry stores only the name of the
+/// last component, so a name can't contain `/` and can't be `.` or `..`.
+///
 /// - Use [`Self::from_fs_reader`] to write with the data from a previous SquashFS image
 /// - Use [`Self::default`] to create an empty SquashFS image without an original image. For example:
 /// ```rust
@@ -71,6 +86,8 @@ pub struct FilesystemWriter<'a, 'b, 'c> {
     pub(crate) mod_time: u32,
     /// 32 bit user and group IDs
     pub(crate) id_table: Vec<Id>,
+    /// `id_table` was set with [`Self::set_id_table`], and ids aren't added to it
+    pub(crate) fixed_id_table: bool,
     /// Compressor used when writing
     pub(crate) fs_compressor: FilesystemCompressor,
     /// All files and directories in filesystem, including root
@@ -80,7 +97,63 @@ pub struct FilesystemWriter<'a, 'b, 'c> {
     pub(crate) pad_len: u32,
     /// Superblock Flag to remove duplicate flags
     pub(crate) no_duplicate_files: bool,
+    /// Superblock Flag to store the tail-end of files in fragments
+    pub(crate) always_fragment: bool,
+    /// Superblock Flag to store fragment blocks uncompressed
+    pub(crate) fragments_uncompressed: bool,
     pub(crate) emit_compression_options: bool,
+    /// Order files are considered for fragment packing
+    pub(crate) fragment_order: FragmentOrder,
+    /// Amount of files added with [`Self::push_file`], the insertion index of the next file
+    pub(crate) file_insertions: usize,
+    /// Write the inode numbers of nodes read from an image, see [`Self::set_preserve_inode_numbers`]
+    pub(crate) preserve_inode_numbers: bool,
+    /// Only write images that mainline linux kernels can mount, see [`Self::set_kernel_compatible`]
+    pub(crate) kernel_compatible: bool,
+    /// Compress file data when pushed, see [`Self::set_eager_compression`]
+    pub(crate) eager_compression: bool,
+    /// Metadata tables that are compressed, see [`Self::set_metadata_compression`]
+    pub(crate) metadata_compression: MetadataComp,
+    /// Bytes written after the padding, see [`Self::set_trailer`]
+    pub(crate) trailer: Vec<u8>,
+    /// Error in [`Self::write`] if only the root exists, see [`Self::set_require_non_empty`]
+    pub(crate) require_non_empty: bool,
+    /// Order of the data and metadata sections, see [`Self::set_section_layout`]
+    pub(crate) section_layout: SectionLayout,
+    /// Written data and fragment blocks, see [`Self::set_compression_trace`]
+    #[cfg(feature = "trace-compression")]
+    pub(crate) compression_trace: Option<Arc<Mutex<Vec<CompressEvent>>>>,
+    /// Image of [`SquashfsFileWriter::Existing`] files, see [`Self::open_existing`]
+    pub(crate) existing: Option<ExistingImage>,
+}
+
+/// Image opened with [`FilesystemWriter::open_existing`]
+pub(crate) struct ExistingImage(FilesystemReader<'static>);
+
+impl fmt::Debug for ExistingImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExistingImage").finish_non_exhaustive()
+    }
+}
+
+/// Create a new file named after `path` with a `.tmp` suffix, numbered if it already exists
+fn create_temp_file(path: &Path) -> Result<(PathBuf, File), BackhandError> {
+    let mut number = 0_u32;
+    loop {
+        let mut temp_path = path.as_os_str().to_owned();
+        if number != 0 {
+            temp_path.push(format!(".{number}"));
+        }
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        match File::options().write(true).create_new(true).open(&temp_path) {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && number < u32::MAX => {
+                number += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
 }
 
 impl Default for FilesystemWriter<'_, '_, '_> {
@@ -94,13 +167,28 @@ impl Default for FilesystemWriter<'_, '_, '_> {
             block_size,
             mod_time: 0,
             id_table: Id::root(),
+            fixed_id_table: false,
             fs_compressor: FilesystemCompressor::default(),
             kind: Kind { inner: Arc::new(LE_V4_0) },
             root: Nodes::new_root(NodeHeader::default()),
             block_log: (block_size as f32).log2() as u16,
             pad_len: DEFAULT_PAD_LEN,
             no_duplicate_files: true,
+            always_fragment: false,
+            fragments_uncompressed: false,
             emit_compression_options: true,
+            fragment_order: FragmentOrder::default(),
+            file_insertions: 0,
+            preserve_inode_numbers: false,
+            kernel_compatible: false,
+            eager_compression: false,
+            metadata_compression: MetadataComp::default(),
+            trailer: vec![],
+            require_non_empty: false,
+            section_layout: SectionLayout::default(),
+            #[cfg(feature = "trace-compression")]
+            compression_trace: None,
+            existing: None,
         }
     }
 }
@@ -137,12 +225,24 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
 
     /// Set kind as `kind`
     ///
+    /// The magic and version of `kind` are written to the [`SuperBlock`]. All fields of the
+    /// superblock, inodes, dirs, and lookup tables are written with the type endian of `kind`, and
+    /// the lengths of metadata blocks with its data endian, such as for big-endian
+    /// [`BE_V4_0`](crate::kind::BE_V4_0) images.
+    ///
     /// # Example: Set kind to default V4.0
     /// ```rust
     /// # use backhand::{FilesystemWriter, kind::Kind, kind};
     /// let mut fs = FilesystemWriter::default();
     /// fs.set_kind(Kind::from_const(kind::LE_V4_0).unwrap());
     /// ```
+    ///
+    /// # Example: Set kind to V4.1
+    /// ```rust
+    /// # use backhand::{FilesystemWriter, kind::Kind, kind};
+    /// let mut fs = FilesystemWriter::default();
+    /// fs.set_kind(Kind::from_const(kind::LE_V4_0).unwrap().with_version(4, 1));
+    /// ```
     pub fn set_kind(&mut self, kind: Kind) {
         self.kind = kind;
     }
@@ -184,6 +284,24 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
         self.id_table = Id::root();
     }
 
+    /// Set id_table to `id_table`, which is written in the same order
+    ///
+    /// The `uid` and `gid` of nodes are no longer added to the id_table, adding a node with an id
+    /// not in `id_table` returns [`BackhandError::InvalidIdTable`].
+    ///
+    /// ```rust
+    /// # use std::io::Cursor;
+    /// # use backhand::{FilesystemWriter, Id, NodeHeader};
+    /// let mut fs = FilesystemWriter::default();
+    /// fs.set_id_table(vec![Id::new(0), Id::new(100), Id::new(1000)]);
+    /// let header = NodeHeader { uid: 1000, gid: 100, ..NodeHeader::default() };
+    /// fs.push_dir("home", header).unwrap();
+    /// ```
+    pub fn set_id_table(&mut self, id_table: Vec<Id>) {
+        self.id_table = id_table;
+        self.fixed_id_table = true;
+    }
+
     /// Set padding(zero bytes) added to the end of the image after calling [`write`].
     ///
     /// For example, if given `pad_kib` of 8; a 8K padding will be added to the end of the image.
@@ -203,23 +321,276 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
         self.no_duplicate_files = value;
     }
 
+    /// Set if the tail-end of files with data blocks are stored in fragments, off by default
+    ///
+    /// Without this, only files smaller than the block size are stored in fragments. Files that
+    /// are a multiple of the block size don't have a tail-end, and are never stored in fragments.
+    pub fn set_always_fragment(&mut self, value: bool) {
+        self.always_fragment = value;
+    }
+
+    /// Set if fragment blocks are stored uncompressed, off by default
+    pub fn set_fragments_uncompressed(&mut self, value: bool) {
+        self.fragments_uncompressed = value;
+    }
+
+    /// Set the order files are considered for fragment packing, see [`FragmentOrder`]
+    ///
+    /// This is also the order the data of files is written.
+    ///
+    /// Default: [`FragmentOrder::ByPathSorted`]
+    pub fn set_fragment_order(&mut self, order: FragmentOrder) {
+        self.fragment_order = order;
+    }
+
     /// Set if compression options are written
     pub fn set_emit_compression_options(&mut self, value: bool) {
         self.emit_compression_options = value;
     }
 
+    /// Set if nodes keep their inode number from the image they were read from, off by default
+    ///
+    /// Without this, inode numbers are assigned sequentially in path order. Nodes without an
+    /// original inode number, such as those pushed after [`Self::from_fs_reader`], use the lowest
+    /// unused inode numbers. Writing fails with [`BackhandError::DuplicatedInodeNumber`] if an
+    /// inode number is used by more than one node, and with [`BackhandError::InvalidInodeNumber`]
+    /// if an inode number is larger than the amount of nodes, such as after nodes were removed.
+    pub fn set_preserve_inode_numbers(&mut self, value: bool) {
+        self.preserve_inode_numbers = value;
+    }
+
+    /// Set if writing fails with [`BackhandError::KernelIncompatible`] for images that mainline
+    /// linux kernels can't mount, off by default
+    ///
+    /// The following are enforced:
+    /// - kind is [`LE_V4_0`], little-endian with the `hsqs` magic and version 4.0. The
+    ///   [`CompressionAction`](crate::compression::CompressionAction) of the kind isn't checked.
+    /// - block size is a power of two from [`MIN_BLOCK_SIZE`] to [`MAX_BLOCK_SIZE`]
+    /// - compressor is one of gzip, lzo, xz, lz4 or zstd
+    /// - xz dictionary size is at least 8KiB, and a power of two or the sum of two consecutive
+    ///   powers of two
+    /// - lz4 options are the legacy version `1`
+    ///
+    /// This doesn't check that the kernel was built with support for the compressor.
+    pub fn set_kernel_compatible(&mut self, value: bool) {
+        self.kernel_compatible = value;
+    }
+
+    /// Set which metadata tables are compressed, independent of the data and fragment blocks
+    ///
+    /// With `inodes` off, the superblock flag [`Flags::InodesStoredUncompressed`] is set. The
+    /// other tables have no superblock flag, the uncompressed bit of each metadata block is used.
+    ///
+    /// ```rust
+    /// # use backhand::{FilesystemWriter, MetadataComp};
+    /// let mut fs = FilesystemWriter::default();
+    /// // same as mksquashfs -noI, without its effect on the other tables
+    /// fs.set_metadata_compression(MetadataComp { inodes: false, ..MetadataComp::default() });
+    /// ```
+    ///
+    /// Default: [`MetadataComp::default`]
+    pub fn set_metadata_compression(&mut self, compression: MetadataComp) {
+        self.metadata_compression = compression;
+    }
+
+    /// Set bytes written after the image and its padding, empty by default
+    ///
+    /// These bytes aren't part of the image and are ignored by the linux kernel and unsquashfs,
+    /// such as for build information. Read with [`FilesystemReader::trailer`], which doesn't return
+    /// leading zero bytes.
+    pub fn set_trailer(&mut self, trailer: Vec<u8>) {
+        self.trailer = trailer;
+    }
+
+    /// Set if [`Self::write`] returns [`BackhandError::EmptyImage`] when no nodes other than the
+    /// root were pushed, off by default
+    pub fn set_require_non_empty(&mut self, value: bool) {
+        self.require_non_empty = value;
+    }
+
+    /// Set the order of the data and metadata sections, [`SectionLayout::DataFirst`] by default
+    ///
+    /// With [`SectionLayout::MetadataFirst`], the inode table and fragment lookup table are always
+    /// stored uncompressed, and all data is held in memory until the tables are written. As all
+    /// sections are found from the positions stored in the superblock and tables, the image is
+    /// read by [`FilesystemReader`] without any other option.
+    pub fn set_section_layout(&mut self, layout: SectionLayout) {
+        self.section_layout = layout;
+    }
+
+    /// Record each data and fragment block written by [`Self::write`] in `trace`
+    ///
+    /// The events are in the order the blocks were compressed, allowing the blocks of two images
+    /// to be compared. Blocks copied as is from a [`FilesystemReader`] aren't recorded, blocks
+    /// compressed when pushed with [`Self::set_eager_compression`] are.
+    ///
+    /// ```rust
+    /// # use std::io::Cursor;
+    /// # use std::sync::{Arc, Mutex};
+    /// # use backhand::{FilesystemWriter, NodeHeader};
+    /// let trace = Arc::new(Mutex::new(vec![]));
+    /// let mut fs = FilesystemWriter::default();
+    /// fs.set_compression_trace(trace.clone());
+    /// fs.push_file(Cursor::new(vec![0; 0x20000]), "file", NodeHeader::default()).unwrap();
+    /// fs.write(Cursor::new(vec![])).unwrap();
+    /// for event in trace.lock().unwrap().iter() {
+    ///     println!("{} -> {}", event.input_len, event.output_len);
+    /// }
+    /// ```
+    #[cfg(feature = "trace-compression")]
+    pub fn set_compression_trace(&mut self, trace: Arc<Mutex<Vec<CompressEvent>>>) {
+        self.compression_trace = Some(trace);
+    }
+
+    /// Set if the data of files is read and compressed when pushed, instead of in
+    /// [`Self::write`], off by default
+    ///
+    /// With this, [`Self::push_file`], [`Self::push_file_with_compression`] and
+    /// [`Self::replace_file`] read all of the reader and hold the compressed data blocks in
+    /// memory, leaving only the tail-end of each file and the tables to [`Self::write`].
+    ///
+    /// The kind, compressor and block size should be set before pushing files. Data compressed
+    /// with a different compressor or block size than used by [`Self::write`] is decompressed and
+    /// compressed again.
+    pub fn set_eager_compression(&mut self, value: bool) {
+        self.eager_compression = value;
+    }
+
     /// Inherit filesystem structure and properties from `reader`
+    ///
+    /// Only the already read nodes of `reader` are used, nothing is read from the image until
+    /// [`Self::write`]. The data of files is then read through the reader of `reader`, and if
+    /// the compressor, compression options and block size are unchanged, the data blocks are
+    /// copied as is without being decompressed. Writing to a different file than the image of
+    /// `reader` doesn't require opening the image again.
     pub fn from_fs_reader(reader: &'a FilesystemReader<'b>) -> Result<Self, BackhandError> {
+        Self::from_fs_reader_subtree(reader, "/")
+    }
+
+    /// Inherit filesystem structure and properties from `reader`, only including the nodes under
+    /// the directory `path`, with `path` as the new root
+    ///
+    /// Returns [`BackhandError::FileNotFound`] if `path` isn't a directory in `reader`.
+    ///
+    /// ```rust,no_run
+    /// # use std::fs::File;
+    /// # use std::io::BufReader;
+    /// # use backhand::{FilesystemReader, FilesystemWriter};
+    /// # let file = BufReader::new(File::open("image.squashfs").unwrap());
+    /// # let read_filesystem = FilesystemReader::from_reader(file).unwrap();
+    /// // "/usr/bin/wget" is written as "/bin/wget"
+    /// let mut write_filesystem =
+    ///     FilesystemWriter::from_fs_reader_subtree(&read_filesystem, "/usr").unwrap();
+    /// ```
+    pub fn from_fs_reader_subtree<P: AsRef<Path>>(
+        reader: &'a FilesystemReader<'b>,
+        path: P,
+    ) -> Result<Self, BackhandError> {
+        Self::from_reader_nodes(reader, path.as_ref(), |file| {
+            SquashfsFileWriter::SquashfsFile(reader.file(file))
+        })
+    }
+
+    /// Open the image file at `path`, to add, replace and remove nodes before writing it again with
+    /// [`Self::write_back`]
+    ///
+    /// Unlike [`Self::from_fs_reader`], the image is read and kept open by the returned
+    /// FilesystemWriter. Files of the image are read from it when writing, with their data blocks
+    /// copied as is when the compressor, compression options and block size are unchanged. This
+    /// avoids recompressing the whole image when only adding a few files.
+    ///
+    /// ```rust,no_run
+    /// # use std::io::Cursor;
+    /// # use backhand::{FilesystemWriter, NodeHeader};
+    /// let mut fs = FilesystemWriter::open_existing("rootfs.squashfs").unwrap();
+    /// let overlay = Cursor::new(b"overlay\n".to_vec());
+    /// fs.push_file(overlay, "etc/overlay", NodeHeader::default()).unwrap();
+    /// fs.write_back("rootfs.squashfs").unwrap();
+    /// ```
+    pub fn open_existing<P: AsRef<Path>>(path: P) -> Result<Self, BackhandError> {
+        let file = BufReader::new(File::open(path.as_ref())?);
+        let reader = FilesystemReader::from_reader(file)?;
+        let mut writer = Self::from_reader_nodes(&reader, Path::new("/"), |file| {
+            SquashfsFileWriter::Existing(file.clone())
+        })?;
+        writer.existing = Some(ExistingImage(reader));
+        Ok(writer)
+    }
+
+    /// Write to a new file next to `path`, then rename it to `path`
+    ///
+    /// `path` is usually the image given to [`Self::open_existing`], which is only replaced after
+    /// it was successfully written, keeping its permissions. The new file is named after `path`
+    /// with a `.tmp` suffix, numbered if such a file already exists. The image opened with
+    /// [`Self::open_existing`] is closed before renaming.
+    ///
+    /// If writing or renaming fails, the new file is removed, and the files of the image opened
+    /// with [`Self::open_existing`] can be written again. Files pushed with a reader were read by
+    /// the failed write, and need to be replaced with [`Self::replace_file`] before retrying,
+    /// unless compressed when pushed with [`Self::set_eager_compression`].
+    pub fn write_back<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<(SuperBlock, u64), BackhandError> {
+        let path = path.as_ref();
+        // files are consumed by writing, keep the files of the image to retry on error
+        let files = self.file_copies();
+
+        let (temp_path, file) = create_temp_file(path)?;
+        let mut w = BufWriter::new(file);
+        let result = self.write(&mut w).and_then(|written| {
+            w.flush()?;
+            // keep the permissions of the replaced image
+            if let Ok(metadata) = fs::metadata(path) {
+                fs::set_permissions(&temp_path, metadata.permissions())?;
+            }
+            Ok(written)
+        });
+        drop(w);
+        let result = result.and_then(|written| {
+            let existing = self.existing.take();
+            if let Err(e) = fs::rename(&temp_path, path) {
+                self.existing = existing;
+                return Err(e.into());
+            }
+            Ok(written)
+        });
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+            self.restore_files(&files);
+        }
+        result
+    }
+
+    /// Inherit filesystem structure and properties from `reader`, with the nodes under the dir
+    /// `subtree`, and files created by `file`
+    fn from_reader_nodes<'r>(
+        reader: &'r FilesystemReader<'_>,
+        subtree: &Path,
+        mut file: impl FnMut(&'r SquashfsFileReader) -> SquashfsFileWriter<'a, 'b, 'c>,
+    ) -> Result<Self, BackhandError> {
+        let subtree = normalize_squashfs_path(subtree)?;
+        let is_dir = reader
+            .root
+            .nodes
+            .iter()
+            .any(|node| node.fullpath == subtree && matches!(node.inner, InnerNode::Dir(_)));
+        if !is_dir {
+            return Err(BackhandError::FileNotFound);
+        }
+
         let mut root: Vec<Node<_>> = reader
             .root
             .nodes
             .iter()
-            .map(|node| {
+            .filter_map(|node| {
+                let path = node.fullpath.strip_prefix(&subtree).ok()?;
+                Some((Path::new(Component::RootDir.as_os_str()).join(path), node))
+            })
+            .map(|(fullpath, node)| {
                 let inner = match &node.inner {
-                    InnerNode::File(file) => {
-                        let reader = reader.file(file);
-                        InnerNode::File(SquashfsFileWriter::SquashfsFile(reader))
-                    }
+                    InnerNode::File(reader_file) => InnerNode::File(file(reader_file)),
                     InnerNode::Symlink(x) => InnerNode::Symlink(x.clone()),
                     InnerNode::Dir(x) => InnerNode::Dir(*x),
                     InnerNode::CharacterDevice(x) => InnerNode::CharacterDevice(*x),
@@ -227,7 +598,15 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
                     InnerNode::NamedPipe => InnerNode::NamedPipe,
                     InnerNode::Socket => InnerNode::Socket,
                 };
-                Node { fullpath: node.fullpath.clone(), header: node.header, inner }
+                Node {
+                    fullpath,
+                    header: node.header,
+                    inner,
+                    inode_number: node.inode_number,
+                    link_count: node.link_count,
+                    file_compression: node.file_compression,
+                    insertion: None,
+                }
             })
             .collect();
         root.sort();
@@ -235,19 +614,117 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
             kind: Kind { inner: reader.kind.inner.clone() },
             block_size: reader.block_size,
             block_log: reader.block_log,
-            fs_compressor: FilesystemCompressor::new(
-                reader.compressor,
-                reader.compression_options,
-            )?,
+            fs_compressor: FilesystemCompressor::from_reader(reader)?,
             mod_time: reader.mod_time,
             id_table: reader.id_table.clone(),
+            fixed_id_table: false,
             root: Nodes { nodes: root },
             pad_len: DEFAULT_PAD_LEN,
             no_duplicate_files: reader.no_duplicate_files,
+            always_fragment: reader.always_fragment,
+            fragments_uncompressed: reader.fragments_uncompressed,
             emit_compression_options: true,
+            fragment_order: FragmentOrder::default(),
+            file_insertions: 0,
+            preserve_inode_numbers: false,
+            kernel_compatible: false,
+            eager_compression: false,
+            metadata_compression: MetadataComp::default(),
+            trailer: vec![],
+            require_non_empty: false,
+            section_layout: SectionLayout::default(),
+            #[cfg(feature = "trace-compression")]
+            compression_trace: None,
+            existing: None,
         })
     }
 
+    /// Create a default FilesystemWriter with the nodes described by `tree`
+    ///
+    /// `tree` must be a [`TreeSpec::Dir`], its header is used for the root and its name is
+    /// ignored. Parent directories are pushed before their children, the data of files is only
+    /// read in [`Self::write`].
+    ///
+    /// ```rust
+    /// # use backhand::{FilesystemWriter, NodeHeader, TreeSpec};
+    /// let header = NodeHeader::default();
+    /// let tree = TreeSpec::dir("", header, vec![
+    ///     TreeSpec::dir("usr", header, vec![
+    ///         TreeSpec::file("hello", header, b"hello\n".to_vec()),
+    ///     ]),
+    ///     TreeSpec::symlink("hello", header, "usr/hello"),
+    /// ]);
+    /// let fs = FilesystemWriter::from_tree(&tree).unwrap();
+    /// ```
+    pub fn from_tree(tree: &'c TreeSpec) -> Result<Self, BackhandError> {
+        let TreeSpec::Dir { header, children, .. } = tree else {
+            return Err(BackhandError::InvalidFilePath);
+        };
+        let mut fs = Self::default();
+        fs.root.root_mut().header = *header;
+        for child in children {
+            fs.push_tree(Path::new("/"), child)?;
+        }
+        Ok(fs)
+    }
+
+    fn push_tree(&mut self, parent: &Path, tree: &'c TreeSpec) -> Result<(), BackhandError> {
+        match tree {
+            TreeSpec::Dir { name, header, children } => {
+                let path = parent.join(name);
+                self.push_dir(&path, *header)?;
+                for child in children {
+                    self.push_tree(&path, child)?;
+                }
+            }
+            TreeSpec::File { name, header, data } => {
+                self.push_file(Cursor::new(data.as_slice()), parent.join(name), *header)?;
+            }
+            TreeSpec::Symlink { name, header, link } => {
+                self.push_symlink(link, parent.join(name), *header)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Inherit filesystem structure from `reader`, normalizing the properties of the image so that
+    /// [`Self::write`] produces a standard image
+    ///
+    /// This is used to repair an image that can be read, but is in a non-standard format:
+    /// - kind is set to [`LE_V4_0`], keeping the [`CompressionAction`] of `reader`
+    /// - `pad_len` is [`DEFAULT_PAD_LEN`], and compression options are emitted
+    /// - `block_log` is computed from `block_size`
+    ///
+    /// The written image doesn't include any offset, data before or after the image in the
+    /// original file, or an export table.
+    ///
+    /// ```rust,no_run
+    /// # use std::fs::File;
+    /// # use std::io::BufReader;
+    /// # use backhand::{FilesystemReader, FilesystemWriter, kind::Kind};
+    /// let file = BufReader::new(File::open("image.squashfs").unwrap());
+    /// let kind = Kind::from_target("be_v4_0").unwrap();
+    /// let read_filesystem = FilesystemReader::from_reader_with_offset_and_kind(file, 0, kind).unwrap();
+    ///
+    /// let mut write_filesystem = FilesystemWriter::from_fs_reader_normalized(&read_filesystem).unwrap();
+    /// let mut output = File::create("normalized.squashfs").unwrap();
+    /// write_filesystem.write(&mut output).unwrap();
+    /// ```
+    ///
+    /// [`CompressionAction`]: crate::compression::CompressionAction
+    pub fn from_fs_reader_normalized(
+        reader: &'a FilesystemReader<'b>,
+    ) -> Result<Self, BackhandError> {
+        let mut fs = Self::from_fs_reader(reader)?;
+        fs.kind = Kind {
+            inner: Arc::new(InnerKind { compressor: reader.kind.inner.compressor, ..LE_V4_0 }),
+        };
+        fs.block_log = (fs.block_size as f32).log2() as u16;
+        fs.pad_len = DEFAULT_PAD_LEN;
+        fs.emit_compression_options = true;
+        Ok(fs)
+    }
+
     //find the node relative to this path and return a mutable reference
     fn mut_node<S>(&mut self, find_path: S) -> Option<&mut Node<SquashfsFileWriter<'a, 'b, 'c>>>
     where
@@ -268,10 +745,17 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
     where
         P: AsRef<Path>,
     {
-        // create gid id
-        self.lookup_add_id(header.gid);
-        // create uid id
-        self.lookup_add_id(header.uid);
+        if self.fixed_id_table {
+            if !self.has_ids(&header) {
+                error!("uid {} or gid {} not in id_table", header.uid, header.gid);
+                return Err(BackhandError::InvalidIdTable);
+            }
+        } else {
+            // create gid id
+            self.lookup_add_id(header.gid);
+            // create uid id
+            self.lookup_add_id(header.uid);
+        }
 
         let path = normalize_squashfs_path(path.as_ref())?;
         let node = Node::new(path, header, node);
@@ -290,12 +774,78 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
     where
         P: AsRef<Path>,
     {
-        let reader = Arc::new(Mutex::new(reader));
-        let new_file = InnerNode::File(SquashfsFileWriter::UserDefined(reader));
-        self.insert_node(path, header, new_file)?;
+        self.push_file_inner(reader, path, header, FileCompression::Default)
+    }
+
+    /// Same as [`Self::push_file`], but with a per-file `compression` override
+    ///
+    /// With [`FileCompression::Store`], the data of this file is always written uncompressed,
+    /// regardless of the compressor of the image.
+    ///
+    /// ```rust
+    /// # use std::io::Cursor;
+    /// # use backhand::{FileCompression, FilesystemWriter, NodeHeader};
+    /// let mut fs = FilesystemWriter::default();
+    /// let header = NodeHeader::default();
+    /// // already compressed data, don't try to compress again
+    /// let bytes = Cursor::new(vec![0x1f, 0x8b, 0x08, 0x00]);
+    /// fs.push_file_with_compression(bytes, "blob.gz", header, FileCompression::Store).unwrap();
+    /// ```
+    pub fn push_file_with_compression<P>(
+        &mut self,
+        reader: impl Read + 'c,
+        path: P,
+        header: NodeHeader,
+        compression: FileCompression,
+    ) -> Result<(), BackhandError>
+    where
+        P: AsRef<Path>,
+    {
+        self.push_file_inner(reader, path, header, compression)
+    }
+
+    fn push_file_inner<P>(
+        &mut self,
+        reader: impl Read + 'c,
+        path: P,
+        header: NodeHeader,
+        compression: FileCompression,
+    ) -> Result<(), BackhandError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = normalize_squashfs_path(path.as_ref())?;
+        let new_file = InnerNode::File(self.file_writer(reader, compression)?);
+        self.insert_node(&path, header, new_file)?;
+        let insertion = self.file_insertions;
+        self.file_insertions += 1;
+        if let Some(node) = self.mut_node(&path) {
+            node.file_compression = compression;
+            node.insertion = Some(insertion);
+        }
         Ok(())
     }
 
+    /// Either `reader`, or with [`Self::set_eager_compression`] the compressed data of `reader`
+    fn file_writer(
+        &self,
+        reader: impl Read + 'c,
+        compression: FileCompression,
+    ) -> Result<SquashfsFileWriter<'a, 'b, 'c>, BackhandError> {
+        if self.eager_compression {
+            let file = CompressedFile::new(
+                self.kind.inner.compressor,
+                self.fs_compressor.clone(),
+                self.block_size,
+                reader,
+                compression,
+            )?;
+            Ok(SquashfsFileWriter::Compressed(file))
+        } else {
+            Ok(SquashfsFileWriter::UserDefined(Arc::new(Mutex::new(reader))))
+        }
+    }
+
     /// Take a mutable reference to existing file at `find_path`
     pub fn mut_file<S>(&mut self, find_path: S) -> Option<&mut SquashfsFileWriter<'a, 'b, 'c>>
     where
@@ -319,9 +869,52 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
     where
         S: AsRef<Path>,
     {
-        let file = self.mut_file(find_path).ok_or(BackhandError::FileNotFound)?;
-        let reader = Arc::new(Mutex::new(reader));
-        *file = SquashfsFileWriter::UserDefined(reader);
+        let path = normalize_squashfs_path(find_path.as_ref())?;
+        let compression = match self.mut_node(&path) {
+            Some(node @ Node { inner: InnerNode::File(_), .. }) => node.file_compression,
+            _ => return Err(BackhandError::FileNotFound),
+        };
+        let new_file = self.file_writer(reader, compression)?;
+        *self.mut_file(&path).unwrap() = new_file;
+        Ok(())
+    }
+
+    /// Replace an existing file with `reader`, and its metadata with `header`
+    ///
+    /// The `uid` and `gid` in `header` are added to FilesystemWriters id's. On error, neither the
+    /// file nor the id's are changed.
+    ///
+    /// ```rust
+    /// # use std::io::Cursor;
+    /// # use backhand::{FilesystemWriter, NodeHeader};
+    /// let mut fs = FilesystemWriter::default();
+    /// fs.push_file(Cursor::new(b"old"), "config", NodeHeader::default()).unwrap();
+    /// let header = NodeHeader { permissions: 0o600, uid: 1000, gid: 1000, mtime: 1 };
+    /// fs.patch_file("config", Cursor::new(b"new"), header).unwrap();
+    /// ```
+    pub fn patch_file<S>(
+        &mut self,
+        find_path: S,
+        reader: impl Read + 'c,
+        header: NodeHeader,
+    ) -> Result<(), BackhandError>
+    where
+        S: AsRef<Path>,
+    {
+        let path = normalize_squashfs_path(find_path.as_ref())?;
+        if self.mut_file(&path).is_none() {
+            return Err(BackhandError::FileNotFound);
+        }
+        if self.fixed_id_table && !self.has_ids(&header) {
+            error!("uid {} or gid {} not in id_table", header.uid, header.gid);
+            return Err(BackhandError::InvalidIdTable);
+        }
+        self.replace_file(&path, reader)?;
+        if !self.fixed_id_table {
+            self.lookup_add_id(header.gid);
+            self.lookup_add_id(header.uid);
+        }
+        self.mut_node(&path).unwrap().header = header;
         Ok(())
     }
 
@@ -343,6 +936,29 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
         Ok(())
     }
 
+    /// Same as [`Self::push_symlink`], but with the `link_count` of the symlink inode, instead
+    /// of `1`
+    ///
+    /// Used when reproducing images with hard-linked symlinks.
+    pub fn push_symlink_with_links<P, S>(
+        &mut self,
+        link: S,
+        path: P,
+        header: NodeHeader,
+        link_count: u32,
+    ) -> Result<(), BackhandError>
+    where
+        P: AsRef<Path>,
+        S: Into<PathBuf>,
+    {
+        let path = normalize_squashfs_path(path.as_ref())?;
+        self.push_symlink(link, &path, header)?;
+        if let Some(node) = self.mut_node(&path) {
+            node.link_count = Some(link_count);
+        }
+        Ok(())
+    }
+
     /// Insert empty `dir` at `path`
     ///
     /// The `uid` and `gid` in `header` are added to FilesystemWriters id's
@@ -443,6 +1059,80 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
         Ok(())
     }
 
+    /// Insert the node at `host_path` on the host at `path`, with the type and header from
+    /// `metadata`
+    ///
+    /// Depending on the file type of `metadata`, this calls [`Self::push_file`] with `host_path`
+    /// opened, [`Self::push_dir`], [`Self::push_symlink`] with the target read from `host_path`,
+    /// [`Self::push_char_device`] or [`Self::push_block_device`] with the device number from
+    /// `st_rdev`, [`Self::push_fifo`], or [`Self::push_socket`]. The header is from
+    /// [`NodeHeader::from_metadata`].
+    ///
+    /// Pass [`std::fs::symlink_metadata`] to insert symlinks as symlinks, or [`std::fs::metadata`]
+    /// to insert their target. Returns [`BackhandError::InvalidDeviceNumber`] for a device number
+    /// that doesn't fit into the 32 bits stored in the image.
+    ///
+    /// ```rust,no_run
+    /// # use backhand::FilesystemWriter;
+    /// let mut fs = FilesystemWriter::default();
+    /// let metadata = std::fs::symlink_metadata("/dev/null").unwrap();
+    /// fs.push_with_metadata("dev/null", "/dev/null", &metadata).unwrap();
+    /// ```
+    #[cfg(unix)]
+    pub fn push_with_metadata<P, H>(
+        &mut self,
+        path: P,
+        host_path: H,
+        metadata: &std::fs::Metadata,
+    ) -> Result<(), BackhandError>
+    where
+        P: AsRef<Path>,
+        H: AsRef<Path>,
+    {
+        use std::io;
+        use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+        use crate::device_number_from_major_minor;
+
+        let header = NodeHeader::from_metadata(metadata);
+        let file_type = metadata.file_type();
+        // the major and minor of the host device number, stored with a 12 and 20 bit limit
+        let device_number = || {
+            #[allow(clippy::unnecessary_cast)]
+            let rdev = metadata.rdev() as nix::libc::dev_t;
+            #[allow(clippy::unnecessary_cast)]
+            let (major, minor) = (nix::libc::major(rdev) as u32, nix::libc::minor(rdev) as u32);
+            if major > 0xfff || minor > 0xf_ffff {
+                return Err(BackhandError::InvalidDeviceNumber(metadata.rdev()));
+            }
+            Ok(device_number_from_major_minor(major, minor))
+        };
+        if file_type.is_file() {
+            let file = std::fs::File::open(host_path)?;
+            self.push_file(file, path, header)
+        } else if file_type.is_dir() {
+            self.push_dir(path, header)
+        } else if file_type.is_symlink() {
+            let link = std::fs::read_link(host_path)?;
+            self.push_symlink(link, path, header)
+        } else if file_type.is_char_device() {
+            self.push_char_device(device_number()?, path, header)
+        } else if file_type.is_block_device() {
+            self.push_block_device(device_number()?, path, header)
+        } else if file_type.is_fifo() {
+            self.push_fifo(path, header)
+        } else if file_type.is_socket() {
+            self.push_socket(path, header)
+        } else {
+            let host_path = host_path.as_ref().display();
+            let e = io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{host_path}: unknown file type"),
+            );
+            Err(e.into())
+        }
+    }
+
     /// Same as [`Self::write`], but seek'ing to `offset` in `w` before reading. This offset
     /// is treated as the base image offset.
     pub fn write_with_offset<W>(
@@ -457,6 +1147,135 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
         self.write(&mut writer)
     }
 
+    /// Same as [`Self::write`], but trying block sizes and compressors until the image fits into
+    /// `max_bytes`
+    ///
+    /// Starting with the compressor and block size of this writer, each larger power of two block
+    /// size up to [`MAX_BLOCK_SIZE`] is tried, as larger blocks usually compress better. This is
+    /// repeated with the highest compression level of the compressor, then with xz at its highest
+    /// level. Each try only counts the bytes of the image, which is then written to `w` with the
+    /// settings that fit, keeping them in this writer.
+    ///
+    /// Files pushed with a reader can only be read once, and are first compressed into memory as
+    /// with [`Self::set_eager_compression`]. Files read from an image are read from it again for
+    /// each try.
+    ///
+    /// Returns [`BackhandError::ImageTooLarge`] if the image doesn't fit with any setting, and
+    /// nothing is written to `w`. The compressor, block size and files of this writer are then
+    /// unchanged, except files pushed with a reader being compressed.
+    ///
+    /// ```rust
+    /// # use std::io::Cursor;
+    /// # use backhand::{FilesystemWriter, NodeHeader};
+    /// let mut fs = FilesystemWriter::default();
+    /// fs.push_file(Cursor::new(vec![0; 0x10000]), "zeros", NodeHeader::default()).unwrap();
+    /// let mut output = Cursor::new(vec![]);
+    /// let (_, bytes_written) = fs.fit_into(&mut output, 0x10_0000).unwrap();
+    /// assert!(bytes_written <= 0x10_0000);
+    /// ```
+    pub fn fit_into<W: Write + Seek>(
+        &mut self,
+        w: W,
+        max_bytes: u64,
+    ) -> Result<(SuperBlock, u64), BackhandError> {
+        // each try consumes the files, readers can't be read again
+        for node in &mut self.root.nodes {
+            let InnerNode::File(file) = &mut node.inner else {
+                continue;
+            };
+            if let SquashfsFileWriter::UserDefined(reader) = file {
+                let compressed = CompressedFile::new(
+                    self.kind.inner.compressor,
+                    self.fs_compressor.clone(),
+                    self.block_size,
+                    &mut *reader.lock().unwrap(),
+                    node.file_compression,
+                )?;
+                *file = SquashfsFileWriter::Compressed(compressed);
+            }
+        }
+        let files = self.file_copies();
+        let block_size = self.block_size;
+        let fs_compressor = self.fs_compressor.clone();
+
+        let fitting = self.fit_settings(&files, max_bytes);
+        self.restore_files(&files);
+        let result = match fitting {
+            Ok((compressor, block_size)) => {
+                self.set_compressor(compressor);
+                self.set_block_size(block_size);
+                self.write(w)
+            }
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            self.restore_files(&files);
+            self.set_compressor(fs_compressor);
+            self.set_block_size(block_size);
+        }
+        result
+    }
+
+    /// First compressor and block size writing an image of at most `max_bytes`, for
+    /// [`Self::fit_into`]
+    fn fit_settings(
+        &mut self,
+        files: &[(usize, SquashfsFileWriter<'a, 'b, 'c>)],
+        max_bytes: u64,
+    ) -> Result<(FilesystemCompressor, u32), BackhandError> {
+        let first_block_size = self.block_size;
+        for (index, compressor) in self.fs_compressor.stronger().into_iter().enumerate() {
+            let mut block_size = first_block_size;
+            loop {
+                self.restore_files(files);
+                self.set_compressor(compressor.clone());
+                self.set_block_size(block_size);
+                match self.write(ByteCounter::default()) {
+                    Ok((_, bytes_written)) if bytes_written <= max_bytes => {
+                        return Ok((compressor, block_size));
+                    }
+                    Ok((_, bytes_written)) => info!(
+                        "{bytes_written} bytes with {:?} and block size {block_size} don't fit into {max_bytes}",
+                        compressor.id
+                    ),
+                    // the compressor of this writer is supported, others might not be
+                    Err(BackhandError::UnsupportedCompression(id)) if index != 0 => {
+                        info!("skipping unsupported compressor {id:?}");
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                }
+                if block_size >= MAX_BLOCK_SIZE {
+                    break;
+                }
+                block_size = (block_size + 1).next_power_of_two();
+            }
+        }
+        Err(BackhandError::ImageTooLarge(max_bytes))
+    }
+
+    /// Copies of the files that can be written again, by node index
+    fn file_copies(&self) -> Vec<(usize, SquashfsFileWriter<'a, 'b, 'c>)> {
+        self.root
+            .nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, node)| match &node.inner {
+                InnerNode::File(file) => Some((index, file.try_clone()?)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Replace the files from [`Self::file_copies`], after they were consumed by writing
+    fn restore_files(&mut self, files: &[(usize, SquashfsFileWriter<'a, 'b, 'c>)]) {
+        for (index, file) in files {
+            if let Some(file) = file.try_clone() {
+                self.root.nodes[*index].inner = InnerNode::File(file);
+            }
+        }
+    }
+
     fn write_data<W>(
         &mut self,
         compressor: FilesystemCompressor,
@@ -467,28 +1286,51 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
     where
         W: WriteSeek,
     {
-        let files = self.root.nodes.iter_mut().filter_map(|node| match &mut node.inner {
-            InnerNode::File(file) => Some(file),
-            _ => None,
-        });
-        for file in files {
+        let mut files: Vec<_> = self
+            .root
+            .nodes
+            .iter_mut()
+            .filter_map(|node| match &mut node.inner {
+                InnerNode::File(file) => Some((node.insertion, node.file_compression, file)),
+                _ => None,
+            })
+            .collect();
+        if self.fragment_order == FragmentOrder::ByInsertion {
+            // files not added with push_file, such as from a FilesystemReader, are first
+            files.sort_by_key(|(insertion, _, _)| *insertion);
+        }
+        for (_, file_compression, file) in files {
             let (filesize, added) = match file {
                 SquashfsFileWriter::UserDefined(file) => {
                     let file_ptr = Arc::clone(file);
                     let mut file_lock = file_ptr.lock().unwrap();
-                    data_writer.add_bytes(&mut *file_lock, &mut writer)?
+                    data_writer.add_bytes_with_compression(
+                        &mut *file_lock,
+                        &mut writer,
+                        file_compression,
+                    )?
                 }
                 SquashfsFileWriter::SquashfsFile(file) => {
-                    // if the source file and the destination files are both
-                    // squashfs files and use the same compressor and block_size
-                    // just copy the data, don't compress->decompress
-                    if file.system.compressor == compressor.id
-                        && file.system.compression_options == compressor.options
-                        && file.system.block_size == block_size
+                    Self::add_reader_file(*file, &compressor, block_size, &mut writer, data_writer)?
+                }
+                SquashfsFileWriter::Existing(file) => {
+                    let existing = self.existing.as_ref().ok_or(BackhandError::ImageNotOpen)?;
+                    let file = existing.0.file(file);
+                    Self::add_reader_file(file, &compressor, block_size, &mut writer, data_writer)?
+                }
+                SquashfsFileWriter::Compressed(file) => {
+                    if file.compressor == compressor.id
+                        && file.compression_options == compressor.options
+                        && file.block_size == block_size
                     {
-                        data_writer.just_copy_it(file.raw_data_reader(), &mut writer)?
+                        data_writer.add_compressed(file, &mut writer)?
                     } else {
-                        data_writer.add_bytes(file.reader(), &mut writer)?
+                        let bytes = file.decompress(self.kind.inner.compressor)?;
+                        data_writer.add_bytes_with_compression(
+                            Cursor::new(bytes),
+                            &mut writer,
+                            file.file_compression,
+                        )?
                     }
                 }
                 SquashfsFileWriter::Consumed(_, _) => unreachable!(),
@@ -498,6 +1340,27 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
         Ok(())
     }
 
+    /// Add the data of `file`, read from an image
+    fn add_reader_file<W: WriteSeek>(
+        file: FilesystemReaderFile<'_, '_>,
+        compressor: &FilesystemCompressor,
+        block_size: u32,
+        writer: W,
+        data_writer: &mut DataWriter<'b>,
+    ) -> Result<(usize, Added), BackhandError> {
+        // if the source file and the destination files are both
+        // squashfs files and use the same compressor and block_size
+        // just copy the data, don't compress->decompress
+        if file.system.compressor == compressor.id
+            && file.system.compression_options == compressor.options
+            && file.system.block_size == block_size
+        {
+            data_writer.just_copy_it(file.raw_data_reader(), writer)
+        } else {
+            data_writer.add_bytes(file.reader(), writer)
+        }
+    }
+
     /// Create SquashFS file system from each node of Tree
     ///
     /// This works by recursively creating Inodes and Dirs for each node in the tree. This also
@@ -513,8 +1376,11 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
         superblock: &SuperBlock,
         kind: &Kind,
         id_table: &Vec<Id>,
+        inode_numbers: &[u32],
+        inode_refs: &mut IntMap<u32, u64>,
     ) -> Result<Entry<'slf>, BackhandError> {
         let node = &self.root.node(node_id).unwrap();
+        let inode_number = inode_numbers[node_id.get() - 1];
         let filename = node.fullpath.file_name().unwrap_or(OsStr::new("/"));
         //if not a dir, return the entry
         match &node.inner {
@@ -522,7 +1388,7 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
                 return Ok(Entry::file(
                     filename,
                     node.header,
-                    node_id.get().try_into().unwrap(),
+                    inode_number,
                     inode_writer,
                     *filesize,
                     added,
@@ -537,7 +1403,8 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
                     filename,
                     node.header,
                     symlink,
-                    node_id.get().try_into().unwrap(),
+                    node.link_count.unwrap_or(1),
+                    inode_number,
                     inode_writer,
                     superblock,
                     kind,
@@ -549,7 +1416,7 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
                     filename,
                     node.header,
                     char,
-                    node_id.get().try_into().unwrap(),
+                    inode_number,
                     inode_writer,
                     superblock,
                     kind,
@@ -561,7 +1428,7 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
                     filename,
                     node.header,
                     block,
-                    node_id.get().try_into().unwrap(),
+                    inode_number,
                     inode_writer,
                     superblock,
                     kind,
@@ -572,7 +1439,7 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
                 return Ok(Entry::named_pipe(
                     filename,
                     node.header,
-                    node_id.get().try_into().unwrap(),
+                    inode_number,
                     inode_writer,
                     superblock,
                     kind,
@@ -583,7 +1450,7 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
                 return Ok(Entry::socket(
                     filename,
                     node.header,
-                    node_id.get().try_into().unwrap(),
+                    inode_number,
                     inode_writer,
                     superblock,
                     kind,
@@ -606,22 +1473,42 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
                 self.write_inode_dir(
                     inode_writer,
                     dir_writer,
-                    node_id.get().try_into().unwrap(),
+                    inode_number,
                     child_id,
                     superblock,
                     kind,
                     id_table,
+                    inode_numbers,
+                    inode_refs,
                 )
             })
             .collect::<Result<_, _>>()?;
         let children_num = entries.len();
+        for entry in &entries {
+            let inode_ref = InodeRef::encode(entry.start, entry.offset);
+            inode_refs.insert(entry.inode, inode_ref);
+        }
 
         // write dir
         let block_index = dir_writer.metadata_start;
         let block_offset = dir_writer.uncompressed_bytes.len() as u16;
         trace!("WRITING DIR: {block_offset:#02x?}");
         let mut total_size: usize = 3;
+        let mut dir_index = vec![];
         for dir in Entry::into_dir(entries) {
+            // index every directory header that starts in a new metadata block, allowing lookups
+            // to skip directly to the header that could contain a name
+            let last_start =
+                dir_index.last().map_or(block_index, |index: &DirectoryIndex| index.start);
+            if dir_writer.metadata_start != last_start {
+                let name = &dir.dir_entries[0].name;
+                dir_index.push(DirectoryIndex {
+                    index: (total_size - 3).try_into().unwrap(),
+                    start: dir_writer.metadata_start,
+                    name_size: name.len() as u32 - 1,
+                    name: name.clone(),
+                });
+            }
             let mut bytes = Cursor::new(vec![]);
             let mut writer = Writer::new(&mut bytes);
             dir.to_writer(&mut writer, kind.inner.type_endian)?;
@@ -631,13 +1518,14 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
         let entry = Entry::path(
             filename,
             node.header,
-            node_id.get().try_into().unwrap(),
+            inode_number,
             children_num,
             parent_node_id,
             inode_writer,
             total_size,
             block_offset,
             block_index,
+            dir_index,
             superblock,
             kind,
             id_table,
@@ -646,11 +1534,205 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
         Ok(entry)
     }
 
+    /// Check the settings of `self` against the constraints of the linux kernel
+    fn check_kernel_compatible(&self) -> Result<(), BackhandError> {
+        let kind = &self.kind.inner;
+        if kind.magic != LE_V4_0.magic
+            || kind.type_endian != LE_V4_0.type_endian
+            || kind.data_endian != LE_V4_0.data_endian
+            || (kind.version_major, kind.version_minor)
+                != (LE_V4_0.version_major, LE_V4_0.version_minor)
+        {
+            return Err(BackhandError::KernelIncompatible("kind is not little-endian v4.0"));
+        }
+
+        let block_size = self.block_size;
+        if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size) || !block_size.is_power_of_two()
+        {
+            return Err(BackhandError::KernelIncompatible("invalid block size"));
+        }
+
+        match (self.fs_compressor.id, &self.fs_compressor.options) {
+            (Compressor::None | Compressor::Lzma, _) => {
+                Err(BackhandError::KernelIncompatible("unsupported compressor"))
+            }
+            (Compressor::Xz, Some(CompressionOptions::Xz(xz))) => {
+                let dict_size = xz.dictionary_size;
+                let n = dict_size.trailing_zeros();
+                let valid =
+                    dict_size >= 0x2000 && (dict_size == 1 << n || u64::from(dict_size) == 3 << n);
+                if valid {
+                    Ok(())
+                } else {
+                    Err(BackhandError::KernelIncompatible("invalid xz dictionary size"))
+                }
+            }
+            (Compressor::Lz4, Some(CompressionOptions::Lz4(lz4))) if lz4.version != 1 => {
+                Err(BackhandError::KernelIncompatible("lz4 options are not the legacy version"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Inode number of each node of `self.root.nodes`
+    fn inode_numbers(&self) -> Result<Vec<u32>, BackhandError> {
+        if !self.preserve_inode_numbers {
+            return Ok((1..=self.root.nodes.len() as u32).collect());
+        }
+
+        // inode numbers are 1 to the inode count, which is less than before if nodes were removed
+        let inode_count = self.root.nodes.len() as u32;
+        let mut used = HashSet::new();
+        for node in &self.root.nodes {
+            if let Some(inode_number) = node.inode_number {
+                if inode_number == 0 || inode_number > inode_count {
+                    error!(
+                        "{}: inode number {inode_number} not within {inode_count} inodes",
+                        node.fullpath.display()
+                    );
+                    return Err(BackhandError::InvalidInodeNumber(inode_number, inode_count));
+                }
+                if !used.insert(inode_number) {
+                    error!("{}: inode number {inode_number} already used", node.fullpath.display());
+                    return Err(BackhandError::DuplicatedInodeNumber(inode_number));
+                }
+            }
+        }
+        let mut unused = (1..).filter(|inode_number| !used.contains(inode_number));
+        Ok(self
+            .root
+            .nodes
+            .iter()
+            .map(|node| node.inode_number.unwrap_or_else(|| unused.next().unwrap()))
+            .collect())
+    }
+
     /// Generate and write the resulting squashfs image to `w`
     ///
     /// # Returns
-    /// (written populated [`SuperBlock`], total amount of bytes written including padding)
-    pub fn write<W: Write + Seek>(&mut self, mut w: W) -> Result<(SuperBlock, u64), BackhandError> {
+    /// (written populated [`SuperBlock`], total amount of bytes written including padding and
+    /// trailer)
+    pub fn write<W: Write + Seek>(&mut self, w: W) -> Result<(SuperBlock, u64), BackhandError> {
+        self.write_image(w, &mut IntMap::default(), &mut WriteStats::default())
+    }
+
+    /// Same as [`Self::write`], but also returning the [`WriteStats`] of the written blocks and
+    /// compressor calls
+    ///
+    /// Useful to find out why an image is larger than expected, such as by blocks stored
+    /// uncompressed as compression didn't reduce their size.
+    ///
+    /// ```rust
+    /// # use std::io::Cursor;
+    /// # use backhand::{FilesystemWriter, NodeHeader};
+    /// let mut fs = FilesystemWriter::default();
+    /// fs.push_file(Cursor::new(vec![0; 0x20000]), "zeros", NodeHeader::default()).unwrap();
+    /// let mut output = Cursor::new(vec![]);
+    /// let (_, _, stats) = fs.write_with_stats(&mut output).unwrap();
+    /// assert_eq!(stats.data_blocks_compressed, 1);
+    /// ```
+    pub fn write_with_stats<W: Write + Seek>(
+        &mut self,
+        w: W,
+    ) -> Result<(SuperBlock, u64, WriteStats), BackhandError> {
+        let mut stats = WriteStats::default();
+        let (superblock, bytes_written) =
+            self.write_image(w, &mut IntMap::default(), &mut stats)?;
+        Ok((superblock, bytes_written, stats))
+    }
+
+    /// Same as [`Self::write`], but also writing a [`Sidecar`] index of all nodes to `sidecar`
+    ///
+    /// The index contains the inode reference and data location of each node, allowing them to
+    /// be found in the image without reading the directory and inode tables.
+    ///
+    /// ```rust,no_run
+    /// # use std::fs::File;
+    /// # use backhand::FilesystemWriter;
+    /// # let mut write_filesystem = FilesystemWriter::default();
+    /// let mut output = File::create("image.squashfs").unwrap();
+    /// let mut sidecar = File::create("image.squashfs.idx").unwrap();
+    /// write_filesystem.write_with_sidecar(&mut output, &mut sidecar).unwrap();
+    /// ```
+    pub fn write_with_sidecar<W: Write + Seek, S: Write>(
+        &mut self,
+        w: W,
+        mut sidecar: S,
+    ) -> Result<(SuperBlock, u64), BackhandError> {
+        let mut inode_refs = IntMap::default();
+        let (superblock, bytes_written) =
+            self.write_image(w, &mut inode_refs, &mut WriteStats::default())?;
+        let inode_numbers = self.inode_numbers()?;
+
+        let entries = self
+            .root
+            .nodes
+            .iter()
+            .zip(inode_numbers)
+            .map(|(node, inode_number)| {
+                let path = node.fullpath.as_os_str().as_bytes().to_vec();
+                let mut entry = SidecarEntry {
+                    path_size: path.len().try_into().map_err(|_| BackhandError::InvalidFilePath)?,
+                    path,
+                    inode_number,
+                    inode: inode_refs[&inode_number],
+                    file_size: 0,
+                    blocks_start: 0,
+                    frag_index: 0xffffffff,
+                    block_offset: 0,
+                    block_count: 0,
+                    block_sizes: vec![],
+                };
+                if let InnerNode::File(SquashfsFileWriter::Consumed(file_size, added)) = &node.inner
+                {
+                    entry.file_size = *file_size as u64;
+                    match added {
+                        Added::Data { blocks_start, block_sizes } => {
+                            entry.blocks_start = u64::from(*blocks_start);
+                            entry.block_sizes.clone_from(block_sizes);
+                        }
+                        Added::Fragment { frag_index, block_offset } => {
+                            entry.frag_index = *frag_index;
+                            entry.block_offset = *block_offset;
+                        }
+                        Added::DataAndFragment {
+                            blocks_start,
+                            block_sizes,
+                            frag_index,
+                            block_offset,
+                        } => {
+                            entry.blocks_start = u64::from(*blocks_start);
+                            entry.block_sizes.clone_from(block_sizes);
+                            entry.frag_index = *frag_index;
+                            entry.block_offset = *block_offset;
+                        }
+                    }
+                    entry.block_count = entry.block_sizes.len() as u32;
+                }
+                Ok(entry)
+            })
+            .collect::<Result<Vec<_>, BackhandError>>()?;
+
+        let index = Sidecar {
+            inode_table: superblock.inode_table,
+            entry_count: entries.len() as u32,
+            entries,
+        };
+        sidecar.write_all(&index.to_bytes()?)?;
+        Ok((superblock, bytes_written))
+    }
+
+    fn write_image<W: Write + Seek>(
+        &mut self,
+        mut w: W,
+        inode_refs: &mut IntMap<u32, u64>,
+        stats: &mut WriteStats,
+    ) -> Result<(SuperBlock, u64), BackhandError> {
+        if self.require_non_empty && self.root.nodes.len() <= 1 {
+            error!("no nodes other than the root");
+            return Err(BackhandError::EmptyImage);
+        }
+
         let mut superblock =
             SuperBlock::new(self.fs_compressor.id, Kind { inner: self.kind.inner.clone() });
 
@@ -658,57 +1740,211 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
             superblock.flags |= Flags::DataHasBeenDeduplicated as u16;
         }
 
+        if self.always_fragment {
+            superblock.flags |= Flags::FragmentsAreAlwaysGenerated as u16;
+        }
+
+        if self.fragments_uncompressed {
+            superblock.flags |= Flags::FragmentsStoredUncompressed as u16;
+        }
+
+        let metadata_compression = match self.section_layout {
+            SectionLayout::DataFirst => self.metadata_compression,
+            // the length of the tables can't depend on the data positions stored in them
+            SectionLayout::MetadataFirst => {
+                MetadataComp { inodes: false, fragments_table: false, ..self.metadata_compression }
+            }
+        };
+
+        if !metadata_compression.inodes {
+            superblock.flags |= Flags::InodesStoredUncompressed as u16;
+        }
+
         trace!("{:#02x?}", self.root);
 
+        // such as the root, with ids set after pushing
+        if let Some(node) = self.root.nodes.iter().find(|node| !self.has_ids(&node.header)) {
+            error!(
+                "{}: uid {} or gid {} not in id_table",
+                node.fullpath.display(),
+                node.header.uid,
+                node.header.gid
+            );
+            return Err(BackhandError::InvalidIdTable);
+        }
+
+        if self.kernel_compatible {
+            self.check_kernel_compatible()?;
+        }
+
+        let inode_numbers = self.inode_numbers()?;
+
         // Empty Squashfs Superblock
         w.write_all(&[0x00; 96])?;
 
         if self.emit_compression_options {
             trace!("writing compression options, if exists");
+            let mut fs_compressor = self.fs_compressor.clone();
+            // not understood, so not written as the options of the compressed data
+            if let Some(CompressionOptions::Raw(_)) = fs_compressor.options {
+                info!("not writing raw compression options");
+                fs_compressor.options = None;
+            }
             let options = self.kind.inner.compressor.compression_options(
                 &mut superblock,
                 &self.kind,
-                self.fs_compressor,
+                fs_compressor,
             )?;
             w.write_all(&options)?;
         }
 
         let mut data_writer = DataWriter::new(
             self.kind.inner.compressor,
-            self.fs_compressor,
+            self.fs_compressor.clone(),
             self.block_size,
             self.no_duplicate_files,
+            self.always_fragment,
+            self.fragments_uncompressed,
         );
+        #[cfg(feature = "trace-compression")]
+        {
+            data_writer.compression_trace = self.compression_trace.clone();
+        }
+
+        match self.section_layout {
+            SectionLayout::DataFirst => {
+                info!("Writing Data");
+                self.write_data(
+                    self.fs_compressor.clone(),
+                    self.block_size,
+                    &mut w,
+                    &mut data_writer,
+                )?;
+                info!("Writing Data Fragments");
+                // Compress fragments and write
+                data_writer.finalize(&mut w)?;
+
+                self.write_tables(
+                    &mut w,
+                    &mut superblock,
+                    &data_writer.fragment_table,
+                    metadata_compression,
+                    &inode_numbers,
+                    inode_refs,
+                    stats,
+                )?;
+            }
+            SectionLayout::MetadataFirst => {
+                info!("Writing Data");
+                let mut data = Cursor::new(vec![]);
+                self.write_data(
+                    self.fs_compressor.clone(),
+                    self.block_size,
+                    &mut data,
+                    &mut data_writer,
+                )?;
+                info!("Writing Data Fragments");
+                data_writer.finalize(&mut data)?;
+
+                // write the tables once to find their length, and with that the start of the data
+                let tables_start = w.stream_position()?;
+                let mut tables = Cursor::new(vec![]);
+                tables.seek(SeekFrom::Start(tables_start))?;
+                self.write_tables(
+                    &mut tables,
+                    &mut superblock.clone(),
+                    &data_writer.fragment_table,
+                    metadata_compression,
+                    &inode_numbers,
+                    &mut IntMap::default(),
+                    &mut WriteStats::default(),
+                )?;
+                let data_start = tables.position();
+                self.move_data(data_start, &mut data_writer.fragment_table);
+
+                self.write_tables(
+                    &mut w,
+                    &mut superblock,
+                    &data_writer.fragment_table,
+                    metadata_compression,
+                    &inode_numbers,
+                    inode_refs,
+                    stats,
+                )?;
+                debug_assert_eq!(w.stream_position()?, data_start);
+
+                info!("Writing Data after Metadata");
+                w.write_all(data.get_ref())?;
+            }
+        }
+
+        stats.add(&data_writer.stats);
+
+        info!("Finalize Superblock and End Bytes");
+        let bytes_written = self.finalize(w, &mut superblock)?;
+
+        info!("Success");
+        Ok((superblock, bytes_written))
+    }
+
+    /// Move the data written by [`Self::write_data`] from the start of the writer to `data_start`
+    fn move_data(&mut self, data_start: u64, fragment_table: &mut [fragment::Fragment]) {
+        for node in &mut self.root.nodes {
+            if let InnerNode::File(SquashfsFileWriter::Consumed(_, added)) = &mut node.inner {
+                match added {
+                    Added::Data { blocks_start, .. }
+                    | Added::DataAndFragment { blocks_start, .. } => {
+                        *blocks_start += data_start as u32;
+                    }
+                    Added::Fragment { .. } => (),
+                }
+            }
+        }
+        for fragment in fragment_table {
+            fragment.start += data_start;
+        }
+    }
+
+    /// Write the inode, dir, fragment lookup and id lookup tables, setting their positions in
+    /// `superblock`
+    #[allow(clippy::too_many_arguments)]
+    fn write_tables<W: Write + Seek>(
+        &self,
+        mut w: W,
+        superblock: &mut SuperBlock,
+        fragment_table: &[fragment::Fragment],
+        metadata_compression: MetadataComp,
+        inode_numbers: &[u32],
+        inode_refs: &mut IntMap<u32, u64>,
+        stats: &mut WriteStats,
+    ) -> Result<(), BackhandError> {
         let mut inode_writer = MetadataWriter::new(
-            self.fs_compressor,
+            self.fs_compressor.clone(),
             self.block_size,
             Kind { inner: self.kind.inner.clone() },
         );
         let mut dir_writer = MetadataWriter::new(
-            self.fs_compressor,
+            self.fs_compressor.clone(),
             self.block_size,
             Kind { inner: self.kind.inner.clone() },
         );
+        inode_writer.compress = metadata_compression.inodes;
+        dir_writer.compress = metadata_compression.dirs;
 
         info!("Creating Inodes and Dirs");
-        //trace!("TREE: {:#02x?}", &self.root);
-        info!("Writing Data");
-        self.write_data(self.fs_compressor, self.block_size, &mut w, &mut data_writer)?;
-        info!("Writing Data Fragments");
-        // Compress fragments and write
-        data_writer.finalize(&mut w)?;
-
-        info!("Writing Other stuff");
         let root = self.write_inode_dir(
             &mut inode_writer,
             &mut dir_writer,
             0,
             1.try_into().unwrap(),
-            &superblock,
+            superblock,
             &self.kind,
             &self.id_table,
+            inode_numbers,
+            inode_refs,
         )?;
-        superblock.root_inode = ((root.start as u64) << 16) | ((root.offset as u64) & 0xffff);
+        superblock.root_inode = InodeRef::encode(root.start, root.offset);
+        inode_refs.insert(root.inode, superblock.root_inode);
         superblock.inode_count = self.root.nodes.len().try_into().unwrap();
         superblock.block_size = self.block_size;
         superblock.block_log = self.block_log;
@@ -721,23 +1957,32 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
         info!("Writing Dirs");
         superblock.dir_table = w.stream_position()?;
         dir_writer.finalize(&mut w)?;
+        stats.add(&inode_writer.stats);
+        stats.add(&dir_writer.stats);
 
         info!("Writing Frag Lookup Table");
-        let (table_position, count) =
-            self.write_lookup_table(&mut w, &data_writer.fragment_table, fragment::SIZE)?;
+        let (table_position, count) = self.write_lookup_table(
+            &mut w,
+            fragment_table,
+            fragment::SIZE,
+            metadata_compression.fragments_table,
+            stats,
+        )?;
         superblock.frag_table = table_position;
         superblock.frag_count = count;
 
         info!("Writing Id Lookup Table");
-        let (table_position, count) = self.write_lookup_table(&mut w, &self.id_table, Id::SIZE)?;
+        let (table_position, count) = self.write_lookup_table(
+            &mut w,
+            &self.id_table,
+            Id::SIZE,
+            metadata_compression.ids,
+            stats,
+        )?;
         superblock.id_table = table_position;
         superblock.id_count = count.try_into().unwrap();
 
-        info!("Finalize Superblock and End Bytes");
-        let bytes_written = self.finalize(w, &mut superblock)?;
-
-        info!("Success");
-        Ok((superblock, bytes_written))
+        Ok(())
     }
 
     fn finalize<W>(&self, mut w: W, superblock: &mut SuperBlock) -> Result<u64, BackhandError>
@@ -773,6 +2018,11 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
             }
         }
 
+        if !self.trailer.is_empty() {
+            info!("Writing Trailer");
+            w.write_all(&self.trailer)?;
+        }
+
         // Seek back the beginning and write the superblock
         info!("Writing Superblock");
         w.rewind()?;
@@ -782,7 +2032,7 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
             (
                 self.kind.inner.magic,
                 self.kind.inner.version_major,
-                self.kind.inner.version_minor,
+                Some(self.kind.inner.version_minor),
                 self.kind.inner.type_endian,
             ),
         )?;
@@ -790,7 +2040,7 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
 
         //clean any cache, make sure the output is on disk
         w.flush()?;
-        Ok(superblock.bytes_used + u64::from(pad_len))
+        Ok(superblock.bytes_used + u64::from(pad_len) + self.trailer.len() as u64)
     }
 
     /// For example, writing a fragment table:
@@ -825,6 +2075,8 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
         mut w: W,
         table: &[D],
         element_size: usize,
+        compress: bool,
+        stats: &mut WriteStats,
     ) -> Result<(u64, u32), BackhandError>
     where
         D: DekuWriter<deku::ctx::Endian>,
@@ -844,12 +2096,30 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
             {
                 ptrs.push(w.stream_position()?);
 
+                let compressed = if compress {
+                    let bytes = table_bytes.get_ref();
+                    let cb = stats.compress(
+                        self.kind.inner.compressor,
+                        bytes,
+                        self.fs_compressor.clone(),
+                        self.block_size,
+                    )?;
+                    // compression didn't reduce size
+                    (cb.len() < bytes.len()).then_some(cb)
+                } else {
+                    None
+                };
+
                 // write metadata len
-                let len = metadata::set_if_uncompressed(table_bytes.get_ref().len() as u16);
+                let len = match &compressed {
+                    Some(cb) => cb.len() as u16,
+                    None => metadata::set_if_uncompressed(table_bytes.get_ref().len() as u16),
+                };
+                stats.add_metadata_block(compressed.is_some());
                 let mut writer = Writer::new(&mut w);
                 len.to_writer(&mut writer, self.kind.inner.data_endian)?;
                 // write metadata bytes
-                w.write_all(table_bytes.get_ref())?;
+                w.write_all(compressed.as_deref().unwrap_or(table_bytes.get_ref()))?;
 
                 table_bytes.get_mut().clear();
                 table_bytes.rewind()?;
@@ -868,6 +2138,11 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
         Ok((table_position, count))
     }
 
+    /// Check that the `uid` and `gid` of `header` are in id_table
+    fn has_ids(&self, header: &NodeHeader) -> bool {
+        [header.uid, header.gid].iter().all(|id| self.id_table.iter().any(|a| a.num == *id))
+    }
+
     /// Return index of id, adding if required
     fn lookup_add_id(&mut self, id: u32) -> u32 {
         let found = self.id_table.iter().position(|a| a.num == id);
@@ -882,6 +2157,42 @@ impl<'a, 'b, 'c> FilesystemWriter<'a, 'b, 'c> {
     }
 }
 
+/// Writer only counting the bytes of the image, see [`FilesystemWriter::fit_into`]
+#[derive(Default)]
+struct ByteCounter {
+    position: u64,
+    len: u64,
+}
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.position += buf.len() as u64;
+        self.len = self.len.max(self.position);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for ByteCounter {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(start) => Some(start),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
 struct WriterWithOffset<W: WriteSeek> {
     w: W,
     offset: u64,
@@ -921,7 +2232,7 @@ where
 }
 
 /// All compression options for [`FilesystemWriter`]
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct FilesystemCompressor {
     pub(crate) id: Compressor,
     pub(crate) options: Option<CompressionOptions>,
@@ -930,7 +2241,7 @@ pub struct FilesystemCompressor {
 
 impl FilesystemCompressor {
     pub fn new(id: Compressor, options: Option<CompressionOptions>) -> Result<Self, BackhandError> {
-        match (id, options) {
+        match (id, &options) {
             // lz4 always requires options
             (Compressor::Lz4, None) => {
                 error!("Lz4 compression options missing");
@@ -957,11 +2268,32 @@ impl FilesystemCompressor {
     /// Set options that are originally derived from the image if from a [`FilesystemReader`].
     /// These options will be written to the image when
     /// <https://github.com/wcampbell0x2a/backhand/issues/53> is fixed.
+    ///
+    /// [`CompressionOptions::Raw`] can't be used to compress, and returns an error.
     pub fn options(&mut self, options: CompressionOptions) -> Result<(), BackhandError> {
+        if matches!(options, CompressionOptions::Raw(_)) {
+            error!("raw compression options can't be used to compress");
+            return Err(BackhandError::InvalidCompressionOption);
+        }
         self.options = Some(options);
         Ok(())
     }
 
+    /// Compressor of the image of `reader`
+    ///
+    /// [`CompressionOptions::Raw`] are kept to compare them with the options of the image, only
+    /// copying its data blocks as is. They aren't written, or used to compress.
+    fn from_reader(reader: &FilesystemReader<'_>) -> Result<Self, BackhandError> {
+        match &reader.compression_options {
+            Some(CompressionOptions::Raw(_)) => Ok(Self {
+                id: reader.compressor,
+                options: reader.compression_options.clone(),
+                extra: None,
+            }),
+            options => Self::new(reader.compressor, options.clone()),
+        }
+    }
+
     /// Extra options that are *only* using during compression and are *not* stored in the
     /// resulting image
     pub fn extra(&mut self, extra: CompressionExtra) -> Result<(), BackhandError> {
@@ -973,6 +2305,128 @@ impl FilesystemCompressor {
         error!("invalid extra compression settings");
         Err(BackhandError::InvalidCompressionOption)
     }
+
+    /// This compressor, followed by the same compressor at its highest level and xz at its
+    /// highest level, for [`FilesystemWriter::fit_into`]
+    pub(crate) fn stronger(&self) -> Vec<Self> {
+        let mut compressors = vec![self.clone()];
+        // gzip without options already uses level 9
+        let highest = match (self.id, &self.options) {
+            (Compressor::Gzip, Some(CompressionOptions::Gzip(gzip)))
+                if gzip.compression_level < 9 =>
+            {
+                Some(CompressionOptions::Gzip(Gzip { compression_level: 9, ..*gzip }))
+            }
+            (Compressor::Zstd, None) => {
+                Some(CompressionOptions::Zstd(Zstd { compression_level: 22 }))
+            }
+            (Compressor::Zstd, Some(CompressionOptions::Zstd(zstd)))
+                if zstd.compression_level < 22 =>
+            {
+                Some(CompressionOptions::Zstd(Zstd { compression_level: 22 }))
+            }
+            _ => None,
+        };
+        if let Some(options) = highest {
+            compressors.push(Self { id: self.id, options: Some(options), extra: self.extra });
+        }
+
+        let xz_highest = Some(CompressionExtra::Xz(ExtraXz { level: Some(9) }));
+        match (self.id, self.extra) {
+            (Compressor::Xz, Some(CompressionExtra::Xz(ExtraXz { level: Some(9) }))) => {}
+            (Compressor::Xz, _) => compressors.push(Self {
+                id: Compressor::Xz,
+                options: self.options.clone(),
+                extra: xz_highest,
+            }),
+            _ => compressors.push(Self { id: Compressor::Xz, options: None, extra: xz_highest }),
+        }
+        compressors
+    }
+}
+
+/// Per-file compression override used with [`FilesystemWriter::push_file_with_compression`]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub enum FileCompression {
+    /// Use the compressor of the image
+    #[default]
+    Default,
+    /// Store the data uncompressed, without using a fragment
+    Store,
+}
+
+/// In-memory description of a directory tree, used with [`FilesystemWriter::from_tree`]
+///
+/// `name` is a single path component, relative to the parent directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeSpec {
+    Dir { name: PathBuf, header: NodeHeader, children: Vec<TreeSpec> },
+    File { name: PathBuf, header: NodeHeader, data: Vec<u8> },
+    Symlink { name: PathBuf, header: NodeHeader, link: PathBuf },
+}
+
+impl TreeSpec {
+    /// Directory `name` containing `children`
+    pub fn dir<P: Into<PathBuf>>(name: P, header: NodeHeader, children: Vec<TreeSpec>) -> Self {
+        Self::Dir { name: name.into(), header, children }
+    }
+
+    /// File `name` with the contents `data`
+    pub fn file<P: Into<PathBuf>>(name: P, header: NodeHeader, data: Vec<u8>) -> Self {
+        Self::File { name: name.into(), header, data }
+    }
+
+    /// Symlink `name` pointing to `link`
+    pub fn symlink<P: Into<PathBuf>, L: Into<PathBuf>>(
+        name: P,
+        header: NodeHeader,
+        link: L,
+    ) -> Self {
+        Self::Symlink { name: name.into(), header, link: link.into() }
+    }
+}
+
+/// Order files are considered for fragment packing, used with
+/// [`FilesystemWriter::set_fragment_order`]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum FragmentOrder {
+    /// Sorted by the full path of the file
+    #[default]
+    ByPathSorted,
+    /// In the order files were added with [`FilesystemWriter::push_file`]
+    ByInsertion,
+}
+
+/// Order of the sections of the image, used with [`FilesystemWriter::set_section_layout`]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum SectionLayout {
+    /// Data and fragment blocks, followed by the metadata tables, as written by mksquashfs
+    #[default]
+    DataFirst,
+    /// Metadata tables, followed by the data and fragment blocks
+    MetadataFirst,
+}
+
+/// Metadata tables that are compressed, used with [`FilesystemWriter::set_metadata_compression`]
+///
+/// A metadata block is only stored compressed if that reduces its size.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MetadataComp {
+    /// Inode table
+    pub inodes: bool,
+    /// Directory table
+    pub dirs: bool,
+    /// Id lookup table
+    pub ids: bool,
+    /// Fragment lookup table
+    pub fragments_table: bool,
+}
+
+impl Default for MetadataComp {
+    /// Compressed inode and directory tables, uncompressed lookup tables
+    fn default() -> Self {
+        Self { inodes: true, dirs: true, ids: false, fragments_table: false }
+    }
 }
 
 /// Compression options only for [`FilesystemWriter`]
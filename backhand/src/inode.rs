@@ -85,6 +85,22 @@ pub enum InodeId {
 }
 
 impl InodeId {
+    /// Inode type of the stored `id`, `None` if not supported, such as from a vendor extension
+    pub(crate) fn from_id(id: u16) -> Option<Self> {
+        match id {
+            1 => Some(Self::BasicDirectory),
+            2 => Some(Self::BasicFile),
+            3 => Some(Self::BasicSymlink),
+            4 => Some(Self::BasicBlockDevice),
+            5 => Some(Self::BasicCharacterDevice),
+            6 => Some(Self::BasicNamedPipe),
+            7 => Some(Self::BasicSocket),
+            8 => Some(Self::ExtendedDirectory),
+            9 => Some(Self::ExtendedFile),
+            _ => None,
+        }
+    }
+
     pub(crate) fn into_base_type(self) -> Self {
         match self {
             Self::ExtendedDirectory => InodeId::BasicDirectory,
@@ -94,6 +110,33 @@ impl InodeId {
     }
 }
 
+/// Encoding of a reference to an inode, such as [`SuperBlock::root_inode`]
+///
+/// The upper bits are the position of the metadata block containing the inode, relative to the
+/// start of the inode table, and the lower 16 bits are the offset of the inode in the
+/// uncompressed metadata block.
+///
+/// ```rust
+/// # use backhand::InodeRef;
+/// let inode_ref = InodeRef::encode(0x1234, 0x20);
+/// assert_eq!(inode_ref, 0x1234_0020);
+/// assert_eq!(InodeRef::decode(inode_ref), (0x1234, 0x20));
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub struct InodeRef;
+
+impl InodeRef {
+    /// Reference to the inode at `offset` in the metadata block at `block_start`
+    pub fn encode(block_start: u32, offset: u16) -> u64 {
+        (u64::from(block_start) << 16) | u64::from(offset)
+    }
+
+    /// Metadata block start and offset of the inode referenced by `inode_ref`
+    pub fn decode(inode_ref: u64) -> (u32, u16) {
+        ((inode_ref >> 16) as u32, (inode_ref & 0xffff) as u16)
+    }
+}
+
 #[derive(Debug, DekuRead, DekuWrite, Clone, PartialEq, Eq)]
 #[deku(
     ctx = "endian: deku::ctx::Endian, id: InodeId, bytes_used: u64, block_size: u32, block_log: u16"
@@ -208,11 +251,14 @@ fn block_count(block_size: u32, block_log: u16, fragment: u32, file_size: u64) -
     }
 }
 
+/// Max length of a symlink target, `PATH_MAX` on linux
+pub(crate) const SYMLINK_TARGET_MAX: u32 = 4096;
+
 #[derive(DekuRead, DekuWrite, Clone, PartialEq, Eq)]
 #[deku(endian = "endian", ctx = "endian: deku::ctx::Endian")]
 pub struct BasicSymlink {
     pub link_count: u32,
-    #[deku(assert = "*target_size < 256")]
+    #[deku(assert = "*target_size <= SYMLINK_TARGET_MAX")]
     pub target_size: u32,
     #[deku(count = "target_size")]
     pub target_path: Vec<u8>,
@@ -228,8 +274,9 @@ impl fmt::Debug for BasicSymlink {
     }
 }
 impl BasicSymlink {
+    /// Target of the symlink, with invalid UTF-8 replaced
     pub fn target(&self) -> String {
-        std::str::from_utf8(&self.target_path).unwrap().to_string()
+        String::from_utf8_lossy(&self.target_path).into_owned()
     }
 }
 
@@ -245,3 +292,18 @@ pub struct BasicDeviceSpecialFile {
 pub struct IPCNode {
     pub link_count: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inode_ref_round_trip() {
+        for (block_start, offset) in [(0, 0), (0, 0x1fff), (0x2012, 0x1a4), (u32::MAX, u16::MAX)] {
+            let inode_ref = InodeRef::encode(block_start, offset);
+            assert_eq!(InodeRef::decode(inode_ref), (block_start, offset));
+        }
+        assert_eq!(InodeRef::encode(1, 2), 0x1_0002);
+        assert_eq!(InodeRef::decode(0xffff_ffff_ffff), (u32::MAX, u16::MAX));
+    }
+}
@@ -4,6 +4,7 @@ use core::fmt;
 use std::sync::Arc;
 
 use crate::compressor::{CompressionAction, DefaultCompressor};
+use crate::OnUnknownInode;
 
 /// Kind Magic - First 4 bytes of image
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -42,6 +43,18 @@ pub struct InnerKind<C: CompressionAction + ?Sized + 'static + Send + Sync> {
     pub(crate) version_major: u16,
     /// Minor version
     pub(crate) version_minor: u16,
+    /// Accept any minor version when reading, see [`Kind::with_lenient_version_minor`]
+    pub(crate) lenient_version_minor: bool,
+    /// Validate the lengths of metadata blocks, see [`Kind::with_strict_metadata`]
+    pub(crate) strict_metadata: bool,
+    /// Only warn when the inode count doesn't match, see [`Kind::with_lenient_inode_count`]
+    pub(crate) lenient_inode_count: bool,
+    /// Check every dir entry in lookups, see [`Kind::with_unsorted_dirs`]
+    pub(crate) unsorted_dirs: bool,
+    /// Keep the inode table in the filesystem reader, see [`Kind::with_retained_inodes`]
+    pub(crate) retain_inodes: bool,
+    /// Handling of unknown inode types, see [`Kind::with_on_unknown_inode`]
+    pub(crate) on_unknown_inode: OnUnknownInode,
     /// Compression impl
     pub(crate) compressor: &'static C,
 }
@@ -62,6 +75,12 @@ impl fmt::Debug for Kind {
             .field("data_endian", &self.inner.data_endian)
             .field("version_major", &self.inner.version_major)
             .field("version_minor", &self.inner.version_minor)
+            .field("lenient_version_minor", &self.inner.lenient_version_minor)
+            .field("strict_metadata", &self.inner.strict_metadata)
+            .field("lenient_inode_count", &self.inner.lenient_inode_count)
+            .field("unsorted_dirs", &self.inner.unsorted_dirs)
+            .field("retain_inodes", &self.inner.retain_inodes)
+            .field("on_unknown_inode", &self.inner.on_unknown_inode)
             .finish()
     }
 }
@@ -181,6 +200,33 @@ impl Kind {
         Self { inner: kind.inner.clone() }
     }
 
+    /// Detect the kind of an image from its superblock, the first 96 bytes of the image
+    ///
+    /// Reads the magic and version fields, returning [`LE_V4_0`] or [`BE_V4_0`]. Returns `None`
+    /// for an unknown magic or another version, such as a v3 image using the same magic.
+    /// [`AVM_BE_V4_0`] images have the same superblock as [`BE_V4_0`], and are detected as such.
+    ///
+    /// ```rust,no_run
+    /// # use std::io::Read;
+    /// # use backhand::kind::Kind;
+    /// let mut superblock = [0; 96];
+    /// std::fs::File::open("image.squashfs").unwrap().read_exact(&mut superblock).unwrap();
+    /// let kind = Kind::detect_from_superblock(&superblock).unwrap();
+    /// ```
+    pub fn detect_from_superblock(superblock: &[u8; 96]) -> Option<Kind> {
+        let kind = [LE_V4_0, BE_V4_0].into_iter().find(|kind| kind.magic == superblock[..4])?;
+        let read_u16 = |offset: usize| {
+            let bytes = [superblock[offset], superblock[offset + 1]];
+            match kind.type_endian {
+                deku::ctx::Endian::Little => u16::from_le_bytes(bytes),
+                deku::ctx::Endian::Big => u16::from_be_bytes(bytes),
+            }
+        };
+        let version = (read_u16(28), read_u16(30));
+        (version == (kind.version_major, kind.version_minor))
+            .then(|| Kind { inner: Arc::new(kind) })
+    }
+
     /// Set magic type at the beginning of the image
     // TODO: example
     pub fn with_magic(mut self, magic: Magic) -> Self {
@@ -192,6 +238,13 @@ impl Kind {
         self.inner.magic
     }
 
+    /// If the magic and endians are the ones of [`AVM_BE_V4_0`]
+    pub(crate) fn is_avm(&self) -> bool {
+        self.inner.magic == AVM_BE_V4_0.magic
+            && self.inner.type_endian == AVM_BE_V4_0.type_endian
+            && self.inner.data_endian == AVM_BE_V4_0.data_endian
+    }
+
     /// Set endian used for data types
     // TODO: example
     pub fn with_type_endian(mut self, endian: Endian) -> Self {
@@ -243,6 +296,104 @@ impl Kind {
         Arc::get_mut(&mut self.inner).unwrap().version_minor = minor;
         self
     }
+
+    /// Set if images with a minor version other than the one of this kind are read, with a
+    /// warning, instead of failing to parse the superblock
+    ///
+    /// The layout of v4 images doesn't depend on the minor version, but some tools write a
+    /// non-zero minor version.
+    ///
+    /// ```rust
+    /// # use backhand::{kind, kind::Kind};
+    /// let kind = Kind::from_const(kind::LE_V4_0).unwrap().with_lenient_version_minor(true);
+    /// ```
+    pub fn with_lenient_version_minor(mut self, lenient: bool) -> Self {
+        Arc::get_mut(&mut self.inner).unwrap().lenient_version_minor = lenient;
+        self
+    }
+
+    /// Set if the lengths of metadata blocks are validated when reading, off by default
+    ///
+    /// A metadata block with a stored length of `0` or over `8KiB`, or a decompressed length over
+    /// `8KiB`, then fails with [`BackhandError::CorruptedOrInvalidSquashfs`] instead of being used
+    /// as is.
+    ///
+    /// [`BackhandError::CorruptedOrInvalidSquashfs`]: crate::BackhandError::CorruptedOrInvalidSquashfs
+    pub fn with_strict_metadata(mut self, strict: bool) -> Self {
+        Arc::get_mut(&mut self.inner).unwrap().strict_metadata = strict;
+        self
+    }
+
+    /// Set if an `inode_count` of the superblock that doesn't match the amount of inodes in the
+    /// inode table is only logged as a warning when reading, off by default
+    ///
+    /// Otherwise this fails with [`BackhandError::CorruptedOrInvalidSquashfs`], as either the
+    /// superblock or the inode table is damaged.
+    ///
+    /// ```rust
+    /// # use backhand::{kind, kind::Kind};
+    /// let kind = Kind::from_const(kind::LE_V4_0).unwrap().with_lenient_inode_count(true);
+    /// ```
+    ///
+    /// [`BackhandError::CorruptedOrInvalidSquashfs`]: crate::BackhandError::CorruptedOrInvalidSquashfs
+    pub fn with_lenient_inode_count(mut self, lenient: bool) -> Self {
+        Arc::get_mut(&mut self.inner).unwrap().lenient_inode_count = lenient;
+        self
+    }
+
+    /// Set if dir entries may not be sorted by name, off by default
+    ///
+    /// [`Squashfs::lookup`] then checks every entry of a dir, instead of using the dir index and
+    /// stopping at the first entry after the name. Some malformed vendor images don't sort their
+    /// entries. Reading all nodes, such as into a [`FilesystemReader`], doesn't depend on this.
+    ///
+    /// ```rust
+    /// # use backhand::{kind, kind::Kind};
+    /// let kind = Kind::from_const(kind::LE_V4_0).unwrap().with_unsorted_dirs(true);
+    /// ```
+    ///
+    /// [`Squashfs::lookup`]: crate::Squashfs::lookup
+    /// [`FilesystemReader`]: crate::FilesystemReader
+    pub fn with_unsorted_dirs(mut self, unsorted: bool) -> Self {
+        Arc::get_mut(&mut self.inner).unwrap().unsorted_dirs = unsorted;
+        self
+    }
+
+    /// Set if the inode table is kept in the [`FilesystemReader`], off by default
+    ///
+    /// This is needed for [`FilesystemReader::inode`] and [`FilesystemReader::parent_inode`],
+    /// using memory for every inode of the image.
+    ///
+    /// ```rust
+    /// # use backhand::{kind, kind::Kind};
+    /// let kind = Kind::from_const(kind::LE_V4_0).unwrap().with_retained_inodes(true);
+    /// ```
+    ///
+    /// [`FilesystemReader`]: crate::FilesystemReader
+    /// [`FilesystemReader::inode`]: crate::FilesystemReader::inode
+    /// [`FilesystemReader::parent_inode`]: crate::FilesystemReader::parent_inode
+    pub fn with_retained_inodes(mut self, retain: bool) -> Self {
+        Arc::get_mut(&mut self.inner).unwrap().retain_inodes = retain;
+        self
+    }
+
+    /// Set the handling of dir entries with an inode type not known to backhand, such as from
+    /// vendor extensions, [`OnUnknownInode::Error`] by default
+    ///
+    /// The inode table can only be read up front when the size of every inode is known, so with
+    /// [`OnUnknownInode::Skip`] inodes are read on demand, the same as
+    /// [`Squashfs::from_reader_lazy`].
+    ///
+    /// ```rust
+    /// # use backhand::{kind, kind::Kind, OnUnknownInode};
+    /// let kind = Kind::from_const(kind::LE_V4_0).unwrap().with_on_unknown_inode(OnUnknownInode::Skip);
+    /// ```
+    ///
+    /// [`Squashfs::from_reader_lazy`]: crate::Squashfs::from_reader_lazy
+    pub fn with_on_unknown_inode(mut self, on_unknown_inode: OnUnknownInode) -> Self {
+        Arc::get_mut(&mut self.inner).unwrap().on_unknown_inode = on_unknown_inode;
+        self
+    }
 }
 
 /// Default `Kind` for linux kernel and squashfs-tools/mksquashfs. Little-Endian v4.0
@@ -252,6 +403,12 @@ pub const LE_V4_0: InnerKind<dyn CompressionAction + Send + Sync> = InnerKind {
     data_endian: deku::ctx::Endian::Little,
     version_major: 4,
     version_minor: 0,
+    lenient_version_minor: false,
+    strict_metadata: false,
+    lenient_inode_count: false,
+    unsorted_dirs: false,
+    retain_inodes: false,
+    on_unknown_inode: OnUnknownInode::Error,
     compressor: &DefaultCompressor,
 };
 
@@ -262,6 +419,12 @@ pub const BE_V4_0: InnerKind<dyn CompressionAction + Send + Sync> = InnerKind {
     data_endian: deku::ctx::Endian::Big,
     version_major: 4,
     version_minor: 0,
+    lenient_version_minor: false,
+    strict_metadata: false,
+    lenient_inode_count: false,
+    unsorted_dirs: false,
+    retain_inodes: false,
+    on_unknown_inode: OnUnknownInode::Error,
     compressor: &DefaultCompressor,
 };
 
@@ -272,5 +435,11 @@ pub const AVM_BE_V4_0: InnerKind<dyn CompressionAction + Send + Sync> = InnerKin
     data_endian: deku::ctx::Endian::Little,
     version_major: 4,
     version_minor: 0,
+    lenient_version_minor: false,
+    strict_metadata: false,
+    lenient_inode_count: false,
+    unsorted_dirs: false,
+    retain_inodes: false,
+    on_unknown_inode: OnUnknownInode::Error,
     compressor: &DefaultCompressor,
 };
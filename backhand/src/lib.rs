@@ -70,28 +70,46 @@ mod inode;
 mod kinds;
 mod metadata;
 mod reader;
+mod sidecar;
 mod squashfs;
 mod unix_string;
+mod xattr;
 
-pub use crate::data::DataSize;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "trace-compression")]
+pub use crate::data::CompressEvent;
+pub use crate::data::{DataSize, WriteStats};
 pub use crate::error::BackhandError;
 pub use crate::export::Export;
+#[cfg(unix)]
+pub use crate::filesystem::extract::ExtractOptions;
 pub use crate::filesystem::node::{
-    InnerNode, Node, NodeHeader, SquashfsBlockDevice, SquashfsCharacterDevice, SquashfsDir,
-    SquashfsFileReader, SquashfsFileWriter, SquashfsSymlink,
+    device_number_from_major_minor, major_minor_from_device_number, InnerNode, Node, NodeHeader,
+    SquashfsBlockDevice, SquashfsCharacterDevice, SquashfsDir, SquashfsFileReader,
+    SquashfsFileWriter, SquashfsSymlink,
+};
+pub use crate::filesystem::reader::{
+    read_file, BlockLocation, FilesystemReader, FilesystemReaderFile, SquashfsReadFile,
 };
-pub use crate::filesystem::reader::{FilesystemReader, FilesystemReaderFile, SquashfsReadFile};
 pub use crate::filesystem::writer::{
-    CompressionExtra, ExtraXz, FilesystemCompressor, FilesystemWriter,
+    CompressionExtra, ExtraXz, FileCompression, FilesystemCompressor, FilesystemWriter,
+    FragmentOrder, MetadataComp, SectionLayout, TreeSpec,
 };
 pub use crate::fragment::Fragment;
 pub use crate::id::Id;
-pub use crate::inode::{BasicFile, Inode};
+pub use crate::inode::{
+    BasicDeviceSpecialFile, BasicDirectory, BasicFile, BasicSymlink, ExtendedDirectory,
+    ExtendedFile, IPCNode, Inode, InodeHeader, InodeId, InodeInner, InodeRef,
+};
 pub use crate::reader::BufReadSeek;
+pub use crate::sidecar::{Sidecar, SidecarEntry};
 pub use crate::squashfs::{
-    Flags, Squashfs, SuperBlock, DEFAULT_BLOCK_SIZE, DEFAULT_PAD_LEN, MAX_BLOCK_SIZE,
-    MIN_BLOCK_SIZE,
+    Flags, OnUnknownInode, Overlap, Squashfs, SuperBlock, TablePointers, DEFAULT_BLOCK_SIZE,
+    DEFAULT_PAD_LEN, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE,
 };
+pub use crate::xattr::{XattrId, XattrIdTable};
 
 /// Support the wonderful world of vendor formats
 pub mod kind {
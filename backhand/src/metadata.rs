@@ -2,8 +2,9 @@ use std::collections::VecDeque;
 use std::io::{self, Read, Seek, Write};
 
 use deku::prelude::*;
-use tracing::trace;
+use tracing::{error, trace};
 
+use crate::data::WriteStats;
 use crate::error::BackhandError;
 use crate::filesystem::writer::FilesystemCompressor;
 use crate::kinds::Kind;
@@ -23,6 +24,10 @@ pub(crate) struct MetadataWriter {
     // All current bytes that are compressed or uncompressed
     pub(crate) final_bytes: Vec<(bool, Vec<u8>)>,
     pub kind: Kind,
+    /// If false, all metadata blocks are stored uncompressed
+    pub(crate) compress: bool,
+    /// Written blocks and compressor calls
+    pub(crate) stats: WriteStats,
 }
 
 impl MetadataWriter {
@@ -34,6 +39,8 @@ impl MetadataWriter {
             uncompressed_bytes: VecDeque::new(),
             final_bytes: vec![],
             kind,
+            compress: true,
+            stats: WriteStats::default(),
         }
     }
 
@@ -52,11 +59,20 @@ impl MetadataWriter {
 
         trace!("time to compress");
         // "Write" the to the saved metablock
-        let compressed =
-            self.kind.inner.compressor.compress(uncompressed, self.compressor, self.block_size)?;
+        let compressed = if self.compress {
+            self.stats.compress(
+                self.kind.inner.compressor,
+                uncompressed,
+                self.compressor.clone(),
+                self.block_size,
+            )?
+        } else {
+            vec![]
+        };
 
-        // Remove the data consumed, if the uncompressed data is smalled, use it.
-        let (compressed, metadata) = if compressed.len() > uncompressed_len {
+        // Remove the data consumed, if the uncompressed data is smalled or compression is off,
+        // use it.
+        let (compressed, metadata) = if !self.compress || compressed.len() > uncompressed_len {
             let uncompressed = self.uncompressed_bytes.drain(0..uncompressed_len).collect();
             (false, uncompressed)
         } else {
@@ -67,6 +83,7 @@ impl MetadataWriter {
         // Metadata len + bytes + last metadata_start
         self.metadata_start += 2 + metadata.len() as u32;
         trace!("new metadata start: {:#02x?}", self.metadata_start);
+        self.stats.add_metadata_block(compressed);
         self.final_bytes.push((compressed, metadata));
 
         trace!("LEN: {:02x?}", self.uncompressed_bytes.len());
@@ -117,11 +134,16 @@ pub fn read_block<R: Read + Seek>(
     superblock: &SuperBlock,
     kind: &Kind,
 ) -> Result<Vec<u8>, BackhandError> {
+    let start = reader.stream_position()?;
     let mut deku_reader = Reader::new(reader);
     let metadata_len = u16::from_reader_with_ctx(&mut deku_reader, kind.inner.data_endian)?;
 
     let byte_len = len(metadata_len);
     tracing::trace!("len: 0x{:02x?}", byte_len);
+    if kind.inner.strict_metadata && (byte_len == 0 || usize::from(byte_len) > METADATA_MAXSIZE) {
+        error!("metadata block at {start:#02x?} has invalid length {byte_len:#02x?}");
+        return Err(BackhandError::CorruptedOrInvalidSquashfs);
+    }
     let mut buf = vec![0u8; byte_len as usize];
     reader.read_exact(&mut buf)?;
 
@@ -136,6 +158,10 @@ pub fn read_block<R: Read + Seek>(
     };
 
     tracing::trace!("uncompressed size: 0x{:02x?}", bytes.len());
+    if kind.inner.strict_metadata && bytes.len() > METADATA_MAXSIZE {
+        error!("metadata block at {start:#02x?} decompressed to {:#02x?} bytes", bytes.len());
+        return Err(BackhandError::CorruptedOrInvalidSquashfs);
+    }
     Ok(bytes)
 }
 
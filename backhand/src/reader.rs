@@ -5,17 +5,18 @@ use std::io::{BufRead, Cursor, Read, Seek, SeekFrom, Write};
 
 use deku::prelude::*;
 use solana_nohash_hasher::IntMap;
-use tracing::{error, trace};
+use tracing::{error, trace, warn};
 
 use crate::error::BackhandError;
 use crate::export::Export;
 use crate::fragment::Fragment;
 use crate::id::Id;
-use crate::inode::Inode;
+use crate::inode::{Inode, InodeRef};
 use crate::kinds::Kind;
 use crate::metadata::METADATA_MAXSIZE;
 use crate::squashfs::{SuperBlock, NOT_SET};
-use crate::{fragment, metadata};
+use crate::xattr::{XattrId, XattrIdHeader, XattrIdTable};
+use crate::{fragment, metadata, xattr};
 
 /// Private struct containing logic to read the `Squashfs` section from a file
 #[derive(Debug)]
@@ -67,6 +68,9 @@ where
     }
 }
 
+/// Pointers to the metadata blocks of a lookup table, and the parsed table
+pub(crate) type LookupTable<T> = (Vec<u64>, Vec<T>);
+
 /// Pseudo-Trait for BufRead + Seek
 pub trait BufReadSeek: BufRead + Seek + Send {}
 impl<T: BufRead + Seek + Send> BufReadSeek for T {}
@@ -77,6 +81,30 @@ impl<T: Write + Seek> WriteSeek for T {}
 
 impl<T: BufReadSeek> SquashFsReader for T {}
 
+/// Read an [`Inode`], with a failed assertion of its fields being an invalid image
+pub(crate) fn read_inode<R: Read + Seek>(
+    reader: &mut Reader<R>,
+    superblock: &SuperBlock,
+    kind: &Kind,
+) -> Result<Inode, BackhandError> {
+    Inode::from_reader_with_ctx(
+        reader,
+        (
+            superblock.bytes_used,
+            superblock.block_size,
+            superblock.block_log,
+            kind.inner.type_endian,
+        ),
+    )
+    .map_err(|e| match e {
+        DekuError::Assertion(_) => {
+            error!("invalid inode: {e}");
+            BackhandError::CorruptedOrInvalidSquashfs
+        }
+        e => e.into(),
+    })
+}
+
 /// Squashfs data extraction methods implemented over [`Read`] and [`Seek`]
 pub trait SquashFsReader: BufReadSeek + Sized {
     /// Cache Inode Table
@@ -102,25 +130,25 @@ pub trait SquashFsReader: BufReadSeek + Sized {
         let mut cursor = Cursor::new(bytes);
         let mut reader = Reader::new(&mut cursor);
         while reader.bits_read != byte_len * 8 {
-            let inode = Inode::from_reader_with_ctx(
-                &mut reader,
-                (
-                    superblock.bytes_used,
-                    superblock.block_size,
-                    superblock.block_log,
-                    kind.inner.type_endian,
-                ),
-            )?;
+            let inode = read_inode(&mut reader, superblock, kind)?;
             inodes.insert(inode.header.inode_number, inode);
         }
 
         if inodes.len() != superblock.inode_count as usize {
-            error!("inodes {} != superblock.inode_count {}", inodes.len(), superblock.inode_count);
-            return Err(BackhandError::CorruptedOrInvalidSquashfs);
+            if !kind.inner.lenient_inode_count {
+                error!(
+                    "inodes {} != superblock.inode_count {}",
+                    inodes.len(),
+                    superblock.inode_count
+                );
+                return Err(BackhandError::CorruptedOrInvalidSquashfs);
+            }
+            warn!("inodes {} != superblock.inode_count {}", inodes.len(), superblock.inode_count);
         }
 
-        let root_inode_start = (superblock.root_inode >> 16) as usize;
-        let root_inode_offset = (superblock.root_inode & 0xffff) as usize;
+        let (root_inode_start, root_inode_offset) = InodeRef::decode(superblock.root_inode);
+        let (root_inode_start, root_inode_offset) =
+            (root_inode_start as usize, root_inode_offset as usize);
 
         let Some(root_offset) = map.get(&(root_inode_start as u64)) else {
             return Err(BackhandError::CorruptedOrInvalidSquashfs);
@@ -130,15 +158,7 @@ pub trait SquashFsReader: BufReadSeek + Sized {
         cursor.seek(SeekFrom::Start(root_offset + root_inode_offset as u64))?;
 
         let mut reader = Reader::new(&mut cursor);
-        let root_inode = Inode::from_reader_with_ctx(
-            &mut reader,
-            (
-                superblock.bytes_used,
-                superblock.block_size,
-                superblock.block_log,
-                kind.inner.type_endian,
-            ),
-        )?;
+        let root_inode = read_inode(&mut reader, superblock, kind)?;
 
         Ok((root_inode, inodes))
     }
@@ -168,22 +188,25 @@ pub trait SquashFsReader: BufReadSeek + Sized {
     }
 
     /// Parse and Cache Fragment Table
+    ///
+    /// `None` without fragments, even if the table pointer is set, as written for images where all
+    /// files only use full blocks.
     fn fragments(
         &mut self,
         superblock: &SuperBlock,
         kind: &Kind,
-    ) -> Result<Option<(u64, Vec<Fragment>)>, BackhandError> {
+    ) -> Result<Option<LookupTable<Fragment>>, BackhandError> {
         if superblock.frag_count == 0 || superblock.frag_table == NOT_SET {
             return Ok(None);
         }
-        let (ptr, table) = self.lookup_table::<Fragment>(
+        let (ptrs, table) = self.lookup_table::<Fragment>(
             superblock,
             superblock.frag_table,
             u64::from(superblock.frag_count) * fragment::SIZE as u64,
             kind,
         )?;
 
-        Ok(Some((ptr, table)))
+        Ok(Some((ptrs, table)))
     }
 
     /// Parse Export Table
@@ -191,12 +214,13 @@ pub trait SquashFsReader: BufReadSeek + Sized {
         &mut self,
         superblock: &SuperBlock,
         kind: &Kind,
-    ) -> Result<Option<(u64, Vec<Export>)>, BackhandError> {
+    ) -> Result<Option<LookupTable<Export>>, BackhandError> {
         if superblock.nfs_export_table_exists() && superblock.export_table != NOT_SET {
             let ptr = superblock.export_table;
-            let count = (superblock.inode_count as f32 / 1024_f32).ceil() as u64;
-            let (ptr, table) = self.lookup_table::<Export>(superblock, ptr, count, kind)?;
-            Ok(Some((ptr, table)))
+            // one u64 inode reference per inode
+            let size = u64::from(superblock.inode_count) * 8;
+            let (ptrs, table) = self.lookup_table::<Export>(superblock, ptr, size, kind)?;
+            Ok(Some((ptrs, table)))
         } else {
             Ok(None)
         }
@@ -207,71 +231,101 @@ pub trait SquashFsReader: BufReadSeek + Sized {
         &mut self,
         superblock: &SuperBlock,
         kind: &Kind,
-    ) -> Result<(u64, Vec<Id>), BackhandError> {
+    ) -> Result<LookupTable<Id>, BackhandError> {
         let ptr = superblock.id_table;
-        let count = superblock.id_count as u64;
-        let (ptr, table) = self.lookup_table::<Id>(superblock, ptr, count, kind)?;
-        Ok((ptr, table))
+        let size = u64::from(superblock.id_count) * Id::SIZE as u64;
+        let (ptrs, table) = self.lookup_table::<Id>(superblock, ptr, size, kind)?;
+        Ok((ptrs, table))
     }
 
-    /// Parse Lookup Table
-    fn lookup_table<T>(
+    /// Parse Xattr Id Table
+    ///
+    /// Unlike the other lookup tables, the pointers to the metadata blocks follow a header with
+    /// the start of the key-value table and the amount of ids.
+    fn xattr_ids(
         &mut self,
         superblock: &SuperBlock,
-        seek: u64,
-        size: u64,
         kind: &Kind,
-    ) -> Result<(u64, Vec<T>), BackhandError>
-    where
-        T: for<'a> DekuReader<'a, deku::ctx::Endian>,
-    {
-        // find the pointer at the initial offset
-        trace!("seek: {:02x?}", seek);
-        self.seek(SeekFrom::Start(seek))?;
-        let buf: &mut [u8] = &mut [0u8; 8];
-        self.read_exact(buf)?;
-        trace!("{:02x?}", buf);
-
+    ) -> Result<Option<XattrIdTable>, BackhandError> {
+        if superblock.xattr_table == NOT_SET {
+            return Ok(None);
+        }
+        self.seek(SeekFrom::Start(superblock.xattr_table))?;
+        let mut buf = [0u8; xattr::HEADER_SIZE];
+        self.read_exact(&mut buf)?;
         let mut cursor = Cursor::new(buf);
         let mut deku_reader = Reader::new(&mut cursor);
-        let ptr = u64::from_reader_with_ctx(&mut deku_reader, kind.inner.type_endian)?;
-
-        let block_count = (size as f32 / METADATA_MAXSIZE as f32).ceil() as u64;
+        let header = XattrIdHeader::from_reader_with_ctx(&mut deku_reader, kind.inner.type_endian)?;
+        trace!("{header:02x?}");
 
-        trace!("ptr: {:02x?}", ptr);
-        let table = self.metadata_with_count::<T>(superblock, ptr, block_count, kind)?;
+        let (_, ids) = self.lookup_table::<XattrId>(
+            superblock,
+            superblock.xattr_table + xattr::HEADER_SIZE as u64,
+            u64::from(header.count) * xattr::ID_SIZE as u64,
+            kind,
+        )?;
+        if ids.len() != header.count as usize {
+            error!("xattr id count {} != {}", ids.len(), header.count);
+            return Err(BackhandError::CorruptedOrInvalidSquashfs);
+        }
 
-        Ok((ptr, table))
+        Ok(Some(XattrIdTable { kv_start: header.kv_start, ids }))
     }
 
-    /// Parse count of `Metadata` block at offset into `T`
-    fn metadata_with_count<T>(
+    /// Parse Lookup Table of `size` bytes, with the pointers to its metadata blocks at `seek`
+    ///
+    /// Each metadata block is read from its pointer, so the blocks don't need to be contiguous.
+    /// Returns the pointers, and the parsed table.
+    fn lookup_table<T>(
         &mut self,
         superblock: &SuperBlock,
         seek: u64,
-        count: u64,
+        size: u64,
         kind: &Kind,
-    ) -> Result<Vec<T>, BackhandError>
+    ) -> Result<LookupTable<T>, BackhandError>
     where
         T: for<'a> DekuReader<'a, deku::ctx::Endian>,
     {
+        let block_count = size.div_ceil(METADATA_MAXSIZE as u64);
+
+        // find the pointers at the initial offset
         trace!("seek: {:02x?}", seek);
         self.seek(SeekFrom::Start(seek))?;
+        let mut buf = vec![0u8; block_count as usize * 8];
+        self.read_exact(&mut buf)?;
+        trace!("{:02x?}", buf);
+
+        let mut cursor = Cursor::new(buf);
+        let mut deku_reader = Reader::new(&mut cursor);
+        let mut ptrs = Vec::with_capacity(block_count as usize);
+        for _ in 0..block_count {
+            ptrs.push(u64::from_reader_with_ctx(&mut deku_reader, kind.inner.type_endian)?);
+        }
+        trace!("ptrs: {:02x?}", ptrs);
 
         let mut all_bytes = vec![];
-        for _ in 0..count {
+        for ptr in &ptrs {
+            self.seek(SeekFrom::Start(*ptr))?;
             let mut bytes = metadata::read_block(self, superblock, kind)?;
             all_bytes.append(&mut bytes);
         }
+        let table = Self::parse_table(all_bytes, kind);
+
+        Ok((ptrs, table))
+    }
 
+    /// Parse `bytes` into `T`s, until one fails to parse
+    fn parse_table<T>(bytes: Vec<u8>, kind: &Kind) -> Vec<T>
+    where
+        T: for<'a> DekuReader<'a, deku::ctx::Endian>,
+    {
         let mut ret_vec = vec![];
         // Read until we fail to turn bytes into `T`
-        let mut cursor = Cursor::new(all_bytes);
+        let mut cursor = Cursor::new(bytes);
         let mut container = Reader::new(&mut cursor);
         while let Ok(t) = T::from_reader_with_ctx(&mut container, kind.inner.type_endian) {
             ret_vec.push(t);
         }
-
-        Ok(ret_vec)
+        ret_vec
     }
 }
@@ -0,0 +1,45 @@
+//! Index of a written image
+
+use deku::prelude::*;
+
+use crate::data::DataSize;
+
+/// Index of each node of an image, written by
+/// [`FilesystemWriter::write_with_sidecar`](crate::FilesystemWriter::write_with_sidecar)
+///
+/// Always little-endian, independent of the [`Kind`](crate::kind::Kind) of the image. Read with
+/// [`DekuContainerRead::from_bytes`].
+#[derive(Debug, Clone, PartialEq, Eq, DekuRead, DekuWrite)]
+#[deku(endian = "little")]
+pub struct Sidecar {
+    /// Start of the inode table in the image, which inode references are relative to
+    pub inode_table: u64,
+    pub entry_count: u32,
+    #[deku(count = "*entry_count")]
+    pub entries: Vec<SidecarEntry>,
+}
+
+/// Location of a node in the image
+#[derive(Debug, Clone, PartialEq, Eq, DekuRead, DekuWrite)]
+#[deku(endian = "endian", ctx = "endian: deku::ctx::Endian")]
+pub struct SidecarEntry {
+    pub path_size: u16,
+    /// Full path, starting with "/"
+    #[deku(count = "*path_size")]
+    pub path: Vec<u8>,
+    pub inode_number: u32,
+    /// Reference to the inode, the start of its metadata block from the start of the inode table
+    /// shifted left by 16, with the offset in the uncompressed block in the lower 16 bits
+    pub inode: u64,
+    /// Uncompressed size of a file, `0` for other nodes
+    pub file_size: u64,
+    /// Start of the data blocks of a file
+    pub blocks_start: u64,
+    /// Index of the fragment storing the tail-end of a file, `0xffffffff` for none
+    pub frag_index: u32,
+    /// Offset of the tail-end of a file in the uncompressed fragment block
+    pub block_offset: u32,
+    pub block_count: u32,
+    #[deku(count = "*block_count")]
+    pub block_sizes: Vec<DataSize>,
+}
@@ -1,24 +1,29 @@
 //! Read from on-disk image
 
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
 use std::ffi::OsString;
-use std::io::{Cursor, Seek, SeekFrom};
-use std::path::PathBuf;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::AtomicU64;
 use std::sync::Mutex;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, OnceLock, RwLock};
 
 use deku::prelude::*;
 use solana_nohash_hasher::IntMap;
-use tracing::{error, info, trace};
+use tracing::{error, info, trace, warn};
 
 use crate::compressor::{CompressionOptions, Compressor};
-use crate::dir::Dir;
+use crate::dir::{Dir, DirEntry};
 use crate::error::BackhandError;
 use crate::filesystem::node::{InnerNode, Nodes};
 use crate::fragment::Fragment;
-use crate::inode::{Inode, InodeId, InodeInner};
+use crate::inode::{Inode, InodeId, InodeInner, InodeRef};
 use crate::kinds::{Kind, LE_V4_0};
-use crate::reader::{BufReadSeek, SquashFsReader, SquashfsReaderWithOffset};
-use crate::unix_string::OsStringExt;
+use crate::reader::{read_inode, BufReadSeek, SquashFsReader, SquashfsReaderWithOffset};
+use crate::unix_string::{OsStrExt, OsStringExt};
+use crate::xattr::XattrIdTable;
 use crate::{
     metadata, Export, FilesystemReader, Id, Node, NodeHeader, SquashfsBlockDevice,
     SquashfsCharacterDevice, SquashfsDir, SquashfsFileReader, SquashfsSymlink,
@@ -39,11 +44,24 @@ pub const MAX_BLOCK_SIZE: u32 = 0x10_0000;
 /// 4KiB
 pub const MIN_BLOCK_SIZE: u32 = 0x1000;
 
+/// Size of the [`SuperBlock`] on disk
+const SUPERBLOCK_SIZE: usize = 96;
+
+/// Maximum length of the header before the superblock of [`AVM_BE_V4_0`](crate::kind::AVM_BE_V4_0)
+/// images that is skipped
+const AVM_HEADER_MAX_LEN: u64 = 0x1_0000;
+
+/// Uncompressed inode table metadata blocks kept by [`Squashfs::from_reader_lazy`]
+const LAZY_INODE_BLOCKS: usize = 8;
+
+/// Uncompressed inode table blocks `(start, bytes, next_start)`, most recently used last
+type LazyInodeBlocks = VecDeque<(u64, Vec<u8>, u64)>;
+
 /// Contains important information about the archive, including the locations of other sections
 #[derive(Debug, Copy, Clone, DekuRead, DekuWrite, PartialEq, Eq)]
 #[deku(
     endian = "ctx_type_endian",
-    ctx = "ctx_magic: [u8; 4], ctx_version_major: u16, ctx_version_minor: u16, ctx_type_endian: deku::ctx::Endian"
+    ctx = "ctx_magic: [u8; 4], ctx_version_major: u16, ctx_version_minor: Option<u16>, ctx_type_endian: deku::ctx::Endian"
 )]
 pub struct SuperBlock {
     /// Must be set to 0x73717368 ("hsqs" on disk).
@@ -69,7 +87,7 @@ pub struct SuperBlock {
     #[deku(assert_eq = "ctx_version_major")]
     /// Major version of the format. Must be set to 4.
     pub version_major: u16,
-    #[deku(assert_eq = "ctx_version_minor")]
+    #[deku(assert = "ctx_version_minor.map_or(true, |minor| *version_minor == minor)")]
     /// Minor version of the format. Must be set to 0.
     pub version_minor: u16,
     /// A reference to the inode of the root directory.
@@ -91,16 +109,25 @@ pub const NOT_SET: u64 = 0xffff_ffff_ffff_ffff;
 
 impl SuperBlock {
     /// flag value
+    ///
+    /// Only informational, when reading each metadata block is decompressed depending on its own
+    /// uncompressed bit, the same as the kernel.
     pub fn inodes_uncompressed(&self) -> bool {
         self.flags & Flags::InodesStoredUncompressed as u16 != 0
     }
 
     /// flag value
+    ///
+    /// Only informational, when reading each data block is decompressed depending on its own
+    /// uncompressed bit.
     pub fn data_block_stored_uncompressed(&self) -> bool {
         self.flags & Flags::DataBlockStoredUncompressed as u16 != 0
     }
 
     /// flag value
+    ///
+    /// Only informational, when reading each fragment block is decompressed depending on its own
+    /// uncompressed bit.
     pub fn fragments_stored_uncompressed(&self) -> bool {
         self.flags & Flags::FragmentsStoredUncompressed as u16 != 0
     }
@@ -191,6 +218,43 @@ pub(crate) struct Cache {
     pub(crate) fragment_cache: IntMap<u64, Vec<u8>>,
 }
 
+/// Handling of directory entries with an inode type not known to backhand, see
+/// [`Kind::with_on_unknown_inode`]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum OnUnknownInode {
+    /// Return [`BackhandError::UnknownInode`]
+    #[default]
+    Error,
+    /// Log and omit the entry from the [`FilesystemReader`]
+    Skip,
+}
+
+/// Data blocks of two files overlapping, see [`Squashfs::check_overlaps`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Overlap {
+    /// Inode number of the file starting first
+    pub first: u32,
+    /// Inode number of the file starting inside the data of `first`
+    pub second: u32,
+    /// Start of the overlapping bytes
+    pub start: u64,
+    /// End of the overlapping bytes, exclusive
+    pub end: u64,
+}
+
+/// Pointers to the metadata blocks of the lookup tables, see [`Squashfs::table_pointers`]
+///
+/// Each pointer is the start of a metadata block holding up to 8KiB of the table.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TablePointers {
+    /// Fragment table, empty if the image has no fragments
+    pub fragment: Vec<u64>,
+    /// Id table
+    pub id: Vec<u64>,
+    /// Export table, empty if the image has no export table
+    pub export: Vec<u64>,
+}
+
 /// Squashfs Image initial read information
 ///
 /// See [`FilesystemReader`] for a representation with the data extracted and uncompressed.
@@ -211,8 +275,20 @@ pub struct Squashfs<'b> {
     pub export: Option<Vec<Export>>,
     /// Id Lookup Table Cache
     pub id: Vec<Id>,
+    /// Xattr Id Lookup Table Cache, `None` if the image has no xattrs or the table is invalid
+    pub xattr_ids: Option<XattrIdTable>,
+    /// Pointers to the metadata blocks of the lookup tables
+    table_pointers: TablePointers,
+    /// Offset of the image in the reader
+    offset: u64,
+    /// Length of the reader, starting at the image offset
+    total_length: u64,
+    /// On-disk bytes of the superblock, before parsing
+    raw_superblock: [u8; SUPERBLOCK_SIZE],
+    /// Recently used inode table blocks, when reading inodes on demand
+    lazy_inode_blocks: Option<Mutex<LazyInodeBlocks>>,
     //file reader
-    file: Box<dyn BufReadSeek + 'b>,
+    file: Mutex<Box<dyn BufReadSeek + 'b>>,
 }
 
 impl<'b> Squashfs<'b> {
@@ -231,10 +307,16 @@ impl<'b> Squashfs<'b> {
             (
                 kind.inner.magic,
                 kind.inner.version_major,
-                kind.inner.version_minor,
+                (!kind.inner.lenient_version_minor).then_some(kind.inner.version_minor),
                 kind.inner.type_endian,
             ),
         )?;
+        if superblock.version_minor != kind.inner.version_minor {
+            warn!(
+                "version_minor({}) is not {}, reading as is",
+                superblock.version_minor, kind.inner.version_minor
+            );
+        }
 
         let block_size = superblock.block_size;
         let power_of_two = block_size != 0 && (block_size & (block_size - 1)) == 0;
@@ -253,27 +335,7 @@ impl<'b> Squashfs<'b> {
         let compression_options = if superblock.compressor != Compressor::None
             && superblock.compressor_options_are_present()
         {
-            let mut bytes = metadata::read_block(reader, &superblock, kind)?;
-            let mut cursor = Cursor::new(&mut bytes);
-            let mut reader = Reader::new(&mut cursor);
-            // data -> compression options
-            match CompressionOptions::from_reader_with_ctx(
-                &mut reader,
-                (kind.inner.type_endian, superblock.compressor),
-            ) {
-                Ok(co) => {
-                    if !reader.end() {
-                        error!("invalid compression, not all bytes read");
-                        None
-                    } else {
-                        Some(co)
-                    }
-                }
-                Err(e) => {
-                    error!("invalid compression options: {e:?}, not using");
-                    None
-                }
-            }
+            Self::compression_options(reader, &superblock, kind)?
         } else {
             None
         };
@@ -282,6 +344,34 @@ impl<'b> Squashfs<'b> {
         Ok((superblock, compression_options))
     }
 
+    /// Read Compression Options at current `reader` offset, an empty metadata block is treated as
+    /// no Compression Options
+    ///
+    /// Options that can't be parsed for the compressor are kept as [`CompressionOptions::Raw`].
+    fn compression_options(
+        reader: &mut Box<dyn BufReadSeek + 'b>,
+        superblock: &SuperBlock,
+        kind: &Kind,
+    ) -> Result<Option<CompressionOptions>, BackhandError> {
+        let mut container = Reader::new(&mut *reader);
+        let metadata_len = u16::from_reader_with_ctx(&mut container, kind.inner.data_endian)?;
+        if metadata::len(metadata_len) == 0 {
+            error!("compression options are present, but empty, not using");
+            return Ok(None);
+        }
+        reader.seek(SeekFrom::Current(-2))?;
+
+        let bytes = metadata::read_block(reader, superblock, kind)?;
+        // data -> compression options
+        match CompressionOptions::from_bytes(&bytes, superblock.compressor, kind) {
+            Ok(co) => Ok(Some(co)),
+            Err(e) => {
+                warn!("unknown compression options: {e:?}, keeping as raw bytes");
+                Ok(Some(CompressionOptions::Raw(bytes)))
+            }
+        }
+    }
+
     /// Create `Squashfs` from `Read`er, with the resulting squashfs having read all fields needed
     /// to regenerate the original squashfs and interact with the fs in memory without needing to
     /// read again from `Read`er. `reader` needs to start with the beginning of the Image.
@@ -289,6 +379,19 @@ impl<'b> Squashfs<'b> {
         Self::from_reader_with_offset(reader, 0)
     }
 
+    /// Same as [`Self::from_reader`], but reading from the image bytes in `bytes`
+    ///
+    /// `bytes` is borrowed for as long as the `Squashfs`, and isn't copied.
+    ///
+    /// ```rust,no_run
+    /// # use backhand::Squashfs;
+    /// let image = std::fs::read("image.squashfs").unwrap();
+    /// let squashfs = Squashfs::from_slice(&image).unwrap();
+    /// ```
+    pub fn from_slice(bytes: &'b [u8]) -> Result<Self, BackhandError> {
+        Self::from_reader(Cursor::new(bytes))
+    }
+
     /// Same as [`Self::from_reader`], but seek'ing to `offset` in `reader` before Reading
     ///
     /// Uses default [`Kind`]: [`LE_V4_0`]
@@ -300,24 +403,80 @@ impl<'b> Squashfs<'b> {
     }
 
     /// Same as [`Self::from_reader_with_offset`], but including custom `kind`
+    ///
+    /// With the [`AVM_BE_V4_0`](crate::kind::AVM_BE_V4_0) kind, a vendor header before the
+    /// superblock is skipped: if the magic isn't at `offset`, the image is read from the first
+    /// magic in the following 64KiB.
     pub fn from_reader_with_offset_and_kind(
-        reader: impl BufReadSeek + 'b,
-        offset: u64,
+        mut reader: impl BufReadSeek + 'b,
+        mut offset: u64,
         kind: Kind,
     ) -> Result<Self, BackhandError> {
+        if kind.is_avm() {
+            offset += Self::avm_header_len(&mut reader, offset, &kind)?;
+        }
         let reader: Box<dyn BufReadSeek + 'b> = if offset == 0 {
             Box::new(reader)
         } else {
             let reader = SquashfsReaderWithOffset::new(reader, offset)?;
             Box::new(reader)
         };
-        Self::inner_from_reader_with_offset_and_kind(reader, kind)
+        Self::inner_from_reader_with_offset_and_kind(reader, offset, kind, false)
+    }
+
+    /// Length of the header before the magic of `kind` at `offset` in `reader`, `0` if the magic
+    /// is at `offset` or isn't found
+    fn avm_header_len(
+        reader: &mut impl BufReadSeek,
+        offset: u64,
+        kind: &Kind,
+    ) -> Result<u64, BackhandError> {
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut bytes = vec![];
+        reader.by_ref().take(AVM_HEADER_MAX_LEN + 4).read_to_end(&mut bytes)?;
+        reader.seek(SeekFrom::Start(offset))?;
+        let header_len = bytes
+            .windows(4)
+            .position(|window| window == kind.inner.magic)
+            .map_or(0, |position| position as u64);
+        if header_len != 0 {
+            info!("skipping {header_len:#x} bytes of AVM header");
+        }
+        Ok(header_len)
+    }
+
+    /// Same as [`Self::from_reader`], but without reading the inode table up front
+    ///
+    /// Inodes are decoded on demand from the inode table when used by [`Self::lookup`] or
+    /// [`Self::into_filesystem_reader`], keeping a small number of recently used uncompressed
+    /// metadata blocks. [`Self::inodes`] is left empty. This allows opening images with a large
+    /// amount of inodes when only a few paths are needed.
+    ///
+    /// ```rust,no_run
+    /// # use std::fs::File;
+    /// # use std::io::BufReader;
+    /// # use backhand::Squashfs;
+    /// let file = BufReader::new(File::open("image.squashfs").unwrap());
+    /// let squashfs = Squashfs::from_reader_lazy(file).unwrap();
+    /// let inode = squashfs.lookup("/usr/bin/wget").unwrap();
+    /// ```
+    pub fn from_reader_lazy(reader: impl BufReadSeek + 'b) -> Result<Self, BackhandError> {
+        let kind = Kind { inner: Arc::new(LE_V4_0) };
+        Self::inner_from_reader_with_offset_and_kind(Box::new(reader), 0, kind, true)
     }
 
     fn inner_from_reader_with_offset_and_kind(
         mut reader: Box<dyn BufReadSeek + 'b>,
+        offset: u64,
         kind: Kind,
+        lazy: bool,
     ) -> Result<Self, BackhandError> {
+        // the inode table can only be read up front when the size of every inode type is known
+        let lazy = lazy || kind.inner.on_unknown_inode == OnUnknownInode::Skip;
+        let mut raw_superblock = [0; SUPERBLOCK_SIZE];
+        reader.read_exact(&mut raw_superblock)?;
+        reader.seek(SeekFrom::Current(-(SUPERBLOCK_SIZE as i64)))?;
+
         let (superblock, compression_options) =
             Self::superblock_and_compression_options(&mut reader, &kind)?;
 
@@ -358,27 +517,59 @@ impl<'b> Squashfs<'b> {
         }
 
         // Read all fields from filesystem to make a Squashfs
-        info!("Reading Inodes");
-        let (root_inode, inodes) = reader.inodes(&superblock, &kind)?;
+        let (root_inode, inodes, lazy_inode_blocks) = if lazy {
+            info!("Reading Root Inode");
+            let mut blocks = VecDeque::with_capacity(LAZY_INODE_BLOCKS);
+            let (root_start, root_offset) = InodeRef::decode(superblock.root_inode);
+            let root_inode = read_lazy_inode(
+                &mut reader,
+                &mut blocks,
+                &superblock,
+                &kind,
+                u64::from(root_start),
+                root_offset,
+            )?;
+            (root_inode, IntMap::default(), Some(Mutex::new(blocks)))
+        } else {
+            info!("Reading Inodes");
+            let (root_inode, inodes) = reader.inodes(&superblock, &kind)?;
+            (root_inode, inodes, None)
+        };
 
         info!("Reading Fragments");
         let fragments = reader.fragments(&superblock, &kind)?;
-        let fragment_ptr = fragments.as_ref().map(|frag| frag.0);
-        let fragment_table = fragments.map(|a| a.1);
+        let fragment_ptr =
+            fragments.as_ref().map(|frag| frag.0.first().copied().unwrap_or(superblock.frag_table));
+        let (fragment_ptrs, fragment_table) = fragments.map(|a| (a.0, a.1)).unzip();
 
         info!("Reading Exports");
         let export = reader.export(&superblock, &kind)?;
-        let export_ptr = export.as_ref().map(|export| export.0);
-        let export_table = export.map(|a| a.1);
+        let export_ptr = export
+            .as_ref()
+            .map(|export| export.0.first().copied().unwrap_or(superblock.export_table));
+        let (export_ptrs, export_table) = export.map(|a| (a.0, a.1)).unzip();
 
         info!("Reading Ids");
-        let id = reader.id(&superblock, &kind)?;
-        let id_ptr = id.0;
-        let id_table = id.1;
+        let (id_ptrs, id_table) = reader.id(&superblock, &kind)?;
+        let id_ptr = id_ptrs.first().copied().unwrap_or(superblock.id_table);
+
+        info!("Reading Xattr Ids");
+        // xattrs aren't used when reading files, so the image is still readable without them
+        let xattr_id_table = match reader.xattr_ids(&superblock, &kind) {
+            Ok(xattr_id_table) => xattr_id_table,
+            Err(e) => {
+                warn!("invalid xattr id table, reading without xattrs: {e}");
+                None
+            }
+        };
 
         let last_dir_position = if let Some(fragment_ptr) = fragment_ptr {
             trace!("using fragment for end of dir");
             fragment_ptr
+        } else if superblock.frag_table != NOT_SET {
+            // without fragments, the table pointer is still after the dirs
+            trace!("using fragment table for end of dir");
+            superblock.frag_table
         } else if let Some(export_ptr) = export_ptr {
             trace!("using export for end of dir");
             export_ptr
@@ -405,7 +596,17 @@ impl<'b> Squashfs<'b> {
             fragments: fragment_table,
             export: export_table,
             id: id_table,
-            file: reader,
+            xattr_ids: xattr_id_table,
+            table_pointers: TablePointers {
+                fragment: fragment_ptrs.unwrap_or_default(),
+                id: id_ptrs,
+                export: export_ptrs.unwrap_or_default(),
+            },
+            offset,
+            total_length,
+            raw_superblock,
+            lazy_inode_blocks,
+            file: Mutex::new(reader),
         };
 
         // show info about flags
@@ -449,18 +650,20 @@ impl<'b> Squashfs<'b> {
         Ok(squashfs)
     }
 
+    /// Uncompressed directory listing bytes of a directory inode
+    ///
+    /// A `file_size` past the end of the directory table is clamped to it, as seen in malformed
+    /// images, the listing then ending with the last entry that can be read.
+    ///
     /// # Returns
-    /// - `Ok(Some(Vec<Dir>))` when found dir
-    /// - `Ok(None)`           when empty dir
-    pub(crate) fn dir_from_index(
+    /// - `Ok(Some(&[u8]))` when found dir
+    /// - `Ok(None)`        when empty dir
+    fn dir_bytes(
         &self,
         block_index: u64,
         file_size: u32,
         block_offset: usize,
-    ) -> Result<Option<Vec<Dir>>, BackhandError> {
-        trace!("- block index : {:02x?}", block_index);
-        trace!("- file_size   : {:02x?}", file_size);
-        trace!("- block offset: {:02x?}", block_offset);
+    ) -> Result<Option<&[u8]>, BackhandError> {
         if file_size < 4 {
             return Ok(None);
         }
@@ -468,15 +671,265 @@ impl<'b> Squashfs<'b> {
         let Some(offset) = self.dir_blocks.0.get(&block_index) else {
             return Err(BackhandError::CorruptedOrInvalidSquashfs);
         };
-        let Some(block) = &self.dir_blocks.1.get(*offset as usize..) else {
+        let Some(block) = self.dir_blocks.1.get(*offset as usize..) else {
+            return Err(BackhandError::CorruptedOrInvalidSquashfs);
+        };
+
+        let Some(bytes) = block.get(block_offset..) else {
+            return Err(BackhandError::CorruptedOrInvalidSquashfs);
+        };
+
+        // file_size includes 3 bytes not stored in the directory table
+        let len = file_size as usize - 3;
+        if bytes.len() < len {
+            warn!(
+                "directory size {len} past the end of the directory table, reading {} bytes",
+                bytes.len()
+            );
+        }
+
+        Ok(Some(&bytes[..len.min(bytes.len())]))
+    }
+
+    /// Find the [`Inode`] of `path`, starting from the root inode
+    ///
+    /// For an [`ExtendedDirectory`](crate::ExtendedDirectory) with a directory index, the index
+    /// is used to skip directly to the directory header that could contain each name instead
+    /// of scanning the whole directory.
+    ///
+    /// When not found this way, all entries are checked in case the directory entries of the
+    /// image aren't sorted.
+    ///
+    /// When read with [`Self::from_reader_lazy`], the found [`Inode`] is decoded from the image
+    /// and returned owned.
+    ///
+    /// # Returns
+    /// - `Ok(Some(Cow<Inode>))` when found
+    /// - `Ok(None)`             when not found
+    pub fn lookup<P: AsRef<Path>>(&self, path: P) -> Result<Option<Cow<'_, Inode>>, BackhandError> {
+        let mut inode = Cow::Borrowed(&self.root_inode);
+        for component in path.as_ref().components() {
+            match component {
+                Component::RootDir | Component::CurDir => (),
+                Component::Normal(name) => match self.dir_lookup(&inode, name.as_bytes())? {
+                    Some(found) => inode = found,
+                    None => return Ok(None),
+                },
+                _ => return Err(BackhandError::InvalidFilePath),
+            }
+        }
+        Ok(Some(inode))
+    }
+
+    /// Find the entry `name` within the directory `dir_inode`
+    fn dir_lookup(
+        &self,
+        dir_inode: &Inode,
+        name: &[u8],
+    ) -> Result<Option<Cow<'_, Inode>>, BackhandError> {
+        let (block_index, file_size, block_offset, dir_index) = match &dir_inode.inner {
+            InodeInner::BasicDirectory(basic_dir) => (
+                u64::from(basic_dir.block_index),
+                u32::from(basic_dir.file_size),
+                basic_dir.block_offset as usize,
+                &[][..],
+            ),
+            InodeInner::ExtendedDirectory(ext_dir) => (
+                u64::from(ext_dir.block_index),
+                ext_dir.file_size,
+                ext_dir.block_offset as usize,
+                &ext_dir.dir_index[..],
+            ),
+            // not a directory, so nothing below it
+            _ => return Ok(None),
+        };
+        let Some(bytes) = self.dir_bytes(block_index, file_size, block_offset)? else {
+            return Ok(None);
+        };
+        if self.kind.inner.unsorted_dirs {
+            return self.dir_scan(bytes, name, false);
+        }
+
+        // Each index points to a directory header, with the name of its first entry. Skip to the
+        // last header starting at or before `name`, as entries are sorted.
+        let skip = dir_index
+            .iter()
+            .take_while(|index| index.name.as_slice() <= name)
+            .last()
+            .map_or(0, |index| index.index as usize);
+        trace!("dir lookup skipping {:02x?} bytes", skip);
+        let Some(sorted_bytes) = bytes.get(skip..) else {
+            return Err(BackhandError::CorruptedOrInvalidSquashfs);
+        };
+        self.dir_scan(sorted_bytes, name, true)
+    }
+
+    /// Find the entry `name` in the directory headers of `bytes`, stopping at the first entry
+    /// after `name` if `sorted`
+    fn dir_scan(
+        &self,
+        bytes: &[u8],
+        name: &[u8],
+        sorted: bool,
+    ) -> Result<Option<Cow<'_, Inode>>, BackhandError> {
+        let mut cursor = Cursor::new(bytes);
+        let mut container = Reader::new(&mut cursor);
+        while let Ok(d) = Dir::from_reader_with_ctx(&mut container, self.kind.inner.type_endian) {
+            for entry in &d.dir_entries {
+                match entry.name.as_slice().cmp(name) {
+                    Ordering::Equal => return self.entry_inode(&d, entry),
+                    Ordering::Greater if sorted => return Ok(None),
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// [`Inode`] of `entry` in the directory header `dir`
+    ///
+    /// When read with [`Self::from_reader_lazy`], this is decoded from the inode table.
+    fn entry_inode(
+        &self,
+        dir: &Dir,
+        entry: &DirEntry,
+    ) -> Result<Option<Cow<'_, Inode>>, BackhandError> {
+        let Ok(inode_key) = (i64::from(dir.inode_num) + i64::from(entry.inode_offset)).try_into()
+        else {
             return Err(BackhandError::CorruptedOrInvalidSquashfs);
         };
+        let Some(blocks) = &self.lazy_inode_blocks else {
+            return Ok(self.inodes.get(&inode_key).map(Cow::Borrowed));
+        };
 
-        if (block.len() as u32) < (block_offset as u32 + file_size - 3) {
+        let mut blocks = blocks.lock().unwrap();
+        let mut file = self.file.lock().unwrap();
+        let inode = read_lazy_inode(
+            &mut *file,
+            &mut blocks,
+            &self.superblock,
+            &self.kind,
+            u64::from(dir.start),
+            entry.offset,
+        )?;
+        if inode.header.inode_number != inode_key {
+            error!("inode number {} != dir entry {}", inode.header.inode_number, inode_key);
             return Err(BackhandError::CorruptedOrInvalidSquashfs);
         }
+        Ok(Some(Cow::Owned(inode)))
+    }
+
+    /// On-disk bytes of the [`SuperBlock`], before parsing
+    ///
+    /// Useful for diagnostics, such as attaching to bug reports.
+    pub fn raw_superblock_bytes(&self) -> [u8; SUPERBLOCK_SIZE] {
+        self.raw_superblock
+    }
+
+    /// Pointers to the metadata blocks of the fragment, id, and export tables, as read from the
+    /// image
+    ///
+    /// Useful for diagnostics, such as finding why a table fails to parse.
+    pub fn table_pointers(&self) -> &TablePointers {
+        &self.table_pointers
+    }
+
+    /// Bytes used by the image, as declared in the [`SuperBlock`]
+    pub fn declared_bytes_used(&self) -> u64 {
+        self.superblock.bytes_used
+    }
+
+    /// Length of the reader, starting at the image offset
+    pub fn actual_length(&self) -> u64 {
+        self.total_length
+    }
 
-        let bytes = &block[block_offset..][..file_size as usize - 3];
+    /// Offset in the reader after this image, aligned to [`DEFAULT_PAD_LEN`]
+    ///
+    /// Where the next image of a blob of multiple images can start, see
+    /// [`Self::next_image_offset_aligned`].
+    pub fn next_image_offset(&self) -> u64 {
+        self.next_image_offset_aligned(u64::from(DEFAULT_PAD_LEN))
+    }
+
+    /// Same as [`Self::next_image_offset`], but rounding up to `alignment` bytes
+    ///
+    /// The alignment is relative to the start of the reader, not the image offset.
+    pub fn next_image_offset_aligned(&self, alignment: u64) -> u64 {
+        (self.offset + self.superblock.bytes_used).next_multiple_of(alignment.max(1))
+    }
+
+    /// Files with data blocks overlapping the data blocks of another file
+    ///
+    /// The data of each file is `blocks_start` until the end of its compressed data blocks. Files
+    /// with exactly the same data are not overlapping, as this is from files being deduplicated.
+    /// Any other overlap is a sign of a corrupted image, such as an inode pointing into the data
+    /// of a different file.
+    ///
+    /// Only the inodes of [`Self::inodes`] are checked, which is empty when reading with
+    /// [`Self::from_reader_lazy`].
+    pub fn check_overlaps(&self) -> Vec<Overlap> {
+        let mut ranges: Vec<(u64, u64, u32)> = self
+            .inodes
+            .iter()
+            .filter_map(|(inode_number, inode)| {
+                let (start, block_sizes) = match &inode.inner {
+                    InodeInner::BasicFile(file) => {
+                        (u64::from(file.blocks_start), &file.block_sizes)
+                    }
+                    InodeInner::ExtendedFile(file) => (file.blocks_start, &file.block_sizes),
+                    _ => return None,
+                };
+                let len: u64 = block_sizes.iter().map(|block| u64::from(block.size())).sum();
+                // sparse files can be only holes
+                (len != 0).then_some((start, start + len, *inode_number))
+            })
+            .collect();
+        ranges.sort_unstable();
+
+        let mut overlaps = vec![];
+        for (index, &(start, end, inode_number)) in ranges.iter().enumerate() {
+            for &(next_start, next_end, next_inode_number) in &ranges[index + 1..] {
+                if next_start >= end {
+                    break;
+                }
+                if (next_start, next_end) == (start, end) {
+                    continue;
+                }
+                overlaps.push(Overlap {
+                    first: inode_number,
+                    second: next_inode_number,
+                    start: next_start,
+                    end: end.min(next_end),
+                });
+            }
+        }
+        overlaps
+    }
+
+    /// Bytes of the reader after [`Self::declared_bytes_used`]
+    ///
+    /// This includes padding of the image, as well as any data appended after the image.
+    pub fn trailing_bytes(&self) -> u64 {
+        self.total_length - self.superblock.bytes_used
+    }
+
+    /// # Returns
+    /// - `Ok(Some(Vec<Dir>))` when found dir
+    /// - `Ok(None)`           when empty dir
+    pub(crate) fn dir_from_index(
+        &self,
+        block_index: u64,
+        file_size: u32,
+        block_offset: usize,
+    ) -> Result<Option<Vec<Dir>>, BackhandError> {
+        trace!("- block index : {:02x?}", block_index);
+        trace!("- file_size   : {:02x?}", file_size);
+        trace!("- block offset: {:02x?}", block_offset);
+        let Some(bytes) = self.dir_bytes(block_index, file_size, block_offset)? else {
+            return Ok(None);
+        };
         let mut dirs = vec![];
         // Read until we fail to turn bytes into `T`
         let mut cursor = Cursor::new(bytes);
@@ -495,6 +948,7 @@ impl<'b> Squashfs<'b> {
         root: &mut Nodes<SquashfsFileReader>,
         dir_inode: &Inode,
         id_table: &[Id],
+        decoded: &mut IntMap<u32, Inode>,
     ) -> Result<(), BackhandError> {
         let dirs = match &dir_inode.inner {
             InodeInner::BasicDirectory(basic_dir) => {
@@ -519,27 +973,38 @@ impl<'b> Squashfs<'b> {
             for d in &dirs {
                 trace!("extracting entry: {:#?}", d.dir_entries);
                 for entry in &d.dir_entries {
-                    let Ok(inode_key) = (d.inode_num as i32 + entry.inode_offset as i32).try_into()
-                    else {
-                        return Err(BackhandError::CorruptedOrInvalidSquashfs);
+                    let Some(t) = InodeId::from_id(entry.t) else {
+                        match self.kind.inner.on_unknown_inode {
+                            OnUnknownInode::Error => {
+                                return Err(BackhandError::UnknownInode(entry.t))
+                            }
+                            OnUnknownInode::Skip => {
+                                warn!(
+                                    "skipping {:?}: unknown inode type {}",
+                                    fullpath.join(entry.name()?),
+                                    entry.t
+                                );
+                                continue;
+                            }
+                        }
                     };
-                    let Some(found_inode) = &self.inodes.get(&inode_key) else {
+                    let Some(found_inode) = self.entry_inode(d, entry)? else {
                         return Err(BackhandError::CorruptedOrInvalidSquashfs);
                     };
                     let header = found_inode.header;
                     fullpath.push(entry.name()?);
 
-                    let inner: InnerNode<SquashfsFileReader> = match entry.t {
+                    let inner: InnerNode<SquashfsFileReader> = match t {
                         // BasicDirectory, ExtendedDirectory
                         InodeId::BasicDirectory | InodeId::ExtendedDirectory => {
                             // its a dir, extract all children inodes
-                            if *found_inode == dir_inode {
+                            if *found_inode == *dir_inode {
                                 error!("self referential dir to already read inode");
                                 return Err(BackhandError::UnexpectedInode(
                                     dir_inode.inner.clone(),
                                 ));
                             }
-                            self.extract_dir(fullpath, root, found_inode, &self.id)?;
+                            self.extract_dir(fullpath, root, &found_inode, &self.id, decoded)?;
                             InnerNode::Dir(SquashfsDir::default())
                         }
                         // BasicFile
@@ -557,21 +1022,33 @@ impl<'b> Squashfs<'b> {
                                     ))
                                 }
                             };
+                            // such as with a frag_count of 0, no fragments can be read
+                            let fragment_count = self.fragments.as_ref().map_or(0, Vec::len);
+                            if inner.frag_index() != 0xffffffff
+                                && inner.frag_index() >= fragment_count
+                            {
+                                error!(
+                                    "{}: fragment {} not in the {fragment_count} fragments",
+                                    fullpath.display(),
+                                    inner.frag_index()
+                                );
+                                return Err(BackhandError::CorruptedOrInvalidSquashfs);
+                            }
                             InnerNode::File(inner)
                         }
                         // Basic Symlink
                         InodeId::BasicSymlink => {
-                            let link = self.symlink_target_path(found_inode)?;
+                            let link = self.symlink_target_path(&found_inode)?;
                             InnerNode::Symlink(SquashfsSymlink { link })
                         }
                         // Basic CharacterDevice
                         InodeId::BasicCharacterDevice => {
-                            let device_number = Self::char_device_number(found_inode)?;
+                            let device_number = Self::char_device_number(&found_inode)?;
                             InnerNode::CharacterDevice(SquashfsCharacterDevice { device_number })
                         }
                         // Basic CharacterDevice
                         InodeId::BasicBlockDevice => {
-                            let device_number = Self::block_device_number(found_inode)?;
+                            let device_number = Self::block_device_number(&found_inode)?;
                             InnerNode::BlockDevice(SquashfsBlockDevice { device_number })
                         }
                         InodeId::BasicNamedPipe => InnerNode::NamedPipe,
@@ -580,12 +1057,19 @@ impl<'b> Squashfs<'b> {
                             return Err(BackhandError::UnsupportedInode(found_inode.inner.clone()))
                         }
                     };
-                    let node = Node::new(
+                    let mut node = Node::new(
                         fullpath.clone(),
                         NodeHeader::from_inode(header, id_table)?,
                         inner,
                     );
+                    node.inode_number = Some(header.inode_number);
+                    if let InodeInner::BasicSymlink(symlink) = &found_inode.inner {
+                        node.link_count = Some(symlink.link_count);
+                    }
                     root.nodes.push(node);
+                    if let Cow::Owned(inode) = found_inode {
+                        decoded.insert(header.inode_number, inode);
+                    }
                     fullpath.pop();
                 }
             }
@@ -637,26 +1121,138 @@ impl<'b> Squashfs<'b> {
     /// Convert into [`FilesystemReader`] by extracting all file bytes and converting into a filesystem
     /// like structure in-memory
     pub fn into_filesystem_reader(self) -> Result<FilesystemReader<'b>, BackhandError> {
+        self.filesystem_reader(false)
+    }
+
+    /// Same as [`Self::into_filesystem_reader`], but retaining `self` to be accessed with
+    /// [`FilesystemReader::raw_squashfs`]
+    ///
+    /// This avoids reading the image twice when both are needed, at the cost of keeping a copy of
+    /// the inodes, fragments, and ids in memory. The retained [`Squashfs`] no longer owns the
+    /// reader, which is moved into the [`FilesystemReader`].
+    pub fn into_filesystem_reader_retaining(self) -> Result<FilesystemReader<'b>, BackhandError> {
+        self.filesystem_reader(true)
+    }
+
+    fn filesystem_reader(mut self, retain: bool) -> Result<FilesystemReader<'b>, BackhandError> {
         info!("creating fs tree");
         let mut root = Nodes::new_root(NodeHeader::from_inode(self.root_inode.header, &self.id)?);
-        self.extract_dir(&mut PathBuf::from("/"), &mut root, &self.root_inode, &self.id)?;
+        root.root_mut().inode_number = Some(self.root_inode.header.inode_number);
+        let mut decoded = IntMap::default();
+        self.extract_dir(
+            &mut PathBuf::from("/"),
+            &mut root,
+            &self.root_inode,
+            &self.id,
+            &mut decoded,
+        )?;
         root.nodes.sort();
+        let mut inode_nodes = IntMap::default();
+        for (index, node) in root.nodes.iter().enumerate() {
+            if let Some(inode_number) = node.inode_number {
+                inode_nodes.entry(inode_number).or_insert(index);
+            }
+        }
+
+        // inodes read on demand are kept, the same as reading all of them
+        if self.lazy_inode_blocks.take().is_some() && self.kind.inner.retain_inodes {
+            decoded.insert(self.root_inode.header.inode_number, self.root_inode.clone());
+            self.inodes = decoded;
+        }
 
         info!("created fs tree");
+        let kind = Kind { inner: self.kind.inner.clone() };
+        let superblock = self.superblock;
+        let compression_options = self.compression_options.clone();
+        let export_count = self.export.as_ref().map_or(0, Vec::len);
+        let file = std::mem::replace(self.file.get_mut().unwrap(), Box::new(Cursor::new(vec![])));
+        let (id_table, fragments, inodes, raw) = if retain {
+            let inodes =
+                if kind.inner.retain_inodes { self.inodes.clone() } else { IntMap::default() };
+            (self.id.clone(), self.fragments.clone(), inodes, Some(Box::new(self)))
+        } else {
+            let inodes = if kind.inner.retain_inodes { self.inodes } else { IntMap::default() };
+            (self.id, self.fragments, inodes, None)
+        };
+
         let filesystem = FilesystemReader {
-            kind: self.kind,
-            block_size: self.superblock.block_size,
-            block_log: self.superblock.block_log,
-            compressor: self.superblock.compressor,
-            compression_options: self.compression_options,
-            mod_time: self.superblock.mod_time,
-            id_table: self.id,
-            fragments: self.fragments,
+            kind,
+            block_size: superblock.block_size,
+            block_log: superblock.block_log,
+            compressor: superblock.compressor,
+            compression_options,
+            mod_time: superblock.mod_time,
+            id_table,
+            fragments,
+            inodes,
+            inode_nodes,
+            fragment_lens: OnceLock::new(),
             root,
-            reader: Mutex::new(Box::new(self.file)),
+            reader: Mutex::new(file),
             cache: RwLock::new(Cache::default()),
-            no_duplicate_files: self.superblock.data_has_been_deduplicated(),
+            no_duplicate_files: superblock.data_has_been_deduplicated(),
+            always_fragment: superblock.fragments_are_always_generated(),
+            fragments_uncompressed: superblock.fragments_stored_uncompressed(),
+            exportable: superblock.nfs_export_table_exists(),
+            export_count,
+            bytes_used: superblock.bytes_used,
+            raw,
+            extract_limit: None,
+            extracted: AtomicU64::new(0),
+            superblock,
         };
         Ok(filesystem)
     }
 }
+
+/// Decode the [`Inode`] at `offset` within the inode table metadata block at `start`
+///
+/// Blocks not found in `blocks` are read from `reader`, keeping the [`LAZY_INODE_BLOCKS`] most
+/// recently used. An inode continues into the following blocks when it doesn't fit in the rest
+/// of the block.
+pub(crate) fn read_lazy_inode<R: Read + Seek>(
+    reader: &mut R,
+    blocks: &mut LazyInodeBlocks,
+    superblock: &SuperBlock,
+    kind: &Kind,
+    mut start: u64,
+    offset: u16,
+) -> Result<Inode, BackhandError> {
+    let mut bytes = vec![];
+    let mut skip = usize::from(offset);
+    loop {
+        if let Some(index) = blocks.iter().position(|(block_start, ..)| *block_start == start) {
+            let block = blocks.remove(index).unwrap();
+            blocks.push_back(block);
+        } else {
+            let position = superblock.inode_table.saturating_add(start);
+            if position >= superblock.dir_table {
+                error!("inode block {start:02x?} outside of inode table");
+                return Err(BackhandError::CorruptedOrInvalidSquashfs);
+            }
+            reader.seek(SeekFrom::Start(position))?;
+            let block = metadata::read_block(reader, superblock, kind)?;
+            let next = reader.stream_position()? - superblock.inode_table;
+            if blocks.len() == LAZY_INODE_BLOCKS {
+                blocks.pop_front();
+            }
+            blocks.push_back((start, block, next));
+        }
+
+        let (_, block, next) = blocks.back().unwrap();
+        let Some(block) = block.get(skip..) else {
+            return Err(BackhandError::CorruptedOrInvalidSquashfs);
+        };
+        bytes.extend_from_slice(block);
+        skip = 0;
+        start = *next;
+
+        let mut cursor = Cursor::new(&bytes);
+        let mut container = Reader::new(&mut cursor);
+        match read_inode(&mut container, superblock, kind) {
+            // continued in the next block
+            Err(BackhandError::Deku(DekuError::Incomplete(_))) => (),
+            inode => return inode,
+        }
+    }
+}
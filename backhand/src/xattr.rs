@@ -0,0 +1,40 @@
+//! Extended attribute support
+
+use deku::prelude::*;
+
+pub(crate) const ID_SIZE: usize =
+    std::mem::size_of::<u64>() + std::mem::size_of::<u32>() + std::mem::size_of::<u32>();
+
+pub(crate) const HEADER_SIZE: usize =
+    std::mem::size_of::<u64>() + std::mem::size_of::<u32>() + std::mem::size_of::<u32>();
+
+/// Start of the xattr id table, followed by the pointers to its metadata blocks
+#[derive(Copy, Clone, Debug, PartialEq, Eq, DekuRead, DekuWrite)]
+#[deku(endian = "type_endian", ctx = "type_endian: deku::ctx::Endian")]
+pub(crate) struct XattrIdHeader {
+    pub(crate) kv_start: u64,
+    pub(crate) count: u32,
+    pub(crate) unused: u32,
+}
+
+/// Location of the set of xattrs of an inode, indexed by the `xattr_index` of the inode
+#[derive(Copy, Clone, Debug, PartialEq, Eq, DekuRead, DekuWrite)]
+#[deku(endian = "type_endian", ctx = "type_endian: deku::ctx::Endian")]
+pub struct XattrId {
+    /// Reference to the first key-value pair, the start of its metadata block from
+    /// [`XattrIdTable::kv_start`] shifted left by 16, with the offset in the uncompressed block in
+    /// the lower 16 bits
+    pub xattr: u64,
+    /// Amount of key-value pairs
+    pub count: u32,
+    /// Total size of the key-value pairs, uncompressed
+    pub size: u32,
+}
+
+/// Xattr Id Lookup Table Cache
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XattrIdTable {
+    /// Start of the key-value table
+    pub kv_start: u64,
+    pub ids: Vec<XattrId>,
+}
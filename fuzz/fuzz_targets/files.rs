@@ -0,0 +1,24 @@
+#![no_main]
+
+use std::io::Read;
+
+use backhand::{FilesystemReader, InnerNode};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: Vec<u8>| {
+    let reader = std::io::Cursor::new(data);
+    let Ok(filesystem) = FilesystemReader::from_reader(reader) else {
+        return;
+    };
+
+    // read the data of all files, limited so a fuzzed file_size can't use all memory
+    for node in filesystem.files() {
+        if let InnerNode::File(file) = &node.inner {
+            let file = filesystem.file(file);
+            let _ = file.block_locations();
+            let _ = file.compressed_size();
+            let _ = file.reader().take(1 << 24).read_to_end(&mut vec![]);
+        }
+    }
+    let _ = filesystem.fragment_usage();
+});